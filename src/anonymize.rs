@@ -0,0 +1,205 @@
+//! Replacing identities and messages with stable pseudonyms, to share the shape of a history
+//! without the people in it.
+use crate::{AnyResult, files, filter::Filter, mirror::CommitDescriptor, policy, state};
+use chacha20poly1305::aead::{OsRng, rand_core::RngCore};
+use gix::{
+    Repository,
+    actor::Signature,
+    bstr::{BString, ByteSlice},
+};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Hex digits of the keyed hash kept in pseudonyms and hashed messages.
+const DIGITS: usize = 10;
+
+/// Domain of pseudonymous emails, reserved so that nothing can ever be sent to them.
+const DOMAIN: &str = "anonymous.invalid";
+
+/// What happens to the messages of anonymized commits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum MessageMode {
+    /// Keep messages, with the identities of trailers like `Signed-off-by` replaced.
+    #[default]
+    Keep,
+    /// Leave every message empty.
+    Blank,
+    /// Replace each message with its keyed hash, so equal messages stay equal.
+    Hash,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Anonymize {
+    /// File holding the key; [`default_key_path`] if unset.
+    pub key_file: Option<PathBuf>,
+    pub messages: MessageMode,
+}
+
+pub fn default_key_path(repo: &Repository) -> PathBuf {
+    state::dir(repo).join("anonymize.key")
+}
+
+/// The key in `path`, created there unless this is a dry run.
+fn load_key(path: &Path, dry_run: bool) -> AnyResult<Vec<u8>> {
+    if path.exists() {
+        let text =
+            fs::read_to_string(path).map_err(|e| format!("reading {}: {e}", path.display()))?;
+        let key = hex::decode(text.trim())
+            .map_err(|e| format!("{} doesn't hold a hex key: {e}", path.display()))?;
+        if key.is_empty() {
+            return Err(format!("{} holds an empty key", path.display()).into());
+        }
+        return Ok(key);
+    }
+    let mut key = vec![0u8; 32];
+    OsRng.fill_bytes(&mut key);
+    if dry_run {
+        println!(
+            "No key in {}; pseudonyms of this dry run are made with a throwaway key",
+            path.display()
+        );
+    } else {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        files::write_atomically(path, format!("{}\n", hex::encode(&key)).as_bytes())
+            .map_err(|e| format!("writing {}: {e}", path.display()))?;
+        println!(
+            "Created anonymization key {}; keep it to give the same people the same \
+             pseudonyms next time, and keep it private",
+            path.display()
+        );
+    }
+    Ok(key)
+}
+
+/// HMAC-SHA1 of `data` under `key`.
+fn keyed_hash(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let sha1 = |parts: &[&[u8]]| -> Vec<u8> {
+        let mut hasher = gix::hash::hasher(gix::hash::Kind::Sha1);
+        for part in parts {
+            hasher.update(part);
+        }
+        hasher
+            .try_finalize()
+            .expect("only colliding input fails, and keys are random")
+            .as_bytes()
+            .to_vec()
+    };
+    let mut block = [0u8; 64];
+    if key.len() > block.len() {
+        block[..20].copy_from_slice(&sha1(&[key]));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let pad = |byte: u8| block.map(|b| b ^ byte);
+    let inner = sha1(&[&pad(0x36), data]);
+    sha1(&[&pad(0x5c), &inner])
+}
+
+/// Replaces every author and committer with a pseudonym derived from a keyed hash of their
+/// email, and blanks or hashes messages if asked to.
+///
+/// Emails are compared regardless of case, so one person gets one pseudonym however they
+/// spelled it. The same key gives the same pseudonyms in every run and repository; without the
+/// key, pseudonyms can't be traced back by hashing known emails.
+pub(crate) struct Anonymizer {
+    key: Vec<u8>,
+    messages: MessageMode,
+    /// Pseudonymous name and email, by lowercased original email.
+    pseudonyms: HashMap<String, (BString, BString)>,
+}
+
+impl Anonymizer {
+    pub(crate) fn new(repo: &Repository, settings: &Anonymize, dry_run: bool) -> AnyResult<Self> {
+        let path = settings
+            .key_file
+            .clone()
+            .unwrap_or_else(|| default_key_path(repo));
+        Ok(Anonymizer {
+            key: load_key(&path, dry_run)?,
+            messages: settings.messages,
+            pseudonyms: HashMap::new(),
+        })
+    }
+
+    fn digest(&self, data: &[u8]) -> String {
+        let mut digest = hex::encode(keyed_hash(&self.key, data));
+        digest.truncate(DIGITS);
+        digest
+    }
+
+    fn pseudonym(&mut self, email: &[u8]) -> &(BString, BString) {
+        let email = email.to_str_lossy().to_lowercase();
+        if !self.pseudonyms.contains_key(&email) {
+            let digest = self.digest(&[b"email\0", email.as_bytes()].concat());
+            let pseudonym = (
+                format!("Contributor {digest}").into(),
+                format!("{digest}@{DOMAIN}").into(),
+            );
+            self.pseudonyms.insert(email.clone(), pseudonym);
+        }
+        &self.pseudonyms[&email]
+    }
+
+    fn replace(&mut self, signature: &mut Signature) {
+        let (name, email) = self.pseudonym(&signature.email).clone();
+        signature.name = name;
+        signature.email = email;
+    }
+
+    /// `message` with the identities of `Key: Name <email>` trailers replaced, if it has any.
+    fn replace_trailer_identities(&mut self, message: &[u8]) -> Option<BString> {
+        let (body, trailers) = policy::split_trailers(message);
+        let mut changed = false;
+        let mut lines = Vec::new();
+        for line in trailers.lines() {
+            let identity = line.find(b": ").and_then(|colon| {
+                let value = line[colon + 2..].trim_end();
+                let open = value.rfind_byte(b'<')?;
+                value
+                    .ends_with(b">")
+                    .then(|| (&line[..colon], &value[open + 1..value.len() - 1]))
+            });
+            match identity {
+                Some((key, email)) => {
+                    let (name, email) = self.pseudonym(email);
+                    lines.push([key, b": ", name, b" <", email, b">"].concat());
+                    changed = true;
+                }
+                None => lines.push(line.to_vec()),
+            }
+        }
+        changed.then(|| [body, b"\n\n", &lines.join(&b'\n'), b"\n"].concat().into())
+    }
+}
+
+impl Filter for Anonymizer {
+    fn name(&self) -> String {
+        "anonymize".into()
+    }
+
+    fn apply(&mut self, _repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        self.replace(&mut descriptor.author);
+        self.replace(&mut descriptor.committer);
+        match self.messages {
+            MessageMode::Keep => {
+                if let Some(message) = self.replace_trailer_identities(&descriptor.message) {
+                    descriptor.message = message;
+                }
+            }
+            MessageMode::Blank => descriptor.message = BString::default(),
+            MessageMode::Hash => {
+                descriptor.message = format!(
+                    "{}\n",
+                    self.digest(&[b"message\0", &descriptor.message[..]].concat())
+                )
+                .into();
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,337 @@
+//! Replacing branches with their mirrors.
+use crate::{
+    AnyResult, doctor,
+    error::Failure,
+    refs::{self, BACKUP_REF_PREFIX, MirrorNaming, MirrorState},
+};
+use colored::Colorize;
+use gix::{
+    ObjectId, Repository,
+    refs::{
+        FullName, Target,
+        transaction::{Change, LogChange, PreviousValue, RefEdit, RefLog},
+    },
+};
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+pub struct ApplyOptions {
+    pub dry_run: bool,
+    pub naming: MirrorNaming,
+    /// Apply even if the branch moved since the mirror was created or is checked out.
+    pub force: bool,
+    /// Update the files of worktrees that have the branch checked out.
+    pub update_worktrees: bool,
+}
+
+/// Point `refs/heads/<target>` at the tip of its mirror and delete the mirror, in one ref
+/// transaction. The original tip is kept under [`BACKUP_REF_PREFIX`].
+pub fn apply(repo: &Repository, target: &str, options: &ApplyOptions) -> AnyResult<()> {
+    let target = target.strip_prefix("refs/heads/").unwrap_or(target);
+    let branch: FullName = format!("refs/heads/{target}").try_into()?;
+    let Some(mut branch_ref) = repo.try_find_reference(branch.as_ref())? else {
+        return Err(format!("`{target}` is not a branch").into());
+    };
+    let current = branch_ref.peel_to_id_in_place()?.detach();
+    let Some(mirror) = refs::find_mirror(repo, &options.naming, target)? else {
+        return Err(format!(
+            "{} does not exist; create it with `gitm mirror <base> {target}`",
+            options.naming.mirror_ref(target)
+        )
+        .into());
+    };
+
+    let moved = match refs::mirror_state(repo, &mirror)? {
+        MirrorState::Pending => None,
+        MirrorState::Applied => {
+            return Err(Failure::NothingToDo(format!(
+                "{target} already points at the tip of {}",
+                mirror.name.shorten()
+            ))
+            .into());
+        }
+        MirrorState::Advanced { new_commits } => Some(format!(
+            "{target} gained {new_commits} commit(s) since {} was created, which applying it \
+             would drop",
+            mirror.name.shorten()
+        )),
+        MirrorState::Diverged => Some(format!(
+            "{target} was rewritten since {} was created",
+            mirror.name.shorten()
+        )),
+        MirrorState::Unknown => Some(format!(
+            "{} doesn't record which commit of {target} it was created from",
+            mirror.name.shorten()
+        )),
+        MirrorState::TargetMissing => unreachable!("the branch was found above"),
+    };
+    if let Some(reason) = moved {
+        if !options.force {
+            return Err(Failure::ProtectedRef(format!(
+                "{reason}; mirror it again (--remirror repeats the command that created it), or pass \
+                 --force to apply it anyway"
+            ))
+            .into());
+        }
+        eprintln!("{} {reason}", "warning:".yellow());
+    }
+
+    ensure_no_operation_in_progress(repo)?;
+    let worktrees = ensure_not_checked_out(repo, target, options.force)?;
+
+    let backup: FullName = format!("{BACKUP_REF_PREFIX}{target}").try_into()?;
+    let short = |id: ObjectId| id.to_hex_with_len(7).to_string();
+    if options.dry_run {
+        println!(
+            "Would update {}: {} -> {}",
+            branch.as_bstr(),
+            short(current),
+            short(mirror.tip)
+        );
+        match earlier_backup(repo, target)? {
+            Some(earlier) => println!(
+                "Would set {} to {}, replacing the earlier backup {}",
+                backup.as_bstr(),
+                short(current),
+                short(earlier)
+            ),
+            None => println!("Would set {} to {}", backup.as_bstr(), short(current)),
+        }
+        println!("Would delete {}", mirror.name.as_bstr());
+        if options.update_worktrees {
+            for path in &worktrees {
+                println!("Would update the files in {}", path.display());
+            }
+        }
+        return Ok(());
+    }
+
+    let message = format!(
+        "magitulator apply: {} {} -> {}",
+        mirror.name.shorten(),
+        short(current),
+        short(mirror.tip)
+    );
+    let delete_mirror = RefEdit {
+        change: Change::Delete {
+            expected: PreviousValue::MustExistAndMatch(Target::Object(mirror.tip)),
+            log: RefLog::AndReference,
+        },
+        name: mirror.name.clone(),
+        deref: false,
+    };
+    replace_branch(
+        repo,
+        target,
+        current,
+        mirror.tip,
+        &message,
+        Some(delete_mirror),
+        options.update_worktrees,
+    )?;
+    println!("Deleted {}", mirror.name.shorten());
+    Ok(())
+}
+
+/// The worktrees, main or linked, that have `refs/heads/<target>` checked out.
+fn worktrees_on(repo: &Repository, target: &str) -> AnyResult<Vec<PathBuf>> {
+    // `HEAD` of a worktree names refs outside any namespace, so namespaced branches are never
+    // checked out.
+    if repo.namespace().is_some() {
+        return Ok(Vec::new());
+    }
+    let branch = format!("refs/heads/{target}");
+    let on_branch = |repo: &Repository| -> AnyResult<bool> {
+        Ok(repo
+            .head_name()?
+            .is_some_and(|head| head.as_bstr() == branch.as_str()))
+    };
+
+    let main = repo.main_repo()?;
+    let mut paths = Vec::new();
+    if let Some(workdir) = main.workdir()
+        && on_branch(&main)?
+    {
+        paths.push(
+            workdir
+                .canonicalize()
+                .unwrap_or_else(|_| workdir.to_owned()),
+        );
+    }
+    for proxy in main.worktrees()? {
+        let base = proxy.base()?;
+        if on_branch(&proxy.into_repo_with_possibly_inaccessible_worktree()?)? {
+            paths.push(base);
+        }
+    }
+    Ok(paths)
+}
+
+/// Fail if a worktree has `refs/heads/<target>` checked out, unless `force` is set. Returns
+/// those worktrees.
+pub(crate) fn ensure_not_checked_out(
+    repo: &Repository,
+    target: &str,
+    force: bool,
+) -> AnyResult<Vec<PathBuf>> {
+    let worktrees = worktrees_on(repo, target)?;
+    if worktrees.is_empty() {
+        return Ok(worktrees);
+    }
+    let places = worktrees
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    if !force {
+        return Err(Failure::DirtyWorktree(format!(
+            "{target} is checked out in {places}; switch to another branch there, or pass \
+             --force (and --update-worktree to update the checked out files)"
+        ))
+        .into());
+    }
+    eprintln!(
+        "{} {target} is checked out in {places}",
+        "warning:".yellow()
+    );
+    Ok(worktrees)
+}
+
+/// Fail if a merge, rebase, cherry-pick, revert, `git am` or bisect is in progress in any
+/// worktree. Those keep commits and refs of their own in the git directory, and moving a branch
+/// underneath them leaves them to continue from history that no longer exists.
+pub(crate) fn ensure_no_operation_in_progress(repo: &Repository) -> AnyResult<()> {
+    let mut in_progress = doctor::operations_in_progress(repo)?.into_iter();
+    let Some((first_path, first_state)) = in_progress.next() else {
+        return Ok(());
+    };
+    for (path, state) in in_progress {
+        let (operation, _) = doctor::describe_in_progress(state);
+        eprintln!(
+            "{} {operation} is also in progress in {}",
+            "note:".dimmed(),
+            path.display()
+        );
+    }
+    let (operation, conclude) = doctor::describe_in_progress(first_state);
+    Err(Failure::DirtyWorktree(format!(
+        "{operation} is in progress in {}; conclude it with `{conclude}` before moving branches",
+        first_path.display()
+    ))
+    .into())
+}
+
+/// The tip an earlier `apply` or `rewrite` of `target` backed up, which the next one replaces.
+pub(crate) fn earlier_backup(repo: &Repository, target: &str) -> AnyResult<Option<ObjectId>> {
+    Ok(repo
+        .try_find_reference(format!("{BACKUP_REF_PREFIX}{target}").as_str())?
+        .map(|mut reference| reference.peel_to_id_in_place())
+        .transpose()?
+        .map(|id| id.detach()))
+}
+
+/// Point `refs/heads/<target>` from `current` at `new_tip` and keep `current` under
+/// [`BACKUP_REF_PREFIX`], in one ref transaction with `also`. An earlier backup is replaced,
+/// which the output says; the backup ref's reflog still has it.
+///
+/// Worktrees that have the branch checked out are switched to the new tip's files with
+/// `update_worktrees`, and are otherwise left with the old ones.
+pub(crate) fn replace_branch(
+    repo: &Repository,
+    target: &str,
+    current: ObjectId,
+    new_tip: ObjectId,
+    message: &str,
+    also: Option<RefEdit>,
+    update_worktrees: bool,
+) -> AnyResult<()> {
+    let worktrees = worktrees_on(repo, target)?;
+    let branch: FullName = format!("refs/heads/{target}").try_into()?;
+    let backup: FullName = format!("{BACKUP_REF_PREFIX}{target}").try_into()?;
+    let earlier = earlier_backup(repo, target)?;
+    let update = |name: FullName, expected: PreviousValue, new| RefEdit {
+        change: Change::Update {
+            log: LogChange {
+                mode: RefLog::AndReference,
+                force_create_reflog: true,
+                message: message.into(),
+            },
+            expected,
+            new: Target::Object(new),
+        },
+        name,
+        deref: false,
+    };
+    repo.edit_references(
+        [
+            update(
+                branch.clone(),
+                PreviousValue::MustExistAndMatch(Target::Object(current)),
+                new_tip,
+            ),
+            update(
+                backup.clone(),
+                match earlier {
+                    Some(earlier) => PreviousValue::MustExistAndMatch(Target::Object(earlier)),
+                    None => PreviousValue::MustNotExist,
+                },
+                current,
+            ),
+        ]
+        .into_iter()
+        .chain(also),
+    )?;
+
+    println!(
+        "Updated {target} to {} ({} before, kept as {})",
+        new_tip.to_hex_with_len(7),
+        current.to_hex_with_len(7),
+        backup.as_bstr()
+    );
+    if let Some(earlier) = earlier.filter(|earlier| *earlier != current) {
+        println!(
+            "Replaced the earlier backup {} of {target}; `gitm recover --list` still lists it",
+            earlier.to_hex_with_len(7)
+        );
+    }
+    for path in &worktrees {
+        if update_worktrees {
+            update_worktree(path, &backup)?;
+            println!("Updated the files in {}", path.display());
+        } else {
+            println!(
+                "{} the files in {} are still those of the original {target}; \
+                 `git read-tree -m -u {} HEAD` there updates them, keeping uncommitted changes",
+                "hint:".dimmed(),
+                path.display(),
+                backup.as_bstr()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Move the index and files of the worktree at `path` from `previous` to its `HEAD`, like
+/// `git checkout` does, keeping uncommitted changes that don't conflict.
+fn update_worktree(path: &Path, previous: &FullName) -> AnyResult<()> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(path)
+        .args(["read-tree", "-m", "-u"])
+        .arg(previous.as_bstr().to_string())
+        .arg("HEAD")
+        .output()
+        .map_err(|e| format!("running git read-tree: {e}"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!(
+            "the branch was updated, but not the files in {}: {}",
+            path.display(),
+            stderr.lines().next().unwrap_or_default()
+        )
+        .into());
+    }
+    Ok(())
+}
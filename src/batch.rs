@@ -0,0 +1,559 @@
+//! Running mirror profiles across many repositories, as listed in a TOML manifest.
+//!
+//! ```toml
+//! [profiles.identity-fix]
+//! base = "main"
+//! target = "dev"
+//! args = ["--authors-file", "/srv/authors.txt", "--drop-empty"]
+//!
+//! [[repos]]
+//! path = "../service-a"
+//! profile = "identity-fix"
+//!
+//! [[repos]]
+//! url = "https://example.com/service-b.git"
+//! profile = "identity-fix"
+//! target = "trunk"
+//! ```
+//!
+//! Strings may refer to environment variables as `${NAME}` or `${NAME:-default}`, like
+//! `url = "https://${GIT_TOKEN}@example.com/service-c.git"`.
+use crate::{
+    AnyResult, config,
+    error::{Failure, NOTHING_TO_DO_EXIT_CODE},
+    pool::Turnstile,
+    refs::MirrorNaming,
+    report::RunReport,
+};
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Manifest {
+    /// Where repositories given by URL are cloned, relative to the manifest.
+    clone_dir: Option<PathBuf>,
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+    #[serde(default)]
+    repos: Vec<RepoEntry>,
+}
+
+/// A named set of `mirror` arguments shared by several repositories.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Profile {
+    pub(crate) base: Option<String>,
+    pub(crate) target: Option<String>,
+    /// Options of `gitm mirror`, as given on the command line.
+    #[serde(default)]
+    pub(crate) args: Vec<String>,
+}
+
+impl Manifest {
+    pub(crate) fn load(path: &Path) -> AnyResult<Self> {
+        let text =
+            fs::read_to_string(path).map_err(|e| format!("reading {}: {e}", path.display()))?;
+        let mut manifest: Manifest =
+            toml::from_str(&text).map_err(|e| format!("{}: {e}", path.display()))?;
+        manifest
+            .interpolate()
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        Ok(manifest)
+    }
+
+    /// Substitute environment variables in every string of the manifest.
+    fn interpolate(&mut self) -> Result<(), String> {
+        let string = |at: &str, value: &mut String| {
+            *value = config::interpolate(value).map_err(|e| format!("{at}: {e}"))?;
+            Ok::<_, String>(())
+        };
+        let optional = |at: &str, value: &mut Option<String>| match value {
+            Some(value) => string(at, value),
+            None => Ok(()),
+        };
+        let path = |at: &str, value: &mut Option<PathBuf>| {
+            if let Some(value) = value {
+                *value = config::interpolate(&value.to_string_lossy())
+                    .map_err(|e| format!("{at}: {e}"))?
+                    .into();
+            }
+            Ok::<_, String>(())
+        };
+
+        path("clone_dir", &mut self.clone_dir)?;
+        for (name, profile) in &mut self.profiles {
+            let at = format!("profiles.{name}");
+            optional(&format!("{at}.base"), &mut profile.base)?;
+            optional(&format!("{at}.target"), &mut profile.target)?;
+            for arg in &mut profile.args {
+                string(&format!("{at}.args"), arg)?;
+            }
+        }
+        for (index, repo) in self.repos.iter_mut().enumerate() {
+            let at = format!("repos[{index}]");
+            optional(&format!("{at}.name"), &mut repo.name)?;
+            path(&format!("{at}.path"), &mut repo.path)?;
+            optional(&format!("{at}.url"), &mut repo.url)?;
+            optional(&format!("{at}.base"), &mut repo.base)?;
+            optional(&format!("{at}.target"), &mut repo.target)?;
+            for arg in &mut repo.args {
+                string(&format!("{at}.args"), arg)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn profile(&self, name: &str) -> AnyResult<&Profile> {
+        self.profiles
+            .get(name)
+            .ok_or_else(|| format!("unknown profile `{name}`").into())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RepoEntry {
+    /// Shown in the output; defaults to the last component of the path or URL.
+    name: Option<String>,
+    /// A local repository, relative to the manifest.
+    path: Option<PathBuf>,
+    /// A repository to clone, or to update if an earlier batch cloned it already.
+    url: Option<String>,
+    profile: Option<String>,
+    /// Override the profile's `base`.
+    base: Option<String>,
+    /// Override the profile's `target`.
+    target: Option<String>,
+    /// Passed after the profile's arguments.
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+impl RepoEntry {
+    fn name(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+        let location = match (&self.path, &self.url) {
+            (Some(path), _) => path.to_string_lossy().into_owned(),
+            (None, Some(url)) => url.clone(),
+            (None, None) => String::new(),
+        };
+        let last = location
+            .trim_end_matches('/')
+            .rsplit(['/', '\\', ':'])
+            .next()
+            .unwrap_or_default();
+        last.strip_suffix(".git").unwrap_or(last).to_owned()
+    }
+}
+
+pub struct BatchOptions {
+    pub dry_run: bool,
+    pub naming: MirrorNaming,
+    /// Keep going after a repository fails.
+    pub keep_going: bool,
+    /// Write the aggregated results to this JSON file.
+    pub report: Option<PathBuf>,
+    /// Repositories processed at once. With more than one, the output of each run is held back
+    /// and printed in the order of the manifest, and `keep_going` must be set.
+    pub jobs: usize,
+    /// Clone repositories given by a local path with `git clone --shared`, borrowing their
+    /// objects until the run is done.
+    pub share_objects: bool,
+}
+
+/// The outcome of one repository of the batch.
+#[derive(Serialize)]
+struct RepoResult {
+    name: String,
+    path: PathBuf,
+    ok: bool,
+    /// Why the repository failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// The report the mirror run saved in the repository, unless it was a dry run.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    run: Option<RunReport>,
+}
+
+/// Run `gitm mirror` with each repository's profile, then summarize the runs.
+pub fn batch(manifest_path: &Path, options: &BatchOptions) -> AnyResult<()> {
+    let manifest = Manifest::load(manifest_path)?;
+    let root = manifest_path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let clone_dir = root.join(
+        manifest
+            .clone_dir
+            .as_deref()
+            .unwrap_or(Path::new(".gitm-batch")),
+    );
+
+    // Catch mistakes in the manifest before rewriting anything.
+    for (index, repo) in manifest.repos.iter().enumerate() {
+        let at = format!("{}: repos[{index}]", manifest_path.display());
+        if repo.path.is_some() == repo.url.is_some() {
+            return Err(format!("{at}: needs exactly one of `path` and `url`").into());
+        }
+        if let Some(profile) = &repo.profile
+            && !manifest.profiles.contains_key(profile)
+        {
+            return Err(format!("{at}: unknown profile `{profile}`").into());
+        }
+        let profile = profile_of(&manifest, repo);
+        if repo.base.as_ref().or(profile.base.as_ref()).is_none()
+            || repo.target.as_ref().or(profile.target.as_ref()).is_none()
+        {
+            return Err(
+                format!("{at}: no `base` and `target` given here or in its profile").into(),
+            );
+        }
+    }
+
+    if options.jobs > 1 && !options.keep_going {
+        // Which repositories would run after a failure would depend on timing.
+        return Err("running several repos at once needs --keep-going".into());
+    }
+
+    let program = std::env::current_exe()?;
+    let capture = options.jobs > 1;
+    let failed = std::sync::atomic::AtomicBool::new(false);
+    let turnstile = Turnstile::default();
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, RepoResult)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..options.jobs.clamp(1, manifest.repos.len().max(1)))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    // Repos are taken in order, so every earlier turn belongs to a run that is
+                    // already going.
+                    while !gix::interrupt::is_triggered() && !failed.load(Ordering::Relaxed) {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(repo) = manifest.repos.get(index) else {
+                            break;
+                        };
+                        let name = repo.name();
+                        let mut turn = turnstile.turn(index, name.clone());
+                        if !capture {
+                            turn.wait();
+                        }
+                        let path = match (&repo.path, &repo.url) {
+                            (Some(path), _) => root.join(path),
+                            _ => clone_dir.join(&name),
+                        };
+                        let started = gix::date::Time::now_utc().seconds;
+                        let (outcome, output) =
+                            run_one(&program, &manifest, repo, &path, options, capture);
+                        turn.wait();
+                        io::stdout().write_all(&output.stdout).ok();
+                        io::stderr().write_all(&output.stderr).ok();
+                        // Runs with nothing to rewrite leave the report of an earlier run in
+                        // place.
+                        let run = match &outcome {
+                            Ok(()) if !options.dry_run => gix::open(&path)
+                                .ok()
+                                .and_then(|repo| RunReport::load(&repo).ok().flatten())
+                                .filter(|run| run.created_at >= started),
+                            _ => None,
+                        };
+                        if let Err(e) = &outcome {
+                            eprintln!("{} {name}: {e}", "error:".red());
+                            if !options.keep_going {
+                                failed.store(true, Ordering::Relaxed);
+                            }
+                        }
+                        drop(turn);
+                        results.push((
+                            index,
+                            RepoResult {
+                                name,
+                                path,
+                                ok: outcome.is_ok(),
+                                error: outcome.err().map(|e| e.to_string()),
+                                run,
+                            },
+                        ));
+                    }
+                    results
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect()
+    });
+    results.sort_by_key(|(index, _)| *index);
+    let results: Vec<RepoResult> = results.into_iter().map(|(_, result)| result).collect();
+    if gix::interrupt::is_triggered() {
+        eprintln!(
+            "{} interrupted; not running the remaining repos",
+            "error:".red()
+        );
+    }
+
+    println!();
+    println!("{}", "Batch summary".bold());
+    for result in &results {
+        let details = match (&result.error, &result.run) {
+            (Some(error), _) => format!("{} {error}", "failed".red()),
+            (None, Some(run)) => format!(
+                "{} {} commit(s) rewritten to {}",
+                "ok".green(),
+                run.rewritten.len(),
+                run.mirror_ref
+            ),
+            (None, None) => "ok".green().to_string(),
+        };
+        println!("  {}: {details}", result.name);
+    }
+    let skipped = manifest.repos.len() - results.len();
+    if skipped > 0 {
+        println!("  {skipped} repo(s) not run; pass --keep-going to continue past failures");
+    }
+
+    if let Some(path) = &options.report {
+        fs::write(path, serde_json::to_vec_pretty(&results)?)?;
+        println!("Wrote the batch report to {}", path.display());
+    }
+
+    let failed = results.iter().filter(|result| !result.ok).count();
+    if failed > 0 {
+        return Err(format!("{failed} of {} repo(s) failed", manifest.repos.len()).into());
+    }
+    Ok(())
+}
+
+fn profile_of<'a>(manifest: &'a Manifest, repo: &RepoEntry) -> &'a Profile {
+    static NONE: Profile = Profile {
+        base: None,
+        target: None,
+        args: Vec::new(),
+    };
+    repo.profile
+        .as_ref()
+        .and_then(|name| manifest.profiles.get(name))
+        .unwrap_or(&NONE)
+}
+
+/// What a `gitm mirror` process printed, when it was held back rather than shown as it ran.
+#[derive(Default)]
+struct Captured {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+}
+
+fn run_one(
+    program: &Path,
+    manifest: &Manifest,
+    repo: &RepoEntry,
+    path: &Path,
+    options: &BatchOptions,
+    capture: bool,
+) -> (AnyResult<()>, Captured) {
+    if let Some(url) = &repo.url
+        && let Err(e) = fetch(url, path, options.share_objects)
+    {
+        return (Err(e), Captured::default());
+    }
+    if !path.exists() {
+        let error = format!("{} does not exist", path.display()).into();
+        return (Err(error), Captured::default());
+    }
+
+    let profile = profile_of(manifest, repo);
+    let base = repo.base.as_ref().or(profile.base.as_ref());
+    let target = repo.target.as_ref().or(profile.target.as_ref());
+    let mut command = mirror_command(
+        program,
+        path,
+        base.into_iter().chain(target),
+        profile.args.iter().chain(&repo.args),
+        options.dry_run,
+        &options.naming,
+    );
+    let (outcome, captured) = if !capture {
+        (mirrored(program, command.status()), Captured::default())
+    } else {
+        match command.output() {
+            Ok(output) => (
+                mirrored(program, Ok(output.status)),
+                Captured {
+                    stdout: output.stdout,
+                    stderr: output.stderr,
+                },
+            ),
+            Err(e) => (mirrored(program, Err(e)), Captured::default()),
+        }
+    };
+    // The clone is kept for later batches, which mustn't depend on the original's objects.
+    let outcome = outcome.and_then(|()| {
+        if options.share_objects && repo.url.is_some() {
+            dissociate(path)
+        } else {
+            Ok(())
+        }
+    });
+    (outcome, captured)
+}
+
+/// `program mirror <revisions> <args>`, to run in the repository at `path`.
+fn mirror_command(
+    program: &Path,
+    path: &Path,
+    revisions: impl IntoIterator<Item = impl AsRef<OsStr>>,
+    args: impl IntoIterator<Item = impl AsRef<OsStr>>,
+    dry_run: bool,
+    naming: &MirrorNaming,
+) -> Command {
+    let mut command = Command::new(program);
+    command.current_dir(path).arg("mirror");
+    if dry_run {
+        command.arg("--dry-run");
+    }
+    if let MirrorNaming::Namespace(prefix) = naming {
+        command.arg("--mirror-ref-namespace").arg(prefix);
+    }
+    command.args(revisions).args(args);
+    command
+}
+
+/// Whether a `gitm mirror` process that ended with `status` succeeded.
+fn mirrored(program: &Path, status: io::Result<ExitStatus>) -> AnyResult<()> {
+    let status = status.map_err(|e| format!("running {}: {e}", program.display()))?;
+    // Having nothing to rewrite isn't a failure here; callers tell by the missing report.
+    if !status.success() && status.code() != Some(NOTHING_TO_DO_EXIT_CODE.into()) {
+        return Err(format!("gitm mirror exited with {status}").into());
+    }
+    Ok(())
+}
+
+/// Run `program mirror <revisions> <args>` in the repository at `path`.
+pub(crate) fn run_mirror(
+    program: &Path,
+    path: &Path,
+    revisions: impl IntoIterator<Item = impl AsRef<OsStr>>,
+    args: impl IntoIterator<Item = impl AsRef<OsStr>>,
+    dry_run: bool,
+    naming: &MirrorNaming,
+) -> AnyResult<()> {
+    let mut command = mirror_command(program, path, revisions, args, dry_run, naming);
+    mirrored(program, command.status())
+}
+
+/// Clone `url` into `path`, or fetch into the clone an earlier batch made there.
+///
+/// Clones are bare, so every branch of the remote is a local branch `base` and `target` can
+/// name, and fetching never touches the mirrors created next to them. With `share_objects`,
+/// `url` must be a local path, and the clone borrows its objects through
+/// `objects/info/alternates` until [`dissociate`]d instead of copying them.
+pub(crate) fn fetch(url: &str, path: &Path, share_objects: bool) -> AnyResult<()> {
+    let mut command = Command::new("git");
+    if path.exists() {
+        command.arg("--git-dir").arg(path).args([
+            "fetch",
+            "--quiet",
+            "--tags",
+            "origin",
+            "+refs/heads/*:refs/heads/*",
+        ]);
+    } else {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        command.args(["clone", "--quiet", "--bare"]);
+        if share_objects {
+            if !Path::new(url).is_dir() {
+                return Err(Failure::Validation(format!(
+                    "--share-objects needs a repository on this machine, not {url}"
+                ))
+                .into());
+            }
+            command.arg("--shared");
+        }
+        command.arg(url).arg(path);
+    }
+    let output = command.output().map_err(|e| format!("running git: {e}"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().last().unwrap_or_default();
+        return Err(format!("fetching {url}: {reason}").into());
+    }
+    Ok(())
+}
+
+/// Copy the objects the clone at `path` borrows from the repository it was cloned from into a
+/// pack of its own, and stop borrowing them, like `git clone --dissociate`. Does nothing for
+/// clones that don't borrow objects.
+pub(crate) fn dissociate(path: &Path) -> AnyResult<()> {
+    let alternates = path.join("objects").join("info").join("alternates");
+    if !alternates.exists() {
+        return Ok(());
+    }
+    let output = Command::new("git")
+        .arg("--git-dir")
+        .arg(path)
+        .args(["repack", "-a", "-d", "-q"])
+        .output()
+        .map_err(|e| format!("running git repack: {e}"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().last().unwrap_or_default();
+        return Err(format!("repacking {}: {reason}", path.display()).into());
+    }
+    fs::remove_file(alternates)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{git, scratch_repo};
+
+    #[test]
+    fn shared_clones_borrow_objects_until_dissociated() {
+        let source = scratch_repo();
+        fs::write(source.join("a.txt"), "a\n").unwrap();
+        git(&source, &["add", "a.txt"]);
+        git(&source, &["commit", "-q", "-m", "base"]);
+        let clone = source.with_extension("clone");
+
+        fetch(source.to_str().unwrap(), &clone, true).unwrap();
+        let alternates = clone.join("objects").join("info").join("alternates");
+        assert!(alternates.exists(), "the clone borrows the objects");
+        dissociate(&clone).unwrap();
+        assert!(!alternates.exists());
+
+        crate::files::remove_dir_all(&source).unwrap();
+        let repo = gix::open(&clone).unwrap();
+        let tip = repo.rev_parse_single("main").unwrap().object().unwrap();
+        assert_eq!(tip.into_commit().message_raw().unwrap(), "base\n");
+        crate::files::remove_dir_all(&clone).unwrap();
+    }
+
+    #[test]
+    fn sharing_objects_needs_a_local_repository() {
+        let clone =
+            std::env::temp_dir().join(format!("magitulator-test-{}", crate::files::unique_name()));
+        let error = fetch("https://example.com/repo.git", &clone, true).unwrap_err();
+        assert!(error.to_string().contains("--share-objects"), "{error}");
+        assert!(!clone.exists());
+    }
+}
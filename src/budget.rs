@@ -0,0 +1,329 @@
+use crate::{
+    AnyResult,
+    filter::Filter,
+    map::CommitMap,
+    metrics::CacheStats,
+    mirror::CommitDescriptor,
+    tree::{self, EntryAction, TreeRewriter},
+};
+use colored::Colorize;
+use gix::{
+    ObjectId, Repository,
+    bstr::{BString, ByteSlice},
+    object::tree::diff::ChangeDetached,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// What happens to a commit whose diff exceeds the size budget.
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
+pub enum BudgetAction {
+    /// Report the commit and continue.
+    #[default]
+    Warn,
+    /// Abort the rewrite.
+    Fail,
+    /// Drop the commit's largest new blobs until it fits, here and in all descendants.
+    Strip,
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+/// A size like `500k`, `10M` or `1G`, in bytes.
+pub fn parse_size(input: &str) -> Result<u64, String> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid size `{input}`"))?;
+    let multiplier = match unit.to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        _ => return Err(format!("unknown size unit `{unit}`")),
+    };
+    Ok(number * multiplier)
+}
+
+/// Paths listed in the report of removed blobs, largest first, before the rest is summarized.
+const MAX_REPORTED_PATHS: usize = 20;
+
+/// Removes every blob larger than a limit from rewritten trees, whichever commit added it, or
+/// replaces it with a stub naming the original.
+pub(crate) struct BlobSizeLimit {
+    limit: u64,
+    stub: bool,
+    rewriter: TreeRewriter,
+    /// Sizes of the blobs over the limit, and their stubs, by id.
+    large: HashMap<ObjectId, (u64, Option<ObjectId>)>,
+    /// Removed blobs, by path.
+    removed: BTreeMap<BString, HashSet<ObjectId>>,
+}
+
+impl BlobSizeLimit {
+    pub(crate) fn new(limit: u64, stub: bool) -> Self {
+        BlobSizeLimit {
+            limit,
+            stub,
+            rewriter: TreeRewriter::default(),
+            large: HashMap::new(),
+            removed: BTreeMap::new(),
+        }
+    }
+}
+
+/// The text that stands in for blob `id` of `size` bytes, removed for being over `limit`.
+fn stub_text(id: ObjectId, size: u64, limit: u64) -> String {
+    format!(
+        "This file was removed from the history for being larger than {}.\n\
+         oid {id}\n\
+         size {size}\n",
+        format_size(limit)
+    )
+}
+
+impl Filter for BlobSizeLimit {
+    fn name(&self) -> String {
+        format!("blobs over {}", format_size(self.limit))
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        Some(self.rewriter.stats())
+    }
+
+    fn apply(&mut self, repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        let BlobSizeLimit {
+            limit,
+            stub,
+            rewriter,
+            large,
+            removed,
+        } = self;
+        let rewritten = rewriter.rewrite(repo, descriptor.tree, &mut |_, mode, id| {
+            if !mode.is_blob() {
+                return Ok(EntryAction::Keep);
+            }
+            let stub_id = match large.get(&id) {
+                Some((_, stub_id)) => *stub_id,
+                None => {
+                    let size = repo.find_header(id)?.size();
+                    if size <= *limit {
+                        return Ok(EntryAction::Keep);
+                    }
+                    let stub_id = if *stub {
+                        Some(repo.write_blob(stub_text(id, size, *limit))?.detach())
+                    } else {
+                        None
+                    };
+                    large.insert(id, (size, stub_id));
+                    stub_id
+                }
+            };
+            Ok(match stub_id {
+                Some(stub_id) => EntryAction::Replace { id: stub_id, mode },
+                None => EntryAction::Remove,
+            })
+        })?;
+        descriptor.tree = rewritten.id;
+        for change in rewritten.changes {
+            removed
+                .entry(change.path)
+                .or_default()
+                .insert(change.old_id);
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, _repo: &Repository, _map: &CommitMap) -> AnyResult<()> {
+        if self.removed.is_empty() {
+            return Ok(());
+        }
+        let mut paths: Vec<(&BString, usize, u64)> = self
+            .removed
+            .iter()
+            .map(|(path, ids)| {
+                let sizes = ids
+                    .iter()
+                    .map(|id| self.large.get(id).map_or(0, |(size, _)| *size));
+                (path, ids.len(), sizes.max().unwrap_or_default())
+            })
+            .collect();
+        paths.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(b.0)));
+        let blobs: HashSet<&ObjectId> = self.removed.values().flatten().collect();
+        println!(
+            "{} {} blob(s) larger than {} at {} path(s){}:",
+            if self.stub { "Replaced" } else { "Removed" },
+            blobs.len(),
+            format_size(self.limit),
+            paths.len(),
+            if self.stub { " with stubs" } else { "" }
+        );
+        for (path, versions, largest) in paths.iter().take(MAX_REPORTED_PATHS) {
+            println!(
+                "  {:>10}  {path}{}",
+                format_size(*largest),
+                match versions {
+                    1 => String::new(),
+                    _ => format!(" ({versions} versions, largest shown)"),
+                }
+            );
+        }
+        if paths.len() > MAX_REPORTED_PATHS {
+            println!(
+                "  ... and {} more path(s)",
+                paths.len() - MAX_REPORTED_PATHS
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Checks that the blobs each commit adds or modifies stay within a byte budget.
+pub(crate) struct SizeBudget {
+    budget: u64,
+    action: BudgetAction,
+    /// Rewritten trees by original commit, to diff against the rewritten parent.
+    trees: HashMap<ObjectId, ObjectId>,
+    stripped: HashSet<ObjectId>,
+    rewriter: TreeRewriter,
+    violations: usize,
+}
+
+impl SizeBudget {
+    pub(crate) fn new(budget: u64, action: BudgetAction) -> Self {
+        SizeBudget {
+            budget,
+            action,
+            trees: HashMap::new(),
+            stripped: HashSet::new(),
+            rewriter: TreeRewriter::default(),
+            violations: 0,
+        }
+    }
+
+    fn parent_tree(
+        &self,
+        repo: &Repository,
+        descriptor: &CommitDescriptor,
+    ) -> AnyResult<Option<ObjectId>> {
+        let Some(parent) = descriptor.original_parent_ids.first() else {
+            return Ok(None);
+        };
+        match self.trees.get(parent) {
+            Some(tree) => Ok(Some(*tree)),
+            None => Ok(Some(repo.find_commit(*parent)?.tree_id()?.detach())),
+        }
+    }
+
+    fn strip_known(&mut self, repo: &Repository, tree_id: ObjectId) -> AnyResult<ObjectId> {
+        if self.stripped.is_empty() {
+            return Ok(tree_id);
+        }
+        let stripped = &self.stripped;
+        Ok(self
+            .rewriter
+            .rewrite(repo, tree_id, &mut |_, mode, id| {
+                Ok(if mode.is_blob() && stripped.contains(&id) {
+                    EntryAction::Remove
+                } else {
+                    EntryAction::Keep
+                })
+            })?
+            .id)
+    }
+}
+
+impl Filter for SizeBudget {
+    fn name(&self) -> String {
+        "--size-budget".into()
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        Some(self.rewriter.stats())
+    }
+
+    fn resumable(&self) -> bool {
+        false
+    }
+
+    fn apply(&mut self, repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        descriptor.tree = self.strip_known(repo, descriptor.tree)?;
+
+        let mut added = Vec::new();
+        for change in tree::diff(repo, self.parent_tree(repo, descriptor)?, descriptor.tree)? {
+            let (location, mode, id) = match change {
+                ChangeDetached::Addition {
+                    location,
+                    entry_mode,
+                    id,
+                    ..
+                }
+                | ChangeDetached::Modification {
+                    location,
+                    entry_mode,
+                    id,
+                    ..
+                } => (location, entry_mode, id),
+                _ => continue,
+            };
+            if mode.is_blob() {
+                added.push((repo.find_header(id)?.size(), location, id));
+            }
+        }
+
+        let total: u64 = added.iter().map(|(size, ..)| size).sum();
+        if total > self.budget {
+            self.violations += 1;
+            let subject = descriptor.message.lines().next().unwrap_or_default();
+            let summary = format!(
+                "{} {} adds {} (budget {})",
+                descriptor.original_id.to_hex_with_len(7),
+                subject.to_str_lossy(),
+                format_size(total),
+                format_size(self.budget),
+            );
+
+            match self.action {
+                BudgetAction::Warn => println!("{} {summary}", "over budget:".yellow()),
+                BudgetAction::Fail => {
+                    return Err(format!("commit over size budget: {summary}").into());
+                }
+                BudgetAction::Strip => {
+                    println!("{} {summary}", "over budget:".yellow());
+                    added.sort_by_key(|(size, ..)| std::cmp::Reverse(*size));
+                    let mut remaining = total;
+                    for (size, location, id) in &added {
+                        if remaining <= self.budget {
+                            break;
+                        }
+                        remaining -= size;
+                        self.stripped.insert(*id);
+                        println!("  stripped {location} ({})", format_size(*size));
+                    }
+                    // The set of stripped blobs changed, so earlier tree results are stale.
+                    self.rewriter.clear();
+                    descriptor.tree = self.strip_known(repo, descriptor.tree)?;
+                }
+            }
+        }
+
+        self.trees.insert(descriptor.original_id, descriptor.tree);
+        Ok(())
+    }
+}
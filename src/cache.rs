@@ -0,0 +1,278 @@
+//! Descriptors dry runs filtered, for the next dry run with the same filters to reuse instead of
+//! filtering the same commits again, while output options like `--verbose` are tuned.
+//!
+//! A cache lives in `.git/magitulator/descriptors/<digest>/<target>`, where the digest covers
+//! the options that shape filters, the files they read and, for filters reading the target like
+//! `--apply-gitignore`, the target's tip. Each commit's entry is keyed by its original id and
+//! checked against a hash of the descriptor the filters started from. Dry runs keep the trees
+//! and blobs filters make out of the object database, so the cache holds them too.
+use crate::{
+    AnyResult, anonymize, files,
+    filter::{self, Filter, FilterProfile},
+    mirror::{CommitDescriptor, MirrorOptions},
+    refs::MirrorNaming,
+    state,
+};
+use gix::{
+    ObjectId, Repository,
+    bstr::BString,
+    object::Kind,
+    objs::{Write as _, WriteTo},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// A cached descriptor: the filtered commit, written as a commit object whose parents are the
+/// original parents, and the hash of the descriptor before the filters.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    input: String,
+    commit: String,
+    /// Dropped by a filter like `--drop-empty`.
+    dropped: bool,
+}
+
+/// The cache of one set of filters.
+pub(crate) struct DescriptorCache {
+    dir: PathBuf,
+    entries: HashMap<ObjectId, Entry>,
+    /// Hashes of the descriptors of this run before the filters, by original commit.
+    inputs: HashMap<ObjectId, ObjectId>,
+}
+
+/// Descriptor as a commit object, with parents for the original parents.
+fn as_commit(descriptor: &CommitDescriptor) -> gix::objs::Commit {
+    gix::objs::Commit {
+        tree: descriptor.tree,
+        parents: descriptor.original_parent_ids.clone().into(),
+        author: descriptor.author.clone(),
+        committer: descriptor.committer.clone(),
+        encoding: descriptor.encoding.clone(),
+        message: descriptor.message.clone(),
+        extra_headers: descriptor.extra_headers.clone(),
+    }
+}
+
+/// The digest of what shapes the filters of `options`: the options, besides those only
+/// changing the output or what happens once commits are written, and the files filters read.
+///
+/// Filters reading the history of the target, like `--apply-gitignore` with the target's
+/// `.gitignore` files, make its tip `target_id` part of it, and `--merge-message-template`,
+/// which names merged branches after local ones, the local branches.
+fn digest(repo: &Repository, options: &MirrorOptions, target_id: ObjectId) -> AnyResult<ObjectId> {
+    let filtering = MirrorOptions {
+        dry_run: false,
+        verbose: false,
+        in_place: false,
+        force: false,
+        update_worktrees: false,
+        naming: MirrorNaming::default(),
+        retarget_tag: false,
+        checkout_mirror: false,
+        summary_note: false,
+        resign_with: None,
+        exec: None,
+        exec_paths: Vec::new(),
+        vault: None,
+        profile_filters: false,
+        metrics_out: None,
+        time_budget: None,
+        cache_descriptors: false,
+        ..options.clone()
+    };
+    let mut key = format!("{}\n{filtering:?}\n", env!("CARGO_PKG_VERSION")).into_bytes();
+    let key_file = options.anonymize.as_ref().map(|settings| {
+        settings
+            .key_file
+            .clone()
+            .unwrap_or_else(|| anonymize::default_key_path(repo))
+    });
+    if options.apply_gitignore || options.follow_renames || !options.erase_identities.is_empty() {
+        key.extend_from_slice(format!("target {target_id}\n").as_bytes());
+    }
+    if options.merge_message_template.is_some() {
+        for reference in repo.references()?.local_branches()? {
+            let mut reference = reference?;
+            let name = reference.name().shorten().to_string();
+            if let Ok(id) = reference.peel_to_id_in_place() {
+                key.extend_from_slice(format!("branch {name} {id}\n").as_bytes());
+            }
+        }
+    }
+    for path in [
+        &options.owners_file,
+        &options.authors_file,
+        &options.issue_map,
+        &options.replace_text,
+        &key_file,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        key.extend_from_slice(&fs::read(path).unwrap_or_default());
+        key.push(0);
+    }
+    Ok(gix::objs::compute_hash(
+        repo.object_hash(),
+        Kind::Blob,
+        &key,
+    )?)
+}
+
+impl DescriptorCache {
+    /// The cache of the filters of `options` for mirroring `target`, at `target_id`, with the
+    /// objects it holds written to `repo`, which keeps them in memory.
+    pub(crate) fn load(
+        repo: &Repository,
+        options: &MirrorOptions,
+        target: &str,
+        target_id: ObjectId,
+    ) -> AnyResult<Self> {
+        let dir = state::descriptor_cache_dir(repo)
+            .join(digest(repo, options, target_id)?.to_string())
+            .join(target.replace('/', "%2F"));
+        let mut entries = HashMap::new();
+        let entries_path = dir.join("entries.json");
+        if entries_path.exists() {
+            let saved: HashMap<String, Entry> =
+                serde_json::from_slice(&fs::read(&entries_path)?)
+                    .map_err(|e| format!("reading {}: {e}", entries_path.display()))?;
+            for (id, entry) in saved {
+                entries.insert(ObjectId::from_hex(id.as_bytes())?, entry);
+            }
+            let objects = fs::read(dir.join("objects"))?;
+            let mut rest = &objects[..];
+            while !rest.is_empty() {
+                let (kind, size, header) = gix::objs::decode::loose_header(rest)?;
+                let end = header + size as usize;
+                repo.objects.write_buf(kind, &rest[header..end])?;
+                rest = &rest[end..];
+            }
+        }
+        Ok(DescriptorCache {
+            dir,
+            entries,
+            inputs: HashMap::new(),
+        })
+    }
+
+    /// The filtered descriptor of `descriptor`, if an earlier run filtered the same one,
+    /// remembering what it started from to save the filtered one under.
+    fn lookup(
+        &mut self,
+        repo: &Repository,
+        descriptor: &CommitDescriptor,
+    ) -> AnyResult<Option<CommitDescriptor>> {
+        let mut encoded = Vec::new();
+        as_commit(descriptor).write_to(&mut encoded)?;
+        let input = gix::objs::compute_hash(repo.object_hash(), Kind::Commit, &encoded)?;
+        self.inputs.insert(descriptor.original_id, input);
+        let Some(entry) = self
+            .entries
+            .get(&descriptor.original_id)
+            .filter(|entry| entry.input == input.to_string())
+        else {
+            return Ok(None);
+        };
+        let commit = repo.find_commit(ObjectId::from_hex(entry.commit.as_bytes())?)?;
+        let decoded = commit.decode()?;
+        Ok(Some(CommitDescriptor {
+            original_id: descriptor.original_id,
+            original_parent_ids: commit.parent_ids().map(|id| id.detach()).collect(),
+            tree: commit.tree_id()?.detach(),
+            author: decoded.author().into(),
+            committer: decoded.committer().into(),
+            encoding: decoded.encoding.map(Into::into),
+            message: decoded.message.into(),
+            extra_headers: decoded
+                .extra_headers
+                .iter()
+                .map(|(k, v)| ((*k).into(), BString::from(v.as_ref())))
+                .collect(),
+            keep_original: descriptor.keep_original,
+            dropped: entry.dropped,
+        }))
+    }
+
+    /// Filter `descriptors` with `filters`, taking those filtered before from the cache.
+    /// Returns how many were.
+    pub(crate) fn apply_filters(
+        &mut self,
+        filters: &mut [Box<dyn Filter>],
+        repo: &Repository,
+        descriptors: &mut Vec<CommitDescriptor>,
+        profiles: Option<&mut Vec<FilterProfile>>,
+    ) -> AnyResult<usize> {
+        let mut cached = Vec::new();
+        let mut pending = Vec::new();
+        for descriptor in std::mem::take(descriptors) {
+            let hit = match descriptor.keep_original || descriptor.dropped {
+                true => None,
+                false => self.lookup(repo, &descriptor)?,
+            };
+            if hit.is_none() {
+                pending.push(descriptor);
+            }
+            cached.push(hit);
+        }
+        filter::apply_filters(filters, repo, &mut pending, profiles, None)?;
+        let reused = cached.iter().flatten().count();
+        let mut pending = pending.into_iter();
+        *descriptors = cached
+            .into_iter()
+            .map(|hit| hit.or_else(|| pending.next()))
+            .collect::<Option<_>>()
+            .expect("a descriptor for every one taken");
+        Ok(reused)
+    }
+
+    /// Save the filtered `descriptors`, with every object `repo` keeps in memory.
+    pub(crate) fn save(
+        &self,
+        repo: &mut Repository,
+        descriptors: &[CommitDescriptor],
+    ) -> AnyResult<()> {
+        let mut entries = HashMap::new();
+        for descriptor in descriptors {
+            let Some(input) = self.inputs.get(&descriptor.original_id) else {
+                continue;
+            };
+            let commit = repo.write_object(as_commit(descriptor))?.detach();
+            entries.insert(
+                descriptor.original_id.to_string(),
+                Entry {
+                    input: input.to_string(),
+                    commit: commit.to_string(),
+                    dropped: descriptor.dropped,
+                },
+            );
+        }
+        let Some(memory) = repo.objects.take_object_memory() else {
+            return Err("descriptors are only cached by dry runs".into());
+        };
+        let mut objects = Vec::new();
+        for (kind, data) in memory.values() {
+            objects.extend_from_slice(&gix::objs::encode::loose_header(*kind, data.len() as u64));
+            objects.extend_from_slice(data);
+        }
+        repo.objects.set_object_memory(memory);
+
+        fs::create_dir_all(&self.dir)?;
+        files::write_atomically(&self.dir.join("objects"), &objects)
+            .map_err(|e| format!("writing {}: {e}", self.dir.display()))?;
+        files::write_atomically(
+            &self.dir.join("entries.json"),
+            &serde_json::to_vec(&entries)?,
+        )
+        .map_err(|e| format!("writing {}: {e}", self.dir.display()))?;
+        Ok(())
+    }
+
+    pub(crate) fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
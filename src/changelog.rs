@@ -0,0 +1,246 @@
+//! Changelogs of a range of commits, grouped by change type.
+use crate::{AnyResult, mirror};
+use gix::{Repository, bstr::ByteSlice};
+use regex::Regex;
+use serde::Serialize;
+use std::{fmt, str::FromStr};
+
+/// Sections for the usual Conventional Commit types, in the order they are listed. Other types
+/// follow under their own name.
+const TYPE_TITLES: [(&str, &str); 11] = [
+    ("feat", "Features"),
+    ("fix", "Bug fixes"),
+    ("perf", "Performance"),
+    ("refactor", "Refactoring"),
+    ("revert", "Reverts"),
+    ("docs", "Documentation"),
+    ("test", "Tests"),
+    ("build", "Build"),
+    ("ci", "CI"),
+    ("style", "Style"),
+    ("chore", "Chores"),
+];
+
+/// Section of commits no group or type claims.
+const OTHER_TITLE: &str = "Other changes";
+
+/// The parts of a `type(scope)!: description` subject.
+pub(crate) struct Conventional<'a> {
+    pub(crate) kind: &'a str,
+    pub(crate) scope: Option<&'a str>,
+    pub(crate) breaking: bool,
+    pub(crate) description: &'a str,
+}
+
+/// Parses subjects written as Conventional Commits.
+pub(crate) struct ConventionalParser {
+    pattern: Regex,
+}
+
+impl ConventionalParser {
+    pub(crate) fn new() -> AnyResult<Self> {
+        Ok(ConventionalParser {
+            pattern: Regex::new(r"^([a-zA-Z]+)(?:\(([^)]*)\))?(!)?: (.*)$")?,
+        })
+    }
+
+    pub(crate) fn parse<'a>(&self, subject: &'a str) -> Option<Conventional<'a>> {
+        let captures = self.pattern.captures(subject)?;
+        Some(Conventional {
+            kind: captures.get(1)?.as_str(),
+            scope: captures.get(2).map(|scope| scope.as_str()),
+            breaking: captures.get(3).is_some(),
+            description: captures.get(4)?.as_str(),
+        })
+    }
+}
+
+/// `<title>=<regex>`: commits whose subject matches go into the section `<title>`.
+#[derive(Clone, Debug)]
+pub struct GroupRule {
+    title: String,
+    pattern: Regex,
+}
+
+impl FromStr for GroupRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (title, pattern) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected `<title>=<regex>`, got `{s}`"))?;
+        if title.trim().is_empty() {
+            return Err("the title of a group can't be empty".into());
+        }
+        Ok(GroupRule {
+            title: title.trim().to_owned(),
+            pattern: Regex::new(pattern).map_err(|e| e.to_string())?,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
+pub enum ChangelogFormat {
+    #[default]
+    Markdown,
+    Json,
+}
+
+pub struct ChangelogOptions {
+    /// Group by these rules, in order, instead of by Conventional Commit type.
+    pub groups: Vec<GroupRule>,
+    pub format: ChangelogFormat,
+}
+
+#[derive(Serialize)]
+struct Entry {
+    id: String,
+    /// The Conventional Commit type, if the subject has one.
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    scope: Option<String>,
+    breaking: bool,
+    summary: String,
+}
+
+#[derive(Serialize)]
+struct Section {
+    title: String,
+    commits: Vec<Entry>,
+}
+
+#[derive(Serialize)]
+struct Changelog {
+    base: String,
+    target: String,
+    sections: Vec<Section>,
+}
+
+impl fmt::Display for Changelog {
+    /// Markdown, a heading per section and a bullet per commit.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, section) in self.sections.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "## {}", section.title)?;
+            writeln!(f)?;
+            for entry in &section.commits {
+                write!(f, "- ")?;
+                if entry.breaking {
+                    write!(f, "**BREAKING** ")?;
+                }
+                if let Some(scope) = &entry.scope {
+                    write!(f, "**{scope}:** ")?;
+                }
+                writeln!(f, "{} ({})", entry.summary, &entry.id[..7])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Print a changelog of the commits in `<base>..<target>`, newest first, leaving out merges.
+///
+/// Commits are grouped by their Conventional Commit type, or by the first of
+/// `options.groups` their subject matches. A `!` after the type or a `BREAKING CHANGE:`
+/// footer marks a commit as breaking.
+pub fn changelog(
+    repo: &Repository,
+    base: &str,
+    target: &str,
+    options: &ChangelogOptions,
+) -> AnyResult<()> {
+    let base_id = mirror::resolve_commit_id(repo, base)?;
+    let target_id = mirror::resolve_commit_id(repo, target)?;
+    let parser = ConventionalParser::new()?;
+
+    let mut sections: Vec<Section> = Vec::new();
+    let mut commits = mirror::get_commits_to_rewrite(repo, base_id, target_id, &[], false)?;
+    commits.reverse();
+    for id in commits {
+        let commit = repo.find_commit(id)?;
+        if commit.parent_ids().count() > 1 {
+            continue;
+        }
+        let message = commit.message_raw()?;
+        let subject = message
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_str_lossy()
+            .trim()
+            .to_owned();
+        // Not a trailer to `policy::split_trailers`, whose keys have no spaces.
+        let breaking_footer = message.lines().skip(1).any(|line| {
+            line.starts_with(b"BREAKING CHANGE: ") || line.starts_with(b"BREAKING-CHANGE: ")
+        });
+        let conventional = parser.parse(&subject);
+
+        let (title, summary) = if options.groups.is_empty() {
+            match &conventional {
+                Some(c) => {
+                    let kind = c.kind.to_lowercase();
+                    let title = TYPE_TITLES
+                        .iter()
+                        .find(|(k, _)| *k == kind)
+                        .map_or(kind.clone(), |(_, title)| (*title).to_owned());
+                    (title, c.description.to_owned())
+                }
+                None => (OTHER_TITLE.to_owned(), subject.clone()),
+            }
+        } else {
+            let title = options
+                .groups
+                .iter()
+                .find(|rule| rule.pattern.is_match(&subject))
+                .map_or(OTHER_TITLE, |rule| &rule.title);
+            (title.to_owned(), subject.clone())
+        };
+        let entry = Entry {
+            id: id.to_string(),
+            kind: conventional.as_ref().map(|c| c.kind.to_lowercase()),
+            scope: conventional
+                .as_ref()
+                .and_then(|c| c.scope)
+                .filter(|_| options.groups.is_empty())
+                .map(str::to_owned),
+            breaking: breaking_footer || conventional.as_ref().is_some_and(|c| c.breaking),
+            summary,
+        };
+        match sections.iter_mut().find(|s| s.title == title) {
+            Some(section) => section.commits.push(entry),
+            None => sections.push(Section {
+                title,
+                commits: vec![entry],
+            }),
+        }
+    }
+
+    // Known types in their usual order, or the groups in the order they were given, then
+    // everything else alphabetically and the unclaimed commits last.
+    let rank = |title: &str| -> (usize, String) {
+        let known = if options.groups.is_empty() {
+            TYPE_TITLES.iter().position(|(_, t)| *t == title)
+        } else {
+            options.groups.iter().position(|rule| rule.title == title)
+        };
+        match known {
+            Some(i) => (i, String::new()),
+            None if title == OTHER_TITLE => (usize::MAX, String::new()),
+            None => (usize::MAX - 1, title.to_owned()),
+        }
+    };
+    sections.sort_by_cached_key(|section| rank(&section.title));
+
+    let changelog = Changelog {
+        base: base.to_owned(),
+        target: target.to_owned(),
+        sections,
+    };
+    match options.format {
+        ChangelogFormat::Markdown => print!("{changelog}"),
+        ChangelogFormat::Json => println!("{}", serde_json::to_string_pretty(&changelog)?),
+    }
+    Ok(())
+}
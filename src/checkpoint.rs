@@ -0,0 +1,187 @@
+//! Checkpoints of runs that ran out of their `--time-budget`, for the next run of the same
+//! command to resume from.
+use crate::{
+    AnyResult, files,
+    map::{self, CommitMap},
+    mirror::Written,
+    state,
+};
+use colored::Colorize;
+use gix::{ObjectId, Repository};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+/// What a checkpoint file holds: the run it belongs to, and what it wrote.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Record {
+    /// The command line, without `--time-budget`, which may change from run to run.
+    pub(crate) command: Vec<String>,
+    pub(crate) saved_at: i64,
+    base_commit: String,
+    target_commit: String,
+    /// Commits to rewrite that were handled, oldest first.
+    pub(crate) done: usize,
+    pub(crate) total: usize,
+    map: BTreeMap<String, String>,
+    dropped: Vec<String>,
+    vanished: Vec<String>,
+    tip: Option<String>,
+    designed: usize,
+}
+
+/// Where the work of a run left off.
+pub(crate) struct Checkpoint {
+    pub(crate) done: usize,
+    pub(crate) written: Written,
+}
+
+/// The checkpoint file of runs mirroring `target`.
+pub(crate) fn path(repo: &Repository, target: &str) -> PathBuf {
+    state::checkpoints_dir(repo).join(format!("{}.json", target.replace('/', "%2F")))
+}
+
+/// This process's command line, without `--time-budget` and its value.
+fn command() -> Vec<String> {
+    let mut command = vec!["gitm".to_owned()];
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--time-budget" {
+            args.next();
+        } else if !arg.starts_with("--time-budget=") {
+            command.push(arg);
+        }
+    }
+    command
+}
+
+/// The checkpoints in `.git/magitulator/checkpoints`, with their paths.
+pub(crate) fn load_all(repo: &Repository) -> AnyResult<Vec<(PathBuf, Record)>> {
+    let dir = state::checkpoints_dir(repo);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut records = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|extension| extension != "json") {
+            continue;
+        }
+        let record: Record = serde_json::from_slice(&fs::read(&path)?)
+            .map_err(|e| format!("reading {}: {e}", path.display()))?;
+        records.push((path, record));
+    }
+    records.sort_by_key(|(_, record)| record.saved_at);
+    Ok(records)
+}
+
+/// The checkpoint of mirroring `target` to resume from. A checkpoint of another command, or of
+/// other base and target commits, is removed, and the run starts over.
+pub(crate) fn load(
+    repo: &Repository,
+    target: &str,
+    base_commit: ObjectId,
+    target_commit: ObjectId,
+) -> AnyResult<Option<Checkpoint>> {
+    let path = path(repo, target);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let record: Record = serde_json::from_slice(&fs::read(&path)?)
+        .map_err(|e| format!("reading {}: {e}", path.display()))?;
+    let parse = |hex: &String| map::resolve_hash(repo, hex);
+    let tip = record.tip.as_ref().map(parse).transpose()?;
+    let stale = if record.command != command() {
+        Some("by another command")
+    } else if record.base_commit != base_commit.to_string()
+        || record.target_commit != target_commit.to_string()
+    {
+        Some("before the branches moved")
+    } else if tip.is_some_and(|tip| repo.find_commit(tip).is_err()) {
+        // Unreferenced, the commits written so far can be pruned by `git gc`.
+        Some("for commits that were pruned since")
+    } else {
+        None
+    };
+    if let Some(reason) = stale {
+        println!(
+            "{} starting over: the checkpoint in {} was saved {reason}",
+            "note:".yellow(),
+            path.display()
+        );
+        files::remove_file(&path)?;
+        return Ok(None);
+    }
+    let written = Written {
+        map: CommitMap {
+            entries: record
+                .map
+                .iter()
+                .map(|(old, new)| Ok((parse(old)?, parse(new)?)))
+                .collect::<Result<_, String>>()?,
+        },
+        dropped: record.dropped.iter().map(parse).collect::<Result<_, _>>()?,
+        vanished: record
+            .vanished
+            .iter()
+            .map(parse)
+            .collect::<Result<_, _>>()?,
+        tip,
+        designed: record.designed,
+    };
+    println!(
+        "Resuming from the checkpoint in {}, {} of {} commit(s) done",
+        path.display(),
+        record.done,
+        record.total
+    );
+    Ok(Some(Checkpoint {
+        done: record.done,
+        written,
+    }))
+}
+
+/// Record that the first `checkpoint.done` of `total` commits to rewrite were written.
+pub(crate) fn save(
+    repo: &Repository,
+    target: &str,
+    base_commit: ObjectId,
+    target_commit: ObjectId,
+    checkpoint: &Checkpoint,
+    total: usize,
+) -> AnyResult<PathBuf> {
+    let written = &checkpoint.written;
+    let record = Record {
+        command: command(),
+        saved_at: gix::date::Time::now_utc().seconds,
+        base_commit: base_commit.to_string(),
+        target_commit: target_commit.to_string(),
+        done: checkpoint.done,
+        total,
+        map: written
+            .map
+            .entries
+            .iter()
+            .map(|(old, new)| (old.to_string(), new.to_string()))
+            .collect(),
+        dropped: written.dropped.iter().map(ToString::to_string).collect(),
+        vanished: written.vanished.iter().map(ToString::to_string).collect(),
+        tip: written.tip.map(|tip| tip.to_string()),
+        designed: written.designed,
+    };
+    let path = path(repo, target);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    files::write_atomically(&path, &serde_json::to_vec_pretty(&record)?)
+        .map_err(|e| format!("writing {}: {e}", path.display()))?;
+    Ok(path)
+}
+
+/// Remove the checkpoint of mirroring `target`, once the run finished.
+pub(crate) fn remove(repo: &Repository, target: &str) -> AnyResult<()> {
+    let path = path(repo, target);
+    if path.exists() {
+        files::remove_file(&path)?;
+    }
+    Ok(())
+}
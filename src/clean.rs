@@ -0,0 +1,158 @@
+use crate::{
+    AnyResult, files,
+    map::CommitMap,
+    refs::{self, MirrorNaming},
+    report::MirrorRecipe,
+    state,
+};
+use gix::{
+    Repository,
+    bstr::{BStr, ByteSlice},
+    date::Time,
+    glob::wildmatch,
+    refs::{
+        FullName,
+        transaction::{Change, PreviousValue, RefEdit, RefLog},
+    },
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+#[derive(Default)]
+pub struct CleanOptions {
+    pub dry_run: bool,
+    pub naming: MirrorNaming,
+    pub mirrors: bool,
+    pub backups: bool,
+    pub checkpoints: bool,
+    pub quarantine: bool,
+    pub caches: bool,
+    /// Only remove entries last touched before this time.
+    pub older_than: Option<Time>,
+    /// Only remove entries whose name matches one of these globs.
+    pub patterns: Vec<String>,
+}
+
+impl CleanOptions {
+    fn matches(&self, names: &[&BStr], touched: Option<Time>) -> bool {
+        if let Some(older_than) = self.older_than {
+            // Without a timestamp the entry can't be proven old enough.
+            match touched {
+                Some(touched) if touched.seconds < older_than.seconds => {}
+                _ => return false,
+            }
+        }
+
+        self.patterns.is_empty()
+            || self.patterns.iter().any(|pattern| {
+                names.iter().any(|name| {
+                    wildmatch(pattern.as_bytes().as_bstr(), name, wildmatch::Mode::empty())
+                })
+            })
+    }
+}
+
+pub fn clean(repo: &Repository, options: &CleanOptions) -> AnyResult<()> {
+    let mut doomed_refs = Vec::new();
+    if options.mirrors {
+        for mirror in refs::list_mirrors(repo, &options.naming)? {
+            doomed_refs.push(mirror.name);
+        }
+    }
+    if options.backups {
+        for reference in repo
+            .references()?
+            .prefixed(refs::BACKUP_REF_PREFIX.as_bytes().as_bstr())?
+        {
+            doomed_refs.push(reference?.name().to_owned());
+        }
+    }
+    doomed_refs.retain(|name| {
+        let touched = last_ref_update(repo, name);
+        options.matches(&[name.as_bstr(), name.shorten()], touched)
+    });
+
+    let mut doomed_dirs = Vec::new();
+    if options.checkpoints {
+        doomed_dirs.extend(entries(&state::checkpoints_dir(repo))?);
+    }
+    if options.quarantine {
+        doomed_dirs.extend(entries(&state::quarantine_dir(repo))?);
+    }
+    if options.caches {
+        doomed_dirs.extend(entries(&state::descriptor_cache_dir(repo))?);
+    }
+    doomed_dirs.retain(|path| {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        options.matches(&[name.as_bytes().as_bstr()], last_modified(path))
+    });
+
+    let verb = if options.dry_run {
+        "Would remove"
+    } else {
+        "Removed"
+    };
+
+    if !options.dry_run && !doomed_refs.is_empty() {
+        repo.edit_references(doomed_refs.iter().map(|name| RefEdit {
+            change: Change::Delete {
+                expected: PreviousValue::MustExist,
+                log: RefLog::AndReference,
+            },
+            name: name.clone(),
+            deref: false,
+        }))?;
+    }
+    for name in &doomed_refs {
+        for path in [
+            CommitMap::path(repo, name.as_ref()),
+            MirrorRecipe::path(repo, name.as_ref()),
+        ] {
+            if !options.dry_run && path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+        println!("{verb} {}", name.as_bstr());
+    }
+
+    for path in &doomed_dirs {
+        if !options.dry_run {
+            if path.is_dir() {
+                files::remove_dir_all(path)?;
+            } else {
+                files::remove_file(path)?;
+            }
+        }
+        println!("{verb} {}", path.display());
+    }
+
+    Ok(())
+}
+
+pub(crate) fn entries(dir: &Path) -> AnyResult<Vec<PathBuf>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        paths.push(entry?.path());
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+pub(crate) fn last_ref_update(repo: &Repository, name: &FullName) -> Option<Time> {
+    let reference = repo.find_reference(name.as_ref()).ok()?;
+    let mut log = reference.log_iter();
+    let mut lines = log.rev().ok()??;
+    Some(lines.next()?.ok()?.signature.time)
+}
+
+pub(crate) fn last_modified(path: &Path) -> Option<Time> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    let seconds = modified.duration_since(UNIX_EPOCH).ok()?.as_secs();
+    Some(Time::new(seconds as i64, 0))
+}
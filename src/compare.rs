@@ -0,0 +1,282 @@
+//! Comparing a mirror with the branch another tool, like git-filter-repo, made of the same
+//! history, for teams checking that a migration to magitulator gives the same result.
+use crate::{
+    AnyResult,
+    error::Failure,
+    map::{self, CommitMap},
+    mirror::{self, CommitDescriptor},
+    preview::DescriptorDiff,
+    refs::{self, MirrorNaming},
+    tree,
+};
+use colored::Colorize;
+use gix::{
+    ObjectId, Repository,
+    bstr::{BString, ByteSlice},
+    object::tree::diff::ChangeDetached,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Paths listed for a tree that differs, before the rest is summarized.
+const MAX_PATHS: usize = 10;
+
+pub struct CompareOptions {
+    /// Branch whose mirror is compared; the checked-out branch if unset.
+    pub target: Option<String>,
+    /// The other tool's map of original to rewritten commits, in the `<old> <new>` lines of
+    /// git-filter-repo's `commit-map`.
+    pub their_map: Option<PathBuf>,
+}
+
+/// Original commit → rewritten commit from a `commit-map` of git-filter-repo. Its header line
+/// and the all-zero ids of pruned commits are skipped.
+fn load_their_map(repo: &Repository, path: &Path) -> AnyResult<HashMap<ObjectId, ObjectId>> {
+    let text = fs::read_to_string(path).map_err(|e| format!("reading {}: {e}", path.display()))?;
+    let mut entries = HashMap::new();
+    for (number, line) in text.lines().enumerate() {
+        let Some((old, new)) = line.split_once(char::is_whitespace) else {
+            continue;
+        };
+        let (old, new) = (old.trim(), new.trim());
+        if old == "old" && new == "new" {
+            continue;
+        }
+        let parse = |hex: &str| {
+            map::resolve_hash(repo, hex)
+                .map_err(|e| format!("{}:{}: {e}", path.display(), number + 1))
+        };
+        if new.bytes().all(|b| b == b'0') {
+            continue;
+        }
+        entries.insert(parse(old)?, parse(new)?);
+    }
+    Ok(entries)
+}
+
+/// The commits of `tip` that aren't in the history of `other`, oldest first.
+fn only_in(repo: &Repository, tip: ObjectId, other: ObjectId) -> AnyResult<Vec<ObjectId>> {
+    let mut commits =
+        gix::traverse::commit::topo::Builder::from_iters(&repo.objects, [tip], Some([other]))
+            .build()?
+            .map(|info| Ok(info?.id))
+            .collect::<AnyResult<Vec<_>>>()?;
+    commits.reverse();
+    Ok(commits)
+}
+
+/// `ours` as a descriptor of a rewrite of `theirs`, so that [`DescriptorDiff`] compares the
+/// two. Parents are given as their counterparts in `pairs`, so they only differ where the
+/// shapes of the histories do.
+fn as_rewrite_of(
+    repo: &Repository,
+    ours: ObjectId,
+    theirs: ObjectId,
+    pairs: &HashMap<ObjectId, ObjectId>,
+) -> AnyResult<CommitDescriptor> {
+    let commit = repo.find_commit(ours)?;
+    let decoded = commit.decode()?;
+    Ok(CommitDescriptor {
+        original_id: theirs,
+        original_parent_ids: commit
+            .parent_ids()
+            .map(|parent| *pairs.get(&parent.detach()).unwrap_or(&parent.detach()))
+            .collect(),
+        tree: commit.tree_id()?.detach(),
+        author: decoded.author().into(),
+        committer: decoded.committer().into(),
+        encoding: decoded.encoding.map(Into::into),
+        message: decoded.message.into(),
+        extra_headers: decoded
+            .extra_headers
+            .iter()
+            .map(|(k, v)| ((*k).into(), BString::from(v.as_ref())))
+            .collect(),
+        keep_original: false,
+        dropped: false,
+    })
+}
+
+fn subject(repo: &Repository, id: ObjectId) -> AnyResult<String> {
+    let commit = repo.find_commit(id)?;
+    let message = commit.message_raw()?;
+    Ok(message
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_str_lossy()
+        .into_owned())
+}
+
+/// Compare the mirror of a branch with `other`, the branch another tool rewrote it to,
+/// commit by commit: metadata, messages and trees. Fails if they differ.
+///
+/// Commits are paired by their original through both commit maps with
+/// [`CompareOptions::their_map`], and by their position in the two histories, oldest first,
+/// without it.
+pub fn compare_with(
+    repo: &Repository,
+    naming: &MirrorNaming,
+    other: &str,
+    options: &CompareOptions,
+) -> AnyResult<()> {
+    let target = match &options.target {
+        Some(target) => target
+            .strip_prefix("refs/heads/")
+            .unwrap_or(target)
+            .to_owned(),
+        None => repo
+            .head_name()?
+            .map(|name| name.shorten().to_string())
+            .ok_or("HEAD is detached; name the branch whose mirror to compare with --target")?,
+    };
+    let mirror = refs::find_mirror(repo, naming, &target)?
+        .ok_or_else(|| format!("{target} has no mirror; create one with `gitm mirror` first"))?;
+    let mirror_name = mirror.name.shorten().to_string();
+    let theirs_tip = mirror::resolve_commit_id(repo, other)?;
+    if mirror.tip == theirs_tip {
+        println!("{mirror_name} and {other} point at the same commit");
+        return Ok(());
+    }
+
+    let ours = only_in(repo, mirror.tip, theirs_tip)?;
+    let theirs = only_in(repo, theirs_tip, mirror.tip)?;
+    // Ours → theirs.
+    let mut pairs = HashMap::new();
+    match &options.their_map {
+        Some(path) => {
+            let their_map = load_their_map(repo, path)?;
+            let our_map = CommitMap::load(repo, mirror.name.as_ref())?.ok_or_else(|| {
+                format!("{mirror_name} has no commit map to pair its commits by original")
+            })?;
+            let theirs: HashSet<ObjectId> = theirs.iter().copied().collect();
+            let mut originals: HashMap<ObjectId, Vec<ObjectId>> = HashMap::new();
+            for (original, rewritten) in &our_map.entries {
+                originals.entry(*rewritten).or_default().push(*original);
+            }
+            for id in &ours {
+                let Some(candidates) = originals.get(id) else {
+                    continue;
+                };
+                // Dropped or squashed commits map onto the commit they were folded into,
+                // which is derived from the one without a parent among them.
+                let folded = |original: &ObjectId| {
+                    repo.find_commit(*original).is_ok_and(|commit| {
+                        commit
+                            .parent_ids()
+                            .any(|parent| candidates.contains(&parent.detach()))
+                    })
+                };
+                let their = candidates
+                    .iter()
+                    .find(|original| !folded(original))
+                    .and_then(|original| their_map.get(original))
+                    .filter(|their| theirs.contains(*their));
+                if let Some(their) = their {
+                    pairs.insert(*id, *their);
+                }
+            }
+        }
+        None => {
+            if ours.len() != theirs.len() {
+                println!(
+                    "{} {mirror_name} has {} commit(s) of its own and {other} {}; without \
+                     --their-map, commits are paired by position, oldest first",
+                    "note:".yellow(),
+                    ours.len(),
+                    theirs.len()
+                );
+            }
+            pairs.extend(ours.iter().copied().zip(theirs.iter().copied()));
+        }
+    }
+
+    println!(
+        "Comparing {} (values shown as {} -> {})",
+        format!("{mirror_name} with {other}").bold(),
+        other.red(),
+        mirror_name.green()
+    );
+    let mut differing = 0;
+    for id in &ours {
+        let Some(&their) = pairs.get(id) else {
+            continue;
+        };
+        let diff = DescriptorDiff::compute(repo, &as_rewrite_of(repo, *id, their, &pairs)?)?;
+        if diff.is_empty() {
+            continue;
+        }
+        differing += 1;
+        println!();
+        println!(
+            "{} {} {}",
+            id.to_hex_with_len(7).to_string().green(),
+            their.to_hex_with_len(7).to_string().red(),
+            subject(repo, *id)?
+        );
+        diff.print();
+        let (our_tree, their_tree) = (
+            repo.find_commit(*id)?.tree_id()?.detach(),
+            repo.find_commit(their)?.tree_id()?.detach(),
+        );
+        if our_tree != their_tree {
+            let changes: Vec<ChangeDetached> = tree::diff(repo, Some(their_tree), our_tree)?
+                .into_iter()
+                .filter(|change| !change.entry_mode().is_tree())
+                .collect();
+            for change in changes.iter().take(MAX_PATHS) {
+                let status = match change {
+                    ChangeDetached::Addition { .. } => format!("only in {mirror_name}"),
+                    ChangeDetached::Deletion { .. } => format!("only in {other}"),
+                    _ => "differs".to_owned(),
+                };
+                println!("      {} ({status})", change.location());
+            }
+            if changes.len() > MAX_PATHS {
+                println!("      ... and {} more path(s)", changes.len() - MAX_PATHS);
+            }
+        }
+    }
+
+    let paired: HashSet<ObjectId> = pairs.values().copied().collect();
+    let unpaired_ours: Vec<ObjectId> = ours
+        .iter()
+        .copied()
+        .filter(|id| !pairs.contains_key(id))
+        .collect();
+    let unpaired_theirs: Vec<ObjectId> = theirs
+        .iter()
+        .copied()
+        .filter(|id| !paired.contains(id))
+        .collect();
+    for (name, unpaired) in [
+        (&mirror_name, &unpaired_ours),
+        (&other.to_owned(), &unpaired_theirs),
+    ] {
+        if unpaired.is_empty() {
+            continue;
+        }
+        println!();
+        println!("{}", format!("Only in {name}:").bold());
+        for id in unpaired {
+            println!("  {} {}", id.to_hex_with_len(7), subject(repo, *id)?);
+        }
+    }
+
+    println!();
+    let paired_count = pairs.len();
+    if differing == 0 && unpaired_ours.is_empty() && unpaired_theirs.is_empty() {
+        println!("{mirror_name} and {other} match: {paired_count} commit(s) compared");
+        return Ok(());
+    }
+    Err(Failure::Validation(format!(
+        "{differing} of {paired_count} paired commit(s) differ; {} only in {mirror_name}, {} \
+         only in {other}",
+        unpaired_ours.len(),
+        unpaired_theirs.len()
+    ))
+    .into())
+}
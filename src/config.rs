@@ -0,0 +1,253 @@
+//! Per-repository defaults for `mirror` and `rewrite`, read from `.magitulator.toml`.
+//!
+//! ```toml
+//! [mirror]
+//! drop-empty = true
+//! derive-header = ["change-type=^(\\w+):"]
+//! authors-file = "authors.txt"
+//! ```
+//!
+//! Keys are the long options of `gitm mirror`, besides those running commands, like `exec`;
+//! options given on the command line win. String
+//! values may refer to environment variables, see [`interpolate`]. The `[policy]` table holds
+//! the rules of [`HistoryPolicy`].
+use crate::{AnyResult, error::Failure, lint::HistoryPolicy};
+use clap::{Arg, ArgAction, Command, builder::Resettable};
+use serde::Deserialize;
+use std::{collections::BTreeMap, ffi::OsString, fs, ops::Range, path::Path};
+use toml::{Spanned, Value};
+
+/// Name of the configuration file, at the top of the repository.
+pub const FILE_NAME: &str = ".magitulator.toml";
+
+/// Options that run commands. Whoever can commit to a repository can change its configuration,
+/// so these are only taken from the command line.
+const COMMAND_KEYS: [&str; 4] = ["exec", "tree-filter", "index-filter", "message-command"];
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    #[serde(default)]
+    mirror: BTreeMap<Spanned<String>, Spanned<Value>>,
+    policy: Option<HistoryPolicy>,
+}
+
+/// The text of the configuration at `path` and what it says, if there is one.
+fn read(path: &Path) -> AnyResult<Option<(String, Config)>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let text = fs::read_to_string(path).map_err(|e| format!("reading {}: {e}", path.display()))?;
+    // Syntax errors, unknown sections and invalid policies come with the span they are about.
+    let config: Config = toml::from_str(&text).map_err(|e| {
+        let at = e.span().map(|span| position(&text, span));
+        format!(
+            "{}:{}: {}",
+            path.display(),
+            at.unwrap_or_default(),
+            e.message()
+        )
+    })?;
+    Ok(Some((text, config)))
+}
+
+/// The `[policy]` of the configuration at `path`, if it has one.
+pub fn policy(path: &Path) -> AnyResult<Option<HistoryPolicy>> {
+    Ok(read(path)?.and_then(|(_, config)| config.policy))
+}
+
+/// Check the configuration at `path` against the options of `mirror`, the `mirror`
+/// subcommand, and return the arguments it stands for. A missing file gives no arguments.
+///
+/// Every problem is printed with its line and column before the run fails, so one run is
+/// enough to fix them all.
+pub fn mirror_args(path: &Path, mirror: &Command) -> AnyResult<Vec<OsString>> {
+    let Some((text, config)) = read(path)? else {
+        return Ok(Vec::new());
+    };
+
+    let mut args = Vec::new();
+    let mut errors = Vec::new();
+    for (key, value) in &config.mirror {
+        let at = |span: Range<usize>| {
+            (
+                span.start,
+                format!("{}:{}", path.display(), position(&text, span)),
+            )
+        };
+        let Some(arg) = option(mirror, key.get_ref()) else {
+            let (start, at) = at(key.span());
+            errors.push((
+                start,
+                format!(
+                    "{at}: unknown key `{}` in [mirror]; keys are the long options of `gitm mirror`",
+                    key.get_ref()
+                ),
+            ));
+            continue;
+        };
+        if COMMAND_KEYS.contains(&key.get_ref().as_str()) {
+            let (start, at) = at(key.span());
+            errors.push((
+                start,
+                format!(
+                    "{at}: `{}` runs a command, so it is only taken from the command line, \
+                     not from a file anyone who commits to the repository can change",
+                    key.get_ref()
+                ),
+            ));
+            continue;
+        }
+        let (start, at) = at(value.span());
+        match values(arg, value.get_ref()) {
+            Ok(values) => {
+                for value_arg in values {
+                    if let Err(e) = check_value(arg, &value_arg) {
+                        errors.push((start, format!("{at}: `{}`: {e}", key.get_ref())));
+                    }
+                    args.push(value_arg);
+                }
+            }
+            Err(e) => errors.push((start, format!("{at}: `{}`: {e}", key.get_ref()))),
+        }
+    }
+    if errors.is_empty() {
+        return Ok(args);
+    }
+    // In file order; the keys were read sorted.
+    errors.sort_by_key(|(start, _)| *start);
+    for (_, error) in &errors {
+        eprintln!("{error}");
+    }
+    Err(Failure::Validation(format!("{} has {} error(s)", path.display(), errors.len())).into())
+}
+
+/// The option of `command` called `--<name>`.
+fn option<'a>(command: &'a Command, name: &str) -> Option<&'a Arg> {
+    command
+        .get_arguments()
+        .filter(|arg| !arg.is_positional())
+        .find(|arg| arg.get_long() == Some(name) && name != "help")
+}
+
+/// The command-line arguments giving `value` to `arg`, or what kind of value it expects.
+fn values(arg: &Arg, value: &Value) -> Result<Vec<OsString>, String> {
+    let long = arg.get_long().unwrap_or_default();
+    let takes_no_value = arg
+        .get_num_args()
+        .is_some_and(|range| range.min_values() == 0);
+    let scalar = |value: &Value| match value {
+        Value::String(s) => Some(interpolate(s)),
+        Value::Integer(i) => Some(Ok(i.to_string())),
+        Value::Float(f) => Some(Ok(f.to_string())),
+        _ => None,
+    };
+    match (arg.get_action(), value) {
+        (ArgAction::SetTrue, Value::Boolean(set)) => Ok(set
+            .then(|| format!("--{long}").into())
+            .into_iter()
+            .collect()),
+        (ArgAction::SetTrue, _) => Err("expects `true` or `false`".into()),
+        // Like `--find-copies` without a threshold.
+        (ArgAction::Set, Value::Boolean(set)) if takes_no_value => Ok(set
+            .then(|| format!("--{long}").into())
+            .into_iter()
+            .collect()),
+        (ArgAction::Append, Value::Array(items)) => items
+            .iter()
+            .map(|item| {
+                let s = scalar(item).ok_or("expects a list of strings")??;
+                Ok(format!("--{long}={s}").into())
+            })
+            .collect(),
+        (ArgAction::Set | ArgAction::Append, value) => match scalar(value) {
+            Some(s) => Ok(vec![format!("--{long}={}", s?).into()]),
+            None if matches!(arg.get_action(), ArgAction::Append) => {
+                Err("expects a string or a list of strings".into())
+            }
+            None => Err("expects a string".into()),
+        },
+        _ => Err("can't be set in the configuration".into()),
+    }
+}
+
+/// Run the parser of `arg` on the argument `value_arg`, as the command line would.
+fn check_value(arg: &Arg, value_arg: &OsString) -> Result<(), String> {
+    // Other options `arg` needs or excludes are checked once the configuration and the command line
+    // are combined.
+    let arg = arg
+        .clone()
+        .requires(Resettable::Reset)
+        .conflicts_with(Resettable::Reset);
+    Command::new("config")
+        .no_binary_name(true)
+        .arg(arg)
+        .try_get_matches_from([value_arg])
+        .map(|_| ())
+        .map_err(|e| {
+            // One line: clap's, followed by the conclusion of a multi-line reason like a regex
+            // parse error, without the `--help` hint after the blank line.
+            let rendered = e.to_string();
+            let lines: Vec<&str> = rendered.lines().take_while(|l| !l.is_empty()).collect();
+            fn strip(line: &str) -> &str {
+                line.strip_prefix("error: ").unwrap_or(line).trim()
+            }
+            match lines[..] {
+                [] => String::new(),
+                [first] => strip(first).to_owned(),
+                [first, .., last] => format!("{} {}", strip(first), strip(last)),
+            }
+        })
+}
+
+/// `line:column` of the start of `span` in `text`, counted from 1.
+fn position(text: &str, span: Range<usize>) -> String {
+    let before = &text[..span.start.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .count()
+        + 1;
+    format!("{line}:{column}")
+}
+
+/// Replace every `${NAME}` in `value` with the environment variable `NAME`, or with `default`
+/// for `${NAME:-default}` when the variable is unset or empty. `$${` stands for a literal `${`.
+///
+/// Lets one file be shared by machines and CI jobs that keep identities, keys and URLs in
+/// their environment.
+pub(crate) fn interpolate(value: &str) -> Result<String, String> {
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            result.push_str(&rest[..start - 1]);
+            result.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        result.push_str(&rest[..start]);
+        let inner = &rest[start + 2..];
+        let end = inner
+            .find('}')
+            .ok_or_else(|| format!("unclosed `${{` in `{value}`"))?;
+        let (name, default) = match inner[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&inner[..end], None),
+        };
+        if name.is_empty() || !name.chars().all(|c| c == '_' || c.is_ascii_alphanumeric()) {
+            return Err(format!("invalid variable name `{name}` in `{value}`"));
+        }
+        match (std::env::var(name).ok().filter(|v| !v.is_empty()), default) {
+            (Some(var), _) => result.push_str(&var),
+            (None, Some(default)) => result.push_str(default),
+            (None, None) => return Err(format!("environment variable {name} is not set")),
+        }
+        rest = &inner[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
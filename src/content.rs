@@ -0,0 +1,420 @@
+//! Filters rewriting the content of files in every rewritten tree.
+use crate::{
+    AnyResult,
+    filter::Filter,
+    map::CommitMap,
+    metrics::CacheStats,
+    mirror::CommitDescriptor,
+    tree::{EntryAction, PathPattern, TreeRewriter},
+};
+use colored::Colorize;
+use gix::{
+    ObjectId, Repository,
+    bstr::{BStr, BString, ByteSlice},
+    date::time::CustomFormat,
+};
+use regex::bytes::{NoExpand, Regex};
+use std::{borrow::Cow, collections::HashMap, fs, path::Path};
+
+/// Only this many leading lines are searched for a copyright header.
+const HEADER_LINES: usize = 30;
+
+/// What `--replace-text` rules without `==>` replace their text with, as in git-filter-repo.
+const REMOVED: &str = "***REMOVED***";
+
+/// Whether `data` looks like text, using git's heuristic of no NUL in the first 8000 bytes.
+pub(crate) fn is_text(data: &[u8]) -> bool {
+    !data[..data.len().min(8000)].contains(&0)
+}
+
+/// Replaces the first copyright line of matching files with a rendered template.
+///
+/// `{years}` renders as `<first>-<year>`, or just `<year>` when both are the same, where `<first>`
+/// is the first year found in the existing header and `<year>` the commit's author year.
+/// `{first}` and `{year}` render the years on their own. Files without a copyright line are
+/// left alone.
+pub(crate) struct CopyrightFilter {
+    template: String,
+    paths: Vec<PathPattern>,
+    /// Results depend on the commit year, so each year gets its own tree cache.
+    rewriters: HashMap<i32, TreeRewriter>,
+    blobs: HashMap<(ObjectId, i32), ObjectId>,
+}
+
+impl CopyrightFilter {
+    pub(crate) fn new(template: String, paths: Vec<PathPattern>) -> Self {
+        CopyrightFilter {
+            template,
+            paths,
+            rewriters: HashMap::new(),
+            blobs: HashMap::new(),
+        }
+    }
+
+    fn render(&self, first: i32, year: i32) -> String {
+        let years = if first >= year {
+            year.to_string()
+        } else {
+            format!("{first}-{year}")
+        };
+        self.template
+            .replace("{years}", &years)
+            .replace("{first}", &first.to_string())
+            .replace("{year}", &year.to_string())
+    }
+
+    fn update_header(&self, data: &[u8], year: i32) -> Option<Vec<u8>> {
+        let mut offset = 0;
+        for line in data.lines_with_terminator().take(HEADER_LINES) {
+            let start = offset;
+            offset += line.len();
+
+            let lower = line.to_ascii_lowercase();
+            let Some(at) = lower.find("copyright") else {
+                continue;
+            };
+
+            let content = line.trim_end_with(|c| c == '\n' || c == '\r');
+            let terminator = &line[content.len()..];
+            let first = first_year(&content[at..]).unwrap_or(year);
+
+            let mut header = content[..at].to_vec();
+            header.extend_from_slice(self.render(first, year).as_bytes());
+            if header == content {
+                return None;
+            }
+
+            let mut updated = data[..start].to_vec();
+            updated.extend_from_slice(&header);
+            updated.extend_from_slice(terminator);
+            updated.extend_from_slice(&data[offset..]);
+            return Some(updated);
+        }
+        None
+    }
+}
+
+fn first_year(text: &[u8]) -> Option<i32> {
+    text.windows(4)
+        .enumerate()
+        .find(|(at, window)| {
+            window.iter().all(u8::is_ascii_digit)
+                && !text.get(at + 4).is_some_and(u8::is_ascii_digit)
+                && (*at == 0 || !text[at - 1].is_ascii_digit())
+        })
+        .and_then(|(_, window)| window.to_str().ok()?.parse().ok())
+}
+
+impl Filter for CopyrightFilter {
+    fn name(&self) -> String {
+        "--copyright-template".into()
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        let mut stats = CacheStats::default();
+        for rewriter in self.rewriters.values() {
+            stats += rewriter.stats();
+        }
+        Some(stats)
+    }
+
+    fn apply(&mut self, repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        let year: i32 = descriptor
+            .author
+            .time
+            .format(CustomFormat::new("%Y"))
+            .parse()?;
+
+        let mut rewriter = self.rewriters.remove(&year).unwrap_or_default();
+        let mut blobs = std::mem::take(&mut self.blobs);
+        // Only the rewriter of the same year knows the parent, as the header depends on the year.
+        let filtered = |path: &BStr| self.matches(path);
+        let rewritten =
+            rewriter.rewrite_commit(repo, descriptor, &filtered, &mut |path, mode, id| {
+                if !mode.is_blob() || !self.matches(path) {
+                    return Ok(EntryAction::Keep);
+                }
+                let new_id = match blobs.get(&(id, year)) {
+                    Some(new_id) => *new_id,
+                    None => {
+                        let blob = repo.find_blob(id)?;
+                        let new_id = match is_text(&blob.data)
+                            .then(|| self.update_header(&blob.data, year))
+                            .flatten()
+                        {
+                            Some(updated) => repo.write_blob(updated)?.detach(),
+                            None => id,
+                        };
+                        blobs.insert((id, year), new_id);
+                        new_id
+                    }
+                };
+                Ok(if new_id == id {
+                    EntryAction::Keep
+                } else {
+                    EntryAction::Replace { id: new_id, mode }
+                })
+            });
+        self.rewriters.insert(year, rewriter);
+        self.blobs = blobs;
+
+        descriptor.tree = rewritten?.id;
+        Ok(())
+    }
+}
+
+impl CopyrightFilter {
+    fn matches(&self, path: &BStr) -> bool {
+        self.paths.is_empty() || self.paths.iter().any(|pattern| pattern.matches(path))
+    }
+}
+
+/// A rule of a `--replace-text` file: `<text>==><replacement>`, or `regex:<regex>==><replacement>`
+/// where `$1` stands for a capture group. Without `==>`, the text is replaced with
+/// [`REMOVED`].
+struct TextReplacement {
+    line: String,
+    pattern: Regex,
+    replacement: BString,
+    /// Whether the replacement is inserted as is, without expanding capture groups.
+    literal: bool,
+    /// Blobs the rule matched in.
+    matched: usize,
+}
+
+impl TextReplacement {
+    fn parse(line: &str) -> Result<Self, String> {
+        // The last `==>`, like git-filter-repo, so a text may contain one.
+        let (text, replacement) = line.rsplit_once("==>").unwrap_or((line, REMOVED));
+        let (pattern, literal) = match text.strip_prefix("regex:") {
+            Some(pattern) => (pattern.to_owned(), false),
+            None => (
+                regex::escape(text.strip_prefix("literal:").unwrap_or(text)),
+                true,
+            ),
+        };
+        if pattern.is_empty() {
+            return Err("the text to replace can't be empty".into());
+        }
+        Ok(TextReplacement {
+            line: line.to_owned(),
+            pattern: Regex::new(&pattern).map_err(|e| e.to_string())?,
+            replacement: replacement.into(),
+            literal,
+            matched: 0,
+        })
+    }
+
+    fn replace<'a>(&self, data: &'a [u8]) -> Cow<'a, [u8]> {
+        match self.literal {
+            true => self
+                .pattern
+                .replace_all(data, NoExpand(self.replacement.as_slice())),
+            false => self.pattern.replace_all(data, self.replacement.as_slice()),
+        }
+    }
+}
+
+/// Replaces text in every text file of every rewritten tree, following the rules of a
+/// `--replace-text` file in order. Files that look binary are left alone.
+pub(crate) struct TextReplacements {
+    rules: Vec<TextReplacement>,
+    rewriter: TreeRewriter,
+    blobs: HashMap<ObjectId, ObjectId>,
+    changed: usize,
+}
+
+impl TextReplacements {
+    /// The rules of `path`, one per line; blank lines and `#` comments are skipped.
+    pub(crate) fn load(path: &Path) -> AnyResult<Self> {
+        let text =
+            fs::read_to_string(path).map_err(|e| format!("reading {}: {e}", path.display()))?;
+        let mut rules = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let rule = TextReplacement::parse(line)
+                .map_err(|e| format!("{}:{}: {e}", path.display(), index + 1))?;
+            rules.push(rule);
+        }
+        if rules.is_empty() {
+            return Err(format!("{}: no replacement rules", path.display()).into());
+        }
+        Ok(TextReplacements {
+            rules,
+            rewriter: TreeRewriter::default(),
+            blobs: HashMap::new(),
+            changed: 0,
+        })
+    }
+
+    /// The id of blob `id` with every rule applied.
+    fn replace(&mut self, repo: &Repository, id: ObjectId) -> AnyResult<ObjectId> {
+        if let Some(new_id) = self.blobs.get(&id) {
+            return Ok(*new_id);
+        }
+        let blob = repo.find_blob(id)?;
+        let mut data = Cow::Borrowed(&blob.data[..]);
+        if is_text(&data) {
+            for rule in &mut self.rules {
+                if let Cow::Owned(replaced) = rule.replace(&data) {
+                    rule.matched += 1;
+                    data = Cow::Owned(replaced);
+                }
+            }
+        }
+        let new_id = match data {
+            Cow::Owned(replaced) => {
+                self.changed += 1;
+                repo.write_blob(replaced)?.detach()
+            }
+            Cow::Borrowed(_) => id,
+        };
+        self.blobs.insert(id, new_id);
+        Ok(new_id)
+    }
+}
+
+impl Filter for TextReplacements {
+    fn name(&self) -> String {
+        "--replace-text".into()
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        Some(self.rewriter.stats())
+    }
+
+    fn apply(&mut self, repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        let mut rewriter = std::mem::take(&mut self.rewriter);
+        let rewritten = rewriter.rewrite_commit(repo, descriptor, &|_| true, &mut |_, mode, id| {
+            if !mode.is_blob() {
+                return Ok(EntryAction::Keep);
+            }
+            let new_id = self.replace(repo, id)?;
+            Ok(if new_id == id {
+                EntryAction::Keep
+            } else {
+                EntryAction::Replace { id: new_id, mode }
+            })
+        });
+        self.rewriter = rewriter;
+        descriptor.tree = rewritten?.id;
+        Ok(())
+    }
+
+    fn finish(&mut self, _repo: &Repository, _map: &CommitMap) -> AnyResult<()> {
+        println!("Replaced text in {} blob(s)", self.changed);
+        for rule in self.rules.iter().filter(|rule| rule.matched == 0) {
+            eprintln!(
+                "{} --replace-text: `{}` matched nothing",
+                "warning:".yellow(),
+                rule.line
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The line endings `--normalize-eol` gives text files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum LineEnding {
+    /// `\n`, as `text` in `.gitattributes` stores files.
+    Lf,
+    /// `\r\n`.
+    Crlf,
+}
+
+/// `data` with every line ending `eol`; `None` if it has them all already. A `\r` not
+/// followed by `\n` isn't a line ending, and is kept.
+fn normalize_eol(data: &[u8], eol: LineEnding) -> Option<Vec<u8>> {
+    let mut normalized = Vec::with_capacity(data.len());
+    for (index, &byte) in data.iter().enumerate() {
+        match (eol, byte) {
+            // Dropped, keeping the `\n` after it.
+            (LineEnding::Lf, b'\r') if data.get(index + 1) == Some(&b'\n') => continue,
+            (LineEnding::Crlf, b'\n') if index == 0 || data[index - 1] != b'\r' => {
+                normalized.push(b'\r');
+            }
+            _ => {}
+        }
+        normalized.push(byte);
+    }
+    (normalized != data).then_some(normalized)
+}
+
+/// Gives every text file of every rewritten tree the same line endings. Files that look
+/// binary are left alone.
+pub(crate) struct EolNormalization {
+    eol: LineEnding,
+    rewriter: TreeRewriter,
+    blobs: HashMap<ObjectId, ObjectId>,
+    changed: usize,
+}
+
+impl EolNormalization {
+    pub(crate) fn new(eol: LineEnding) -> Self {
+        EolNormalization {
+            eol,
+            rewriter: TreeRewriter::default(),
+            blobs: HashMap::new(),
+            changed: 0,
+        }
+    }
+
+    /// The id of blob `id` with its line endings normalized.
+    fn normalize(&mut self, repo: &Repository, id: ObjectId) -> AnyResult<ObjectId> {
+        if let Some(new_id) = self.blobs.get(&id) {
+            return Ok(*new_id);
+        }
+        let blob = repo.find_blob(id)?;
+        let new_id = match is_text(&blob.data)
+            .then(|| normalize_eol(&blob.data, self.eol))
+            .flatten()
+        {
+            Some(normalized) => {
+                self.changed += 1;
+                repo.write_blob(normalized)?.detach()
+            }
+            None => id,
+        };
+        self.blobs.insert(id, new_id);
+        Ok(new_id)
+    }
+}
+
+impl Filter for EolNormalization {
+    fn name(&self) -> String {
+        match self.eol {
+            LineEnding::Lf => "--normalize-eol lf".into(),
+            LineEnding::Crlf => "--normalize-eol crlf".into(),
+        }
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        Some(self.rewriter.stats())
+    }
+
+    fn apply(&mut self, repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        let mut rewriter = std::mem::take(&mut self.rewriter);
+        let rewritten = rewriter.rewrite_commit(repo, descriptor, &|_| true, &mut |_, mode, id| {
+            if !mode.is_blob() {
+                return Ok(EntryAction::Keep);
+            }
+            let new_id = self.normalize(repo, id)?;
+            Ok(if new_id == id {
+                EntryAction::Keep
+            } else {
+                EntryAction::Replace { id: new_id, mode }
+            })
+        });
+        self.rewriter = rewriter;
+        descriptor.tree = rewritten?.id;
+        Ok(())
+    }
+
+    fn finish(&mut self, _repo: &Repository, _map: &CommitMap) -> AnyResult<()> {
+        println!("Normalized the line endings of {} blob(s)", self.changed);
+        Ok(())
+    }
+}
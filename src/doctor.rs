@@ -0,0 +1,431 @@
+//! Pre-flight checks of a repository before rewriting it, and repairs after runs that died.
+use crate::{
+    AnyResult,
+    budget::format_size,
+    checkpoint, clean,
+    error::Failure,
+    files, interrupt,
+    map::CommitMap,
+    refs::{self, MirrorNaming},
+    state,
+};
+use colored::Colorize;
+use gix::{
+    Repository,
+    hash::Kind,
+    refs::transaction::{Change, PreviousValue, RefEdit, RefLog},
+    state::InProgress,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Lock files younger than this are left alone by `--repair`: git holds ref locks for an
+/// instant, but a large checkout keeps `index.lock` for a while.
+const STALE_LOCK_SECONDS: u64 = 10 * 60;
+
+/// What a git operation left in progress is, and how to conclude it.
+pub(crate) fn describe_in_progress(state: InProgress) -> (&'static str, &'static str) {
+    match state {
+        InProgress::ApplyMailbox | InProgress::ApplyMailboxRebase => {
+            ("`git am`", "git am --continue` or `git am --abort")
+        }
+        InProgress::Bisect => ("a bisect", "git bisect reset"),
+        InProgress::CherryPick | InProgress::CherryPickSequence => (
+            "a cherry-pick",
+            "git cherry-pick --continue` or `git cherry-pick --abort",
+        ),
+        InProgress::Merge => ("a merge", "git merge --continue` or `git merge --abort"),
+        InProgress::Rebase | InProgress::RebaseInteractive => {
+            ("a rebase", "git rebase --continue` or `git rebase --abort")
+        }
+        InProgress::Revert | InProgress::RevertSequence => {
+            ("a revert", "git revert --continue` or `git revert --abort")
+        }
+    }
+}
+
+enum Outcome {
+    Ok,
+    /// The rewrite works, but slower or with surprises.
+    Warning,
+    /// The rewrite is likely to fail or to leave a mess.
+    Problem,
+}
+
+#[derive(Default)]
+struct Checks {
+    warnings: usize,
+    problems: usize,
+}
+
+impl Checks {
+    fn report(&mut self, outcome: Outcome, message: impl AsRef<str>) {
+        let label = match outcome {
+            Outcome::Ok => "ok".green(),
+            Outcome::Warning => {
+                self.warnings += 1;
+                "warning".yellow()
+            }
+            Outcome::Problem => {
+                self.problems += 1;
+                "problem".red()
+            }
+        };
+        println!("  {label:<8} {}", message.as_ref());
+    }
+}
+
+/// Check that `repo` is in a state a rewrite can work with: its object format, commit-graph,
+/// shallowness, operations in progress in any worktree, stale lock files and free disk space.
+/// Fails if any check finds a problem; warnings only slow a rewrite down or surprise.
+pub fn doctor(repo: &Repository) -> AnyResult<()> {
+    let common_dir = repo.common_dir();
+    println!(
+        "Checking {}",
+        common_dir
+            .canonicalize()
+            .unwrap_or_else(|_| common_dir.to_owned())
+            .display()
+    );
+    let mut checks = Checks::default();
+
+    match repo.object_hash() {
+        Kind::Sha1 => checks.report(Outcome::Ok, "object format: SHA-1"),
+        #[allow(unreachable_patterns)]
+        other => checks.report(
+            Outcome::Problem,
+            format!("object format {other} isn't supported; only SHA-1 repositories are"),
+        ),
+    }
+
+    let info = common_dir.join("objects").join("info");
+    if info.join("commit-graph").exists() || info.join("commit-graphs").exists() {
+        checks.report(Outcome::Ok, "commit-graph present");
+    } else {
+        checks.report(
+            Outcome::Warning,
+            "no commit-graph; `git commit-graph write --reachable` speeds up walking long \
+             histories",
+        );
+    }
+
+    if repo.is_shallow() {
+        checks.report(
+            Outcome::Warning,
+            "the repository is shallow, so the oldest commits lack their parents; run \
+             `git fetch --unshallow` before rewriting history from the root",
+        );
+    } else {
+        checks.report(Outcome::Ok, "complete history (not shallow)");
+    }
+
+    let mut in_progress = Vec::new();
+    for (path, state) in operations_in_progress(repo)? {
+        let (operation, conclude) = describe_in_progress(state);
+        in_progress.push(format!(
+            "{operation} is in progress in {}; conclude it with `{conclude}`",
+            path.display()
+        ));
+    }
+    if in_progress.is_empty() {
+        checks.report(Outcome::Ok, "no git operation in progress");
+    }
+    for message in in_progress {
+        checks.report(Outcome::Problem, message);
+    }
+
+    let locks = lock_files(common_dir)?;
+    if locks.is_empty() {
+        checks.report(Outcome::Ok, "no lock files");
+    } else {
+        let listed: Vec<String> = locks
+            .iter()
+            .map(|lock| {
+                lock.strip_prefix(common_dir)
+                    .unwrap_or(lock)
+                    .display()
+                    .to_string()
+            })
+            .collect();
+        checks.report(
+            Outcome::Problem,
+            format!(
+                "lock file(s) {} block ref and index updates; remove them if no git process is \
+                 running, or let `gitm doctor --repair` remove the stale ones",
+                listed.join(", ")
+            ),
+        );
+    }
+
+    let unfinished: Vec<String> = interrupt::load_runs(repo)?
+        .into_iter()
+        .filter(|(_, record)| !record.is_running())
+        .map(|(_, record)| record.pid.to_string())
+        .collect();
+    if unfinished.is_empty() {
+        checks.report(Outcome::Ok, "no runs left unfinished");
+    } else {
+        checks.report(
+            Outcome::Warning,
+            format!(
+                "run(s) {} died before finishing; `gitm doctor --repair` removes what they \
+                 left behind",
+                unfinished.join(", ")
+            ),
+        );
+    }
+
+    for (_, record) in checkpoint::load_all(repo)? {
+        checks.report(
+            Outcome::Warning,
+            format!(
+                "`{}` ran out of its --time-budget after {} of {} commit(s); run it again to \
+                 resume, before `git gc` prunes the commits written so far",
+                interrupt::command_line(&record.command),
+                record.done,
+                record.total
+            ),
+        );
+    }
+
+    let objects = directory_size(&common_dir.join("objects"))?;
+    match available_space(common_dir) {
+        // Rewritten objects are written loose, so a large rewrite can need about as much
+        // again as the objects take now.
+        Some(free) if free < objects => checks.report(
+            Outcome::Warning,
+            format!(
+                "only {} free on the disk, and the objects take {}; rewriting most of the \
+                 history could fill it",
+                format_size(free),
+                format_size(objects)
+            ),
+        ),
+        Some(free) => checks.report(
+            Outcome::Ok,
+            format!(
+                "{} free on the disk (objects take {})",
+                format_size(free),
+                format_size(objects)
+            ),
+        ),
+        None => checks.report(
+            Outcome::Warning,
+            format!(
+                "couldn't tell the free disk space; the objects take {}",
+                format_size(objects)
+            ),
+        ),
+    }
+
+    println!(
+        "{} problem(s), {} warning(s)",
+        checks.problems, checks.warnings
+    );
+    if checks.problems > 0 {
+        return Err(Failure::Validation(format!(
+            "{} problem(s) found; fix them before rewriting",
+            checks.problems
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Clean up after runs that were killed or crashed, printing each action taken:
+///
+/// - for each run whose record outlived its process, remove its scratch directories and the
+///   mirror refs it created without recording their commit map, then the record itself;
+/// - when no run is in progress, remove quarantine directories, which only a run in progress
+///   can use;
+/// - remove lock files untouched for [`STALE_LOCK_SECONDS`].
+pub fn repair(repo: &Repository, naming: &MirrorNaming, dry_run: bool) -> AnyResult<()> {
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    let remove = |path: &Path| -> std::io::Result<()> {
+        match dry_run {
+            true => Ok(()),
+            false if path.is_dir() => files::remove_dir_all(path),
+            false => files::remove_file(path),
+        }
+    };
+    let mut actions = 0;
+
+    let (running, dead): (Vec<_>, Vec<_>) = interrupt::load_runs(repo)?
+        .into_iter()
+        .partition(|(_, record)| record.is_running());
+    for (_, record) in &running {
+        println!(
+            "{} run {} (`{}`) is in progress; leaving its files alone",
+            "note:".yellow(),
+            record.pid,
+            record.command_line()
+        );
+    }
+    for (path, record) in &dead {
+        let command = record.command_line();
+        for dir in record.scratch_dirs.iter().filter(|dir| dir.exists()) {
+            remove(dir)?;
+            println!("{verb} {}, left by run {}", dir.display(), record.pid);
+            actions += 1;
+        }
+        // The map is saved right after the ref is created; a mirror without one was cut short.
+        let mut half_created = Vec::new();
+        for mirror in refs::list_mirrors(repo, naming)? {
+            let updated = clean::last_ref_update(repo, &mirror.name);
+            if !CommitMap::path(repo, mirror.name.as_ref()).exists()
+                && updated.is_some_and(|time| time.seconds >= record.started_at)
+            {
+                half_created.push(mirror.name);
+            }
+        }
+        if !dry_run && !half_created.is_empty() {
+            repo.edit_references(half_created.iter().map(|name| RefEdit {
+                change: Change::Delete {
+                    expected: PreviousValue::MustExist,
+                    log: RefLog::AndReference,
+                },
+                name: name.clone(),
+                deref: false,
+            }))?;
+        }
+        for name in &half_created {
+            println!(
+                "{verb} {}, which run {} created without its commit map; rerun `{command}` to \
+                 recreate it",
+                name.as_bstr(),
+                record.pid
+            );
+            actions += 1;
+        }
+        remove(path)?;
+        println!("{verb} the record of run {} (`{command}`)", record.pid);
+        actions += 1;
+    }
+
+    if running.is_empty() {
+        for path in clean::entries(&state::quarantine_dir(repo))? {
+            remove(&path)?;
+            println!("{verb} {}, which no run in progress owns", path.display());
+            actions += 1;
+        }
+    }
+    // Checkpoints are kept for the next run to resume from.
+    for (path, record) in checkpoint::load_all(repo)? {
+        println!(
+            "{} kept {}, for `{}` to resume from; `gitm clean --checkpoints` removes it",
+            "note:".yellow(),
+            path.display(),
+            interrupt::command_line(&record.command)
+        );
+    }
+
+    let common_dir = repo.common_dir();
+    // Under a ref namespace, the locks of other tenants' refs aren't ours to remove.
+    let lock_dir = match repo.namespace() {
+        Some(namespace) => common_dir.join(gix::path::from_bstr(namespace.as_bstr())),
+        None => common_dir.to_owned(),
+    };
+    let locks = match lock_dir.exists() {
+        true => lock_files(&lock_dir)?,
+        false => Vec::new(),
+    };
+    for lock in locks {
+        let name = lock.strip_prefix(common_dir).unwrap_or(&lock).display();
+        let age = fs::metadata(&lock)?
+            .modified()?
+            .elapsed()
+            .unwrap_or_default()
+            .as_secs();
+        if age < STALE_LOCK_SECONDS {
+            println!(
+                "{} {name} changed {age}s ago and may belong to a running git; leaving it alone",
+                "note:".yellow()
+            );
+            continue;
+        }
+        remove(&lock)?;
+        println!(
+            "{verb} stale lock {name}, untouched for {} minutes",
+            age / 60
+        );
+        actions += 1;
+    }
+
+    if actions == 0 {
+        println!("Nothing to repair");
+    }
+    Ok(())
+}
+
+/// The operations in progress in the main worktree and in each linked one, with their paths.
+pub(crate) fn operations_in_progress(repo: &Repository) -> AnyResult<Vec<(PathBuf, InProgress)>> {
+    let main = repo.main_repo()?;
+    let mut found = Vec::new();
+    let path_of = |repo: &Repository| {
+        let path = repo.workdir().unwrap_or(repo.git_dir());
+        path.canonicalize().unwrap_or_else(|_| path.to_owned())
+    };
+    if let Some(state) = main.state() {
+        found.push((path_of(&main), state));
+    }
+    for proxy in main.worktrees()? {
+        let base = proxy.base()?;
+        if let Some(state) = proxy
+            .into_repo_with_possibly_inaccessible_worktree()?
+            .state()
+        {
+            found.push((base, state));
+        }
+    }
+    Ok(found)
+}
+
+/// `*.lock` files below `dir`, leaving out the object database.
+fn lock_files(dir: &Path) -> AnyResult<Vec<PathBuf>> {
+    let mut locks = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if path.file_name().is_some_and(|name| name != "objects") {
+                locks.extend(lock_files(&path)?);
+            }
+        } else if path.extension().is_some_and(|ext| ext == "lock") {
+            locks.push(path);
+        }
+    }
+    Ok(locks)
+}
+
+fn directory_size(dir: &Path) -> AnyResult<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += if metadata.is_dir() {
+            directory_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+    Ok(size)
+}
+
+/// Bytes available on the file system holding `path`, as `df` reports them.
+fn available_space(path: &Path) -> Option<u64> {
+    let output = Command::new("df").arg("-Pk").arg(path).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let kilobytes: u64 = stdout
+        .lines()
+        .nth(1)?
+        .split_whitespace()
+        .nth(3)?
+        .parse()
+        .ok()?;
+    Some(kilobytes * 1024)
+}
@@ -0,0 +1,65 @@
+//! Failures scripts can tell apart by the exit code of `gitm`.
+use std::fmt;
+
+/// Exit code of errors of no [`Failure`] class: I/O, git or bugs.
+pub const INTERNAL_EXIT_CODE: u8 = 1;
+pub const NOTHING_TO_DO_EXIT_CODE: u8 = 3;
+pub const VALIDATION_EXIT_CODE: u8 = 4;
+pub const PROTECTED_REF_EXIT_CODE: u8 = 5;
+pub const DIRTY_WORKTREE_EXIT_CODE: u8 = 6;
+pub const PAUSED_EXIT_CODE: u8 = 7;
+
+/// Failures of a known class. The message is what was wrong and how to proceed.
+#[derive(thiserror::Error)]
+#[error("{}", self.message())]
+pub enum Failure {
+    /// There was nothing to rewrite or apply.
+    NothingToDo(String),
+    /// A check of the configuration or of the rewrite failed: the change policy, consistency
+    /// with other mirrors, conflicting path filters, pre-flight checks.
+    Validation(String),
+    /// A branch wasn't moved because it changed since it was mirrored.
+    ProtectedRef(String),
+    /// A branch wasn't moved because a worktree has it checked out or is in the middle of a
+    /// git operation.
+    DirtyWorktree(String),
+    /// A run stopped at its `--time-budget`, leaving a checkpoint for the next run to resume
+    /// from.
+    Paused(String),
+}
+
+impl Failure {
+    fn message(&self) -> &str {
+        match self {
+            Failure::NothingToDo(message)
+            | Failure::Validation(message)
+            | Failure::ProtectedRef(message)
+            | Failure::DirtyWorktree(message)
+            | Failure::Paused(message) => message,
+        }
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Failure::NothingToDo(_) => NOTHING_TO_DO_EXIT_CODE,
+            Failure::Validation(_) => VALIDATION_EXIT_CODE,
+            Failure::ProtectedRef(_) => PROTECTED_REF_EXIT_CODE,
+            Failure::DirtyWorktree(_) => DIRTY_WORKTREE_EXIT_CODE,
+            Failure::Paused(_) => PAUSED_EXIT_CODE,
+        }
+    }
+}
+
+/// Printed like the plain string errors it stands beside.
+impl fmt::Debug for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.message(), f)
+    }
+}
+
+/// The exit code `gitm` reports `error` with.
+pub fn exit_code(error: &(dyn std::error::Error + Send + Sync + 'static)) -> u8 {
+    error
+        .downcast_ref::<Failure>()
+        .map_or(INTERNAL_EXIT_CODE, Failure::exit_code)
+}
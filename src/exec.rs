@@ -0,0 +1,544 @@
+//! Running commands on the trees of rewritten commits: `--exec`, like `git rebase -x`, and
+//! `--tree-filter` and `--index-filter`, like their `git filter-branch` namesakes.
+use crate::{
+    AnyResult, files, filter::Filter, interrupt, map::CommitMap, mirror::CommitDescriptor, tree,
+};
+use colored::Colorize;
+use gix::{
+    ObjectId, Repository,
+    bstr::{BStr, BString, ByteSlice},
+    object::tree::{EntryKind, diff::ChangeDetached},
+    objs::tree::EntryMode,
+    traverse::tree::Recorder,
+};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+/// A scratch directory holding the files of one tree after another. Only the differences to
+/// the tree before are applied, so files left untracked survive as in a normal worktree.
+struct Checkout {
+    dir: PathBuf,
+    /// Directories to check out; everything when empty.
+    cones: Vec<BString>,
+    checked_out: Option<ObjectId>,
+}
+
+/// Runs `command` with `sh -c` in a scratch checkout of the commit.
+///
+/// The checkout is kept between commits, so build outputs left by the command survive.
+pub(crate) struct Exec {
+    command: String,
+    checkout: Checkout,
+}
+
+impl Exec {
+    pub(crate) fn new(command: &str, cones: &[String]) -> Self {
+        Exec {
+            command: command.to_owned(),
+            checkout: Checkout {
+                dir: std::env::temp_dir()
+                    .join(format!("magitulator-exec-{}", files::unique_name())),
+                cones: cones
+                    .iter()
+                    .map(|cone| cone.trim_matches('/').into())
+                    .collect(),
+                checked_out: None,
+            },
+        }
+    }
+
+    /// Check out the tree of `commit` and run the command in it. The commit id and the id of the
+    /// commit it was rewritten from are passed as `MAGITULATOR_COMMIT` and
+    /// `MAGITULATOR_ORIGINAL`.
+    pub(crate) fn run(
+        &mut self,
+        repo: &Repository,
+        commit: ObjectId,
+        original: ObjectId,
+        tree: ObjectId,
+    ) -> AnyResult<()> {
+        self.checkout.check_out(repo, tree)?;
+
+        println!(
+            "{} {} {}",
+            "exec".dimmed(),
+            commit.to_hex_with_len(7).to_string().dimmed(),
+            self.command
+        );
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .current_dir(&self.checkout.dir)
+            .env("MAGITULATOR_COMMIT", commit.to_string())
+            .env("MAGITULATOR_ORIGINAL", original.to_string())
+            .status()
+            .map_err(|e| format!("running `{}`: {e}", self.command))?;
+        // The command got the signal too, so its failure says nothing about the tree.
+        interrupt::check()?;
+        if !status.success() {
+            return Err(format!(
+                "`{}` failed on {} (rewritten from {}); no refs were updated. Its tree is left in {}",
+                self.command,
+                commit.to_hex_with_len(7),
+                original.to_hex_with_len(7),
+                self.checkout.dir.display()
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// The scratch checkout.
+    pub(crate) fn dir(&self) -> &Path {
+        &self.checkout.dir
+    }
+
+    /// Remove the scratch checkout after a successful or interrupted run.
+    pub(crate) fn finish(&self) -> AnyResult<()> {
+        self.checkout.remove()
+    }
+}
+
+impl Checkout {
+    fn remove(&self) -> AnyResult<()> {
+        if self.dir.exists() {
+            files::remove_dir_all(&self.dir)?;
+        }
+        Ok(())
+    }
+
+    fn check_out(&mut self, repo: &Repository, tree: ObjectId) -> AnyResult<()> {
+        match self.checked_out {
+            Some(previous) => {
+                for change in tree::diff(repo, Some(previous), tree)? {
+                    if !self.in_cone(change.location()) {
+                        continue;
+                    }
+                    let path = files::checkout_path(&self.dir, change.location())?;
+                    match change {
+                        // Deleted trees are followed by their entries, which are gone by then.
+                        ChangeDetached::Deletion { .. } => remove(&path)?,
+                        _ => {
+                            let (mode, id) = change.entry_mode_and_id();
+                            write_entry(repo, &path, mode, id.to_owned())?;
+                        }
+                    }
+                }
+            }
+            None => {
+                if self.dir.exists() {
+                    files::remove_dir_all(&self.dir)?;
+                }
+                fs::create_dir_all(&self.dir)?;
+                let mut recorder = Recorder::default();
+                repo.find_tree(tree)?
+                    .traverse()
+                    .breadthfirst(&mut recorder)?;
+                for entry in recorder.records {
+                    if self.in_cone(entry.filepath.as_ref()) {
+                        let path = files::checkout_path(&self.dir, entry.filepath.as_ref())?;
+                        write_entry(repo, &path, entry.mode, entry.oid)?;
+                    }
+                }
+            }
+        }
+        self.checked_out = Some(tree);
+        Ok(())
+    }
+
+    /// Whether `path` is inside a cone, or a directory leading to one.
+    fn in_cone(&self, path: &BStr) -> bool {
+        let below = |outer: &[u8], inner: &[u8]| {
+            inner.starts_with(outer) && inner.get(outer.len()).is_none_or(|b| *b == b'/')
+        };
+        self.cones.is_empty()
+            || self
+                .cones
+                .iter()
+                .any(|cone| below(cone, path) || below(path, cone))
+    }
+
+    /// The files in the scratch directory as a tree, which becomes the tree checked out.
+    /// Directories that were submodules in `tree`, the tree checked out before, stay submodules;
+    /// `.git` directories are left out, and so are empty directories, which Git doesn't track.
+    fn read_tree(&mut self, repo: &Repository, tree: ObjectId) -> AnyResult<ObjectId> {
+        let mut editor = repo.edit_tree(ObjectId::empty_tree(repo.object_hash()))?;
+        let mut dirs = vec![self.dir.clone()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                let path = entry.path();
+                if entry.file_name() == ".git" {
+                    continue;
+                }
+                let file_type = entry.file_type()?;
+                if file_type.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                let relative = path.strip_prefix(&self.dir)?;
+                let location =
+                    gix::path::to_unix_separators_on_windows(gix::path::into_bstr(relative))
+                        .into_owned();
+                let (kind, data) = if file_type.is_symlink() {
+                    let target = fs::read_link(&path)?;
+                    (EntryKind::Link, gix::path::into_bstr(target).to_vec())
+                } else if is_executable(repo, &entry, tree, relative)? {
+                    (EntryKind::BlobExecutable, fs::read(&path)?)
+                } else {
+                    (EntryKind::Blob, fs::read(&path)?)
+                };
+                let id = repo.write_blob(data)?.detach();
+                editor.upsert(location.as_bstr(), kind, id)?;
+            }
+        }
+        let mut recorder = Recorder::default();
+        repo.find_tree(tree)?
+            .traverse()
+            .breadthfirst(&mut recorder)?;
+        for entry in recorder.records {
+            if entry.mode.is_commit()
+                && files::checkout_path(&self.dir, entry.filepath.as_ref())?.is_dir()
+            {
+                editor.upsert(entry.filepath.as_bstr(), EntryKind::Commit, entry.oid)?;
+            }
+        }
+        let written = editor.write()?.detach();
+        self.checked_out = Some(written);
+        Ok(written)
+    }
+}
+
+/// Whether the file `entry` is executable. Where files have no executable bit, it is taken
+/// from the entry at `relative` in `tree`.
+fn is_executable(
+    repo: &Repository,
+    entry: &fs::DirEntry,
+    tree: ObjectId,
+    relative: &Path,
+) -> AnyResult<bool> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let _ = (repo, tree, relative);
+        Ok(entry.metadata()?.permissions().mode() & 0o111 != 0)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = entry;
+        Ok(repo
+            .find_tree(tree)?
+            .lookup_entry_by_path(relative)?
+            .is_some_and(|entry| entry.mode().is_executable()))
+    }
+}
+
+/// Replaces the tree of every commit with what is left in a scratch checkout of it after
+/// running `command` there with `sh -c`, like `git filter-branch --tree-filter`.
+///
+/// Every file the command leaves is committed, ignored or not. Commits with a tree the command
+/// ran on before get the same result without running it again.
+pub(crate) struct TreeCommand {
+    command: String,
+    checkout: Checkout,
+    /// Resulting trees by the tree the command ran on.
+    trees: HashMap<ObjectId, ObjectId>,
+    runs: usize,
+    /// Whether a failed run left the checkout for inspection.
+    failed: bool,
+}
+
+impl TreeCommand {
+    pub(crate) fn new(command: &str) -> Self {
+        TreeCommand {
+            command: command.to_owned(),
+            checkout: Checkout {
+                dir: std::env::temp_dir()
+                    .join(format!("magitulator-tree-filter-{}", files::unique_name())),
+                cones: Vec::new(),
+                checked_out: None,
+            },
+            trees: HashMap::new(),
+            runs: 0,
+            failed: false,
+        }
+    }
+}
+
+impl Filter for TreeCommand {
+    fn name(&self) -> String {
+        "--tree-filter".into()
+    }
+
+    fn apply(&mut self, repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        if let Some(tree) = self.trees.get(&descriptor.tree) {
+            descriptor.tree = *tree;
+            return Ok(());
+        }
+        self.checkout.check_out(repo, descriptor.tree)?;
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .current_dir(&self.checkout.dir)
+            .env("MAGITULATOR_ORIGINAL", descriptor.original_id.to_string())
+            .status()
+            .map_err(|e| format!("running `{}`: {e}", self.command))?;
+        interrupt::check()?;
+        if !status.success() {
+            self.failed = true;
+            return Err(format!(
+                "`{}` failed on the tree of {}; no refs were updated. The tree is left in {}",
+                self.command,
+                descriptor.original_id.to_hex_with_len(7),
+                self.checkout.dir.display()
+            )
+            .into());
+        }
+        self.runs += 1;
+        let tree = self.checkout.read_tree(repo, descriptor.tree)?;
+        self.trees.insert(descriptor.tree, tree);
+        descriptor.tree = tree;
+        Ok(())
+    }
+
+    fn finish(&mut self, _repo: &Repository, _map: &CommitMap) -> AnyResult<()> {
+        println!(
+            "Ran `{}` on {} tree(s), changing {}",
+            self.command,
+            self.runs,
+            self.trees.iter().filter(|(old, new)| old != new).count()
+        );
+        Ok(())
+    }
+}
+
+impl Drop for TreeCommand {
+    fn drop(&mut self) {
+        if !self.failed {
+            self.checkout.remove().ok();
+        }
+    }
+}
+
+/// Edits the tree of every commit as `command`, run with `sh -c`, asks, like
+/// `git filter-branch --index-filter`, without checking anything out.
+///
+/// The command gets the blobs and submodules of the tree on stdin, in `git ls-tree -r` lines,
+/// and prints the entries to change in the `git update-index --index-info` format: `<mode>
+/// <id>\t<path>` adds or replaces an entry, mode `0` removes the entry or directory at
+/// `<path>`. New content is written with `git hash-object -w`; `GIT_DIR` is set for it.
+pub(crate) struct IndexCommand {
+    command: String,
+    git_dir: PathBuf,
+    /// Where the command runs: the worktree, or the Git directory of a bare repository.
+    dir: PathBuf,
+    /// Resulting trees by the tree the command ran on.
+    trees: HashMap<ObjectId, ObjectId>,
+    runs: usize,
+}
+
+impl IndexCommand {
+    pub(crate) fn new(repo: &Repository, command: &str) -> Self {
+        IndexCommand {
+            command: command.to_owned(),
+            git_dir: repo.git_dir().to_owned(),
+            dir: repo.workdir().unwrap_or(repo.git_dir()).to_owned(),
+            trees: HashMap::new(),
+            runs: 0,
+        }
+    }
+
+    /// `tree` in `git ls-tree -r` lines, without trees.
+    fn listing(repo: &Repository, tree: ObjectId) -> AnyResult<Vec<u8>> {
+        let mut recorder = Recorder::default();
+        repo.find_tree(tree)?
+            .traverse()
+            .breadthfirst(&mut recorder)?;
+        let mut listing = Vec::new();
+        for entry in recorder
+            .records
+            .iter()
+            .filter(|entry| !entry.mode.is_tree())
+        {
+            if entry.filepath.contains(&b'\n') {
+                return Err(format!(
+                    "`{}` can't be listed for --index-filter, its path has a newline",
+                    entry.filepath
+                )
+                .into());
+            }
+            let kind = if entry.mode.is_commit() {
+                "commit"
+            } else {
+                "blob"
+            };
+            writeln!(
+                listing,
+                "{} {kind} {}\t{}",
+                entry.mode.kind().as_octal_str(),
+                entry.oid,
+                entry.filepath
+            )?;
+        }
+        Ok(listing)
+    }
+
+    /// Edit `tree` with the `--index-info` lines of `output`.
+    fn edit(
+        &self,
+        repo: &Repository,
+        descriptor: &CommitDescriptor,
+        output: &[u8],
+    ) -> AnyResult<ObjectId> {
+        let mut editor = repo.edit_tree(descriptor.tree)?;
+        for (number, line) in output.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let invalid = || {
+                format!(
+                    "`{}` printed `{}` for the tree of {}, on line {}; expected `<mode> <id>\t<path>`",
+                    self.command,
+                    line.as_bstr(),
+                    descriptor.original_id.to_hex_with_len(7),
+                    number + 1
+                )
+            };
+            let (fields, path) = line.split_once_str("\t").ok_or_else(invalid)?;
+            let fields: Vec<&[u8]> = fields.fields().collect();
+            let (mode, id) = match fields[..] {
+                [mode, id] | [mode, _, id] => (mode, id),
+                _ => return Err(invalid().into()),
+            };
+            let id = ObjectId::from_hex(id).map_err(|_| invalid())?;
+            if mode == b"0" {
+                editor.remove(path.as_bstr())?;
+                continue;
+            }
+            let kind = EntryMode::from_bytes(mode)
+                .filter(|mode| !mode.is_tree())
+                .ok_or_else(invalid)?
+                .kind();
+            if kind != EntryKind::Commit && !repo.has_object(id) {
+                return Err(format!(
+                    "`{}` printed {id} for `{}`, which isn't in the repository; write new \
+                     content with `git hash-object -w`",
+                    self.command,
+                    path.as_bstr()
+                )
+                .into());
+            }
+            editor.upsert(path.as_bstr(), kind, id)?;
+        }
+        Ok(editor.write()?.detach())
+    }
+}
+
+impl Filter for IndexCommand {
+    fn name(&self) -> String {
+        "--index-filter".into()
+    }
+
+    fn apply(&mut self, repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        if let Some(tree) = self.trees.get(&descriptor.tree) {
+            descriptor.tree = *tree;
+            return Ok(());
+        }
+        let listing = Self::listing(repo, descriptor.tree)?;
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .current_dir(&self.dir)
+            .env("GIT_DIR", &self.git_dir)
+            .env("MAGITULATOR_ORIGINAL", descriptor.original_id.to_string())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("running `{}`: {e}", self.command))?;
+        let mut stdin = child.stdin.take().ok_or("no stdin for the index filter")?;
+        let output = std::thread::scope(|scope| {
+            // Written alongside, as the command may print before it read everything, or exit
+            // without reading it at all.
+            scope.spawn(move || stdin.write_all(&listing).ok());
+            child.wait_with_output()
+        })?;
+        interrupt::check()?;
+        if !output.status.success() {
+            return Err(format!(
+                "`{}` failed on the tree of {}; no refs were updated",
+                self.command,
+                descriptor.original_id.to_hex_with_len(7)
+            )
+            .into());
+        }
+        self.runs += 1;
+        let tree = self.edit(repo, descriptor, &output.stdout)?;
+        self.trees.insert(descriptor.tree, tree);
+        descriptor.tree = tree;
+        Ok(())
+    }
+
+    fn finish(&mut self, _repo: &Repository, _map: &CommitMap) -> AnyResult<()> {
+        println!(
+            "Ran `{}` on {} tree(s), changing {}",
+            self.command,
+            self.runs,
+            self.trees.iter().filter(|(old, new)| old != new).count()
+        );
+        Ok(())
+    }
+}
+
+/// Write one tree entry, replacing whatever is at `path`. Directories are created for the
+/// blobs written into them, and submodules become empty directories.
+fn write_entry(repo: &Repository, path: &Path, mode: EntryMode, id: ObjectId) -> AnyResult<()> {
+    if mode.is_tree() || mode.is_commit() {
+        if !path.is_dir() {
+            remove(path)?;
+        }
+        if mode.is_commit() {
+            fs::create_dir_all(path)?;
+        }
+        return Ok(());
+    }
+    remove(path)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let data = &repo.find_blob(id)?.data;
+    if mode.is_link() {
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(
+            <std::ffi::OsStr as std::os::unix::ffi::OsStrExt>::from_bytes(data),
+            path,
+        )?;
+        #[cfg(not(unix))]
+        fs::write(path, data)?;
+        return Ok(());
+    }
+    fs::write(path, data)?;
+    #[cfg(unix)]
+    if mode.is_executable() {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+fn remove(path: &Path) -> AnyResult<()> {
+    let result = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => files::remove_dir_all(path),
+        Ok(_) => files::remove_file(path),
+        Err(e) => Err(e),
+    };
+    match result {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
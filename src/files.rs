@@ -0,0 +1,190 @@
+//! File system operations that behave on Windows as they do elsewhere.
+use crate::AnyResult;
+use gix::bstr::{BStr, ByteSlice};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Device names Windows reserves in every directory, with or without an extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Why Windows can't have a file called `name`, if it can't.
+fn invalid_on_windows(name: &str) -> Option<String> {
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        return Some(format!("`{stem}` is a reserved device name"));
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| r#"<>:"|?*\"#.contains(*c) || c.is_control())
+    {
+        return Some(format!("it contains `{}`", c.escape_debug()));
+    }
+    if name.ends_with(['.', ' ']) {
+        return Some("it ends with a dot or space".into());
+    }
+    None
+}
+
+/// The location of the tree entry `path` below `root`, built component by component so that no
+/// `/` is left for Windows to interpret. Fails for names this platform can't check out.
+pub(crate) fn checkout_path(root: &Path, path: &BStr) -> AnyResult<PathBuf> {
+    let mut location = root.to_owned();
+    for component in path.split_str("/") {
+        let component = component.to_str_lossy();
+        if component.is_empty() || component == "." || component == ".." {
+            return Err(format!("`{path}` isn't a path that can be checked out").into());
+        }
+        if cfg!(windows)
+            && let Some(reason) = invalid_on_windows(&component)
+        {
+            return Err(format!("`{path}` can't be checked out on Windows: {reason}").into());
+        }
+        location.push(component.as_ref());
+    }
+    Ok(location)
+}
+
+/// A name for scratch files and directories that no other one of this process or of another
+/// running process has: the pid, followed by a count from the second name on.
+pub(crate) fn unique_name() -> String {
+    static NAMES: AtomicUsize = AtomicUsize::new(0);
+    let pid = std::process::id();
+    match NAMES.fetch_add(1, Ordering::Relaxed) {
+        0 => pid.to_string(),
+        count => format!("{pid}-{count}"),
+    }
+}
+
+/// Write `data` to `path` through a temporary file next to it, so that readers see either the
+/// old content or all of the new one.
+pub(crate) fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(format!(".{}.tmp", unique_name()));
+    let temporary = PathBuf::from(temporary);
+    fs::write(&temporary, data)?;
+    fs::rename(&temporary, path).inspect_err(|_| {
+        fs::remove_file(&temporary).ok();
+    })
+}
+
+/// Remove a file, even one marked read-only.
+pub(crate) fn remove_file(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied && cfg!(windows) => {
+            make_writable(path)?;
+            fs::remove_file(path)
+        }
+        result => result,
+    }
+}
+
+/// Remove a directory and everything in it, like [`fs::remove_dir_all`].
+///
+/// Git writes objects and packs read-only, and Windows refuses to delete read-only files, so
+/// there they are made writable first.
+pub(crate) fn remove_dir_all(path: &Path) -> io::Result<()> {
+    match fs::remove_dir_all(path) {
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied && cfg!(windows) => {
+            make_all_writable(path)?;
+            fs::remove_dir_all(path)
+        }
+        result => result,
+    }
+}
+
+fn make_all_writable(dir: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            make_all_writable(&entry.path())?;
+        } else {
+            make_writable(&entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+fn make_writable(path: &Path) -> io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    // Only called on Windows, where this clears the read-only attribute rather than making the
+    // file world-writable.
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(false);
+    fs::set_permissions(path, permissions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserved_names_are_invalid_on_windows() {
+        for name in [
+            "CON",
+            "con",
+            "aux.txt",
+            "Nul.tar.gz",
+            "COM1",
+            "lpt9.log",
+            "CON .txt",
+        ] {
+            assert!(invalid_on_windows(name).is_some(), "{name}");
+        }
+        for name in [
+            "CONSOLE",
+            "aux_notes.txt",
+            "COM10",
+            "readme.md",
+            ".gitignore",
+        ] {
+            assert_eq!(invalid_on_windows(name), None, "{name}");
+        }
+    }
+
+    #[test]
+    fn trailing_dots_spaces_and_colons_are_invalid_on_windows() {
+        for name in ["notes.", "notes ", "a:b", "c:", "what?", "tab\there"] {
+            assert!(invalid_on_windows(name).is_some(), "{name:?}");
+        }
+    }
+
+    #[test]
+    fn checkout_path_rejects_paths_leaving_the_root() {
+        let root = Path::new("root");
+        assert_eq!(
+            checkout_path(root, "dir/file.txt".into()).unwrap(),
+            root.join("dir").join("file.txt")
+        );
+        for path in ["../outside", "dir/../../outside", "dir//file", "./file", ""] {
+            assert!(checkout_path(root, path.into()).is_err(), "{path:?}");
+        }
+        let reserved = checkout_path(root, "dir/aux.txt".into());
+        assert_eq!(reserved.is_err(), cfg!(windows));
+    }
+
+    #[test]
+    fn read_only_trees_are_removed() {
+        let dir = std::env::temp_dir().join(format!("magitulator-test-{}", unique_name()));
+        fs::create_dir_all(dir.join("objects").join("ab")).unwrap();
+        for file in [
+            dir.join("packed"),
+            dir.join("objects").join("ab").join("cdef"),
+        ] {
+            fs::write(&file, "object").unwrap();
+            let mut permissions = fs::metadata(&file).unwrap().permissions();
+            permissions.set_readonly(true);
+            fs::set_permissions(&file, permissions).unwrap();
+        }
+        remove_dir_all(&dir).unwrap();
+        assert!(!dir.exists());
+    }
+}
@@ -0,0 +1,1345 @@
+use crate::{
+    AnyResult, interrupt,
+    map::CommitMap,
+    metrics::{CacheStats, FilterMetrics},
+    mirror::CommitDescriptor,
+    policy, tree,
+};
+use colored::Colorize;
+use gix::{
+    ObjectId, Repository,
+    actor::Signature,
+    bstr::{BString, ByteSlice},
+};
+use regex::bytes::{Captures, Regex};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    path::Path,
+    str::FromStr,
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
+
+/// A single rewrite step applied to every selected commit before it is written.
+pub(crate) trait Filter {
+    /// The option enabling the filter, as `--profile-filters` shows it.
+    fn name(&self) -> String;
+
+    /// Called before the filter sees any commit, with the number of commits it will see.
+    fn prepare(&mut self, _commits: usize) {}
+
+    fn apply(&mut self, repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()>;
+
+    /// Called once the rewritten commits are written, with the original → new mapping.
+    fn finish(&mut self, _repo: &Repository, _map: &CommitMap) -> AnyResult<()> {
+        Ok(())
+    }
+
+    /// How well the filter's caches did, for `--metrics-out`; `None` without caches.
+    fn cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
+
+    /// Whether a run resuming from a `--time-budget` checkpoint, which starts with a new
+    /// filter, gets what one run would: false for filters numbering the commits they see or
+    /// judging them by what they did to earlier ones.
+    fn resumable(&self) -> bool {
+        true
+    }
+}
+
+/// Time one filter took over a run, and what it changed.
+#[derive(Default)]
+pub(crate) struct FilterProfile {
+    elapsed: Duration,
+    /// Commits whose tree, message, identities or headers the filter changed, or that it
+    /// dropped.
+    commits: usize,
+    /// Files the filter added, removed or changed, counted once per commit.
+    paths: usize,
+}
+
+/// The parts of a descriptor filters change, to tell what one filter did.
+struct Snapshot {
+    tree: ObjectId,
+    author: Signature,
+    committer: Signature,
+    encoding: Option<BString>,
+    message: BString,
+    extra_headers: Vec<(BString, BString)>,
+    dropped: bool,
+}
+
+impl Snapshot {
+    fn of(descriptor: &CommitDescriptor) -> Self {
+        Snapshot {
+            tree: descriptor.tree,
+            author: descriptor.author.clone(),
+            committer: descriptor.committer.clone(),
+            encoding: descriptor.encoding.clone(),
+            message: descriptor.message.clone(),
+            extra_headers: descriptor.extra_headers.clone(),
+            dropped: descriptor.dropped,
+        }
+    }
+
+    fn differs_from(&self, descriptor: &CommitDescriptor) -> bool {
+        self.tree != descriptor.tree
+            || self.author != descriptor.author
+            || self.committer != descriptor.committer
+            || self.encoding != descriptor.encoding
+            || self.message != descriptor.message
+            || self.extra_headers != descriptor.extra_headers
+            || self.dropped != descriptor.dropped
+    }
+}
+
+/// Run every filter on every descriptor to rewrite, in order. With `profiles`, also record
+/// the time each filter takes and what it changes there, one entry per filter.
+///
+/// Stops at `deadline` once at least one descriptor was filtered, returning how many were.
+pub(crate) fn apply_filters(
+    filters: &mut [Box<dyn Filter>],
+    repo: &Repository,
+    descriptors: &mut [CommitDescriptor],
+    mut profiles: Option<&mut Vec<FilterProfile>>,
+    deadline: Option<Instant>,
+) -> AnyResult<usize> {
+    if let Some(profiles) = profiles.as_deref_mut() {
+        profiles.resize_with(filters.len(), FilterProfile::default);
+    }
+    let commits = descriptors
+        .iter()
+        .filter(|d| !d.keep_original && !d.dropped)
+        .count();
+    for filter in filters.iter_mut() {
+        filter.prepare(commits);
+    }
+    for (count, descriptor) in descriptors.iter_mut().enumerate() {
+        if descriptor.keep_original || descriptor.dropped {
+            continue;
+        }
+        interrupt::check()?;
+        if count > 0 && deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Ok(count);
+        }
+        for (index, filter) in filters.iter_mut().enumerate() {
+            let Some(profiles) = profiles.as_deref_mut() else {
+                filter.apply(repo, descriptor)?;
+                continue;
+            };
+            let before = Snapshot::of(descriptor);
+            let started = Instant::now();
+            filter.apply(repo, descriptor)?;
+            let profile = &mut profiles[index];
+            profile.elapsed += started.elapsed();
+            if before.differs_from(descriptor) {
+                profile.commits += 1;
+            }
+            if before.tree != descriptor.tree {
+                profile.paths += tree::diff(repo, Some(before.tree), descriptor.tree)?
+                    .iter()
+                    .filter(|change| !change.entry_mode().is_tree())
+                    .count();
+            }
+        }
+    }
+    Ok(descriptors.len())
+}
+
+pub(crate) fn finish_filters(
+    filters: &mut [Box<dyn Filter>],
+    repo: &Repository,
+    map: &CommitMap,
+    mut profiles: Option<&mut Vec<FilterProfile>>,
+) -> AnyResult<()> {
+    for (index, filter) in filters.iter_mut().enumerate() {
+        let started = Instant::now();
+        filter.finish(repo, map)?;
+        if let Some(profile) = profiles.as_deref_mut().and_then(|p| p.get_mut(index)) {
+            profile.elapsed += started.elapsed();
+        }
+    }
+    Ok(())
+}
+
+/// Print the time and changes of each filter, in pipeline order.
+pub(crate) fn print_profiles(filters: &[Box<dyn Filter>], profiles: &[FilterProfile]) {
+    if filters.is_empty() {
+        println!("Filter profile: no filters ran");
+        return;
+    }
+    let names: Vec<String> = filters.iter().map(|filter| filter.name()).collect();
+    let width = names.iter().map(String::len).max().unwrap_or_default();
+    println!("Filter profile:");
+    for (name, profile) in names.iter().zip(profiles) {
+        println!(
+            "  {name:<width$}  {:>10}  {:>5} commit(s)  {:>6} path(s)",
+            format!("{:.1?}", profile.elapsed),
+            profile.commits,
+            profile.paths
+        );
+    }
+    let total: Duration = profiles.iter().map(|profile| profile.elapsed).sum();
+    println!("  {:<width$}  {:>10}", "total", format!("{total:.1?}"));
+}
+
+/// The time, changes and cache use of each filter, in pipeline order.
+pub(crate) fn metrics(
+    filters: &[Box<dyn Filter>],
+    profiles: &[FilterProfile],
+) -> Vec<FilterMetrics> {
+    filters
+        .iter()
+        .zip(profiles)
+        .map(|(filter, profile)| FilterMetrics {
+            name: filter.name(),
+            seconds: profile.elapsed.as_secs_f64(),
+            commits: profile.commits,
+            paths: profile.paths,
+            cache: filter.cache_stats().map(Into::into),
+        })
+        .collect()
+}
+
+/// Headers git itself writes; a rule must never produce one of these.
+const RESERVED_HEADERS: [&str; 8] = [
+    "tree",
+    "parent",
+    "author",
+    "committer",
+    "encoding",
+    "gpgsig",
+    "gpgsig-sha256",
+    "mergetag",
+];
+
+/// Whether `name` is one of [`RESERVED_HEADERS`] in any case, which would be just as confusing
+/// next to git's own.
+fn is_reserved_header(name: &str) -> bool {
+    RESERVED_HEADERS
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(name))
+}
+
+/// `<header>=<regex>`: when the message matches, set `<header>` to the first
+/// capture group (or the whole match if the regex has no groups).
+#[derive(Clone, Debug)]
+pub struct HeaderRule {
+    header: BString,
+    pattern: Regex,
+}
+
+impl FromStr for HeaderRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (header, pattern) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected `<header>=<regex>`, got `{s}`"))?;
+
+        if header.is_empty() || header.chars().any(|c| c.is_whitespace() || c == ':') {
+            return Err(format!("invalid header name `{header}`"));
+        }
+        if is_reserved_header(header) {
+            return Err(format!("header `{header}` is reserved by git"));
+        }
+
+        let pattern = Regex::new(pattern).map_err(|e| e.to_string())?;
+
+        Ok(HeaderRule {
+            header: header.into(),
+            pattern,
+        })
+    }
+}
+
+impl fmt::Display for HeaderRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.header, self.pattern)
+    }
+}
+
+impl Filter for HeaderRule {
+    fn name(&self) -> String {
+        format!("--derive-header {}", self.header)
+    }
+
+    fn apply(&mut self, _repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        let Some(captures) = self.pattern.captures(&descriptor.message) else {
+            return Ok(());
+        };
+        let value = captures
+            .get(1)
+            .or_else(|| captures.get(0))
+            .map(|m| m.as_bytes().trim())
+            .unwrap_or_default();
+
+        // Header values are single-line; only the first line of a multi-line capture is kept.
+        let value: BString = value.lines().next().unwrap_or_default().into();
+        if value.is_empty() {
+            return Ok(());
+        }
+
+        match descriptor
+            .extra_headers
+            .iter_mut()
+            .find(|(key, _)| *key == self.header)
+        {
+            Some((_, existing)) => *existing = value,
+            None => descriptor.extra_headers.push((self.header.clone(), value)),
+        }
+
+        Ok(())
+    }
+}
+
+/// Rewrites issue references from an old tracker to a new one, keeping surrounding text.
+///
+/// Mappings come from a CSV file with one `old-id,new` pair per line, where `new` is an id or
+/// a URL. Both bare ids and links ending in an id (`https://jira.example.com/browse/PROJ-1`)
+/// are replaced.
+pub(crate) struct IssueMap {
+    pattern: Regex,
+    mapping: HashMap<BString, BString>,
+}
+
+impl IssueMap {
+    pub(crate) fn load(path: &Path) -> AnyResult<Self> {
+        let mut mapping = HashMap::new();
+        for (index, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((old, new)) = line.split_once(',') else {
+                return Err(format!("{}:{}: expected `old,new`", path.display(), index + 1).into());
+            };
+            let unquote = |field: &str| field.trim().trim_matches('"').to_owned();
+            mapping.insert(unquote(old).into(), unquote(new).into());
+        }
+        if mapping.is_empty() {
+            return Err(format!("{}: no issue mappings", path.display()).into());
+        }
+
+        // Longest ids first, so `PROJ-12` never shadows `PROJ-123`.
+        let mut ids: Vec<&BString> = mapping.keys().collect();
+        ids.sort_by(|a, b| b.len().cmp(&a.len()).then(a.cmp(b)));
+        let alternatives: Vec<String> = ids
+            .iter()
+            .map(|id| regex::escape(&id.to_str_lossy()))
+            .collect();
+        let pattern = Regex::new(&format!(
+            r"(?:https?://[^\s/]+(?:/[^\s/]+)*/)?\b({})\b",
+            alternatives.join("|")
+        ))?;
+
+        Ok(IssueMap { pattern, mapping })
+    }
+}
+
+impl Filter for IssueMap {
+    fn name(&self) -> String {
+        "--issue-map".into()
+    }
+
+    fn apply(&mut self, _repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        let replaced = self
+            .pattern
+            .replace_all(&descriptor.message, |captures: &Captures<'_>| {
+                self.mapping[captures[1].as_bstr()].to_vec()
+            });
+        if let std::borrow::Cow::Owned(message) = replaced {
+            descriptor.message = message.into();
+        }
+        Ok(())
+    }
+}
+
+/// `<regex>==><replacement>`: replace every match in the message, where the replacement may
+/// refer to capture groups as `$1` or `${name}`.
+#[derive(Clone, Debug)]
+pub struct MessageReplacement {
+    pattern: Regex,
+    replacement: BString,
+}
+
+impl FromStr for MessageReplacement {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, replacement) = s
+            .split_once("==>")
+            .ok_or_else(|| format!("expected `<regex>==><replacement>`, got `{s}`"))?;
+        if pattern.is_empty() {
+            return Err("the regex of a replacement can't be empty".into());
+        }
+        Ok(MessageReplacement {
+            pattern: Regex::new(pattern).map_err(|e| e.to_string())?,
+            replacement: replacement.into(),
+        })
+    }
+}
+
+impl fmt::Display for MessageReplacement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}==>{}", self.pattern, self.replacement)
+    }
+}
+
+impl Filter for MessageReplacement {
+    fn name(&self) -> String {
+        format!("--replace-message {self}")
+    }
+
+    fn apply(&mut self, _repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        let replaced = self
+            .pattern
+            .replace_all(&descriptor.message, self.replacement.as_slice());
+        if let std::borrow::Cow::Owned(message) = replaced {
+            descriptor.message = message.into();
+        }
+        Ok(())
+    }
+}
+
+/// Placeholders of `--message-template`.
+const TEMPLATE_PLACEHOLDERS: [&str; 11] = [
+    "message",
+    "subject",
+    "body",
+    "original_message",
+    "original_subject",
+    "original_id",
+    "original_id_short",
+    "author_name",
+    "author_email",
+    "index",
+    "total",
+];
+
+/// Rebuilds every message from a template with `{{placeholder}}`s for the message as the
+/// filters before it left it, and for the original commit.
+pub(crate) struct MessageTemplate {
+    template: String,
+    /// Whether the template has `{{index}}` or `{{total}}`.
+    numbered: bool,
+    index: usize,
+    total: usize,
+}
+
+/// The placeholders of `template`, an error naming `option` if one is unclosed or not in
+/// `known`.
+fn placeholders<'a>(option: &str, template: &'a str, known: &[&str]) -> AnyResult<Vec<&'a str>> {
+    let mut names = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let end = rest[start..]
+            .find("}}")
+            .ok_or_else(|| format!("{option}: unclosed `{{{{` in `{template}`"))?;
+        let name = rest[start + 2..start + end].trim();
+        if !known.contains(&name) {
+            return Err(format!(
+                "{option}: unknown placeholder `{{{{{name}}}}}`; known are {}",
+                known.join(", ")
+            )
+            .into());
+        }
+        names.push(name);
+        rest = &rest[start + end + 2..];
+    }
+    Ok(names)
+}
+
+/// `template`, checked by [`placeholders`], with each placeholder replaced by `value` of its
+/// name, ending in a newline.
+fn render(template: &str, mut value: impl FnMut(&str) -> String) -> String {
+    let mut message = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        message.push_str(&rest[..start]);
+        let end = rest[start..].find("}}").expect("checked by `placeholders`");
+        message.push_str(&value(rest[start + 2..start + end].trim()));
+        rest = &rest[start + end + 2..];
+    }
+    message.push_str(rest);
+    if !message.ends_with('\n') {
+        message.push('\n');
+    }
+    message
+}
+
+impl MessageTemplate {
+    pub(crate) fn new(template: &str) -> AnyResult<Self> {
+        let numbered = placeholders("--message-template", template, &TEMPLATE_PLACEHOLDERS)?
+            .iter()
+            .any(|name| *name == "index" || *name == "total");
+        Ok(MessageTemplate {
+            template: template.to_owned(),
+            numbered,
+            index: 0,
+            total: 0,
+        })
+    }
+}
+
+/// The first line of `message`, and what follows the blank line after it.
+fn subject_and_body(message: &[u8]) -> (String, String) {
+    let message = message.to_str_lossy();
+    let (subject, body) = message.split_once('\n').unwrap_or((&message, ""));
+    (
+        subject.trim_end().to_owned(),
+        body.trim_start_matches('\n').to_owned(),
+    )
+}
+
+impl Filter for MessageTemplate {
+    fn name(&self) -> String {
+        "--message-template".into()
+    }
+
+    fn prepare(&mut self, commits: usize) {
+        self.index = 0;
+        self.total = commits;
+    }
+
+    fn resumable(&self) -> bool {
+        !self.numbered
+    }
+
+    fn apply(&mut self, repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        self.index += 1;
+        // Commits synthesized by the rewrite have no original of their own.
+        let original = repo.find_commit(descriptor.original_id).ok();
+        let original_message: BString = match &original {
+            Some(commit) => commit.message_raw()?.into(),
+            None => descriptor.message.clone(),
+        };
+        let (author_name, author_email) = match &original {
+            Some(commit) => {
+                let author = commit.author()?;
+                (author.name.to_string(), author.email.to_string())
+            }
+            None => (
+                descriptor.author.name.to_string(),
+                descriptor.author.email.to_string(),
+            ),
+        };
+        let (subject, body) = subject_and_body(&descriptor.message);
+        let (original_subject, _) = subject_and_body(&original_message);
+
+        let message = render(&self.template, |name| match name {
+            "message" => descriptor.message.to_str_lossy().into_owned(),
+            "subject" => subject.clone(),
+            "body" => body.clone(),
+            "original_message" => original_message.to_str_lossy().into_owned(),
+            "original_subject" => original_subject.clone(),
+            "original_id" => descriptor.original_id.to_string(),
+            "original_id_short" => descriptor.original_id.to_hex_with_len(7).to_string(),
+            "author_name" => author_name.clone(),
+            "author_email" => author_email.clone(),
+            "index" => self.index.to_string(),
+            "total" => self.total.to_string(),
+            _ => unreachable!("checked by `new`"),
+        });
+        descriptor.message = message.into();
+        Ok(())
+    }
+}
+
+/// Placeholders of `--merge-message-template`.
+const MERGE_PLACEHOLDERS: [&str; 6] = [
+    "branch",
+    "merged_id",
+    "merged_id_short",
+    "subject",
+    "body",
+    "original_subject",
+];
+
+/// `<old>=<new>`: the name a merged branch goes by in regenerated merge messages.
+#[derive(Clone, Debug)]
+pub struct BranchRename {
+    from: String,
+    to: String,
+}
+
+impl FromStr for BranchRename {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((from, to)) if !from.trim().is_empty() && !to.trim().is_empty() => {
+                Ok(BranchRename {
+                    from: from.trim().to_owned(),
+                    to: to.trim().to_owned(),
+                })
+            }
+            _ => Err(format!("expected `<old>=<new>`, got `{s}`")),
+        }
+    }
+}
+
+impl fmt::Display for BranchRename {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.from, self.to)
+    }
+}
+
+/// The branches the subjects of `git merge`, `git pull` and GitHub name: `Merge branch 'x' of
+/// <url> into y`, `Merge branches 'x' and 'y'`, `Merge remote-tracking branch 'origin/x'`,
+/// `Merge pull request #1 from owner/x`.
+static MERGE_SUBJECT: LazyLock<regex::Regex> = LazyLock::new(|| {
+    regex::Regex::new(
+        "^Merge (?:(remote-tracking )?branch(?:es)? (.+?)(?: of \\S+)?(?: into \\S+)?|pull \
+         request #\\d+ from [^/\\s]+/(\\S+))$",
+    )
+    .expect("valid regex")
+});
+
+/// Rebuilds the messages of merge commits from a template naming the merged branches, for
+/// subjects like `Merge branch 'x' of internal.host` that give away where a history came from.
+///
+/// Branches are named as the original subject names them, without the remote of
+/// remote-tracking ones, or else as the local branches pointing at the merged commits, and
+/// renamed by the [`BranchRename`]s.
+pub(crate) struct MergeMessages {
+    template: String,
+    renames: Vec<BranchRename>,
+    /// Local branches by the commit they point at, read on the first merge.
+    branches: Option<HashMap<ObjectId, String>>,
+    changed: usize,
+}
+
+impl MergeMessages {
+    pub(crate) fn new(template: &str, renames: Vec<BranchRename>) -> AnyResult<Self> {
+        placeholders("--merge-message-template", template, &MERGE_PLACEHOLDERS)?;
+        Ok(MergeMessages {
+            template: template.to_owned(),
+            renames,
+            branches: None,
+            changed: 0,
+        })
+    }
+
+    fn rename(&self, branch: &str) -> String {
+        self.renames
+            .iter()
+            .find(|rename| rename.from == branch)
+            .map_or(branch, |rename| &rename.to)
+            .to_owned()
+    }
+
+    /// The branches merged by a commit with `subject` and `parents`, renamed.
+    fn merged_branches(
+        &mut self,
+        repo: &Repository,
+        subject: &str,
+        parents: &[ObjectId],
+    ) -> AnyResult<Vec<String>> {
+        if let Some(captures) = MERGE_SUBJECT.captures(subject) {
+            let remote = captures.get(1).is_some();
+            let names: Vec<&str> = match captures.get(2) {
+                Some(list) => list
+                    .as_str()
+                    .split(['\'', ','])
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty() && *name != "and")
+                    .collect(),
+                None => vec![&captures[3]],
+            };
+            if !names.is_empty() {
+                return Ok(names
+                    .iter()
+                    .map(|name| match name.split_once('/') {
+                        Some((_, branch)) if remote => self.rename(branch),
+                        _ => self.rename(name),
+                    })
+                    .collect());
+            }
+        }
+        if self.branches.is_none() {
+            let mut branches = HashMap::new();
+            for reference in repo.references()?.local_branches()? {
+                let mut reference = reference?;
+                let name = reference.name().shorten().to_string();
+                if let Ok(id) = reference.peel_to_id_in_place() {
+                    branches.entry(id.detach()).or_insert(name);
+                }
+            }
+            self.branches = Some(branches);
+        }
+        let branches = self.branches.as_ref().expect("read above");
+        Ok(parents
+            .iter()
+            .skip(1)
+            .map(|parent| match branches.get(parent) {
+                Some(branch) => self.rename(branch),
+                None => parent.to_hex_with_len(7).to_string(),
+            })
+            .collect())
+    }
+}
+
+impl Filter for MergeMessages {
+    fn name(&self) -> String {
+        "--merge-message-template".into()
+    }
+
+    fn apply(&mut self, repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        let parents = descriptor.original_parent_ids.clone();
+        if parents.len() < 2 {
+            return Ok(());
+        }
+        let original_subject = match repo.find_commit(descriptor.original_id) {
+            Ok(commit) => subject_and_body(commit.message_raw()?).0,
+            Err(_) => subject_and_body(&descriptor.message).0,
+        };
+        let branches = self.merged_branches(repo, &original_subject, &parents)?;
+        let (subject, body) = subject_and_body(&descriptor.message);
+        let merged = &parents[1..];
+        let ids = |len: usize| {
+            merged
+                .iter()
+                .map(|id| id.to_hex_with_len(len).to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let message = render(&self.template, |name| match name {
+            "branch" => branches.join(", "),
+            "merged_id" => ids(40),
+            "merged_id_short" => ids(7),
+            "subject" => subject.clone(),
+            "body" => body.clone(),
+            "original_subject" => original_subject.clone(),
+            _ => unreachable!("checked by `new`"),
+        });
+        if message.as_bytes() != descriptor.message.as_slice() {
+            descriptor.message = message.into();
+            self.changed += 1;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, _repo: &Repository, _map: &CommitMap) -> AnyResult<()> {
+        println!("Regenerated the messages of {} merge(s)", self.changed);
+        Ok(())
+    }
+}
+
+/// Reorders the parents of two-parent merges, for imports that recorded them the wrong way round.
+pub(crate) struct ParentOrder {
+    listed: HashSet<ObjectId>,
+    auto: bool,
+    swapped: HashSet<ObjectId>,
+}
+
+impl ParentOrder {
+    pub(crate) fn new(listed: HashSet<ObjectId>, auto: bool) -> Self {
+        ParentOrder {
+            listed,
+            auto,
+            swapped: HashSet::new(),
+        }
+    }
+
+    /// The first parent of a merge is usually the mainline, which had more going on since the
+    /// fork than the branch merged into it. Swap when the second parent holds more of the
+    /// commits that are unique to either side.
+    fn looks_swapped(repo: &Repository, first: ObjectId, second: ObjectId) -> AnyResult<bool> {
+        let unique = |tip: ObjectId, hidden: ObjectId| -> AnyResult<usize> {
+            Ok(repo.rev_walk([tip]).with_hidden([hidden]).all()?.count())
+        };
+        Ok(unique(second, first)? > unique(first, second)?)
+    }
+}
+
+impl Filter for ParentOrder {
+    fn name(&self) -> String {
+        "--swap-parents".into()
+    }
+
+    fn apply(&mut self, repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        let id = descriptor.original_id;
+        let listed = self.listed.contains(&id);
+        let [first, second] = descriptor.original_parent_ids[..] else {
+            if listed {
+                return Err(format!("--swap-parents: {id} is not a merge of two parents").into());
+            }
+            return Ok(());
+        };
+
+        if listed || (self.auto && Self::looks_swapped(repo, first, second)?) {
+            descriptor.original_parent_ids.swap(0, 1);
+            self.swapped.insert(id);
+            let subject = descriptor.message.lines().next().unwrap_or_default();
+            println!(
+                "{} {} {}",
+                "swapped parents:".yellow(),
+                id.to_hex_with_len(7),
+                subject.to_str_lossy()
+            );
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, _repo: &Repository, _map: &CommitMap) -> AnyResult<()> {
+        for id in self.listed.difference(&self.swapped) {
+            eprintln!(
+                "{} --swap-parents {id} was not rewritten",
+                "warning:".yellow()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// What happens to a trailer with a given key.
+#[derive(Clone, Debug)]
+pub enum TrailerAction {
+    Delete,
+    /// Move the trailer's value into this extra header.
+    Header(BString),
+}
+
+/// `<key>=delete`, `<key>=header` or `<key>=header:<name>`: delete trailers with `<key>` from
+/// the end of messages, or move them into an extra header named `<name>` (default: the key
+/// in lowercase).
+#[derive(Clone, Debug)]
+pub struct TrailerRule {
+    key: String,
+    action: TrailerAction,
+}
+
+impl TrailerRule {
+    pub fn delete(key: &str) -> Self {
+        TrailerRule {
+            key: key.to_owned(),
+            action: TrailerAction::Delete,
+        }
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+}
+
+impl FromStr for TrailerRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, action) = s.split_once('=').ok_or_else(|| {
+            format!("expected `<key>=delete` or `<key>=header[:<name>]`, got `{s}`")
+        })?;
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(format!("invalid trailer key `{key}`"));
+        }
+
+        let action = match action.split_once(':') {
+            None if action == "delete" => TrailerAction::Delete,
+            None if action == "header" => TrailerAction::Header(key.to_lowercase().into()),
+            Some(("header", name)) => {
+                if name.is_empty() || name.chars().any(|c| c.is_whitespace() || c == ':') {
+                    return Err(format!("invalid header name `{name}`"));
+                }
+                TrailerAction::Header(name.into())
+            }
+            _ => return Err(format!("unknown trailer action `{action}`")),
+        };
+        if let TrailerAction::Header(name) = &action
+            && is_reserved_header(&name.to_str_lossy())
+        {
+            return Err(format!("header `{name}` is reserved by git"));
+        }
+
+        Ok(TrailerRule {
+            key: key.to_owned(),
+            action,
+        })
+    }
+}
+
+impl fmt::Display for TrailerRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.action {
+            TrailerAction::Delete => write!(f, "{}=delete", self.key),
+            TrailerAction::Header(name) => write!(f, "{}=header:{name}", self.key),
+        }
+    }
+}
+
+/// Applies [`TrailerRule`]s to the trailer block at the end of messages.
+pub(crate) struct Trailers {
+    rules: Vec<TrailerRule>,
+}
+
+impl Trailers {
+    pub(crate) fn new(rules: Vec<TrailerRule>) -> Self {
+        Trailers { rules }
+    }
+}
+
+impl Filter for Trailers {
+    fn name(&self) -> String {
+        "--trailer".into()
+    }
+
+    fn apply(&mut self, _repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        let (body, trailers) = policy::split_trailers(&descriptor.message);
+        if trailers.is_empty() {
+            return Ok(());
+        }
+
+        let mut kept: Vec<&[u8]> = Vec::new();
+        let mut headers: Vec<(BString, BString)> = Vec::new();
+        for line in trailers.lines() {
+            let (key, value) = line.split_once_str(":").unwrap_or((line, b""));
+            let rule = self
+                .rules
+                .iter()
+                .find(|rule| rule.key.as_bytes().eq_ignore_ascii_case(key));
+            match rule.map(|rule| &rule.action) {
+                None => kept.push(line),
+                Some(TrailerAction::Delete) => {}
+                Some(TrailerAction::Header(name)) => {
+                    headers.push((name.clone(), value.trim().into()));
+                }
+            }
+        }
+        if kept.len() == trailers.lines().count() {
+            return Ok(());
+        }
+
+        let mut message = BString::from(body);
+        if !kept.is_empty() {
+            message.extend_from_slice(b"\n\n");
+            message.extend_from_slice(&kept.join(&b'\n'));
+        }
+        message.push(b'\n');
+        descriptor.message = message;
+
+        for (name, value) in headers {
+            match descriptor
+                .extra_headers
+                .iter_mut()
+                .find(|(key, _)| *key == name)
+            {
+                Some((_, existing)) => *existing = value,
+                None => descriptor.extra_headers.push((name, value)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `<key>: <value>`: a trailer to add to the end of every message that doesn't have it yet.
+/// `{{original_id}}` in the value stands for the id of the original commit.
+#[derive(Clone, Debug)]
+pub struct AddedTrailer {
+    key: String,
+    value: String,
+    /// Whether a trailer with the same key and any value is enough.
+    any_value: bool,
+}
+
+impl AddedTrailer {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Only add the trailer to messages without any trailer of its key.
+    pub(crate) fn unless_key_present(self) -> Self {
+        AddedTrailer {
+            any_value: true,
+            ..self
+        }
+    }
+}
+
+impl FromStr for AddedTrailer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, value) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected `<key>: <value>`, got `{s}`"))?;
+        let (key, value) = (key.trim(), value.trim());
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(format!("invalid trailer key `{key}`"));
+        }
+        if value.is_empty() || value.contains('\n') {
+            return Err(format!(
+                "the value of `{key}` must be a single non-empty line"
+            ));
+        }
+        Ok(AddedTrailer {
+            key: key.to_owned(),
+            value: value.to_owned(),
+            any_value: false,
+        })
+    }
+}
+
+impl fmt::Display for AddedTrailer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.key, self.value)
+    }
+}
+
+impl Filter for AddedTrailer {
+    fn name(&self) -> String {
+        match self.any_value {
+            true => format!("required trailer {}", self.key),
+            false => format!("--add-trailer {self}"),
+        }
+    }
+
+    fn apply(&mut self, _repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        let value = self
+            .value
+            .replace("{{original_id}}", &descriptor.original_id.to_string());
+        let (body, trailers) = policy::split_trailers(&descriptor.message);
+        // Keys compare like git's, ignoring case; values must match exactly.
+        let present = trailers.lines().any(|line| {
+            line.split_once_str(":").is_some_and(|(key, existing)| {
+                key.eq_ignore_ascii_case(self.key.as_bytes())
+                    && (self.any_value || existing.trim() == value.as_bytes())
+            })
+        });
+        if present {
+            return Ok(());
+        }
+
+        let mut message = BString::from(body);
+        if !message.is_empty() {
+            message.extend_from_slice(b"\n\n");
+        }
+        if !trailers.is_empty() {
+            message.extend_from_slice(trailers);
+            message.push(b'\n');
+        }
+        message.extend_from_slice(format!("{}: {value}\n", self.key).as_bytes());
+        descriptor.message = message;
+        Ok(())
+    }
+}
+
+/// Gives signatures recorded without a timezone (`+0000`) the given offset, keeping the instant.
+pub(crate) struct DefaultTimezone {
+    offset: i32,
+}
+
+impl DefaultTimezone {
+    pub(crate) fn new(offset: i32) -> Self {
+        DefaultTimezone { offset }
+    }
+}
+
+impl Filter for DefaultTimezone {
+    fn name(&self) -> String {
+        "--default-timezone".into()
+    }
+
+    fn apply(&mut self, _repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        for signature in [&mut descriptor.author, &mut descriptor.committer] {
+            if signature.time.offset == 0 {
+                signature.time.offset = self.offset;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Moves author and committer dates by the same amount, keeping their timezones.
+pub(crate) struct ShiftDates {
+    seconds: i64,
+}
+
+impl ShiftDates {
+    pub(crate) fn new(seconds: i64) -> Self {
+        ShiftDates { seconds }
+    }
+}
+
+impl Filter for ShiftDates {
+    fn name(&self) -> String {
+        "--shift-dates".into()
+    }
+
+    fn apply(&mut self, _repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        for signature in [&mut descriptor.author, &mut descriptor.committer] {
+            signature.time.seconds += self.seconds;
+        }
+        Ok(())
+    }
+}
+
+/// Gives the rewritten commits evenly spaced dates from `start` to `end`, in the order they
+/// are rewritten, so parents keep coming before their children.
+pub(crate) struct SpreadDates {
+    start: i64,
+    end: i64,
+    index: usize,
+    total: usize,
+}
+
+impl SpreadDates {
+    pub(crate) fn new(start: i64, end: i64) -> Self {
+        SpreadDates {
+            start,
+            end,
+            index: 0,
+            total: 0,
+        }
+    }
+}
+
+impl Filter for SpreadDates {
+    fn name(&self) -> String {
+        "--spread-dates".into()
+    }
+
+    fn prepare(&mut self, commits: usize) {
+        self.index = 0;
+        self.total = commits;
+    }
+
+    fn resumable(&self) -> bool {
+        false
+    }
+
+    fn apply(&mut self, _repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        // Integer steps: the last commit lands exactly on `end`, and ties can't reorder anything.
+        let steps = self.total.saturating_sub(1).max(1) as i128;
+        let offset = i128::from(self.end - self.start) * self.index as i128 / steps;
+        let seconds = self.start + offset as i64;
+        for signature in [&mut descriptor.author, &mut descriptor.committer] {
+            signature.time.seconds = seconds;
+        }
+        self.index += 1;
+        Ok(())
+    }
+}
+
+/// A seed for [`Jitter`] when none was given, printed so that the run can be repeated.
+pub(crate) fn random_seed() -> u64 {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let seed = now.as_nanos() as u64 ^ u64::from(std::process::id());
+    println!("Jittering dates with seed {seed}; pass --seed {seed} to repeat it");
+    seed
+}
+
+/// Adds noise of up to `seconds` either way to author and committer dates, the same for every
+/// run with the same seed.
+///
+/// The noise changes smoothly with the date: it is random at knots `4 * seconds` apart and
+/// linear in between, so a date moves by less than half as much as time passes, and no date
+/// can overtake another. Order and equal dates survive, and a commit is never committed before
+/// it was written.
+pub(crate) struct Jitter {
+    seconds: i64,
+    seed: u64,
+}
+
+impl Jitter {
+    pub(crate) fn new(seconds: i64, seed: u64) -> Self {
+        Jitter { seconds, seed }
+    }
+
+    /// The noise at `knot`, from splitmix64 of the seed and the knot.
+    fn noise_at(&self, knot: i64) -> i64 {
+        let mut z = self.seed ^ (knot as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z % (2 * self.seconds as u64 + 1)) as i64 - self.seconds
+    }
+
+    fn jitter(&self, seconds: i64) -> i64 {
+        let spacing = 4 * self.seconds;
+        let knot = seconds.div_euclid(spacing);
+        let (from, to) = (self.noise_at(knot), self.noise_at(knot + 1));
+        let between = i128::from(to - from) * i128::from(seconds.rem_euclid(spacing));
+        seconds + from + between.div_euclid(i128::from(spacing)) as i64
+    }
+}
+
+impl Filter for Jitter {
+    fn name(&self) -> String {
+        format!("--jitter {}", self.seconds)
+    }
+
+    fn apply(&mut self, _repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        for signature in [&mut descriptor.author, &mut descriptor.committer] {
+            signature.time.seconds = self.jitter(signature.time.seconds);
+        }
+        Ok(())
+    }
+}
+
+/// Which date of a commit gives both of its signatures their date.
+#[derive(Clone, Copy, Debug)]
+pub enum DateCoupling {
+    /// `--author-date-is-committer-date`.
+    CommitterDate,
+    /// `--committer-date-is-author-date`.
+    AuthorDate,
+}
+
+/// Gives author and committer the same date, offset included, like the options of `git rebase`
+/// of the same names.
+pub(crate) struct CoupleDates {
+    coupling: DateCoupling,
+}
+
+impl CoupleDates {
+    pub(crate) fn new(coupling: DateCoupling) -> Self {
+        CoupleDates { coupling }
+    }
+}
+
+impl Filter for CoupleDates {
+    fn name(&self) -> String {
+        match self.coupling {
+            DateCoupling::CommitterDate => "--author-date-is-committer-date".into(),
+            DateCoupling::AuthorDate => "--committer-date-is-author-date".into(),
+        }
+    }
+
+    fn apply(&mut self, _repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        match self.coupling {
+            DateCoupling::CommitterDate => descriptor.author.time = descriptor.committer.time,
+            DateCoupling::AuthorDate => descriptor.committer.time = descriptor.author.time,
+        }
+        Ok(())
+    }
+}
+
+/// Records author and committer dates in the given offset, keeping the instant.
+pub(crate) struct SetTimezone {
+    offset: i32,
+}
+
+impl SetTimezone {
+    pub(crate) fn new(offset: i32) -> Self {
+        SetTimezone { offset }
+    }
+}
+
+impl Filter for SetTimezone {
+    fn name(&self) -> String {
+        "--set-timezone".into()
+    }
+
+    fn apply(&mut self, _repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        for signature in [&mut descriptor.author, &mut descriptor.committer] {
+            signature.time.offset = self.offset;
+        }
+        Ok(())
+    }
+}
+
+/// Drops commits that don't change the tree of their only parent, like the commits SVN
+/// conversions produce for branch and tag creation, or those whose only changes the other
+/// filters removed.
+///
+/// Runs after the other filters and compares trees as they left them: the parent's is the one
+/// this filter saw last for it, or its original tree for parents that aren't rewritten.
+#[derive(Default)]
+pub(crate) struct DropEmpty {
+    /// Filtered trees by original commit.
+    trees: HashMap<ObjectId, ObjectId>,
+}
+
+impl Filter for DropEmpty {
+    fn name(&self) -> String {
+        "--drop-empty".into()
+    }
+
+    // The filtered trees of commits handled by an earlier run are gone.
+    fn resumable(&self) -> bool {
+        false
+    }
+
+    fn apply(&mut self, repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        self.trees.insert(descriptor.original_id, descriptor.tree);
+        let [parent] = descriptor.original_parent_ids[..] else {
+            return Ok(());
+        };
+        let parent_tree = match self.trees.get(&parent) {
+            Some(tree) => *tree,
+            None => match repo.find_commit(parent) {
+                Ok(parent) => parent.tree_id()?.detach(),
+                Err(_) => return Ok(()),
+            },
+        };
+        if parent_tree == descriptor.tree {
+            descriptor.dropped = true;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mirror::{self, MirrorOptions},
+        refs::MirrorNaming,
+        testing::{git, scratch_repo},
+    };
+
+    #[test]
+    fn reserved_headers_are_rejected_in_any_case() {
+        for rule in ["tree=x", "Tree=x", "PARENT=x", "GpgSig=x"] {
+            let error = rule.parse::<HeaderRule>().unwrap_err();
+            assert!(error.contains("reserved"), "{rule}: {error}");
+        }
+        assert!("X-Ticket=x".parse::<HeaderRule>().is_ok());
+        let error = "Signed-off-by=header:Author"
+            .parse::<TrailerRule>()
+            .unwrap_err();
+        assert!(error.contains("reserved"), "{error}");
+    }
+
+    #[test]
+    fn drop_empty_drops_commits_emptied_by_path_filters() {
+        let dir = scratch_repo();
+        std::fs::write(dir.join("a.txt"), "a\n").unwrap();
+        git(&dir, &["add", "a.txt"]);
+        git(&dir, &["commit", "-q", "-m", "base"]);
+        git(&dir, &["checkout", "-q", "-b", "dev"]);
+        std::fs::write(dir.join("secret.key"), "hunter2\n").unwrap();
+        git(&dir, &["add", "secret.key"]);
+        git(&dir, &["commit", "-q", "-m", "add the key"]);
+        std::fs::write(dir.join("a.txt"), "b\n").unwrap();
+        git(&dir, &["commit", "-q", "-am", "change a"]);
+        git(&dir, &["checkout", "-q", "main"]);
+
+        let repo = gix::open(&dir).unwrap();
+        let options = MirrorOptions {
+            remove_paths: vec!["secret.key".parse().unwrap()],
+            drop_empty: true,
+            ..MirrorOptions::default()
+        };
+        mirror::mirror(&repo, "main", "dev", &options).unwrap();
+
+        let tip = repo
+            .find_reference(MirrorNaming::default().mirror_ref("dev").as_str())
+            .unwrap()
+            .peel_to_commit()
+            .unwrap();
+        assert_eq!(tip.message_raw().unwrap(), "change a\n");
+        let parent = tip.parent_ids().next().unwrap();
+        let base = repo.rev_parse_single("main").unwrap();
+        assert_eq!(
+            parent, base,
+            "the commit that only added the key is dropped"
+        );
+        crate::files::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,253 @@
+//! Normalizing gitmoji and other emoji at the start of commit subjects.
+use crate::{AnyResult, filter::Filter, map::CommitMap, mirror::CommitDescriptor};
+use colored::Colorize;
+use gix::{
+    Repository,
+    bstr::{BString, ByteSlice},
+};
+use regex::Regex;
+use std::{collections::BTreeMap, fmt, str::FromStr, sync::LazyLock};
+
+/// Gitmoji as emoji, shortcode and the Conventional Commit type closest to their meaning.
+const GITMOJI: [(&str, &str, &str); 45] = [
+    ("✨", "sparkles", "feat"),
+    ("🌐", "globe_with_meridians", "feat"),
+    ("💄", "lipstick", "feat"),
+    ("🚸", "children_crossing", "feat"),
+    ("♿\u{FE0F}", "wheelchair", "feat"),
+    ("🐛", "bug", "fix"),
+    ("🚑\u{FE0F}", "ambulance", "fix"),
+    ("🩹", "adhesive_bandage", "fix"),
+    ("🔒\u{FE0F}", "lock", "fix"),
+    ("✏\u{FE0F}", "pencil2", "fix"),
+    ("🥅", "goal_net", "fix"),
+    ("📝", "memo", "docs"),
+    ("💡", "bulb", "docs"),
+    ("📄", "page_facing_up", "docs"),
+    ("🎨", "art", "style"),
+    ("🚨", "rotating_light", "style"),
+    ("⚡\u{FE0F}", "zap", "perf"),
+    ("♻\u{FE0F}", "recycle", "refactor"),
+    ("🔥", "fire", "refactor"),
+    ("⚰\u{FE0F}", "coffin", "refactor"),
+    ("🗑\u{FE0F}", "wastebasket", "refactor"),
+    ("🚚", "truck", "refactor"),
+    ("🏗\u{FE0F}", "building_construction", "refactor"),
+    ("✅", "white_check_mark", "test"),
+    ("🧪", "test_tube", "test"),
+    ("🤡", "clown_face", "test"),
+    ("📦\u{FE0F}", "package", "build"),
+    ("➕", "heavy_plus_sign", "build"),
+    ("➖", "heavy_minus_sign", "build"),
+    ("⬆\u{FE0F}", "arrow_up", "build"),
+    ("⬇\u{FE0F}", "arrow_down", "build"),
+    ("📌", "pushpin", "build"),
+    ("👷", "construction_worker", "ci"),
+    ("💚", "green_heart", "ci"),
+    ("⏪\u{FE0F}", "rewind", "revert"),
+    ("🔧", "wrench", "chore"),
+    ("🔨", "hammer", "chore"),
+    ("🚀", "rocket", "chore"),
+    ("🔖", "bookmark", "chore"),
+    ("🎉", "tada", "chore"),
+    ("🚧", "construction", "chore"),
+    ("🙈", "see_no_evil", "chore"),
+    ("🏷\u{FE0F}", "label", "chore"),
+    ("🔀", "twisted_rightwards_arrows", "chore"),
+    ("👥", "busts_in_silhouette", "chore"),
+];
+
+/// A `:shortcode:`, or a run of emoji and the characters that join and modify them, followed
+/// by spaces.
+static PREFIX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        "^(?::([a-z0-9_+-]+):|([\u{1F000}-\u{1FAFF}\u{2190}-\u{21FF}\u{2300}-\u{23FF}\
+         \u{2600}-\u{27BF}\u{2B00}-\u{2BFF}\u{3030}\u{303D}\u{3297}\u{3299}]\
+         [\u{FE0F}\u{200D}\u{20E3}\u{1F3FB}-\u{1F3FF}\u{1F000}-\u{1FAFF}\u{2600}-\u{27BF}]*))\
+         [ \t]*",
+    )
+    .expect("valid regex")
+});
+
+/// A Conventional Commit prefix, like `feat:`, `fix(parser)!:`.
+static TYPE_PREFIX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[a-zA-Z]+(\([^)]*\))?!?: ").expect("valid regex"));
+
+/// What happens to emoji at the start of subjects.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum GitmojiPolicy {
+    /// Remove them.
+    Strip,
+    /// Replace them with the Conventional Commit type they stand for (`✨ Add x` becomes
+    /// `feat: Add x`), or remove them where the subject has a type already or none is known.
+    Conventional,
+    /// Write gitmoji as `:shortcode:`.
+    Shortcode,
+    /// Write gitmoji as emoji.
+    Unicode,
+}
+
+/// `<emoji or :shortcode:>=<type>`: the Conventional Commit type an emoji stands for, over
+/// the built-in one.
+#[derive(Clone, Debug)]
+pub struct GitmojiType {
+    emoji: String,
+    kind: String,
+}
+
+impl FromStr for GitmojiType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected `<emoji or :shortcode:>=<type>`, got `{s}`");
+        let (emoji, kind) = s.rsplit_once('=').ok_or_else(invalid)?;
+        let emoji = match PREFIX.captures(emoji.trim()) {
+            Some(captures) if captures[0].len() == emoji.trim().len() => key(&captures),
+            _ => return Err(invalid()),
+        };
+        if kind.is_empty() || !kind.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Err(invalid());
+        }
+        Ok(GitmojiType {
+            emoji,
+            kind: kind.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for GitmojiType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.emoji, self.kind)
+    }
+}
+
+/// The emoji a prefix match stands for, as the gitmoji emoji if it is one: shortcodes are
+/// looked up, variation selectors dropped.
+fn key(captures: &regex::Captures<'_>) -> String {
+    match (captures.get(1), captures.get(2)) {
+        (Some(shortcode), _) => GITMOJI
+            .iter()
+            .find(|(_, code, _)| *code == shortcode.as_str())
+            .map_or_else(
+                || format!(":{}:", shortcode.as_str()),
+                |(emoji, ..)| without_variation(emoji),
+            ),
+        (None, Some(emoji)) => without_variation(emoji.as_str()),
+        (None, None) => unreachable!("the prefix regex has two alternatives"),
+    }
+}
+
+/// `emoji` without the variation selector that asks for emoji rather than text presentation,
+/// which some tools write and others don't.
+fn without_variation(emoji: &str) -> String {
+    emoji.replace('\u{FE0F}', "")
+}
+
+/// Strips or standardizes gitmoji and other emoji at the start of every subject.
+pub(crate) struct Gitmoji {
+    policy: GitmojiPolicy,
+    /// Conventional Commit types by [`key`].
+    types: BTreeMap<String, String>,
+    changed: usize,
+    /// Emoji `conventional` knew no type for, with the number of subjects they started.
+    untyped: BTreeMap<String, usize>,
+}
+
+impl Gitmoji {
+    pub(crate) fn new(policy: GitmojiPolicy, types: &[GitmojiType]) -> Self {
+        let mut known: BTreeMap<String, String> = GITMOJI
+            .iter()
+            .map(|(emoji, _, kind)| (without_variation(emoji), (*kind).to_owned()))
+            .collect();
+        for extra in types {
+            known.insert(extra.emoji.clone(), extra.kind.clone());
+        }
+        Gitmoji {
+            policy,
+            types: known,
+            changed: 0,
+            untyped: BTreeMap::new(),
+        }
+    }
+
+    fn normalize(&mut self, subject: &str) -> Option<String> {
+        let captures = PREFIX.captures(subject)?;
+        let rest = &subject[captures[0].len()..];
+        // Kept when only the form of the emoji changes.
+        let token_end = match captures.get(1) {
+            // Past the closing colon.
+            Some(shortcode) => shortcode.end() + 1,
+            None => captures.get(2)?.end(),
+        };
+        let space = &subject[token_end..captures[0].len()];
+        let emoji = key(&captures);
+        let gitmoji = GITMOJI.iter().find(|(e, ..)| without_variation(e) == emoji);
+        let normalized = match self.policy {
+            GitmojiPolicy::Strip => rest.to_owned(),
+            GitmojiPolicy::Conventional if TYPE_PREFIX.is_match(rest) => rest.to_owned(),
+            GitmojiPolicy::Conventional => match self.types.get(&emoji) {
+                // `🐛(parser): ...` only lacks the type.
+                Some(kind) if rest.starts_with('(') => format!("{kind}{rest}"),
+                Some(kind) => format!("{kind}: {rest}"),
+                None => {
+                    *self.untyped.entry(emoji).or_default() += 1;
+                    rest.to_owned()
+                }
+            },
+            GitmojiPolicy::Shortcode => match gitmoji {
+                Some((_, code, _)) => format!(":{code}:{space}{rest}"),
+                None => return None,
+            },
+            GitmojiPolicy::Unicode => match gitmoji {
+                Some((emoji, ..)) => format!("{emoji}{space}{rest}"),
+                None => return None,
+            },
+        };
+        (normalized != subject).then_some(normalized)
+    }
+}
+
+impl Filter for Gitmoji {
+    fn name(&self) -> String {
+        let policy = match self.policy {
+            GitmojiPolicy::Strip => "strip",
+            GitmojiPolicy::Conventional => "conventional",
+            GitmojiPolicy::Shortcode => "shortcode",
+            GitmojiPolicy::Unicode => "unicode",
+        };
+        format!("--gitmoji {policy}")
+    }
+
+    fn apply(&mut self, _repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        let end = descriptor.message.find_byte(b'\n');
+        let subject = &descriptor.message[..end.unwrap_or(descriptor.message.len())];
+        // Subjects in other encodings than UTF-8 are left alone.
+        let Some(subject) = subject.to_str().ok().and_then(|s| self.normalize(s)) else {
+            return Ok(());
+        };
+        let mut normalized = BString::from(subject);
+        if let Some(end) = end {
+            normalized.extend_from_slice(&descriptor.message[end..]);
+        }
+        descriptor.message = normalized;
+        self.changed += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self, _repo: &Repository, _map: &CommitMap) -> AnyResult<()> {
+        println!("Normalized the emoji of {} subject(s)", self.changed);
+        if !self.untyped.is_empty() {
+            eprintln!(
+                "{} --gitmoji conventional: no type for {}, so they were only removed; \
+                 --gitmoji-type <emoji>=<type> gives them one",
+                "warning:".yellow(),
+                self.untyped
+                    .iter()
+                    .map(|(emoji, count)| format!("{emoji} (in {count})"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        Ok(())
+    }
+}
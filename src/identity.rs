@@ -0,0 +1,733 @@
+//! Author and committer identities.
+use crate::{
+    AnyResult,
+    error::Failure,
+    filter::Filter,
+    map::CommitMap,
+    mirror::CommitDescriptor,
+    provenance::REWRITER_NAME,
+    tree::{self, PathPattern},
+};
+use colored::Colorize;
+use gix::{
+    ObjectId, Repository,
+    actor::{Signature, SignatureRef},
+    bstr::{BStr, BString, ByteSlice},
+};
+use regex::bytes::Regex;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Mutex, PoisonError},
+};
+
+/// A name or email given for the identities of rewritten commits.
+#[derive(Clone, Debug)]
+pub enum IdentityPart {
+    /// `user.name` or `user.email` from the repository's git config.
+    Configured,
+    Given(String),
+}
+
+/// Which identities of rewritten commits are changed; the others are kept as they were.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IdentityScope {
+    #[default]
+    Both,
+    AuthorOnly,
+    CommitterOnly,
+}
+
+impl IdentityScope {
+    pub(crate) fn author(self) -> bool {
+        self != IdentityScope::CommitterOnly
+    }
+
+    pub(crate) fn committer(self) -> bool {
+        self != IdentityScope::AuthorOnly
+    }
+}
+
+/// Names and emails replacing those of every rewritten commit. Names default to
+/// [`REWRITER_NAME`], emails to the original ones.
+#[derive(Clone, Debug, Default)]
+pub struct RewriteIdentity {
+    pub author_name: Option<IdentityPart>,
+    pub author_email: Option<IdentityPart>,
+    pub committer_name: Option<IdentityPart>,
+    pub committer_email: Option<IdentityPart>,
+}
+
+/// A [`RewriteIdentity`] with the configured parts looked up.
+pub(crate) struct ResolvedIdentity {
+    author_name: BString,
+    author_email: Option<BString>,
+    committer_name: BString,
+    committer_email: Option<BString>,
+}
+
+impl RewriteIdentity {
+    pub(crate) fn resolve(&self, repo: &Repository) -> AnyResult<ResolvedIdentity> {
+        let config = repo.config_snapshot();
+        let part = |part: &Option<IdentityPart>, key: &str, flag: &str| -> AnyResult<_> {
+            Ok(match part {
+                None => None,
+                Some(IdentityPart::Given(value)) => Some(BString::from(value.as_str())),
+                Some(IdentityPart::Configured) => Some(
+                    config
+                        .string(key)
+                        .ok_or_else(|| format!("{key} is not set; pass a value to --{flag}"))?
+                        .into_owned(),
+                ),
+            })
+        };
+        Ok(ResolvedIdentity {
+            author_name: part(&self.author_name, "user.name", "author-name")?
+                .unwrap_or_else(|| REWRITER_NAME.into()),
+            author_email: part(&self.author_email, "user.email", "author-email")?,
+            committer_name: part(&self.committer_name, "user.name", "committer-name")?
+                .unwrap_or_else(|| REWRITER_NAME.into()),
+            committer_email: part(&self.committer_email, "user.email", "committer-email")?,
+        })
+    }
+}
+
+impl ResolvedIdentity {
+    pub(crate) fn apply(&self, author: &mut Signature, committer: &mut Signature) {
+        author.name = self.author_name.clone();
+        committer.name = self.committer_name.clone();
+        if let Some(email) = &self.author_email {
+            author.email = email.clone();
+        }
+        if let Some(email) = &self.committer_email {
+            committer.email = email.clone();
+        }
+    }
+}
+
+/// One side of an [`IdentityMap`] rule: `Name <email>`, `<email>` or just `Name`.
+struct IdentityPattern {
+    name: Option<BString>,
+    email: Option<BString>,
+}
+
+/// Like `Name <email>`, `<email>` or `Name` in a map file.
+impl fmt::Display for IdentityPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.name, &self.email) {
+            (Some(name), Some(email)) => write!(f, "{name} <{email}>"),
+            (None, Some(email)) => write!(f, "<{email}>"),
+            (Some(name), None) => write!(f, "{name}"),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+impl IdentityPattern {
+    fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let (name, email) = match text.strip_suffix('>') {
+            Some(rest) => {
+                let (name, email) = rest.split_once('<')?;
+                (name.trim(), Some(email.trim()))
+            }
+            None => (text, None),
+        };
+        // Git can't record these in a signature.
+        if [name, email.unwrap_or_default()]
+            .iter()
+            .any(|part| part.contains(['<', '>', '\n']))
+        {
+            return None;
+        }
+        let pattern = IdentityPattern {
+            name: (!name.is_empty()).then(|| name.into()),
+            email: email.filter(|e| !e.is_empty()).map(Into::into),
+        };
+        (pattern.name.is_some() || pattern.email.is_some()).then_some(pattern)
+    }
+
+    /// Names match exactly, emails regardless of case.
+    fn matches(&self, signature: &Signature) -> bool {
+        self.name
+            .as_ref()
+            .is_none_or(|name| *name == signature.name)
+            && self.email.as_ref().is_none_or(|email| {
+                email.to_str_lossy().to_lowercase() == signature.email.to_str_lossy().to_lowercase()
+            })
+    }
+}
+
+/// Rules of `Old Name <old@email> => New Name <new@email>` lines, applied to the original
+/// author and committer of each rewritten commit.
+///
+/// Either side may leave out the name or the email. On the left, that matches any; on the
+/// right, that keeps the original one. The first matching rule wins.
+pub(crate) struct IdentityMap {
+    rules: Vec<(IdentityPattern, IdentityPattern)>,
+}
+
+impl IdentityMap {
+    pub(crate) fn load(path: &Path) -> AnyResult<Self> {
+        let text =
+            fs::read_to_string(path).map_err(|e| format!("reading {}: {e}", path.display()))?;
+        let mut rules = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let rule = line.split_once("=>").and_then(|(old, new)| {
+                Some((IdentityPattern::parse(old)?, IdentityPattern::parse(new)?))
+            });
+            let Some(rule) = rule else {
+                return Err(format!(
+                    "{}:{}: expected `Old Name <old@email> => New Name <new@email>`",
+                    path.display(),
+                    index + 1
+                )
+                .into());
+            };
+            rules.push(rule);
+        }
+        Ok(IdentityMap { rules })
+    }
+
+    /// The identities among `originals` that no rule matches, once each, in order.
+    fn unmapped<'a>(&self, originals: impl IntoIterator<Item = &'a Signature>) -> Vec<String> {
+        let mut unmapped: Vec<String> = Vec::new();
+        for original in originals {
+            let identity = format!("{} <{}>", original.name, original.email);
+            if !self.rules.iter().any(|(old, _)| old.matches(original))
+                && !unmapped.contains(&identity)
+            {
+                unmapped.push(identity);
+            }
+        }
+        unmapped
+    }
+
+    /// Deal with the identities among `originals` that no rule of the map at `path` matches:
+    /// fail with a stub of rules for them if `strict`, otherwise ask for a rule for each on the
+    /// terminal and append the answers to the map, so they are settled for the next run.
+    /// Without a terminal, they are only listed.
+    pub(crate) fn fill_gaps<'a>(
+        &mut self,
+        path: &Path,
+        originals: impl IntoIterator<Item = &'a Signature>,
+        strict: bool,
+        dry_run: bool,
+    ) -> AnyResult<()> {
+        let unmapped = self.unmapped(originals);
+        if unmapped.is_empty() {
+            return Ok(());
+        }
+        if strict {
+            let mut stub = path.as_os_str().to_owned();
+            stub.push(".stub");
+            let stub = PathBuf::from(stub);
+            let mut text = format!(
+                "# Identities no rule of {} matches. Fill in the right sides and append the\n\
+                 # lines to it.\n",
+                path.display()
+            );
+            for identity in &unmapped {
+                text.push_str(&format!("{identity} => {identity}\n"));
+            }
+            fs::write(&stub, text).map_err(|e| format!("writing {}: {e}", stub.display()))?;
+            return Err(Failure::Validation(format!(
+                "no rule of {} matches {} identity(ies): {}; wrote rules to fill in to {}",
+                path.display(),
+                unmapped.len(),
+                unmapped.join(", "),
+                stub.display()
+            ))
+            .into());
+        }
+        if !io::stdin().is_terminal() {
+            eprintln!(
+                "{} no rule of {} matches {} identity(ies), which only the other identity \
+                 options change: {}; pass --strict-identities to fail instead",
+                "warning:".yellow(),
+                path.display(),
+                unmapped.len(),
+                unmapped.join(", ")
+            );
+            return Ok(());
+        }
+
+        // Mirrors of several branches at once must not ask at the same time.
+        static ASKING: Mutex<()> = Mutex::new(());
+        let _asking = ASKING.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut answered = String::new();
+        'identities: for identity in &unmapped {
+            println!("No rule of {} matches {identity}", path.display());
+            let old = IdentityPattern::parse(identity).expect("formatted as `Name <email>`");
+            let new = loop {
+                print!("  Map it to (`Name <email>`, `Name` or `<email>`; empty keeps it): ");
+                io::stdout().flush()?;
+                let mut answer = String::new();
+                if io::stdin().read_line(&mut answer)? == 0 {
+                    println!();
+                    break 'identities;
+                }
+                let answer = answer.trim();
+                if answer.is_empty() {
+                    break IdentityPattern::parse(identity).expect("formatted as `Name <email>`");
+                }
+                match IdentityPattern::parse(answer) {
+                    Some(new) => break new,
+                    None => println!("  `{answer}` is not an identity"),
+                }
+            };
+            answered.push_str(&format!("{identity} => {new}\n"));
+            self.rules.push((old, new));
+        }
+        if answered.is_empty() || dry_run {
+            return Ok(());
+        }
+        let mut file = fs::OpenOptions::new()
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("opening {}: {e}", path.display()))?;
+        if !fs::read(path)?.ends_with(b"\n") && fs::metadata(path)?.len() > 0 {
+            file.write_all(b"\n")?;
+        }
+        file.write_all(answered.as_bytes())
+            .map_err(|e| format!("writing {}: {e}", path.display()))?;
+        println!("Added the answers to {}", path.display());
+        Ok(())
+    }
+
+    /// Give `signature` the identity the first rule matching `original` maps to, if any.
+    pub(crate) fn apply(&self, original: &Signature, signature: &mut Signature) {
+        if let Some((_, new)) = self.rules.iter().find(|(old, _)| old.matches(original)) {
+            signature.name = new.name.clone().unwrap_or_else(|| original.name.clone());
+            signature.email = new.email.clone().unwrap_or_else(|| original.email.clone());
+        }
+    }
+}
+
+/// The repository's mailmap, from `.mailmap` in the worktree (or in `HEAD` of a bare
+/// repository) and the files `mailmap.file` and `mailmap.blob` name.
+pub(crate) fn load_mailmap(repo: &Repository) -> AnyResult<gix::mailmap::Snapshot> {
+    let mut mailmap = gix::mailmap::Snapshot::default();
+    repo.open_mailmap_into(&mut mailmap)?;
+    if mailmap.entries().is_empty() {
+        eprintln!(
+            "{} --use-mailmap: the repository has no mailmap entries",
+            "warning:".yellow()
+        );
+    }
+    Ok(mailmap)
+}
+
+/// Give `signature` the canonical identity `mailmap` has for `original`, if any.
+pub(crate) fn apply_mailmap(
+    mailmap: &gix::mailmap::Snapshot,
+    original: SignatureRef<'_>,
+    signature: &mut Signature,
+) {
+    if let Some(canonical) = mailmap.try_resolve(original) {
+        signature.name = canonical.name;
+        signature.email = canonical.email;
+    }
+}
+
+/// Replaces identities using a git-svn style authors file of `user = Name <email>` lines.
+///
+/// `user` is matched against the whole email and against its local part, which is where
+/// conversions keep the SVN username (`user <user@repository-uuid>`).
+pub(crate) struct AuthorsFile {
+    identities: HashMap<String, (BString, BString)>,
+    scope: IdentityScope,
+}
+
+impl AuthorsFile {
+    pub(crate) fn load(path: &Path, scope: IdentityScope) -> AnyResult<Self> {
+        let mut identities = HashMap::new();
+        for (index, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parsed = line.split_once('=').and_then(|(user, identity)| {
+                let (name, email) = identity.trim().strip_suffix('>')?.split_once('<')?;
+                Some((user.trim(), name.trim(), email.trim()))
+            });
+            let Some((user, name, email)) = parsed else {
+                return Err(format!(
+                    "{}:{}: expected `user = Name <email>`",
+                    path.display(),
+                    index + 1
+                )
+                .into());
+            };
+            identities.insert(user.to_lowercase(), (name.into(), email.into()));
+        }
+        Ok(AuthorsFile { identities, scope })
+    }
+
+    fn map(&self, signature: &mut Signature) {
+        let email = signature.email.to_str_lossy().to_lowercase();
+        let local = email.split('@').next().unwrap_or_default();
+        if let Some((name, new_email)) = self
+            .identities
+            .get(&email)
+            .or_else(|| self.identities.get(local))
+        {
+            signature.name = name.clone();
+            signature.email = new_email.clone();
+        }
+    }
+}
+
+impl Filter for AuthorsFile {
+    fn name(&self) -> String {
+        "--authors-file".into()
+    }
+
+    fn apply(&mut self, _repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        if self.scope.author() {
+            self.map(&mut descriptor.author);
+        }
+        if self.scope.committer() {
+            self.map(&mut descriptor.committer);
+        }
+        Ok(())
+    }
+}
+
+/// A `Name <email>` identity.
+#[derive(Clone, Debug)]
+pub struct NamedIdentity {
+    pub name: BString,
+    pub email: BString,
+}
+
+impl FromStr for NamedIdentity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match IdentityPattern::parse(s) {
+            Some(IdentityPattern {
+                name: Some(name),
+                email: Some(email),
+            }) => Ok(NamedIdentity { name, email }),
+            _ => Err(format!("expected `Name <email>`, got `{s}`")),
+        }
+    }
+}
+
+impl Default for NamedIdentity {
+    fn default() -> Self {
+        NamedIdentity {
+            name: "Erased Contributor".into(),
+            email: "erased@anonymous.invalid".into(),
+        }
+    }
+}
+
+/// Erases people from a history, for right-to-be-forgotten requests: reassigns the commits
+/// they authored or committed to a replacement identity, and replaces their emails, and the
+/// names they committed under, wherever messages, trailers or headers mention them.
+///
+/// Their names are collected from every commit reachable from the target, so mentions in
+/// commits before their first one are caught too.
+pub(crate) struct EraseIdentity {
+    /// Lowercased.
+    emails: HashSet<String>,
+    replacement: NamedIdentity,
+    email_pattern: Regex,
+    name_pattern: Option<Regex>,
+    names: Vec<String>,
+    reassigned: usize,
+    scrubbed: usize,
+}
+
+impl EraseIdentity {
+    pub(crate) fn new(
+        repo: &Repository,
+        target_id: ObjectId,
+        emails: &[String],
+        replacement: NamedIdentity,
+    ) -> AnyResult<Self> {
+        let emails: HashSet<String> = emails
+            .iter()
+            .map(|e| e.trim().to_lowercase())
+            .filter(|e| !e.is_empty())
+            .collect();
+        if emails.is_empty() {
+            return Err("--erase-identity needs an email".into());
+        }
+        let matches = |email: &[u8]| emails.contains(&email.to_str_lossy().to_lowercase());
+        let mut names = HashSet::new();
+        for info in repo.rev_walk([target_id]).all()? {
+            let commit = info?.object()?;
+            let decoded = commit.decode()?;
+            for signature in [decoded.author(), decoded.committer()] {
+                if matches(signature.email) {
+                    names.insert(signature.name.to_str_lossy().trim().to_owned());
+                }
+            }
+            // Names given in trailers, such as `Co-authored-by: Name <email>`.
+            for line in decoded.message.lines() {
+                let identity = line
+                    .find(b": ")
+                    .and_then(|colon| IdentityPattern::parse(&line[colon + 2..].to_str_lossy()));
+                if let Some(IdentityPattern {
+                    name: Some(name),
+                    email: Some(email),
+                }) = identity
+                    && matches(&email)
+                {
+                    names.insert(name.to_str_lossy().into_owned());
+                }
+            }
+        }
+        names.retain(|name| name.chars().count() > 1 && *name != replacement.name);
+        let mut names: Vec<String> = names.into_iter().collect();
+        // Longest first, so that a full name wins over the first name alone.
+        names.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+
+        let alternatives = |items: &mut dyn Iterator<Item = &String>| {
+            items
+                .map(|item| regex::escape(item))
+                .collect::<Vec<_>>()
+                .join("|")
+        };
+        let email_pattern = Regex::new(&format!("(?i){}", alternatives(&mut emails.iter())))?;
+        let name_pattern = (!names.is_empty())
+            .then(|| Regex::new(&format!(r"\b(?:{})\b", alternatives(&mut names.iter()))))
+            .transpose()?;
+        Ok(EraseIdentity {
+            emails,
+            replacement,
+            email_pattern,
+            name_pattern,
+            names,
+            reassigned: 0,
+            scrubbed: 0,
+        })
+    }
+
+    fn is_erased(&self, signature: &Signature) -> bool {
+        self.emails
+            .contains(&signature.email.to_str_lossy().to_lowercase())
+    }
+
+    fn scrub(&self, text: &[u8]) -> Option<BString> {
+        let mut scrubbed = self
+            .email_pattern
+            .replace_all(text, self.replacement.email.as_slice());
+        if let Some(pattern) = &self.name_pattern {
+            let named = pattern.replace_all(&scrubbed, self.replacement.name.as_slice());
+            scrubbed = named.into_owned().into();
+        }
+        (*scrubbed != *text).then(|| scrubbed.into_owned().into())
+    }
+}
+
+impl Filter for EraseIdentity {
+    fn name(&self) -> String {
+        "--erase-identity".into()
+    }
+
+    fn apply(&mut self, repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        // The identity options may have replaced the original emails already.
+        let (mut original_author, mut original_committer) = (None, None);
+        if let Ok(commit) = repo.find_commit(descriptor.original_id) {
+            original_author = Some(Signature::from(commit.author()?));
+            original_committer = Some(Signature::from(commit.committer()?));
+        }
+        let mut reassigned = false;
+        for (signature, original) in [
+            (&mut descriptor.author, original_author),
+            (&mut descriptor.committer, original_committer),
+        ] {
+            if self.is_erased(signature) || original.is_some_and(|o| self.is_erased(&o)) {
+                signature.name = self.replacement.name.clone();
+                signature.email = self.replacement.email.clone();
+                reassigned = true;
+            }
+        }
+        self.reassigned += usize::from(reassigned);
+
+        let mut scrubbed = false;
+        if let Some(message) = self.scrub(&descriptor.message) {
+            descriptor.message = message;
+            scrubbed = true;
+        }
+        for (_, value) in &mut descriptor.extra_headers {
+            if let Some(new_value) = self.scrub(value) {
+                *value = new_value;
+                scrubbed = true;
+            }
+        }
+        self.scrubbed += usize::from(scrubbed);
+        Ok(())
+    }
+
+    fn finish(&mut self, _repo: &Repository, _map: &CommitMap) -> AnyResult<()> {
+        println!(
+            "Erased {} identity(ies), known as {}: reassigned {} commit(s) to {} <{}> and removed \
+             mentions from {} message(s)",
+            self.emails.len(),
+            match self.names.is_empty() {
+                true => "no name".to_owned(),
+                false => self.names.join(", "),
+            },
+            self.reassigned,
+            self.replacement.name,
+            self.replacement.email,
+            self.scrubbed
+        );
+        Ok(())
+    }
+}
+
+/// Domain of the emails of team identities `--owners-file` derives from `@handles`.
+pub const DEFAULT_OWNERS_DOMAIN: &str = "teams.invalid";
+
+/// Reassigns the author of every commit touching owned paths to the owner of most of the
+/// paths it changes, by the rules of a CODEOWNERS file, for team-level attribution.
+///
+/// As on GitHub, the last rule matching a path decides its owner, and a rule without owners
+/// leaves paths unowned. Of the owners of a rule, the first is credited. Commits that touch no
+/// owned path keep their author.
+pub(crate) struct CodeOwners {
+    /// Path patterns with the index of their owner in `owners`.
+    rules: Vec<(PathPattern, Option<usize>)>,
+    /// Owners as written: `@org/team`, `@user` or an email.
+    owners: Vec<String>,
+    identities: Vec<(BString, BString)>,
+    /// Commits attributed to each owner.
+    attributed: Vec<usize>,
+}
+
+impl CodeOwners {
+    /// The rules of the CODEOWNERS file `path`. `@`-handles get emails at `domain`.
+    pub(crate) fn load(path: &Path, domain: &str) -> AnyResult<Self> {
+        let text =
+            fs::read_to_string(path).map_err(|e| format!("reading {}: {e}", path.display()))?;
+        let mut owners: Vec<String> = Vec::new();
+        let mut rules = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            // GitLab's `[Section]` headers only group rules.
+            if line.is_empty() || line.starts_with('#') || line.starts_with(['[', '^']) {
+                continue;
+            }
+            let mut tokens = line.split_whitespace();
+            let Some(pattern) = tokens.next() else {
+                continue;
+            };
+            let pattern: PathPattern = pattern
+                .replace("\\#", "#")
+                .parse()
+                .map_err(|e| format!("{}:{}: {e}", path.display(), index + 1))?;
+            let owner = tokens.take_while(|token| !token.starts_with('#')).next();
+            let owner = owner.map(|owner| match owners.iter().position(|o| o == owner) {
+                Some(position) => position,
+                None => {
+                    owners.push(owner.to_owned());
+                    owners.len() - 1
+                }
+            });
+            rules.push((pattern, owner));
+        }
+        if owners.is_empty() {
+            return Err(format!("{}: no rule names an owner", path.display()).into());
+        }
+        let identities = owners
+            .iter()
+            .map(|owner| match owner.strip_prefix('@') {
+                Some(handle) => (
+                    handle.into(),
+                    format!("{}@{domain}", handle.replace('/', "-")).into(),
+                ),
+                None => (
+                    owner.split('@').next().unwrap_or(owner).into(),
+                    owner.as_str().into(),
+                ),
+            })
+            .collect();
+        Ok(CodeOwners {
+            attributed: vec![0; owners.len()],
+            rules,
+            owners,
+            identities,
+        })
+    }
+
+    fn owner_of(&self, path: &BStr) -> Option<usize> {
+        self.rules
+            .iter()
+            .rev()
+            .find(|(pattern, _)| pattern.matches(path))
+            .and_then(|(_, owner)| *owner)
+    }
+}
+
+impl Filter for CodeOwners {
+    fn name(&self) -> String {
+        "--owners-file".into()
+    }
+
+    fn apply(&mut self, repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        // Commits synthesized by the rewrite change nothing of their own.
+        let Ok(commit) = repo.find_commit(descriptor.original_id) else {
+            return Ok(());
+        };
+        let parent_tree = match commit.parent_ids().next() {
+            Some(parent) => Some(repo.find_commit(parent)?.tree_id()?.detach()),
+            None => None,
+        };
+        let mut counts = vec![0usize; self.owners.len()];
+        for change in tree::diff(repo, parent_tree, commit.tree_id()?.detach())? {
+            if change.entry_mode().is_tree() {
+                continue;
+            }
+            if let Some(owner) = self.owner_of(change.location()) {
+                counts[owner] += 1;
+            }
+        }
+        // Ties go to the owner named first in the file.
+        let Some((owner, _)) = counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .max_by_key(|(owner, count)| (**count, std::cmp::Reverse(*owner)))
+        else {
+            return Ok(());
+        };
+        let (name, email) = &self.identities[owner];
+        descriptor.author.name = name.clone();
+        descriptor.author.email = email.clone();
+        self.attributed[owner] += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self, _repo: &Repository, _map: &CommitMap) -> AnyResult<()> {
+        let listed: Vec<String> = self
+            .owners
+            .iter()
+            .zip(&self.attributed)
+            .filter(|(_, count)| **count > 0)
+            .map(|(owner, count)| format!("{owner} ({count})"))
+            .collect();
+        println!(
+            "Attributed {} commit(s) to the owners of their paths: {}",
+            self.attributed.iter().sum::<usize>(),
+            if listed.is_empty() {
+                "none".to_owned()
+            } else {
+                listed.join(", ")
+            }
+        );
+        Ok(())
+    }
+}
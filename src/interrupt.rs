@@ -0,0 +1,130 @@
+//! Stopping runs at a safe point on SIGINT or SIGTERM, and recording runs so that what a run
+//! killed outright leaves behind can be found.
+use crate::{AnyResult, files, state};
+use gix::Repository;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+/// Let the first SIGINT or SIGTERM stop the run at the next [`check`] instead of killing it
+/// halfway through writing. A second one terminates at once, removing the lock files of ref
+/// updates in flight.
+pub fn install_handler() -> AnyResult<()> {
+    // SAFETY: the handler does nothing beyond what gix does itself, setting an atomic flag.
+    unsafe { gix::interrupt::init_handler(1, || {}) }?;
+    Ok(())
+}
+
+/// Fail if SIGINT or SIGTERM arrived. Called between steps that leave nothing half-done, up to
+/// the point where refs are updated.
+pub(crate) fn check() -> AnyResult<()> {
+    if gix::interrupt::is_triggered() {
+        return Err("interrupted before any ref was updated".into());
+    }
+    Ok(())
+}
+
+/// What a run in progress records about itself in `.git/magitulator/runs`.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct RunRecord {
+    pub(crate) pid: u32,
+    pub(crate) started_at: i64,
+    pub(crate) command: Vec<String>,
+    /// Scratch directories outside the repository the run may leave behind.
+    pub(crate) scratch_dirs: Vec<PathBuf>,
+}
+
+/// `command`, quoted for a POSIX shell where needed.
+pub(crate) fn command_line(command: &[String]) -> String {
+    let quote = |arg: &String| {
+        let plain = !arg.is_empty()
+            && arg
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_./=:@^~".contains(c));
+        match plain {
+            true => arg.clone(),
+            false => format!("'{}'", arg.replace('\'', r"'\''")),
+        }
+    };
+    command.iter().map(quote).collect::<Vec<_>>().join(" ")
+}
+
+impl RunRecord {
+    /// The command line, quoted for a POSIX shell where needed.
+    pub(crate) fn command_line(&self) -> String {
+        command_line(&self.command)
+    }
+
+    /// Whether the process that wrote the record is still alive. A process of another user
+    /// counts as gone, and a recycled pid as alive.
+    pub(crate) fn is_running(&self) -> bool {
+        let pid = self.pid.to_string();
+        if cfg!(windows) {
+            Command::new("tasklist")
+                .args(["/FI", &format!("PID eq {pid}"), "/NH"])
+                .output()
+                .is_ok_and(|output| {
+                    String::from_utf8_lossy(&output.stdout)
+                        .split_whitespace()
+                        .any(|word| word == pid)
+                })
+        } else {
+            Command::new("kill")
+                .args(["-0", &pid])
+                .stderr(Stdio::null())
+                .status()
+                .is_ok_and(|status| status.success())
+        }
+    }
+}
+
+/// The records of runs in `.git/magitulator/runs`, with their paths, oldest first.
+pub(crate) fn load_runs(repo: &Repository) -> AnyResult<Vec<(PathBuf, RunRecord)>> {
+    let dir = state::runs_dir(repo);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut runs = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        let record: RunRecord = serde_json::from_slice(&fs::read(&path)?)
+            .map_err(|e| format!("reading {}: {e}", path.display()))?;
+        runs.push((path, record));
+    }
+    runs.sort_by_key(|(_, record)| record.started_at);
+    Ok(runs)
+}
+
+/// Marks a run as in progress for as long as it lives. The record stays behind only if the
+/// process is killed, for `doctor` to find.
+pub(crate) struct RunMarker {
+    path: PathBuf,
+}
+
+impl RunMarker {
+    pub(crate) fn create(repo: &Repository, scratch_dirs: Vec<PathBuf>) -> AnyResult<Self> {
+        let dir = state::runs_dir(repo);
+        fs::create_dir_all(&dir)?;
+        let record = RunRecord {
+            pid: std::process::id(),
+            started_at: gix::date::Time::now_utc().seconds,
+            command: std::iter::once("gitm".to_owned())
+                .chain(std::env::args().skip(1))
+                .collect(),
+            scratch_dirs,
+        };
+        // Pipelines running side by side in one process each have their own record.
+        let path = dir.join(format!("{}.json", files::unique_name()));
+        fs::write(&path, serde_json::to_vec_pretty(&record)?)?;
+        Ok(RunMarker { path })
+    }
+}
+
+impl Drop for RunMarker {
+    fn drop(&mut self) {
+        fs::remove_file(&self.path).ok();
+    }
+}
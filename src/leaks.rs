@@ -0,0 +1,221 @@
+//! Analysis of traits that keep identifying authors after names and emails are anonymized.
+use crate::{AnyResult, changelog::ConventionalParser, mirror};
+use colored::Colorize;
+use gix::{Repository, bstr::ByteSlice};
+use std::collections::{BTreeMap, BTreeSet};
+
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Hours on each side of an author's peak hour counted as their active window.
+const WINDOW: i64 = 2;
+
+#[derive(Default)]
+struct AuthorTraits {
+    commits: usize,
+    offsets: BTreeMap<i32, usize>,
+    hours: [usize; 24],
+    subject_chars: usize,
+    conventional: usize,
+    trailing_period: usize,
+    lowercase: usize,
+    with_body: usize,
+    signed_off: usize,
+}
+
+impl AuthorTraits {
+    fn peak_hour(&self) -> usize {
+        (0..24)
+            .max_by_key(|&h| (self.hours[h], 24 - h))
+            .unwrap_or(0)
+    }
+
+    /// Share of commits within `WINDOW` hours of the peak.
+    fn window_share(&self) -> f64 {
+        let peak = self.peak_hour() as i64;
+        let in_window: usize = (-WINDOW..=WINDOW)
+            .map(|d| self.hours[(peak + d).rem_euclid(24) as usize])
+            .sum();
+        in_window as f64 / self.commits as f64
+    }
+
+    fn share(&self, count: usize) -> f64 {
+        count as f64 / self.commits as f64
+    }
+
+    /// Style traits most of this author's messages have.
+    fn style(&self) -> Vec<&'static str> {
+        [
+            (self.conventional, "type: prefixes"),
+            (self.trailing_period, "trailing periods"),
+            (self.lowercase, "lowercase subjects"),
+            (self.with_body, "message bodies"),
+            (self.signed_off, "Signed-off-by trailers"),
+        ]
+        .into_iter()
+        .filter(|(count, _)| self.share(*count) > 0.5)
+        .map(|(_, name)| name)
+        .collect()
+    }
+}
+
+fn sparkline(hours: &[usize; 24]) -> String {
+    let max = hours.iter().copied().max().unwrap_or(0).max(1);
+    hours
+        .iter()
+        .map(|&n| {
+            if n == 0 {
+                ' '
+            } else {
+                SPARKS[(n * (SPARKS.len() - 1)).div_ceil(max)]
+            }
+        })
+        .collect()
+}
+
+fn format_offset(offset: i32) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    let minutes = offset.abs() / 60;
+    format!("{sign}{:02}{:02}", minutes / 60, minutes % 60)
+}
+
+/// Report, per author of the commits `mirror <base> <target>` would rewrite, the timezones,
+/// commit hours and message style that could single them out.
+pub fn leaks(repo: &Repository, base: &str, target: &str) -> AnyResult<()> {
+    let base_id = mirror::resolve_commit_id(repo, base)?;
+    let target_id = mirror::resolve_commit_id(repo, target)?;
+    let conventional = ConventionalParser::new()?;
+
+    let mut authors: BTreeMap<String, AuthorTraits> = BTreeMap::new();
+    let commits = mirror::get_commits_to_rewrite(repo, base_id, target_id, &[], false)?;
+    for id in &commits {
+        let commit = repo.find_commit(*id)?;
+        let author = commit.author()?;
+        let time = author.time()?;
+        let message = commit.message_raw()?;
+        let subject = message.lines().next().unwrap_or_default().to_str_lossy();
+
+        let traits = authors
+            .entry(author.email.to_str_lossy().to_lowercase())
+            .or_default();
+        traits.commits += 1;
+        *traits.offsets.entry(time.offset).or_default() += 1;
+        traits.hours[((time.seconds + time.offset as i64).rem_euclid(86400) / 3600) as usize] += 1;
+        traits.subject_chars += subject.chars().count();
+        traits.conventional += conventional.parse(&subject).is_some() as usize;
+        traits.trailing_period += subject.ends_with('.') as usize;
+        traits.lowercase += subject.starts_with(|c: char| c.is_lowercase()) as usize;
+        traits.with_body += message.lines().skip(1).any(|l| !l.trim().is_empty()) as usize;
+        traits.signed_off += message.find("\nSigned-off-by:").is_some() as usize;
+    }
+
+    println!(
+        "{} commit(s) by {} author(s) in {base}..{target}",
+        commits.len(),
+        authors.len()
+    );
+    for (email, traits) in &authors {
+        let offsets: Vec<String> = traits
+            .offsets
+            .iter()
+            .map(|(offset, n)| format!("{} ({n})", format_offset(*offset)))
+            .collect();
+        let style = traits.style();
+        println!();
+        println!("{} ({} commit(s))", email.bold(), traits.commits);
+        println!("  timezones: {}", offsets.join(", "));
+        println!(
+            "  hours:     |{}| peak {:02}:00, {:.0}% within ±{WINDOW}h",
+            sparkline(&traits.hours),
+            traits.peak_hour(),
+            traits.window_share() * 100.0
+        );
+        println!(
+            "  messages:  {} chars per subject, mostly {}",
+            traits.subject_chars / traits.commits,
+            if style.is_empty() {
+                "plain".to_owned()
+            } else {
+                style.join(", ")
+            }
+        );
+    }
+
+    if authors.len() < 2 {
+        println!();
+        println!("A single author; nothing to tell apart.");
+        return Ok(());
+    }
+
+    let mut findings = Vec::new();
+    for (email, traits) in &authors {
+        let others = || authors.iter().filter(|(other, _)| *other != email);
+
+        let shared: BTreeSet<i32> = others()
+            .flat_map(|(_, t)| t.offsets.keys().copied())
+            .collect();
+        let unique: Vec<String> = traits
+            .offsets
+            .keys()
+            .filter(|offset| !shared.contains(offset))
+            .map(|offset| format_offset(*offset))
+            .collect();
+        if !unique.is_empty() {
+            findings.push((
+                "timezone",
+                format!("{email} is the only author in {}", unique.join(", ")),
+            ));
+        }
+
+        let peak = traits.peak_hour() as i64;
+        let distance = |other: &AuthorTraits| {
+            let d = (other.peak_hour() as i64 - peak).rem_euclid(24);
+            d.min(24 - d)
+        };
+        if traits.window_share() > 0.5 && others().all(|(_, t)| distance(t) > 2 * WINDOW) {
+            findings.push((
+                "hours",
+                format!(
+                    "{email} commits around {peak:02}:00, apart from everyone else ({:.0}% within ±{WINDOW}h)",
+                    traits.window_share() * 100.0
+                ),
+            ));
+        }
+
+        let style = traits.style();
+        if !style.is_empty() && others().all(|(_, t)| t.style() != style) {
+            findings.push((
+                "style",
+                format!(
+                    "{email} is the only author writing mostly {}",
+                    style.join(", ")
+                ),
+            ));
+        }
+    }
+
+    println!();
+    if findings.is_empty() {
+        println!(
+            "{} no author stands out by timezone, hours or style",
+            "ok:".green()
+        );
+        return Ok(());
+    }
+    for (vector, finding) in &findings {
+        println!("{} {finding}", format!("{vector}:").yellow());
+    }
+    let vectors: BTreeSet<&str> = findings.iter().map(|(vector, _)| *vector).collect();
+    println!();
+    println!("Anonymizing names and emails leaves these vectors open:");
+    for vector in vectors {
+        println!(
+            "  - {}",
+            match vector {
+                "timezone" => "timezone offsets: normalize them to a single offset",
+                "hours" => "commit hours: shift or coarsen author and committer times",
+                _ => "message style: normalize subjects and strip trailers",
+            }
+        );
+    }
+    Ok(())
+}
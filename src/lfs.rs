@@ -0,0 +1,249 @@
+//! Moving files of a history into Git LFS: matching blobs become pointer files, their content
+//! goes to `.git/lfs/objects`, and `.gitattributes` marks their paths for the LFS filter.
+use crate::{
+    AnyResult,
+    budget::format_size,
+    files,
+    filter::Filter,
+    map::CommitMap,
+    metrics::CacheStats,
+    mirror::CommitDescriptor,
+    tree::{EntryAction, PathPattern, TreeRewriter},
+};
+use gix::{
+    ObjectId, Repository,
+    bstr::{BStr, BString, ByteSlice},
+    object::tree::EntryKind,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::PathBuf,
+};
+
+/// The first line of every pointer file.
+const POINTER_VERSION: &str = "version https://git-lfs.github.com/spec/v1";
+
+/// The attributes that hand a path to the LFS filter, as `git lfs track` writes them.
+const ATTRIBUTES: &str = "filter=lfs diff=lfs merge=lfs -text";
+
+/// The pointer file standing for content with SHA-256 `oid` and `size` bytes.
+fn pointer(oid: &str, size: usize) -> String {
+    format!("{POINTER_VERSION}\noid sha256:{oid}\nsize {size}\n")
+}
+
+/// The `.gitattributes` pattern for the files below the directories `pattern` matches.
+/// Attributes don't apply to what is below a matching directory, and a pattern with a `/` is
+/// anchored to the root.
+fn below(pattern: &PathPattern) -> String {
+    let pattern = pattern.to_string();
+    match pattern.contains('/') {
+        true => format!("{pattern}/**"),
+        false => format!("**/{pattern}/**"),
+    }
+}
+
+/// `.gitattributes` with a line tracking each of `patterns` with LFS: lines for the same
+/// pattern are replaced, others appended.
+fn with_attributes(existing: &[u8], patterns: &[String]) -> BString {
+    let mut text = BString::default();
+    let mut tracked = HashSet::new();
+    for line in existing.lines() {
+        let pattern = line.fields().next().and_then(|field| field.to_str().ok());
+        match pattern.filter(|pattern| patterns.iter().any(|p| p == pattern)) {
+            Some(pattern) if tracked.insert(pattern) => {
+                text.extend_from_slice(format!("{pattern} {ATTRIBUTES}").as_bytes());
+            }
+            // A second line for the same pattern would undo the first.
+            Some(_) => continue,
+            None => text.extend_from_slice(line),
+        }
+        text.push(b'\n');
+    }
+    for pattern in patterns {
+        if !tracked.contains(pattern.as_str()) {
+            text.extend_from_slice(format!("{pattern} {ATTRIBUTES}\n").as_bytes());
+        }
+    }
+    text
+}
+
+/// Replaces blobs at matching paths with LFS pointer files, storing their content as LFS
+/// objects, and tracks the patterns in the root `.gitattributes` of every rewritten tree.
+///
+/// Blobs that are pointer files already are left alone. Objects are written as commits are
+/// rewritten, except in dry runs, so a run resuming from a `--time-budget` checkpoint finds
+/// the objects of the runs before.
+pub(crate) struct LfsConvert {
+    patterns: Vec<PathPattern>,
+    objects_dir: PathBuf,
+    dry_run: bool,
+    rewriter: TreeRewriter,
+    /// Pointer files by the blob they stand for.
+    pointers: HashMap<ObjectId, ObjectId>,
+    /// Rewritten `.gitattributes` by the one they replace; `None` for trees without one.
+    attributes: HashMap<Option<ObjectId>, ObjectId>,
+    /// The patterns that matched files themselves, and those that matched directories, by
+    /// index.
+    file_matches: HashSet<usize>,
+    directory_matches: HashSet<usize>,
+    /// Converted paths, and the bytes moved into LFS.
+    paths: HashSet<BString>,
+    bytes: u64,
+}
+
+impl LfsConvert {
+    pub(crate) fn new(repo: &Repository, patterns: Vec<PathPattern>, dry_run: bool) -> Self {
+        LfsConvert {
+            patterns,
+            objects_dir: repo.common_dir().join("lfs").join("objects"),
+            dry_run,
+            rewriter: TreeRewriter::default(),
+            pointers: HashMap::new(),
+            attributes: HashMap::new(),
+            file_matches: HashSet::new(),
+            directory_matches: HashSet::new(),
+            paths: HashSet::new(),
+            bytes: 0,
+        }
+    }
+
+    /// The pointer file for blob `id`, writing its content to the LFS object store.
+    fn convert(&mut self, repo: &Repository, id: ObjectId) -> AnyResult<ObjectId> {
+        if let Some(pointer) = self.pointers.get(&id) {
+            return Ok(*pointer);
+        }
+        let blob = repo.find_blob(id)?;
+        let pointer_id = if blob.data.starts_with(POINTER_VERSION.as_bytes()) {
+            id
+        } else {
+            let oid = hex::encode(Sha256::digest(&blob.data));
+            let path = self.objects_dir.join(&oid[..2]).join(&oid[2..4]).join(&oid);
+            if !self.dry_run && !path.exists() {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                files::write_atomically(&path, &blob.data)
+                    .map_err(|e| format!("writing {}: {e}", path.display()))?;
+            }
+            self.bytes += blob.data.len() as u64;
+            repo.write_blob(pointer(&oid, blob.data.len()))?.detach()
+        };
+        self.pointers.insert(id, pointer_id);
+        Ok(pointer_id)
+    }
+
+    /// Note how the patterns matched a converted `path`. Whenever that tells something new, the
+    /// `.gitattributes` written so far are left for the next trees.
+    fn record_match(&mut self, path: &BStr) {
+        let depth = path.split_str("/").count();
+        let mut learned = false;
+        for (index, pattern) in self.patterns.iter().enumerate() {
+            let matches = match pattern.match_depth(path) {
+                Some(matched) if matched == depth => &mut self.file_matches,
+                Some(_) => &mut self.directory_matches,
+                None => continue,
+            };
+            learned |= matches.insert(index);
+        }
+        if learned {
+            self.attributes.clear();
+        }
+    }
+
+    /// The `.gitattributes` patterns tracking what the patterns matched: a pattern itself for the
+    /// files it matched, and for patterns that matched nothing yet; the files below them for the
+    /// directories it matched.
+    fn attribute_patterns(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for (index, pattern) in self.patterns.iter().enumerate() {
+            let directory = self.directory_matches.contains(&index);
+            if self.file_matches.contains(&index) || !directory {
+                lines.push(pattern.to_string());
+            }
+            if directory {
+                lines.push(below(pattern));
+            }
+        }
+        lines
+    }
+
+    /// `tree` with the patterns tracked in its root `.gitattributes`.
+    fn track(&mut self, repo: &Repository, tree: ObjectId) -> AnyResult<ObjectId> {
+        let existing = repo
+            .find_tree(tree)?
+            .find_entry(".gitattributes")
+            .filter(|entry| entry.mode().is_blob())
+            .map(|entry| entry.object_id());
+        let attributes = match self.attributes.get(&existing) {
+            Some(id) => *id,
+            None => {
+                let text = match existing {
+                    Some(id) => repo.find_blob(id)?.data.clone(),
+                    None => Vec::new(),
+                };
+                let id = repo
+                    .write_blob(with_attributes(&text, &self.attribute_patterns()))?
+                    .detach();
+                self.attributes.insert(existing, id);
+                id
+            }
+        };
+        if existing == Some(attributes) {
+            return Ok(tree);
+        }
+        let mut editor = repo.edit_tree(tree)?;
+        editor.upsert(".gitattributes", EntryKind::Blob, attributes)?;
+        Ok(editor.write()?.detach())
+    }
+}
+
+impl Filter for LfsConvert {
+    fn name(&self) -> String {
+        "--lfs-convert".into()
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        Some(self.rewriter.stats())
+    }
+
+    fn apply(&mut self, repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        let patterns = self.patterns.clone();
+        let filtered = |path: &BStr| patterns.iter().any(|p| p.matches(path));
+        let mut rewriter = std::mem::take(&mut self.rewriter);
+        let rewritten =
+            rewriter.rewrite_commit(repo, descriptor, &filtered, &mut |path, mode, id| {
+                Ok(if mode.is_blob() && filtered(path) {
+                    EntryAction::Replace {
+                        id: self.convert(repo, id)?,
+                        mode,
+                    }
+                } else {
+                    EntryAction::Keep
+                })
+            });
+        self.rewriter = rewriter;
+        let rewritten = rewritten?;
+        for change in rewritten.changes {
+            self.record_match(change.path.as_ref());
+            self.paths.insert(change.path);
+        }
+        descriptor.tree = self.track(repo, rewritten.id)?;
+        Ok(())
+    }
+
+    fn finish(&mut self, _repo: &Repository, _map: &CommitMap) -> AnyResult<()> {
+        println!(
+            "Moved {} blob(s) at {} path(s) into LFS, {} in {}",
+            self.pointers
+                .iter()
+                .filter(|(blob, pointer)| blob != pointer)
+                .count(),
+            self.paths.len(),
+            format_size(self.bytes),
+            self.objects_dir.display()
+        );
+        Ok(())
+    }
+}
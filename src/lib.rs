@@ -1,6 +1,58 @@
+pub mod anonymize;
+pub mod apply;
+pub mod batch;
+pub mod budget;
+mod cache;
+pub mod changelog;
+mod checkpoint;
+pub mod clean;
+pub mod compare;
+pub mod config;
+pub mod content;
+pub mod doctor;
+pub mod error;
+pub mod exec;
+mod files;
+pub mod filter;
+pub mod gitmoji;
+pub mod identity;
+pub mod interrupt;
+pub mod leaks;
+pub mod lfs;
+pub mod lint;
+pub mod list;
+pub mod lookup;
+pub mod map;
+pub mod mbox;
+mod metrics;
 pub mod mirror;
+pub mod notes;
+pub mod paths;
+pub mod policy;
+pub mod pool;
+pub mod preview;
+#[cfg(feature = "remote-rules")]
+pub mod protection;
+pub mod provenance;
+pub mod recover;
+pub mod refs;
+pub mod remote;
+pub mod report;
+pub mod schedule;
+pub mod show;
+pub mod signatures;
+pub mod state;
+pub mod status;
+#[cfg(test)]
+mod testing;
+pub mod topology;
+pub mod translate;
+pub mod tree;
+pub mod vault;
+pub mod verify;
+pub mod worktree;
 
-type Anyhow = Box<dyn std::error::Error>;
+type Anyhow = Box<dyn std::error::Error + Send + Sync>;
 pub type AnyResult<T> = Result<T, Anyhow>;
 
-pub const BRANCH_POSTFIX: &'static str = "-magitied";
+pub const BRANCH_POSTFIX: &str = "-magitied";
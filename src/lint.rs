@@ -0,0 +1,380 @@
+//! Declarative rules for histories, from the `[policy]` table of `.magitulator.toml`.
+//!
+//! ```toml
+//! [policy]
+//! max-blob-size = "5M"
+//! required-trailers = ["Signed-off-by", "License: MIT"]
+//! allowed-author-domains = ["example.com"]
+//! forbidden-paths = ["*.pem", "secrets/"]
+//! ```
+//!
+//! `gitm lint` checks the commits `mirror` would rewrite against the rules, `mirror --check`
+//! the rewritten commits, and `mirror --fix` turns the rules into the filters that follow them.
+use crate::{
+    AnyResult,
+    budget::{self, format_size},
+    config,
+    error::Failure,
+    filter::AddedTrailer,
+    mirror::{self, CommitDescriptor, MirrorOptions},
+    policy,
+    tree::{self, PathPattern},
+};
+use colored::Colorize;
+use gix::{
+    ObjectId, Repository,
+    bstr::{BString, ByteSlice},
+    object::tree::diff::ChangeDetached,
+};
+use serde::{Deserialize, Deserializer, de::Error as _};
+use std::{collections::HashMap, fmt, str::FromStr};
+
+/// The `[policy]` table: rules every commit of a history should follow.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct HistoryPolicy {
+    /// Size of the largest blob a commit may add or modify.
+    #[serde(default, deserialize_with = "size")]
+    pub max_blob_size: Option<u64>,
+    /// Trailers every message must have.
+    #[serde(default, deserialize_with = "parsed")]
+    pub required_trailers: Vec<RequiredTrailer>,
+    /// Domains author emails must be at, or below; any domain if empty.
+    #[serde(default, deserialize_with = "parsed")]
+    pub allowed_author_domains: Vec<String>,
+    /// Paths no commit may add or modify.
+    #[serde(default, deserialize_with = "parsed")]
+    pub forbidden_paths: Vec<PathPattern>,
+}
+
+/// `<key>` or `<key>: <value>`: a trailer key messages must have, and the trailer `--fix`
+/// adds to those without it.
+#[derive(Clone, Debug)]
+pub struct RequiredTrailer {
+    key: String,
+    fix: Option<AddedTrailer>,
+}
+
+impl FromStr for RequiredTrailer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains(':') {
+            let fix: AddedTrailer = s.parse()?;
+            return Ok(RequiredTrailer {
+                key: fix.key().to_owned(),
+                fix: Some(fix),
+            });
+        }
+        let key = s.trim();
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(format!("invalid trailer key `{key}`"));
+        }
+        Ok(RequiredTrailer {
+            key: key.to_owned(),
+            fix: None,
+        })
+    }
+}
+
+/// Strings of the configuration, with environment variables interpolated, parsed with
+/// `FromStr`.
+fn parsed<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr<Err: fmt::Display>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|s| {
+            let s = config::interpolate(s).map_err(D::Error::custom)?;
+            s.parse().map_err(D::Error::custom)
+        })
+        .collect()
+}
+
+/// A number of bytes, or a size like `10M`.
+fn size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(u64),
+        Text(String),
+    }
+    match Size::deserialize(deserializer)? {
+        Size::Bytes(bytes) => Ok(Some(bytes)),
+        Size::Text(text) => budget::parse_size(&text)
+            .map(Some)
+            .map_err(D::Error::custom),
+    }
+}
+
+/// How a commit breaks a [`HistoryPolicy`].
+#[derive(Clone, Debug)]
+enum Problem {
+    LargeBlob { path: BString, size: u64 },
+    MissingTrailer(String),
+    AuthorDomain(BString),
+    ForbiddenPath(BString),
+}
+
+struct Violation {
+    id: ObjectId,
+    subject: String,
+    problem: Problem,
+}
+
+impl HistoryPolicy {
+    /// Whether `email` is at one of the allowed domains or below one.
+    fn allows_email(&self, email: &[u8]) -> bool {
+        let email = email.to_str_lossy().to_lowercase();
+        let domain = email.rsplit_once('@').map_or("", |(_, domain)| domain);
+        self.allowed_author_domains.iter().any(|allowed| {
+            let allowed = allowed.trim_start_matches('@').to_lowercase();
+            domain == allowed || domain.ends_with(&format!(".{allowed}"))
+        })
+    }
+
+    /// How a commit with `message`, `author_email` and `tree` breaks the rules, with paths and
+    /// blobs judged by what changed since `parent_tree`.
+    fn problems(
+        &self,
+        repo: &Repository,
+        message: &[u8],
+        author_email: &[u8],
+        parent_tree: Option<ObjectId>,
+        tree: ObjectId,
+    ) -> AnyResult<Vec<Problem>> {
+        let mut problems = Vec::new();
+        if self.max_blob_size.is_some() || !self.forbidden_paths.is_empty() {
+            for change in tree::diff(repo, parent_tree, tree)? {
+                let (location, mode, id) = match change {
+                    ChangeDetached::Addition {
+                        location,
+                        entry_mode,
+                        id,
+                        ..
+                    }
+                    | ChangeDetached::Modification {
+                        location,
+                        entry_mode,
+                        id,
+                        ..
+                    } => (location, entry_mode, id),
+                    _ => continue,
+                };
+                if mode.is_tree() {
+                    continue;
+                }
+                if self
+                    .forbidden_paths
+                    .iter()
+                    .any(|pattern| pattern.matches(location.as_ref()))
+                {
+                    problems.push(Problem::ForbiddenPath(location.clone()));
+                }
+                if let Some(limit) = self.max_blob_size {
+                    let size = repo.find_header(id)?.size();
+                    if mode.is_blob() && size > limit {
+                        problems.push(Problem::LargeBlob {
+                            path: location,
+                            size,
+                        });
+                    }
+                }
+            }
+        }
+        let (_, trailers) = policy::split_trailers(message);
+        for required in &self.required_trailers {
+            let present = trailers.lines().any(|line| {
+                line.split_once_str(":")
+                    .is_some_and(|(key, _)| key.eq_ignore_ascii_case(required.key.as_bytes()))
+            });
+            if !present {
+                problems.push(Problem::MissingTrailer(required.key.clone()));
+            }
+        }
+        if !self.allowed_author_domains.is_empty() && !self.allows_email(author_email) {
+            problems.push(Problem::AuthorDomain(author_email.into()));
+        }
+        Ok(problems)
+    }
+
+    /// Fail when a rewritten commit breaks the rules, before anything is written.
+    pub(crate) fn check(
+        &self,
+        repo: &Repository,
+        descriptors: &[CommitDescriptor],
+    ) -> AnyResult<()> {
+        // Rewritten trees by original commit, to diff against the rewritten parent.
+        let mut trees = HashMap::new();
+        let mut violations = Vec::new();
+        for descriptor in descriptors {
+            trees.insert(descriptor.original_id, descriptor.tree);
+            if descriptor.dropped {
+                continue;
+            }
+            let parent_tree = match descriptor.original_parent_ids.first() {
+                Some(parent) => match trees.get(parent) {
+                    Some(tree) => Some(*tree),
+                    None => Some(repo.find_commit(*parent)?.tree_id()?.detach()),
+                },
+                None => None,
+            };
+            let problems = self.problems(
+                repo,
+                &descriptor.message,
+                &descriptor.author.email,
+                parent_tree,
+                descriptor.tree,
+            )?;
+            violations.extend(problems.into_iter().map(|problem| Violation {
+                id: descriptor.original_id,
+                subject: subject(&descriptor.message),
+                problem,
+            }));
+        }
+        if violations.is_empty() {
+            return Ok(());
+        }
+        print_violations(&violations, self.max_blob_size);
+        Err(Failure::Validation(format!(
+            "{} rewritten commit(s) break the [policy] of {}; nothing was written",
+            commit_count(&violations),
+            config::FILE_NAME
+        ))
+        .into())
+    }
+
+    /// Fill in the options of `mirror` that make a history follow the rules, keeping those
+    /// already given: forbidden paths and blobs over the size limit are removed, and required
+    /// trailers with a value added where their key is missing.
+    ///
+    /// Authors outside the allowed domains and trailers without a value can't be fixed this
+    /// way, which is warned about.
+    pub fn fix(&self, options: &mut MirrorOptions) {
+        options
+            .remove_paths
+            .extend(self.forbidden_paths.iter().cloned());
+        if let Some(limit) = self.max_blob_size {
+            options.max_blob_size = Some(options.max_blob_size.map_or(limit, |l| l.min(limit)));
+        }
+        for required in &self.required_trailers {
+            match &required.fix {
+                Some(trailer) => options
+                    .added_trailers
+                    .push(trailer.clone().unless_key_present()),
+                None => eprintln!(
+                    "{} --fix can't add `{}` trailers: give them a value in [policy] \
+                     required-trailers, like `{}: <value>`",
+                    "warning:".yellow(),
+                    required.key,
+                    required.key
+                ),
+            }
+        }
+        if !self.allowed_author_domains.is_empty() {
+            eprintln!(
+                "{} --fix doesn't change authors outside allowed-author-domains; map them \
+                 with --map-file",
+                "warning:".yellow()
+            );
+        }
+    }
+}
+
+fn subject(message: &[u8]) -> String {
+    message
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_str_lossy()
+        .into_owned()
+}
+
+fn commit_count(violations: &[Violation]) -> usize {
+    let mut ids: Vec<ObjectId> = violations.iter().map(|v| v.id).collect();
+    ids.dedup();
+    ids.len()
+}
+
+fn print_violations(violations: &[Violation], max_blob_size: Option<u64>) {
+    eprintln!("Commits breaking the policy:");
+    for violation in violations {
+        let problem = match &violation.problem {
+            Problem::LargeBlob { path, size } => format!(
+                "adds {path} of {} (limit {})",
+                format_size(*size),
+                format_size(max_blob_size.unwrap_or_default())
+            ),
+            Problem::MissingTrailer(key) => format!("has no {key} trailer"),
+            Problem::AuthorDomain(email) => {
+                format!("is by {email}, outside the allowed domains")
+            }
+            Problem::ForbiddenPath(path) => format!("adds forbidden {path}"),
+        };
+        eprintln!(
+            "  {} {}: {problem}",
+            violation.id.to_hex_with_len(7),
+            violation.subject
+        );
+    }
+}
+
+/// Check the commits `mirror <base> <target>` would rewrite against `policy`, failing if any
+/// breaks it.
+pub fn lint(repo: &Repository, policy: &HistoryPolicy, base: &str, target: &str) -> AnyResult<()> {
+    let base_id = mirror::resolve_commit_id(repo, base)?;
+    let target_id = mirror::resolve_commit_id(repo, target)?;
+    let commits = mirror::get_commits_to_rewrite(repo, base_id, target_id, &[], false)?;
+    let mut violations = Vec::new();
+    for id in &commits {
+        let commit = repo.find_commit(*id)?;
+        let parent_tree = match commit.parent_ids().next() {
+            Some(parent) => Some(repo.find_commit(parent)?.tree_id()?.detach()),
+            None => None,
+        };
+        let message = commit.message_raw()?;
+        let problems = policy.problems(
+            repo,
+            message,
+            commit.author()?.email,
+            parent_tree,
+            commit.tree_id()?.detach(),
+        )?;
+        violations.extend(problems.into_iter().map(|problem| Violation {
+            id: *id,
+            subject: subject(message),
+            problem,
+        }));
+    }
+    if violations.is_empty() {
+        println!(
+            "All {} commit(s) in {base}..{target} follow the policy",
+            commits.len()
+        );
+        return Ok(());
+    }
+    print_violations(&violations, policy.max_blob_size);
+    let fixable = violations.iter().any(|violation| match &violation.problem {
+        Problem::LargeBlob { .. } | Problem::ForbiddenPath(_) => true,
+        Problem::MissingTrailer(key) => policy
+            .required_trailers
+            .iter()
+            .any(|required| &required.key == key && required.fix.is_some()),
+        Problem::AuthorDomain(_) => false,
+    });
+    if fixable {
+        eprintln!(
+            "`gitm mirror --fix` removes forbidden paths and large blobs, and adds required \
+             trailers that have a value"
+        );
+    }
+    Err(Failure::Validation(format!(
+        "{} of {} commit(s) in {base}..{target} break the [policy] of {}",
+        commit_count(&violations),
+        commits.len(),
+        config::FILE_NAME
+    ))
+    .into())
+}
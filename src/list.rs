@@ -0,0 +1,39 @@
+use crate::{
+    AnyResult,
+    refs::{self, MirrorNaming, MirrorState},
+};
+use colored::Colorize;
+use gix::Repository;
+
+pub fn list(repo: &Repository, naming: &MirrorNaming) -> AnyResult<()> {
+    for mirror in refs::list_mirrors(repo, naming)? {
+        let source_tip = match mirror.source_tip {
+            Some(oid) => oid.to_hex_with_len(7).to_string(),
+            None => "unknown".into(),
+        };
+
+        let state = match refs::mirror_state(repo, &mirror)? {
+            MirrorState::Pending => "apply pending".yellow(),
+            MirrorState::Applied => "applied".green(),
+            MirrorState::Advanced { new_commits } => format!(
+                "stale: {} advanced by {new_commits} commit(s)",
+                mirror.target
+            )
+            .red(),
+            MirrorState::Diverged => format!("stale: {} was rewritten", mirror.target).red(),
+            MirrorState::TargetMissing => format!("{} not found", mirror.target).red(),
+            MirrorState::Unknown => "source unknown".dimmed(),
+        };
+
+        println!(
+            "{} {} <- {} {} ({})",
+            mirror.name.shorten().to_string().green(),
+            mirror.tip.to_hex_with_len(7).to_string().dimmed(),
+            mirror.target,
+            source_tip.dimmed(),
+            state,
+        );
+    }
+
+    Ok(())
+}
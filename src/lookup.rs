@@ -0,0 +1,111 @@
+//! Translating commit hashes between original and rewritten history.
+use crate::{AnyResult, map::CommitMap, mirror};
+use colored::Colorize;
+use gix::{ObjectId, Repository};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Rewrites recorded in all commit maps, by original and by rewritten commit.
+#[derive(Default)]
+struct Links {
+    forward: BTreeMap<ObjectId, Vec<(ObjectId, String)>>,
+    backward: BTreeMap<ObjectId, Vec<(ObjectId, String)>>,
+}
+
+impl Links {
+    fn load(repo: &Repository) -> AnyResult<Self> {
+        let mut links = Links::default();
+        for (name, map) in CommitMap::load_all(repo)? {
+            let name = name.shorten().to_string();
+            for (old, new) in map.entries {
+                // Commits kept by `--after` map to themselves and were never rewritten.
+                if old == new {
+                    continue;
+                }
+                links
+                    .forward
+                    .entry(old)
+                    .or_default()
+                    .push((new, name.clone()));
+                links
+                    .backward
+                    .entry(new)
+                    .or_default()
+                    .push((old, name.clone()));
+            }
+        }
+        Ok(links)
+    }
+
+    /// The recorded commits `hash` abbreviates, or the commit it names.
+    fn matching(&self, repo: &Repository, hash: &str) -> AnyResult<Vec<ObjectId>> {
+        let is_hex = hash.len() >= 4 && hash.chars().all(|c| c.is_ascii_hexdigit());
+        if !is_hex {
+            return Ok(vec![mirror::resolve_commit_id(repo, hash)?]);
+        }
+        let hash = hash.to_ascii_lowercase();
+        let ids: BTreeSet<ObjectId> = self
+            .forward
+            .keys()
+            .chain(self.backward.keys())
+            .filter(|id| id.to_string().starts_with(&hash))
+            .copied()
+            .collect();
+        Ok(ids.into_iter().collect())
+    }
+
+    /// Print the commits reachable from `id` over `links`, one level of indentation per
+    /// rewrite.
+    fn print(
+        links: &BTreeMap<ObjectId, Vec<(ObjectId, String)>>,
+        id: ObjectId,
+        verb: &str,
+        depth: usize,
+        seen: &mut BTreeSet<ObjectId>,
+    ) {
+        for (next, name) in links.get(&id).into_iter().flatten() {
+            println!(
+                "{:indent$}{verb} {next} {}",
+                "",
+                format!("({name})").dimmed(),
+                indent = depth * 2
+            );
+            if seen.insert(*next) {
+                Self::print(links, *next, verb, depth + 1, seen);
+            }
+        }
+    }
+}
+
+/// Print what the commit `hash` was rewritten as, and what it was rewritten from, by every
+/// rewrite its commit maps record. Rewrites of rewrites are followed too, so a hash from before
+/// several rewrites still leads to the current commit.
+///
+/// `hash` may be abbreviated, and needn't exist in the repository any more: maps keep naming
+/// the original commits after the original branch is gone.
+pub fn map_lookup(repo: &Repository, hash: &str) -> AnyResult<()> {
+    let links = Links::load(repo)?;
+    let ids = links.matching(repo, hash)?;
+    let ids: Vec<ObjectId> = ids
+        .into_iter()
+        .filter(|id| links.forward.contains_key(id) || links.backward.contains_key(id))
+        .collect();
+    if ids.is_empty() {
+        return Err(format!("`{hash}` isn't recorded in any commit map").into());
+    }
+
+    for (i, id) in ids.into_iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        println!("{}", id.to_string().yellow());
+        Links::print(&links.forward, id, "rewritten as", 1, &mut BTreeSet::new());
+        Links::print(
+            &links.backward,
+            id,
+            "rewritten from",
+            1,
+            &mut BTreeSet::new(),
+        );
+    }
+    Ok(())
+}
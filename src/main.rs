@@ -1,5 +1,44 @@
-use clap::{Parser, Subcommand};
-use magitulator::{AnyResult, mirror};
+use clap::{CommandFactory, Parser, Subcommand};
+use gix::diff::rewrites::{Copies, CopySource};
+use magitulator::{
+    AnyResult,
+    anonymize::{Anonymize, MessageMode},
+    apply::{self, ApplyOptions},
+    batch::{self, BatchOptions},
+    budget::{self, BudgetAction},
+    changelog::{self, ChangelogFormat, ChangelogOptions, GroupRule},
+    clean::{self, CleanOptions},
+    compare::{self, CompareOptions},
+    config,
+    content::LineEnding,
+    doctor,
+    error::{self, Failure},
+    filter::{
+        AddedTrailer, BranchRename, DateCoupling, HeaderRule, MessageReplacement, TrailerRule,
+    },
+    gitmoji::{GitmojiPolicy, GitmojiType},
+    identity::{IdentityPart, IdentityScope, NamedIdentity, RewriteIdentity},
+    interrupt, leaks,
+    lint::{self, HistoryPolicy},
+    list, lookup,
+    mirror::{self, MirrorOptions, Preset},
+    paths::PathRename,
+    policy::{ChangePolicy, Field},
+    pool,
+    provenance::RewrittenAction,
+    recover,
+    refs::{self, MirrorNaming, MirrorState},
+    remote::{self, RemoteRewriteOptions},
+    report::MirrorRecipe,
+    schedule::BusinessHours,
+    show,
+    signatures::{self, SigningKey},
+    status,
+    topology::DatePolicy,
+    tree::PathPattern,
+    vault, verify,
+};
+use std::{ffi::OsString, num::NonZeroUsize, path::PathBuf, process::ExitCode, time::Duration};
 
 #[derive(Parser, Debug)]
 #[command(
@@ -13,9 +52,42 @@ struct Cli {
     #[command(subcommand)]
     command: Commands,
 
+    /// Operate on the repository at this path instead of the current directory.
+    #[arg(
+        short = 'C',
+        long = "repo",
+        global = true,
+        value_name = "PATH",
+        default_value = "."
+    )]
+    repo: PathBuf,
+
     /// Perform a dry run without writing any changes to the repository.
     #[arg(long, global = true)]
     dry_run: bool,
+
+    /// With --dry-run, show the fields each rewritten commit changes, old and new.
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    /// Keep mirrors under this ref namespace (e.g. `refs/magitied/`) instead of
+    /// `<target>-magitied` branches.
+    #[arg(long, global = true, value_name = "PREFIX")]
+    mirror_ref_namespace: Option<String>,
+
+    /// Only read and write refs under `refs/namespaces/<NAME>/`, like `git --namespace`, for
+    /// repositories shared by several tenants. Defaults to `$GIT_NAMESPACE`.
+    #[arg(long, global = true, value_name = "NAME")]
+    namespace: Option<String>,
+}
+
+impl Cli {
+    fn naming(&self) -> AnyResult<MirrorNaming> {
+        match &self.mirror_ref_namespace {
+            Some(prefix) => MirrorNaming::namespace(prefix),
+            None => Ok(MirrorNaming::Postfix),
+        }
+    }
 }
 
 #[derive(Subcommand, Debug)]
@@ -25,12 +97,33 @@ enum Commands {
         /// Starting object for the rewrite.
         base: String,
         /// Target object (branch name / commit hash) to rewrite.
-        target: String,
+        #[arg(required_unless_present = "all")]
+        target: Option<String>,
+        /// Mirror every local branch besides `base` and the mirrors, each onto `base`.
+        #[arg(long, conflicts_with = "target")]
+        all: bool,
+        /// With --all, how many branches to rewrite at once; defaults to the number of CPUs.
+        #[arg(short, long, value_name = "N", requires = "all")]
+        jobs: Option<NonZeroUsize>,
+        #[command(flatten)]
+        rewrite: Box<RewriteArgs>,
     },
     /// Replace an original branch with its mirrored counterpart.
     Apply {
         /// The original target branch to replace
         target: String,
+        /// Apply even if the branch moved since the mirror was created, dropping its new
+        /// commits, or is checked out in a worktree.
+        #[arg(long)]
+        force: bool,
+        /// Also update the files of worktrees that have the branch checked out, keeping
+        /// uncommitted changes.
+        #[arg(long, requires = "force")]
+        update_worktree: bool,
+        /// If the branch moved since the mirror was created, first mirror it again with the
+        /// command line the mirror was created with, so its new commits are kept.
+        #[arg(long)]
+        remirror: bool,
     },
     /// Rewrite commits and immediately update the target branch.
     Rewrite {
@@ -38,23 +131,1273 @@ enum Commands {
         base: String,
         /// Target branch to rewrite in-place.
         target: String,
+        /// Rewrite the branch even if it is checked out in a worktree.
+        #[arg(long)]
+        force: bool,
+        /// Also update the files of worktrees that have the branch checked out, keeping
+        /// uncommitted changes.
+        #[arg(long, requires = "force")]
+        update_worktree: bool,
+        #[command(flatten)]
+        rewrite: Box<RewriteArgs>,
+    },
+    /// Mirror `target` with every author and committer replaced by a pseudonym derived from
+    /// a keyed hash of their email, to share the structure of a repository without personal
+    /// data.
+    Anonymize {
+        /// Starting object for the rewrite.
+        base: String,
+        /// Target object (branch name / commit hash) to rewrite.
+        target: String,
+        /// File holding the hex key pseudonyms are derived from, created if missing [default:
+        /// .git/magitulator/anonymize.key].
+        #[arg(long, value_name = "FILE")]
+        key_file: Option<PathBuf>,
+        /// What to do with commit messages.
+        #[arg(long, value_name = "MODE", default_value = "keep")]
+        messages: MessageMode,
+        #[command(flatten)]
+        rewrite: Box<RewriteArgs>,
+    },
+    /// List existing mirrors and the commit they were created from.
+    List,
+    /// Print the original content of a blob redacted with `--redact` from the vault, or list
+    /// the tips branches had before they were rewritten.
+    Recover {
+        /// Rewritten commit containing the redacted blob.
+        #[arg(required_unless_present = "list")]
+        commit: Option<String>,
+        /// Path of the blob in that commit.
+        #[arg(required_unless_present = "list")]
+        path: Option<String>,
+        /// Vault file [default: .git/magitulator/vault.json].
+        #[arg(long, value_name = "FILE")]
+        vault: Option<PathBuf>,
+        /// List the tips branches had before magitulator rewrote them, found in backup refs,
+        /// reflogs and the report of the last run, even if the backup refs were deleted.
+        #[arg(long, conflicts_with_all = ["commit", "path", "vault"])]
+        list: bool,
+    },
+    /// Check whether the remote's branch protection would reject force-pushing the mirror of
+    /// `target`. Supports GitHub and GitLab remotes.
+    #[cfg(feature = "remote-rules")]
+    Protection {
+        /// Target branch whose mirror would be pushed.
+        target: String,
+        /// Remote to check.
+        #[arg(long, default_value = "origin")]
+        remote: String,
+    },
+    /// Show a rewritten commit next to the original it was created from.
+    Show {
+        /// Rewritten commit.
+        commit: String,
+    },
+    /// Compare the mirror of a branch, commit by commit, with the branch another tool (such
+    /// as git-filter-repo) rewrote it to: authors, committers, messages, headers, trees and
+    /// parents.
+    CompareWith {
+        /// The other tool's rewrite of the branch.
+        other: String,
+        /// Branch whose mirror to compare [default: the checked-out branch].
+        #[arg(long)]
+        target: Option<String>,
+        /// The other tool's `<old> <new>` commit map, like `.git/filter-repo/commit-map`, to
+        /// pair commits by their original instead of by position.
+        #[arg(long, value_name = "FILE")]
+        their_map: Option<PathBuf>,
+    },
+    /// Translate a commit hash between original and rewritten history, using the commit maps
+    /// of all mirror runs, so links to old hashes keep leading somewhere.
+    MapLookup {
+        /// Original or rewritten commit, possibly abbreviated.
+        commit: String,
+    },
+    /// Show what changed since the last mirror run.
+    Status,
+    /// Check that the repository is ready for a rewrite: object format, commit-graph, shallow
+    /// history, git operations in progress, unfinished runs, stale lock files and free disk
+    /// space.
+    Doctor {
+        /// First clean up after runs that were killed or crashed: their scratch directories,
+        /// half-created mirror refs, quarantined files and stale lock files.
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Report timezones, commit hours and message styles that could identify authors
+    /// of the commits `mirror <base> <target>` would rewrite, even after anonymization.
+    Leaks {
+        /// Starting object for the rewrite.
+        base: String,
+        /// Target object (branch name / commit hash) to rewrite.
+        target: String,
     },
+    /// Check the commits `mirror <base> <target>` would rewrite against the `[policy]` of
+    /// `.magitulator.toml`: blob sizes, required trailers, author domains and forbidden paths.
+    Lint {
+        /// Starting object for the rewrite.
+        base: String,
+        /// Target object (branch name / commit hash) to rewrite.
+        target: String,
+    },
+    /// Print a changelog of the commits in `<base>..<target>`, grouped by Conventional Commit
+    /// type.
+    Changelog {
+        /// Commit the changelog starts after.
+        base: String,
+        /// Commit the changelog ends at, such as a mirror.
+        target: String,
+        /// `<title>=<regex>`: list commits whose subject matches under `<title>`, instead of
+        /// grouping by type. Repeatable; the first matching rule wins.
+        #[arg(long = "group", value_name = "RULE")]
+        groups: Vec<GroupRule>,
+        #[arg(long, value_name = "FORMAT", default_value = "markdown")]
+        format: ChangelogFormat,
+    },
+    /// Report which commits `mirror <base> <target>` would rewrite are signed, by which keys,
+    /// and whether those keys are expired or revoked.
+    AuditSignatures {
+        /// Starting object for the rewrite.
+        base: String,
+        /// Target object (branch name / commit hash) to rewrite.
+        target: String,
+    },
+    /// Check that mirrors sharing history rewrote every shared commit identically.
+    Verify,
+    /// Run `mirror` in every repository of a TOML manifest, each with its profile's base,
+    /// target and options, and summarize the runs. Repositories given by URL are cloned.
+    Batch {
+        /// Manifest listing `[profiles.<name>]` and `[[repos]]`.
+        manifest: PathBuf,
+        /// Continue with the next repository when one fails.
+        #[arg(long)]
+        keep_going: bool,
+        /// Also write the results of all runs to this JSON file.
+        #[arg(long, value_name = "FILE")]
+        report: Option<PathBuf>,
+        /// How many repositories to process at once; their output is printed in the order of
+        /// the manifest.
+        #[arg(
+            short,
+            long,
+            value_name = "N",
+            default_value = "1",
+            requires = "keep_going"
+        )]
+        jobs: NonZeroUsize,
+        /// Clone repositories whose `url` is a local path with `git clone --shared`, borrowing
+        /// their objects instead of copying them, and repack each clone once its run is done.
+        #[arg(long)]
+        share_objects: bool,
+    },
+    /// Clone a repository bare into a temporary directory, mirror it there and push the
+    /// mirror, without needing a local checkout.
+    RemoteRewrite {
+        /// Repository to rewrite.
+        url: String,
+        /// Remote the mirror is pushed to.
+        #[arg(long, value_name = "URL")]
+        push_to: String,
+        /// Manifest to read `--profile` from, as used by `batch`.
+        #[arg(long, value_name = "FILE")]
+        manifest: Option<PathBuf>,
+        /// Profile giving the base, target and `mirror` options.
+        #[arg(long, value_name = "NAME", requires = "manifest")]
+        profile: Option<String>,
+        /// Starting object for the rewrite, instead of the profile's.
+        #[arg(long)]
+        base: Option<String>,
+        /// Target to rewrite, instead of the profile's.
+        #[arg(long)]
+        target: Option<String>,
+        /// Ref to push the mirror as [default: `refs/heads/<target>`].
+        #[arg(long, value_name = "REF")]
+        push_ref: Option<String>,
+        /// Replace the ref on the push remote even if the mirror doesn't descend from it.
+        #[arg(long)]
+        force: bool,
+        /// Clone `url`, a local path, with `git clone --shared`, borrowing its objects instead
+        /// of copying them, and repack the clone before pushing.
+        #[arg(long)]
+        share_objects: bool,
+        /// Further `mirror` options, after `--`.
+        #[arg(last = true, value_name = "MIRROR OPTIONS")]
+        args: Vec<String>,
+    },
+    /// Delete mirrors, backup refs, checkpoints, quarantined files and descriptor caches left
+    /// by earlier runs.
+    /// Without a category flag, all categories are cleaned.
+    Clean {
+        /// Remove mirror refs.
+        #[arg(long)]
+        mirrors: bool,
+        /// Remove backup refs of applied mirrors.
+        #[arg(long)]
+        backups: bool,
+        /// Remove saved checkpoints.
+        #[arg(long)]
+        checkpoints: bool,
+        /// Remove quarantine directories.
+        #[arg(long)]
+        quarantine: bool,
+        /// Remove descriptor caches of `--cache-descriptors`.
+        #[arg(long)]
+        caches: bool,
+        /// Only remove entries last touched before this date (`2024-05-01`, `2 weeks ago`, ...).
+        #[arg(long, value_name = "DATE", value_parser = parse_date)]
+        older_than: Option<gix::date::Time>,
+        /// Only remove entries whose name matches this glob. Repeatable.
+        #[arg(long = "pattern", value_name = "GLOB")]
+        patterns: Vec<String>,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct RewriteArgs {
+    /// Derive an extra commit header from the message: `<header>=<regex>`.
+    /// The header value is the first capture group, or the whole match.
+    #[arg(long = "derive-header", value_name = "RULE")]
+    header_rules: Vec<HeaderRule>,
+    /// Only rewrite commits committed on or after this date (`2024-05-01`, `2 weeks ago`, ...).
+    /// Older commits keep their hashes unless one of their ancestors is rewritten.
+    #[arg(long, value_name = "DATE", value_parser = parse_date)]
+    after: Option<gix::date::Time>,
+    /// Also leave out the history of this revision, in addition to `base`. Repeatable.
+    #[arg(long = "exclude", value_name = "REV")]
+    excludes: Vec<String>,
+    /// Fail unless `base` is an ancestor of `target`, instead of rewriting from their merge base.
+    #[arg(long)]
+    no_ff_base: bool,
+    /// Also rewrite `base` itself, keeping its original parents, e.g. to fix its metadata too.
+    #[arg(long)]
+    include_base: bool,
+    /// What to do with commits an earlier run already rewrote, recognized by the commit maps,
+    /// summary notes and the rewritten committer name.
+    #[arg(long, value_name = "ACTION", default_value = "warn")]
+    on_rewritten: RewrittenAction,
+    /// Author name of rewritten commits [default: Dr. Magitulator]; `user.name` from the git
+    /// config when given without a value.
+    #[arg(long, value_name = "NAME")]
+    author_name: Option<Option<String>>,
+    /// Author email of rewritten commits [default: the original one]; `user.email` from the
+    /// git config when given without a value.
+    #[arg(long, value_name = "EMAIL")]
+    author_email: Option<Option<String>>,
+    /// Committer name of rewritten commits [default: Dr. Magitulator]; `user.name` from the
+    /// git config when given without a value.
+    #[arg(long, value_name = "NAME")]
+    committer_name: Option<Option<String>>,
+    /// Committer email of rewritten commits [default: the original one]; `user.email` from
+    /// the git config when given without a value.
+    #[arg(long, value_name = "EMAIL")]
+    committer_email: Option<Option<String>>,
+    /// Map original identities to new ones with `Old Name <old@email> => New Name <new@email>`
+    /// lines. Either side may leave out the name or the email.
+    #[arg(long, value_name = "FILE")]
+    map_file: Option<PathBuf>,
+    /// Fail if `--map-file` has no rule for some authors or committers, and write a stub of
+    /// rules for them next to it, instead of asking for rules on the terminal.
+    #[arg(long, requires = "map_file")]
+    strict_identities: bool,
+    /// Replace identities with their canonical ones from the repository's `.mailmap`.
+    #[arg(long)]
+    use_mailmap: bool,
+    /// Erase the person with this email: reassign their commits to `--erased-as`, and replace
+    /// their email and names wherever messages, trailers and headers mention them. Repeatable.
+    #[arg(long = "erase-identity", value_name = "EMAIL")]
+    erase_identities: Vec<String>,
+    /// `Name <email>` that commits of erased people are reassigned to [default: Erased
+    /// Contributor <erased@anonymous.invalid>].
+    #[arg(long, value_name = "IDENTITY", requires = "erase_identities")]
+    erased_as: Option<NamedIdentity>,
+    /// Make the owners of paths in this CODEOWNERS file the authors of the commits that touch
+    /// them: the owner of most of a commit's changed paths. `@org/team` becomes `org/team
+    /// <org-team@teams.invalid>`.
+    #[arg(long, value_name = "FILE")]
+    owners_file: Option<PathBuf>,
+    /// Domain of the emails `--owners-file` gives `@` owners [default: teams.invalid].
+    #[arg(long, value_name = "DOMAIN", requires = "owners_file")]
+    owners_domain: Option<String>,
+    /// Only change the authors of rewritten commits, keeping the original committers.
+    #[arg(long, conflicts_with_all = ["committer_only", "committer_name", "committer_email"])]
+    author_only: bool,
+    /// Only change the committers of rewritten commits, keeping the original authors.
+    #[arg(long, conflicts_with_all = ["author_name", "author_email"])]
+    committer_only: bool,
+    /// Swap the two parents of these merges. Comma-separated or repeated.
+    #[arg(long, value_name = "REV", value_delimiter = ',')]
+    swap_parents: Vec<String>,
+    /// Swap the parents of merges whose second parent looks like the mainline.
+    #[arg(long)]
+    swap_parents_auto: bool,
+    /// Replace these merges by their second-parent commits, replayed onto the first parent.
+    /// Comma-separated or repeated.
+    #[arg(long, value_name = "REV", value_delimiter = ',')]
+    demerge: Vec<String>,
+    /// Move runs of linear commits onto branches merged back by synthetic merges, from a file
+    /// of `<from-rev> <to-rev> <branch name>` lines.
+    #[arg(long, value_name = "FILE")]
+    remerge: Option<PathBuf>,
+    /// Move the commits leading up to each tag matching this glob onto a merged branch.
+    #[arg(long, value_name = "GLOB")]
+    remerge_tags: Option<String>,
+    /// Collapse the commits of `<rev>..<rev>` into one. Repeatable.
+    #[arg(long, value_name = "RANGE")]
+    squash: Vec<String>,
+    /// File with the message template for squashed commits. Placeholders: `{first}`, `{last}`,
+    /// `{count}`, `{subjects}`, `{paths}`.
+    #[arg(long, value_name = "FILE", requires = "squash")]
+    squash_template: Option<PathBuf>,
+    /// Which author date squashed commits carry.
+    #[arg(
+        long,
+        value_name = "POLICY",
+        default_value = "first",
+        requires = "squash"
+    )]
+    date_policy: DatePolicy,
+    /// Only allow rewritten commits to differ from their originals in these fields; anything
+    /// else aborts the run before it writes commits. Comma-separated or repeated.
+    #[arg(long, value_name = "FIELD", value_delimiter = ',')]
+    allow_change: Vec<Field>,
+    /// Abort the run before it writes commits if a rewritten commit breaks the `[policy]` of
+    /// `.magitulator.toml`.
+    #[arg(long)]
+    check: bool,
+    /// Follow the `[policy]` of `.magitulator.toml`: remove forbidden paths and blobs over
+    /// its size limit, and add its required trailers where they are missing.
+    #[arg(long)]
+    fix: bool,
+    /// Sign every rewritten commit, and a tag retargeted with `--retarget-tag`, with this key.
+    /// Follows `gpg.format`; for `ssh`, the key is a key file.
+    #[arg(long, value_name = "KEYID")]
+    resign_with: Option<String>,
+    /// Like `--resign-with`, with `user.signingkey` from the git config when given without a
+    /// key.
+    #[arg(
+        long,
+        value_name = "KEYID",
+        require_equals = true,
+        conflicts_with = "resign_with"
+    )]
+    gpg_sign: Option<Option<String>>,
+    /// Keep the signatures of rewritten commits instead of removing them, although they no
+    /// longer verify.
+    #[arg(long, conflicts_with_all = ["resign_with", "gpg_sign"])]
+    keep_broken_signatures: bool,
+    /// Run this shell command in a checkout of every rewritten commit and abort the run, before
+    /// any ref is updated, on the first commit it fails for. Like `git rebase -x`.
+    #[arg(long, value_name = "CMD")]
+    exec: Option<String>,
+    /// Only check out this directory for `--exec`, for huge repositories. Repeatable.
+    #[arg(long = "exec-path", value_name = "DIR", requires = "exec")]
+    exec_paths: Vec<String>,
+    /// Run this shell command in a scratch checkout of every commit's tree and commit the
+    /// files it leaves there instead, like `git filter-branch --tree-filter`. Slow: each tree
+    /// is read back in full.
+    #[arg(long, value_name = "CMD")]
+    tree_filter: Option<String>,
+    /// Give this shell command the entries of every commit's tree, in `git ls-tree -r` lines,
+    /// and change those it prints in the `git update-index --index-info` format (mode `0`
+    /// removes). Like `git filter-branch --index-filter`, but nothing is checked out.
+    #[arg(long, value_name = "CMD")]
+    index_filter: Option<String>,
+    /// Apply a bundle of options, see the README. Explicit options still take precedence.
+    #[arg(long, value_name = "PRESET")]
+    preset: Option<Preset>,
+    /// Strip mbox separators, mail headers, `>From ` escapes and `-- ` signatures left in
+    /// messages by patch imports, taking the author from an embedded `From:` header.
+    #[arg(long)]
+    scrub_mbox: bool,
+    /// Remove trailers with this key (e.g. `git-svn-id`) from messages. Repeatable.
+    #[arg(long = "strip-trailer", value_name = "KEY")]
+    strip_trailers: Vec<String>,
+    /// `<key>=delete`, `<key>=header` or `<key>=header:<name>`: delete trailers with `<key>`,
+    /// or move their value into an extra header. Repeatable.
+    #[arg(long = "trailer", value_name = "RULE")]
+    trailer_rules: Vec<TrailerRule>,
+    /// Replace every message with the output of this shell command, given the message on stdin,
+    /// e.g. to translate messages. Outputs are cached in `.git/magitulator/messages`.
+    #[arg(long, value_name = "CMD")]
+    message_command: Option<String>,
+    /// Replace identities using a git-svn style authors file of `user = Name <email>` lines.
+    #[arg(long, value_name = "FILE")]
+    authors_file: Option<PathBuf>,
+    /// Give dates recorded without a timezone (`+0000`) this offset, e.g. `+0100`.
+    #[arg(long, value_name = "OFFSET", value_parser = parse_offset)]
+    default_timezone: Option<i32>,
+    /// Move every author and committer date by this much, e.g. `+8h`, `-30m` or `1d12h`
+    /// (units `s`, `m`, `h`, `d`, `w`).
+    #[arg(long, value_name = "DURATION", value_parser = parse_shift, allow_hyphen_values = true)]
+    shift_dates: Option<i64>,
+    /// `<start>..<end>`: give the rewritten commits evenly spaced dates from `<start>` to
+    /// `<end>`, oldest first, e.g. `2021-01-04..2021-06-30`.
+    #[arg(long, value_name = "RANGE", value_parser = parse_date_range, conflicts_with = "shift_dates")]
+    spread_dates: Option<(i64, i64)>,
+    /// `<days> <from>-<until> [<zone>]`: move dates outside this weekly schedule to the
+    /// nearest time inside it, keeping their order, e.g. `"Mon-Fri 09:00-17:00 Europe/Helsinki"`.
+    #[arg(long, value_name = "SCHEDULE")]
+    business_hours: Option<BusinessHours>,
+    /// Move every author and committer date by random noise of up to this many seconds either
+    /// way (or a duration like `5m`), keeping the order of all dates, e.g. to break up dates
+    /// at exactly `:00`.
+    #[arg(long, value_name = "SECONDS", value_parser = parse_jitter)]
+    jitter: Option<i64>,
+    /// Seed of the `--jitter` noise, so a run can be repeated with the same dates. Without it,
+    /// a random seed is picked and printed.
+    #[arg(long, value_name = "SEED", requires = "jitter")]
+    seed: Option<u64>,
+    /// Give every commit its committer date as author date too.
+    #[arg(long, conflicts_with = "committer_date_is_author_date")]
+    author_date_is_committer_date: bool,
+    /// Give every commit its author date as committer date too, like `git rebase
+    /// --committer-date-is-author-date`.
+    #[arg(long)]
+    committer_date_is_author_date: bool,
+    /// Record every author and committer date in this offset, e.g. `+0000`, keeping the
+    /// instant.
+    #[arg(long, value_name = "OFFSET", value_parser = parse_offset, allow_hyphen_values = true)]
+    set_timezone: Option<i32>,
+    /// Drop commits that don't change their parent's tree, once the other filters ran.
+    #[arg(long)]
+    drop_empty: bool,
+    /// Rewrite issue references in messages using a CSV file of `old-id,new` lines,
+    /// where `new` is an issue id or URL.
+    #[arg(long, value_name = "CSV")]
+    issue_map: Option<PathBuf>,
+    /// Strip gitmoji and other emoji from the start of subjects, or turn them into
+    /// Conventional Commit types, shortcodes or emoji.
+    #[arg(long, value_name = "POLICY")]
+    gitmoji: Option<GitmojiPolicy>,
+    /// `<emoji or :shortcode:>=<type>`: the Conventional Commit type `--gitmoji conventional`
+    /// gives an emoji, over the built-in one. Repeatable.
+    #[arg(long = "gitmoji-type", value_name = "RULE", requires = "gitmoji")]
+    gitmoji_types: Vec<GitmojiType>,
+    /// `<regex>==><replacement>`: replace every match in messages, with `$1` or `${name}`
+    /// standing for capture groups. Repeatable; applied in order.
+    #[arg(long = "replace-message", value_name = "RULE")]
+    message_replacements: Vec<MessageReplacement>,
+    /// Rebuild the messages of merge commits from this template, e.g. `Merge branch
+    /// '{{branch}}'`. Placeholders: `{{branch}}`, the merged branches as the original subject
+    /// or the local branches name them; `{{merged_id}}`, `{{merged_id_short}}`, the merged
+    /// commits before the rewrite; `{{subject}}`, `{{body}}`, `{{original_subject}}`.
+    #[arg(long, value_name = "TEMPLATE")]
+    merge_message_template: Option<String>,
+    /// `<old>=<new>`: name the merged branch `<old>` `<new>` in regenerated merge messages.
+    /// Repeatable.
+    #[arg(
+        long = "rename-merged-branch",
+        value_name = "RULE",
+        requires = "merge_message_template"
+    )]
+    merged_branch_renames: Vec<BranchRename>,
+    /// Rebuild every message from this template. Placeholders: `{{message}}`, `{{subject}}`,
+    /// `{{body}}`, `{{original_message}}`, `{{original_subject}}`, `{{original_id}}`,
+    /// `{{original_id_short}}`, `{{author_name}}`, `{{author_email}}`, `{{index}}`, `{{total}}`.
+    #[arg(long, value_name = "TEMPLATE")]
+    message_template: Option<String>,
+    /// `<key>: <value>`: append this trailer to every message that doesn't have it already,
+    /// e.g. `Signed-off-by: Name <email>`. `{{original_id}}` in the value stands for the
+    /// original commit's id. Repeatable.
+    #[arg(long = "add-trailer", value_name = "TRAILER")]
+    added_trailers: Vec<AddedTrailer>,
+    /// Only keep paths matching this glob in rewritten commits. Repeatable.
+    #[arg(long = "keep-path", value_name = "GLOB")]
+    keep_paths: Vec<PathPattern>,
+    /// Remove paths matching this glob from rewritten commits. Repeatable.
+    #[arg(long = "remove-path", value_name = "GLOB")]
+    remove_paths: Vec<PathPattern>,
+    /// `<old>:<new>`: move a file or directory to a new path in every rewritten commit, as if
+    /// it had always lived there. Repeatable; applied in order.
+    #[arg(long = "rename-path", value_name = "RENAME")]
+    rename_paths: Vec<PathRename>,
+    /// Make this directory the root of every rewritten commit, pruning commits that don't
+    /// change it, e.g. to split a directory of a monorepo into a repository of its own.
+    #[arg(long, value_name = "DIR", value_parser = parse_directory)]
+    subdirectory_filter: Option<String>,
+    /// Make `--keep-path` and `--remove-path` also match the names files had before they were
+    /// renamed, found with rename detection over the target's history.
+    #[arg(long)]
+    follow_renames: bool,
+    /// Consider a file renamed when at least this share of it is unchanged, like `git -M`.
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        value_parser = parse_similarity,
+        default_value = "50%",
+        requires = "follow_renames"
+    )]
+    find_renames: f32,
+    /// Also follow files back to the source they were copied from, when at least this similar
+    /// to it (50% if no value is given), like `git -C`.
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        value_parser = parse_similarity,
+        num_args = 0..=1,
+        default_missing_value = "50%",
+        requires = "follow_renames"
+    )]
+    find_copies: Option<f32>,
+    /// Look for copy sources among all files of the parent commit, not only the modified ones,
+    /// like `git --find-copies-harder`; implies `--find-copies`. Slow on large trees.
+    #[arg(long, requires = "follow_renames")]
+    find_copies_harder: bool,
+    /// Only compare up to this many added and deleted files with each other when looking for
+    /// inexact renames in a commit, like `diff.renameLimit`; 0 for no limit.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1000,
+        requires = "follow_renames"
+    )]
+    rename_limit: usize,
+    /// Remove every path that the target's `.gitignore` files ignore from all rewritten commits.
+    #[arg(long)]
+    apply_gitignore: bool,
+    /// Replace the first copyright line of files with this template. `{years}` renders as
+    /// `<first>-<year>` from the existing header's first year and the commit's author year;
+    /// `{first}` and `{year}` render each on its own.
+    #[arg(long, value_name = "TEMPLATE")]
+    copyright_template: Option<String>,
+    /// Only update copyright lines in files matching this glob. Repeatable.
+    #[arg(
+        long = "copyright-path",
+        value_name = "GLOB",
+        requires = "copyright_template"
+    )]
+    copyright_paths: Vec<PathPattern>,
+    /// Replace text in every text file with the rules of this file, one per line:
+    /// `<text>==><replacement>`, `regex:<regex>==><replacement>`, or a text to replace with
+    /// `***REMOVED***`.
+    #[arg(long, value_name = "FILE")]
+    replace_text: Option<PathBuf>,
+    /// Give every text file of every rewritten commit these line endings. Files with a NUL
+    /// byte in their first 8000 bytes are taken for binary and left alone.
+    #[arg(long, value_name = "EOL")]
+    normalize_eol: Option<LineEnding>,
+    /// Move blobs at paths matching this glob into Git LFS: they become pointer files, their
+    /// content goes to `.git/lfs/objects`, and the glob is tracked in `.gitattributes`.
+    /// Repeatable.
+    #[arg(long = "lfs-convert", value_name = "GLOB")]
+    lfs_convert: Vec<PathPattern>,
+    /// Remove every blob larger than this size (`500k`, `10M`, `1G`) from all rewritten
+    /// commits, and report the paths they were at.
+    #[arg(long, value_name = "SIZE", value_parser = budget::parse_size)]
+    strip_blobs_bigger_than: Option<u64>,
+    /// Replace the blobs `--strip-blobs-bigger-than` removes with a stub naming the original
+    /// blob and its size, instead of removing their paths.
+    #[arg(long, requires = "strip_blobs_bigger_than")]
+    stub_stripped_blobs: bool,
+    /// Flag commits whose added or modified blobs exceed this size (`500k`, `10M`, `1G`).
+    #[arg(long, value_name = "SIZE", value_parser = budget::parse_size)]
+    size_budget: Option<u64>,
+    /// What to do with commits over `--size-budget`.
+    #[arg(
+        long,
+        value_name = "ACTION",
+        default_value = "warn",
+        requires = "size_budget"
+    )]
+    size_budget_action: BudgetAction,
+    /// Replace blobs at paths matching this glob with a placeholder, keeping the originals
+    /// encrypted in the vault. The passphrase is read from `MAGITULATOR_VAULT_PASSPHRASE`.
+    #[arg(long = "redact", value_name = "GLOB")]
+    redact_paths: Vec<PathPattern>,
+    /// Vault file for `--redact` [default: .git/magitulator/vault.json].
+    #[arg(long, value_name = "FILE")]
+    vault: Option<PathBuf>,
+    /// Warn instead of failing when path filters undo each other on the same file, like a
+    /// `--redact` of a file `--remove-path` removes anyway, and let the later filter win.
+    #[arg(long)]
+    allow_filter_conflicts: bool,
+    /// After the run, report how long each filter took and how many commits and paths it
+    /// changed.
+    #[arg(long)]
+    profile_filters: bool,
+    /// Write timings, commit and object counts and cache hit rates of the run to this JSON
+    /// file, for tuning rewrites of large repositories. Stays on this machine.
+    #[arg(long, value_name = "FILE")]
+    metrics_out: Option<PathBuf>,
+    /// Stop after this long (`45m`, `2h`, `1h30m`), saving a checkpoint that the next run of
+    /// the same command resumes from, and exit with code 7 until every commit is done.
+    #[arg(long, value_name = "DURATION", value_parser = parse_time_budget)]
+    time_budget: Option<Duration>,
+    /// With `--dry-run`, keep the filtered commits in `.git/magitulator/descriptors` and reuse
+    /// them in dry runs with the same filters, so tuning output options like `--verbose`
+    /// doesn't filter every commit again.
+    #[arg(long)]
+    cache_descriptors: bool,
+    /// If the target is an annotated tag, also create `magitulator/<tag>` pointing at the mirror.
+    #[arg(long)]
+    retarget_tag: bool,
+    /// Check the mirror out in a new linked worktree in the temporary directory.
+    #[arg(long)]
+    checkout_mirror: bool,
+    /// Attach a summary of the run (command, commits, operator, date) to the mirror tip as a
+    /// note under `refs/notes/magitulator-summary`.
+    #[arg(long)]
+    summary_note: bool,
+}
+
+fn parse_offset(input: &str) -> Result<i32, String> {
+    let invalid = || format!("expected an offset like `+0100` or `-0530`, got `{input}`");
+    let (sign, digits) = match input.as_bytes().first() {
+        Some(b'+') => (1, &input[1..]),
+        Some(b'-') => (-1, &input[1..]),
+        _ => return Err(invalid()),
+    };
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let hours: i32 = digits[..2].parse().map_err(|_| invalid())?;
+    let minutes: i32 = digits[2..].parse().map_err(|_| invalid())?;
+    Ok(sign * (hours * 3600 + minutes * 60))
+}
+
+/// A signed duration like `+8h`, `-30m` or `1d12h`, in seconds.
+fn parse_shift(input: &str) -> Result<i64, String> {
+    let invalid = || format!("expected a duration like `+8h`, `-30m` or `1d12h`, got `{input}`");
+    let (sign, mut rest) = match input.as_bytes().first() {
+        Some(b'+') => (1, &input[1..]),
+        Some(b'-') => (-1, &input[1..]),
+        _ => (1, input),
+    };
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+    let mut seconds: i64 = 0;
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(invalid)?;
+        let number: i64 = rest[..split].parse().map_err(|_| invalid())?;
+        let unit = match rest.as_bytes()[split] {
+            b's' => 1,
+            b'm' => 60,
+            b'h' => 60 * 60,
+            b'd' => 24 * 60 * 60,
+            b'w' => 7 * 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        seconds = number
+            .checked_mul(unit)
+            .and_then(|part| seconds.checked_add(part))
+            .ok_or_else(invalid)?;
+        rest = &rest[split + 1..];
+    }
+    Ok(sign * seconds)
+}
+
+/// An identity option's value, or the git config's when it was given without one.
+fn identity_part(value: Option<String>) -> IdentityPart {
+    value.map_or(IdentityPart::Configured, IdentityPart::Given)
+}
+
+/// A directory of the repository, without leading and trailing slashes.
+fn parse_directory(input: &str) -> Result<String, String> {
+    let directory = input.trim_matches('/');
+    if directory.is_empty()
+        || directory
+            .split('/')
+            .any(|c| c.is_empty() || c == "." || c == "..")
+    {
+        return Err(format!(
+            "expected a directory like `libs/parser`, got `{input}`"
+        ));
+    }
+    Ok(directory.to_owned())
+}
+
+/// A similarity threshold like `75%`, as a fraction.
+fn parse_similarity(input: &str) -> Result<f32, String> {
+    let percent: u8 = input
+        .strip_suffix('%')
+        .unwrap_or(input)
+        .parse()
+        .ok()
+        .filter(|percent| *percent <= 100)
+        .ok_or_else(|| format!("expected a percentage like `75%`, got `{input}`"))?;
+    Ok(f32::from(percent) / 100.0)
+}
+
+fn parse_date(input: &str) -> Result<gix::date::Time, String> {
+    gix::date::parse(input, Some(std::time::SystemTime::now())).map_err(|e| e.to_string())
+}
+
+/// A positive number of seconds, or a duration like `5m`.
+fn parse_jitter(input: &str) -> Result<i64, String> {
+    let seconds = match input.parse() {
+        Ok(seconds) => seconds,
+        Err(_) => parse_shift(input)?,
+    };
+    if seconds <= 0 {
+        return Err(format!("`{input}` must be more than zero seconds"));
+    }
+    // Knots of the noise are four times as far apart.
+    if seconds.checked_mul(4).is_none() {
+        return Err(format!("`{input}` is too many seconds"));
+    }
+    Ok(seconds)
+}
+
+/// A positive duration like `45m` or `1h30m`.
+fn parse_time_budget(input: &str) -> Result<Duration, String> {
+    if input.starts_with(['+', '-']) {
+        return Err(format!(
+            "expected a duration like `45m` or `1h30m`, got `{input}`"
+        ));
+    }
+    let seconds = parse_shift(input)?;
+    if seconds <= 0 {
+        return Err(format!("`{input}` must be more than zero seconds"));
+    }
+    Ok(Duration::from_secs(seconds as u64))
 }
 
-fn main() -> AnyResult<()> {
-    let cli = Cli::parse();
+/// `<start>..<end>`, as seconds since the UNIX epoch.
+fn parse_date_range(input: &str) -> Result<(i64, i64), String> {
+    let (start, end) = input
+        .split_once("..")
+        .ok_or_else(|| format!("expected `<start>..<end>`, got `{input}`"))?;
+    let (start, end) = (parse_date(start)?.seconds, parse_date(end)?.seconds);
+    if end < start {
+        return Err(format!("`{input}` ends before it starts"));
+    }
+    Ok((start, end))
+}
+
+impl RewriteArgs {
+    fn into_options(
+        self,
+        dry_run: bool,
+        verbose: bool,
+        naming: MirrorNaming,
+        history_policy: Option<&HistoryPolicy>,
+    ) -> AnyResult<MirrorOptions> {
+        if (self.check || self.fix) && history_policy.is_none() {
+            return Err(format!(
+                "{} a [policy] table in {}",
+                match (self.check, self.fix) {
+                    (true, true) => "--check and --fix need",
+                    (true, false) => "--check needs",
+                    _ => "--fix needs",
+                },
+                config::FILE_NAME
+            )
+            .into());
+        }
+        let squash_template = match &self.squash_template {
+            Some(path) => Some(std::fs::read_to_string(path)?),
+            None => None,
+        };
+        let mut options = MirrorOptions {
+            dry_run,
+            verbose,
+            in_place: false,
+            force: false,
+            update_worktrees: false,
+            naming,
+            header_rules: self.header_rules,
+            after: self.after,
+            retarget_tag: self.retarget_tag,
+            checkout_mirror: self.checkout_mirror,
+            summary_note: self.summary_note,
+            excludes: self.excludes,
+            no_ff_base: self.no_ff_base,
+            include_base: self.include_base,
+            on_rewritten: self.on_rewritten,
+            identity: RewriteIdentity {
+                author_name: self.author_name.map(identity_part),
+                author_email: self.author_email.map(identity_part),
+                committer_name: self.committer_name.map(identity_part),
+                committer_email: self.committer_email.map(identity_part),
+            },
+            map_file: self.map_file,
+            strict_identities: self.strict_identities,
+            use_mailmap: self.use_mailmap,
+            erase_identities: self.erase_identities,
+            erased_as: self.erased_as,
+            owners_file: self.owners_file,
+            owners_domain: self.owners_domain,
+            identity_scope: match (self.author_only, self.committer_only) {
+                (true, _) => IdentityScope::AuthorOnly,
+                (_, true) => IdentityScope::CommitterOnly,
+                _ => IdentityScope::Both,
+            },
+            swap_parents: self.swap_parents,
+            swap_parents_auto: self.swap_parents_auto,
+            demerge: self.demerge,
+            remerge: self.remerge,
+            remerge_tags: self.remerge_tags,
+            squash: self.squash,
+            squash_template,
+            squash_dates: self.date_policy,
+            policy: (!self.allow_change.is_empty()).then(|| ChangePolicy {
+                allowed: self.allow_change.into_iter().collect(),
+            }),
+            history_policy: history_policy.filter(|_| self.check).cloned(),
+            issue_map: self.issue_map,
+            gitmoji: self.gitmoji,
+            gitmoji_types: self.gitmoji_types,
+            message_replacements: self.message_replacements,
+            merge_message_template: self.merge_message_template,
+            merged_branch_renames: self.merged_branch_renames,
+            message_template: self.message_template,
+            added_trailers: self.added_trailers,
+            keep_paths: self.keep_paths,
+            remove_paths: self.remove_paths,
+            rename_paths: self.rename_paths,
+            subdirectory: self.subdirectory_filter,
+            follow_renames: self.follow_renames,
+            rewrites: gix::diff::Rewrites {
+                percentage: Some(self.find_renames),
+                copies: (self.find_copies.is_some() || self.find_copies_harder).then(|| Copies {
+                    source: if self.find_copies_harder {
+                        CopySource::FromSetOfModifiedFilesAndAllSources
+                    } else {
+                        CopySource::FromSetOfModifiedFiles
+                    },
+                    percentage: Some(self.find_copies.unwrap_or(0.5)),
+                }),
+                limit: self.rename_limit,
+                ..Default::default()
+            },
+            apply_gitignore: self.apply_gitignore,
+            copyright_template: self.copyright_template,
+            copyright_paths: self.copyright_paths,
+            replace_text: self.replace_text,
+            normalize_eol: self.normalize_eol,
+            lfs_convert: self.lfs_convert,
+            max_blob_size: self.strip_blobs_bigger_than,
+            stub_stripped_blobs: self.stub_stripped_blobs,
+            size_budget: self.size_budget,
+            size_budget_action: self.size_budget_action,
+            redact_paths: self.redact_paths,
+            vault: self.vault,
+            allow_filter_conflicts: self.allow_filter_conflicts,
+            profile_filters: self.profile_filters,
+            metrics_out: self.metrics_out,
+            time_budget: self.time_budget,
+            cache_descriptors: self.cache_descriptors,
+            trailer_rules: self
+                .strip_trailers
+                .iter()
+                .map(|key| TrailerRule::delete(key))
+                .chain(self.trailer_rules)
+                .collect(),
+            message_command: self.message_command,
+            scrub_mbox: self.scrub_mbox,
+            authors_file: self.authors_file,
+            default_timezone: self.default_timezone,
+            shift_dates: self.shift_dates,
+            spread_dates: self.spread_dates,
+            business_hours: self.business_hours,
+            jitter: self.jitter,
+            date_coupling: match (
+                self.author_date_is_committer_date,
+                self.committer_date_is_author_date,
+            ) {
+                (true, _) => Some(DateCoupling::CommitterDate),
+                (_, true) => Some(DateCoupling::AuthorDate),
+                (false, false) => None,
+            },
+            seed: self.seed,
+            set_timezone: self.set_timezone,
+            drop_empty: self.drop_empty,
+            anonymize: None,
+            resign_with: match (self.resign_with, self.gpg_sign) {
+                (Some(key), _) | (None, Some(Some(key))) => Some(SigningKey::Given(key)),
+                (None, Some(None)) => Some(SigningKey::Configured),
+                (None, None) => None,
+            },
+            keep_broken_signatures: self.keep_broken_signatures,
+            exec: self.exec,
+            exec_paths: self.exec_paths,
+            tree_filter: self.tree_filter,
+            index_filter: self.index_filter,
+        };
+        if let Some(preset) = self.preset {
+            preset.apply(&mut options);
+        }
+        if let Some(policy) = history_policy.filter(|_| self.fix) {
+            policy.fix(&mut options);
+        }
+        Ok(options)
+    }
+}
 
-    match &cli.command {
-        Commands::Mirror { base, target } => {
-            mirror::mirror(&base, &target, cli.dry_run)?;
+/// The command line `args` with `config_args` inserted after the subcommand, so options given
+/// on the command line come later and win.
+fn with_config_args(mut args: Vec<OsString>, config_args: Vec<OsString>) -> Vec<OsString> {
+    let mut index = 1;
+    while let Some(arg) = args.get(index) {
+        index += 1;
+        let arg = arg.to_string_lossy();
+        // The value of a global option is the next argument, unless it is given with `=` or
+        // attached to `-C`, as in `-Cpath` and `-vCpath`.
+        let value_follows = match arg.strip_prefix("--") {
+            Some(long) => ["repo", "mirror-ref-namespace", "namespace"].contains(&long),
+            None => arg.len() > 1 && arg.starts_with('-') && arg.ends_with('C'),
+        };
+        if value_follows {
+            index += 1;
+        } else if !arg.starts_with('-') {
+            break;
+        }
+    }
+    args.splice(index.min(args.len())..index.min(args.len()), config_args);
+    args
+}
+
+/// Mirror `target` again with the recorded command line of its mirror, if the branch gained
+/// commits or was rewritten since the mirror was created. Returns whether it did.
+fn remirror_if_moved(
+    repo: &gix::Repository,
+    target: &str,
+    naming: &MirrorNaming,
+    config_args: Vec<OsString>,
+    history_policy: Option<&HistoryPolicy>,
+    verbose: bool,
+    dry_run: bool,
+) -> AnyResult<bool> {
+    let target = target.strip_prefix("refs/heads/").unwrap_or(target);
+    // Without a mirror, `apply` explains how to create one.
+    let Some(mirror) = refs::find_mirror(repo, naming, target)? else {
+        return Ok(false);
+    };
+    if !matches!(
+        refs::mirror_state(repo, &mirror)?,
+        MirrorState::Advanced { .. } | MirrorState::Diverged
+    ) {
+        return Ok(false);
+    }
+    let Some(recipe) = MirrorRecipe::load(repo, mirror.name.as_ref())? else {
+        return Err(Failure::ProtectedRef(format!(
+            "{target} moved since {} was created, which doesn't record how it was created; \
+             mirror it again yourself",
+            mirror.name.shorten()
+        ))
+        .into());
+    };
+    let command = recipe.command.join(" ");
+    let args = with_config_args(
+        recipe.command.into_iter().map(Into::into).collect(),
+        config_args,
+    );
+    let recorded = Cli::try_parse_from(args)
+        .map_err(|e| format!("can't mirror {target} again with `{command}`: {e}"))?;
+    let (base, rewrite, anonymize) = match recorded.command {
+        Commands::Mirror { base, rewrite, .. } => (base, rewrite, None),
+        Commands::Anonymize {
+            base,
+            key_file,
+            messages,
+            rewrite,
+            ..
+        } => (base, rewrite, Some(Anonymize { key_file, messages })),
+        _ => return Err(format!("`{command}` doesn't create a mirror").into()),
+    };
+    println!(
+        "{target} moved since {} was created; mirroring it again as `{command}`",
+        mirror.name.shorten()
+    );
+    let mut options = rewrite.into_options(dry_run, verbose, naming.clone(), history_policy)?;
+    options.anonymize = anonymize;
+    mirror::mirror(repo, &base, target, &options)?;
+    println!();
+    Ok(true)
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            ExitCode::from(error::exit_code(&*e))
+        }
+    }
+}
+
+fn run() -> AnyResult<()> {
+    let mut cli = Cli::parse();
+    // Checked for every command, so mistakes surface before they matter.
+    let mirror_command = Cli::command();
+    let config_args = config::mirror_args(
+        &cli.repo.join(config::FILE_NAME),
+        mirror_command
+            .find_subcommand("mirror")
+            .expect("`mirror` is a subcommand"),
+    )?;
+    if matches!(
+        cli.command,
+        Commands::Mirror { .. } | Commands::Rewrite { .. } | Commands::Anonymize { .. }
+    ) && !config_args.is_empty()
+    {
+        cli = Cli::parse_from(with_config_args(
+            std::env::args_os().collect(),
+            config_args.clone(),
+        ));
+    }
+    if let Some(namespace) = &cli.namespace {
+        // SAFETY: no other thread runs yet. Through the environment, the namespace also
+        // reaches the repositories `batch` opens and the `gitm` and `git` processes it runs.
+        unsafe { std::env::set_var("GIT_NAMESPACE", namespace) };
+    }
+    if matches!(
+        cli.command,
+        Commands::Mirror { .. }
+            | Commands::Rewrite { .. }
+            | Commands::Anonymize { .. }
+            | Commands::Apply { remirror: true, .. }
+            | Commands::Batch { .. }
+            | Commands::RemoteRewrite { .. }
+    ) {
+        interrupt::install_handler()?;
+    }
+    let naming = cli.naming()?;
+    let history_policy = config::policy(&cli.repo.join(config::FILE_NAME))?;
+    // `batch` and `remote-rewrite` work on repositories of their own.
+    let open = || -> AnyResult<gix::Repository> { Ok(gix::open(&cli.repo)?) };
+
+    match cli.command {
+        Commands::Mirror {
+            base,
+            target,
+            all: false,
+            rewrite,
+            ..
+        } => {
+            mirror::mirror(
+                &open()?,
+                &base,
+                &target.expect("required without --all"),
+                &rewrite.into_options(cli.dry_run, cli.verbose, naming, history_policy.as_ref())?,
+            )?;
+        }
+        Commands::Mirror {
+            base,
+            all: true,
+            jobs,
+            rewrite,
+            ..
+        } => {
+            let jobs = jobs
+                .or_else(|| std::thread::available_parallelism().ok())
+                .map_or(1, NonZeroUsize::get);
+            pool::mirror_all(
+                &open()?,
+                &base,
+                rewrite.into_options(cli.dry_run, cli.verbose, naming, history_policy.as_ref())?,
+                jobs,
+            )?;
+        }
+        Commands::Apply {
+            target,
+            force,
+            update_worktree,
+            remirror,
+        } => {
+            let repo = open()?;
+            if remirror
+                && remirror_if_moved(
+                    &repo,
+                    &target,
+                    &naming,
+                    config_args,
+                    history_policy.as_ref(),
+                    cli.verbose,
+                    cli.dry_run,
+                )?
+                && cli.dry_run
+            {
+                println!("Would then apply the new mirror");
+                return Ok(());
+            }
+            apply::apply(
+                &repo,
+                &target,
+                &ApplyOptions {
+                    dry_run: cli.dry_run,
+                    naming,
+                    force,
+                    update_worktrees: update_worktree,
+                },
+            )?;
+        }
+        Commands::Rewrite {
+            base,
+            target,
+            force,
+            update_worktree,
+            rewrite,
+        } => {
+            let mut options =
+                rewrite.into_options(cli.dry_run, cli.verbose, naming, history_policy.as_ref())?;
+            options.in_place = true;
+            options.force = force;
+            options.update_worktrees = update_worktree;
+            mirror::mirror(&open()?, &base, &target, &options)?;
+        }
+        Commands::Anonymize {
+            base,
+            target,
+            key_file,
+            messages,
+            rewrite,
+        } => {
+            let mut options =
+                rewrite.into_options(cli.dry_run, cli.verbose, naming, history_policy.as_ref())?;
+            options.anonymize = Some(Anonymize { key_file, messages });
+            mirror::mirror(&open()?, &base, &target, &options)?;
+        }
+        Commands::List => {
+            list::list(&open()?, &naming)?;
+        }
+        Commands::Recover {
+            commit,
+            path,
+            vault,
+            list,
+        } => match (commit, path) {
+            (Some(commit), Some(path)) if !list => {
+                vault::recover(&open()?, &commit, &path, vault.as_deref())?;
+            }
+            _ => recover::list(&open()?)?,
+        },
+        #[cfg(feature = "remote-rules")]
+        Commands::Protection { target, remote } => {
+            magitulator::protection::protection(&open()?, &target, &remote)?;
+        }
+        Commands::Show { commit } => {
+            show::show(&open()?, &commit)?;
+        }
+        Commands::CompareWith {
+            other,
+            target,
+            their_map,
+        } => {
+            compare::compare_with(
+                &open()?,
+                &naming,
+                &other,
+                &CompareOptions { target, their_map },
+            )?;
+        }
+        Commands::MapLookup { commit } => {
+            lookup::map_lookup(&open()?, &commit)?;
+        }
+        Commands::Status => {
+            status::status(&open()?, &naming)?;
+        }
+        Commands::Leaks { base, target } => {
+            leaks::leaks(&open()?, &base, &target)?;
+        }
+        Commands::Lint { base, target } => {
+            let policy = history_policy.ok_or_else(|| {
+                format!("{} has no [policy] table to lint with", config::FILE_NAME)
+            })?;
+            lint::lint(&open()?, &policy, &base, &target)?;
+        }
+        Commands::Changelog {
+            base,
+            target,
+            groups,
+            format,
+        } => {
+            changelog::changelog(
+                &open()?,
+                &base,
+                &target,
+                &ChangelogOptions { groups, format },
+            )?;
+        }
+        Commands::AuditSignatures { base, target } => {
+            signatures::audit(&open()?, &base, &target)?;
+        }
+        Commands::Doctor { repair } => {
+            let repo = open()?;
+            if repair {
+                doctor::repair(&repo, &naming, cli.dry_run)?;
+                println!();
+            }
+            doctor::doctor(&repo)?;
+        }
+        Commands::Verify => {
+            verify::verify(&open()?, &naming)?;
+        }
+        Commands::Batch {
+            manifest,
+            keep_going,
+            report,
+            jobs,
+            share_objects,
+        } => {
+            batch::batch(
+                &manifest,
+                &BatchOptions {
+                    dry_run: cli.dry_run,
+                    naming,
+                    keep_going,
+                    report,
+                    jobs: jobs.get(),
+                    share_objects,
+                },
+            )?;
         }
-        Commands::Apply { target } => {
-            // Logic to delete original and rename mirrored branch
-            println!("Applying changes to {}", target);
+        Commands::RemoteRewrite {
+            url,
+            push_to,
+            manifest,
+            profile,
+            base,
+            target,
+            push_ref,
+            force,
+            share_objects,
+            args,
+        } => {
+            remote::remote_rewrite(
+                &url,
+                &RemoteRewriteOptions {
+                    dry_run: cli.dry_run,
+                    naming,
+                    manifest,
+                    profile,
+                    base,
+                    target,
+                    args,
+                    push_to,
+                    push_ref,
+                    force,
+                    share_objects,
+                },
+            )?;
         }
-        Commands::Rewrite { base, target } => {
-            // Logic to mirror and then immediately apply
-            println!("Rewriting from {} to {} in-place", base, target);
+        Commands::Clean {
+            mirrors,
+            backups,
+            checkpoints,
+            quarantine,
+            caches,
+            older_than,
+            patterns,
+        } => {
+            let all = !(mirrors || backups || checkpoints || quarantine || caches);
+            clean::clean(
+                &open()?,
+                &CleanOptions {
+                    dry_run: cli.dry_run,
+                    naming,
+                    mirrors: all || mirrors,
+                    backups: all || backups,
+                    checkpoints: all || checkpoints,
+                    quarantine: all || quarantine,
+                    caches: all || caches,
+                    older_than,
+                    patterns,
+                },
+            )?;
         }
     }
 
@@ -0,0 +1,182 @@
+//! Original → rewritten commit mappings, recorded per mirror ref over all runs for it.
+use crate::{AnyResult, state};
+use gix::{
+    ObjectId, Repository,
+    hash::Prefix,
+    refs::{FullName, FullNameRef},
+};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Parse a full object id, or resolve an abbreviated one against the object database.
+///
+/// Full ids are taken as they are, so maps can still name objects that were pruned since.
+pub(crate) fn resolve_hash(repo: &Repository, hex: &str) -> Result<ObjectId, String> {
+    if hex.len() == repo.object_hash().len_in_hex() {
+        return hex
+            .parse()
+            .map_err(|_| format!("`{hex}` is not an object id"));
+    }
+    let prefix = Prefix::from_hex(hex).map_err(|e| format!("`{hex}`: {e}"))?;
+    let mut candidates = HashSet::new();
+    repo.objects
+        .lookup_prefix(prefix, Some(&mut candidates))
+        .map_err(|e| format!("`{hex}`: {e}"))?;
+    let mut candidates: Vec<ObjectId> = candidates.into_iter().collect();
+    candidates.sort();
+    match candidates[..] {
+        [id] => Ok(id),
+        [] => Err(format!("no object starts with `{hex}`")),
+        _ => Err(format!(
+            "`{hex}` is ambiguous, it could be {}",
+            candidates
+                .iter()
+                .map(|id| id.to_hex_with_len(hex.len() + 4).to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct CommitMap {
+    pub entries: BTreeMap<ObjectId, ObjectId>,
+}
+
+impl CommitMap {
+    pub fn path(repo: &Repository, mirror_ref: &FullNameRef) -> PathBuf {
+        state::maps_dir(repo).join(mirror_ref.as_bstr().to_string())
+    }
+
+    /// The map recorded for `mirror_ref`, if the mirror was created with one.
+    pub fn load(repo: &Repository, mirror_ref: &FullNameRef) -> AnyResult<Option<Self>> {
+        let path = Self::path(repo, mirror_ref);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let mut entries = BTreeMap::new();
+        for (index, line) in fs::read_to_string(&path)?.lines().enumerate() {
+            let at = |message: String| format!("{}:{}: {message}", path.display(), index + 1);
+            let Some((old, new)) = line.split_once(' ') else {
+                return Err(at("malformed map line".into()).into());
+            };
+            entries.insert(
+                resolve_hash(repo, old).map_err(at)?,
+                resolve_hash(repo, new.trim()).map_err(at)?,
+            );
+        }
+
+        Ok(Some(CommitMap { entries }))
+    }
+
+    /// Every recorded map with the mirror ref it was saved for, including those of mirrors
+    /// that were applied or deleted since.
+    pub fn load_all(repo: &Repository) -> AnyResult<Vec<(FullName, Self)>> {
+        let dir = state::maps_dir(repo);
+        let mut maps = Vec::new();
+        for name in map_names(&dir, &dir)? {
+            let Ok(name) = FullName::try_from(name.as_str()) else {
+                continue;
+            };
+            if let Some(map) = Self::load(repo, name.as_ref())? {
+                maps.push((name, map));
+            }
+        }
+        Ok(maps)
+    }
+
+    /// Record the entries under `mirror_ref`, adding to those of earlier runs for it, so after
+    /// rewriting an applied mirror again the commits it was rewritten from stay resolvable.
+    /// Commits rewritten again replace their earlier entries.
+    pub fn save(&self, repo: &Repository, mirror_ref: &FullNameRef) -> AnyResult<()> {
+        let path = Self::path(repo, mirror_ref);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut entries = Self::load(repo, mirror_ref)?.unwrap_or_default().entries;
+        entries.extend(&self.entries);
+        let mut out = Vec::new();
+        for (old, new) in &entries {
+            writeln!(out, "{old} {new}")?;
+        }
+        fs::write(path, out)?;
+
+        Ok(())
+    }
+}
+
+/// Map files below `dir`, as the mirror ref names they were saved for.
+fn map_names(root: &Path, dir: &Path) -> AnyResult<Vec<String>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            names.extend(map_names(root, &path)?);
+        } else if let Ok(relative) = path.strip_prefix(root) {
+            names.push(relative.to_string_lossy().replace('\\', "/"));
+        }
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        apply::{self, ApplyOptions},
+        mirror::{self, MirrorOptions},
+        refs::MirrorNaming,
+        testing::{git, scratch_repo},
+    };
+
+    #[test]
+    fn rewriting_an_applied_mirror_keeps_earlier_entries() {
+        let dir = scratch_repo();
+        fs::write(dir.join("a.txt"), "a\n").unwrap();
+        git(&dir, &["add", "a.txt"]);
+        git(&dir, &["commit", "-q", "-m", "base"]);
+        git(&dir, &["checkout", "-q", "-b", "dev"]);
+        fs::write(dir.join("first.key"), "1\n").unwrap();
+        fs::write(dir.join("second.key"), "2\n").unwrap();
+        fs::write(dir.join("a.txt"), "b\n").unwrap();
+        git(&dir, &["add", "."]);
+        git(&dir, &["commit", "-q", "-m", "change a"]);
+        git(&dir, &["checkout", "-q", "main"]);
+
+        let repo = gix::open(&dir).unwrap();
+        let tip = |repo: &Repository| repo.rev_parse_single("dev").unwrap().detach();
+        let apply_options = ApplyOptions {
+            dry_run: false,
+            naming: MirrorNaming::default(),
+            force: false,
+            update_worktrees: false,
+        };
+        let mut generations = vec![tip(&repo)];
+        for key in ["first.key", "second.key"] {
+            let options = MirrorOptions {
+                remove_paths: vec![key.parse().unwrap()],
+                ..MirrorOptions::default()
+            };
+            mirror::mirror(&repo, "main", "dev", &options).unwrap();
+            apply::apply(&repo, "dev", &apply_options).unwrap();
+            generations.push(tip(&repo));
+        }
+
+        let mirror_ref = FullName::try_from(MirrorNaming::default().mirror_ref("dev")).unwrap();
+        let map = CommitMap::load(&repo, mirror_ref.as_ref())
+            .unwrap()
+            .unwrap();
+        assert_eq!(map.entries.get(&generations[0]), Some(&generations[1]));
+        assert_eq!(map.entries.get(&generations[1]), Some(&generations[2]));
+        crate::files::remove_dir_all(&dir).unwrap();
+    }
+}
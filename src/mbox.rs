@@ -0,0 +1,211 @@
+//! Cleanup of commit messages imported from mailboxes and patch queues.
+use crate::{AnyResult, filter::Filter, map::CommitMap, mirror::CommitDescriptor};
+use gix::{
+    Repository,
+    bstr::{BString, ByteSlice},
+};
+use regex::bytes::Regex;
+
+/// Mail headers left in messages by patch imports.
+const MAIL_HEADERS: [&str; 16] = [
+    "from",
+    "date",
+    "subject",
+    "message-id",
+    "in-reply-to",
+    "references",
+    "to",
+    "cc",
+    "reply-to",
+    "sender",
+    "return-path",
+    "received",
+    "delivered-to",
+    "mime-version",
+    "content-type",
+    "content-transfer-encoding",
+];
+
+/// Values of the `From:`, `Date:` and `Subject:` headers, in that order.
+type Headers = [Option<BString>; 3];
+
+/// Strips mbox separators, mail header blocks, `>From ` escapes and `-- ` signatures from
+/// messages, and takes the author from an embedded `From:` and `Date:` header.
+pub(crate) struct MboxScrub {
+    separator: Regex,
+    address: Regex,
+    patch_prefix: Regex,
+    version: Regex,
+    scrubbed: usize,
+}
+
+impl MboxScrub {
+    pub(crate) fn new() -> AnyResult<Self> {
+        Ok(MboxScrub {
+            // `From <sha> Mon Sep 17 00:00:00 2001`, as written by `git format-patch`.
+            separator: Regex::new(r"^From \S+ \w{3} \w{3} [ \d]\d \d\d:\d\d:\d\d \d{4}$")?,
+            address: Regex::new(r#"^\s*"?([^"<]*?)"?\s*<([^>]+)>\s*$"#)?,
+            patch_prefix: Regex::new(r"^(\[[^\]]*\]\s*)+")?,
+            version: Regex::new(r"^\d+\.\d+\S*$")?,
+            scrubbed: 0,
+        })
+    }
+
+    /// A block of mail headers starting at `lines[start]`, with the index of its last line.
+    fn headers(lines: &[&[u8]], start: usize) -> Option<(usize, Headers)> {
+        let mut headers = Headers::default();
+        // The last header line, and which of `headers` it set.
+        let mut last: Option<(usize, Option<usize>)> = None;
+        let mut index = start;
+        while let Some(line) = lines.get(index).filter(|line| !line.is_empty()) {
+            if line.starts_with(b" ") || line.starts_with(b"\t") {
+                // A folded continuation of the previous header.
+                let (end, field) = last.as_mut()?;
+                if let Some(value) = field.and_then(|field| headers[field].as_mut()) {
+                    value.push(b' ');
+                    value.extend_from_slice(line.trim());
+                }
+                *end = index;
+            } else {
+                let colon = line
+                    .find(b": ")
+                    .or_else(|| line.ends_with(b":").then(|| line.len() - 1))?;
+                let name = line[..colon].to_ascii_lowercase();
+                let field = match name.as_slice() {
+                    b"from" => Some(0),
+                    b"date" => Some(1),
+                    b"subject" => Some(2),
+                    _ if name.starts_with(b"x-")
+                        || MAIL_HEADERS.iter().any(|h| h.as_bytes() == name) =>
+                    {
+                        None
+                    }
+                    _ => return None,
+                };
+                if let Some(field) = field {
+                    headers[field] = Some(line[colon + 1..].trim().into());
+                }
+                last = Some((index, field));
+            }
+            index += 1;
+        }
+        Some((last?.0, headers))
+    }
+}
+
+impl Filter for MboxScrub {
+    fn name(&self) -> String {
+        "--scrub-mbox".into()
+    }
+
+    fn apply(&mut self, _repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        let message = descriptor.message.clone();
+        let mut lines: Vec<&[u8]> = message.lines().collect();
+
+        let mut changed = false;
+        if lines
+            .first()
+            .is_some_and(|line| self.separator.is_match(line))
+        {
+            lines.remove(0);
+            changed = true;
+        }
+
+        // Headers either open the message or, as with `git am`, open the body.
+        let mut subject = None;
+        for start in [0, 2] {
+            if start == 2 && lines.get(1).is_none_or(|line| !line.is_empty()) {
+                continue;
+            }
+            let Some((end, [from, date, headers_subject])) = Self::headers(&lines, start) else {
+                continue;
+            };
+            // A paragraph starting with `From: ` is only a header block if it holds a real
+            // address or date.
+            let address = from.as_ref().and_then(|from| self.address.captures(from));
+            let date = date
+                .as_ref()
+                .and_then(|date| gix::date::parse(date.to_str().ok()?, None).ok());
+            if address.is_none() && date.is_none() {
+                continue;
+            }
+            // Drop the block along with the blank line after it.
+            let end = if lines.get(end + 1).is_some_and(|line| line.is_empty()) {
+                end + 1
+            } else {
+                end
+            };
+            lines.drain(start..=end);
+            changed = true;
+
+            if let Some(captures) = address {
+                let name = captures[1].trim();
+                descriptor.author.name = if name.is_empty() { &captures[2] } else { name }.into();
+                descriptor.author.email = captures[2].into();
+            }
+            if let Some(date) = date {
+                descriptor.author.time = date;
+            }
+            if start == 0 {
+                subject = headers_subject
+                    .map(|s| BString::from(self.patch_prefix.replace(&s, &b""[..]).into_owned()));
+            }
+            break;
+        }
+
+        // `git format-patch` signatures: `-- ` (trimmed to `--` by `git commit`) followed by
+        // the git version.
+        if let Some(dash) = lines.iter().rposition(|line| line.trim_end() == b"--")
+            && lines[dash + 1..]
+                .iter()
+                .filter(|line| !line.trim().is_empty())
+                .all(|line| self.version.is_match(line.trim()))
+        {
+            lines.truncate(dash);
+            changed = true;
+        }
+        let unescaped: Vec<&[u8]> = lines
+            .iter()
+            .map(|line| match line.iter().position(|b| *b != b'>') {
+                // Undo mboxrd escaping of lines starting with `From `.
+                Some(i) if i > 0 && line[i..].starts_with(b"From ") => &line[1..],
+                _ => line,
+            })
+            .collect();
+        changed |= unescaped != lines;
+        if !changed {
+            return Ok(());
+        }
+        let mut lines = unescaped;
+        while lines.last().is_some_and(|line| line.trim().is_empty()) {
+            lines.pop();
+        }
+
+        let mut scrubbed = BString::default();
+        if let Some(subject) = subject
+            && lines
+                .first()
+                .is_none_or(|first| *first != subject.as_slice())
+        {
+            scrubbed.extend_from_slice(&subject);
+            scrubbed.push(b'\n');
+            if !lines.is_empty() {
+                scrubbed.push(b'\n');
+            }
+        }
+        for line in lines {
+            scrubbed.extend_from_slice(line);
+            scrubbed.push(b'\n');
+        }
+        descriptor.message = scrubbed;
+        self.scrubbed += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self, _repo: &Repository, _map: &CommitMap) -> AnyResult<()> {
+        if self.scrubbed > 0 {
+            println!("Scrubbed mail artifacts from {} message(s)", self.scrubbed);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+//! Measurements of a mirror run, written to a local file with `--metrics-out` for tuning
+//! rewrites of very large repositories. Nothing is sent anywhere.
+use crate::AnyResult;
+use gix::Repository;
+use serde::Serialize;
+use std::{fs, ops::AddAssign, path::Path, time::Instant};
+
+/// Lookups a cache answered, and lookups it had to compute.
+#[derive(Clone, Copy, Default, Serialize)]
+pub(crate) struct CacheStats {
+    pub(crate) hits: usize,
+    pub(crate) misses: usize,
+}
+
+impl AddAssign for CacheStats {
+    fn add_assign(&mut self, other: CacheStats) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+    }
+}
+
+#[derive(Serialize)]
+struct Phase {
+    name: &'static str,
+    seconds: f64,
+}
+
+/// Durations of the phases of a run, measured one after the other.
+pub(crate) struct Phases {
+    started: Instant,
+    last: Instant,
+    phases: Vec<Phase>,
+}
+
+impl Phases {
+    pub(crate) fn start() -> Self {
+        let now = Instant::now();
+        Phases {
+            started: now,
+            last: now,
+            phases: Vec::new(),
+        }
+    }
+
+    /// End the phase `name`, which began where the previous one ended.
+    pub(crate) fn lap(&mut self, name: &'static str) {
+        let now = Instant::now();
+        self.phases.push(Phase {
+            name,
+            seconds: (now - self.last).as_secs_f64(),
+        });
+        self.last = now;
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct Cache {
+    hits: usize,
+    misses: usize,
+    /// Share of lookups answered from the cache; missing before the first lookup.
+    hit_rate: Option<f64>,
+}
+
+impl From<CacheStats> for Cache {
+    fn from(stats: CacheStats) -> Self {
+        let lookups = stats.hits + stats.misses;
+        Cache {
+            hits: stats.hits,
+            misses: stats.misses,
+            hit_rate: (lookups > 0).then(|| stats.hits as f64 / lookups as f64),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct FilterMetrics {
+    pub(crate) name: String,
+    pub(crate) seconds: f64,
+    /// Commits the filter changed.
+    pub(crate) commits: usize,
+    /// Files the filter added, removed or changed, counted once per commit.
+    pub(crate) paths: usize,
+    pub(crate) cache: Option<Cache>,
+}
+
+#[derive(Default, Serialize)]
+pub(crate) struct CommitCounts {
+    /// Commits between base and target.
+    pub(crate) walked: usize,
+    pub(crate) rewritten: usize,
+    pub(crate) dropped: usize,
+    /// Commits kept as they were, by `--after`.
+    pub(crate) kept: usize,
+}
+
+#[derive(Serialize)]
+pub(crate) struct Metrics {
+    pub(crate) created_at: i64,
+    pub(crate) command: Vec<String>,
+    pub(crate) base: String,
+    pub(crate) target: String,
+    pub(crate) dry_run: bool,
+    pub(crate) seconds: f64,
+    phases: Vec<Phase>,
+    pub(crate) commits: CommitCounts,
+    /// Loose objects the run added; missing for dry runs, which keep objects in memory.
+    pub(crate) objects_written: Option<usize>,
+    pub(crate) filters: Vec<FilterMetrics>,
+}
+
+impl Metrics {
+    pub(crate) fn new(phases: Phases, base: &str, target: &str, dry_run: bool) -> Self {
+        Metrics {
+            created_at: gix::date::Time::now_utc().seconds,
+            command: std::iter::once("gitm".to_owned())
+                .chain(std::env::args().skip(1))
+                .collect(),
+            base: base.to_owned(),
+            target: target.to_owned(),
+            dry_run,
+            seconds: phases.started.elapsed().as_secs_f64(),
+            phases: phases.phases,
+            commits: CommitCounts::default(),
+            objects_written: None,
+            filters: Vec::new(),
+        }
+    }
+
+    pub(crate) fn save(&self, path: &Path) -> AnyResult<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+            .map_err(|e| format!("writing {}: {e}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// Loose objects in the object database, to tell how many a run wrote.
+pub(crate) fn count_loose_objects(repo: &Repository) -> AnyResult<usize> {
+    let objects = repo.common_dir().join("objects");
+    let mut count = 0;
+    for entry in fs::read_dir(objects)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let is_fan_out = name.len() == 2
+            && name
+                .to_str()
+                .is_some_and(|name| name.chars().all(|c| c.is_ascii_hexdigit()));
+        if is_fan_out && entry.file_type()?.is_dir() {
+            count += fs::read_dir(entry.path())?.count();
+        }
+    }
+    Ok(count)
+}
@@ -1,80 +1,1068 @@
-use crate::{AnyResult, BRANCH_POSTFIX};
+use crate::{
+    AnyResult,
+    anonymize::{Anonymize, Anonymizer},
+    apply,
+    budget::{BlobSizeLimit, BudgetAction, SizeBudget},
+    cache::DescriptorCache,
+    checkpoint::{self, Checkpoint},
+    content::{CopyrightFilter, EolNormalization, LineEnding, TextReplacements},
+    error::Failure,
+    exec::{Exec, IndexCommand, TreeCommand},
+    filter::{
+        self, AddedTrailer, BranchRename, CoupleDates, DateCoupling, DefaultTimezone, DropEmpty,
+        Filter, FilterProfile, HeaderRule, IssueMap, Jitter, MergeMessages, MessageReplacement,
+        MessageTemplate, ParentOrder, SetTimezone, ShiftDates, SpreadDates, TrailerRule, Trailers,
+    },
+    gitmoji::{Gitmoji, GitmojiPolicy, GitmojiType},
+    identity::{
+        self, AuthorsFile, CodeOwners, EraseIdentity, IdentityMap, IdentityScope, NamedIdentity,
+        RewriteIdentity,
+    },
+    interrupt::{self, RunMarker},
+    lfs::LfsConvert,
+    lint::HistoryPolicy,
+    map::CommitMap,
+    mbox::MboxScrub,
+    metrics::{self, CommitCounts, Metrics, Phases},
+    notes,
+    paths::{
+        self, Effect, IgnoreFilter, PathFilter, PathMode, PathRename, PathRule, RenameFilter,
+        SubdirectoryFilter,
+    },
+    policy::ChangePolicy,
+    pool::Turn,
+    preview::DescriptorDiff,
+    provenance::{self, RewrittenAction},
+    refs::{self, MirrorNaming, RETARGETED_TAG_PREFIX},
+    report::{MirrorRecipe, RunReport},
+    schedule::BusinessHours,
+    signatures::{self, Signer, SigningKey},
+    topology,
+    translate::MessageCommand,
+    tree::PathPattern,
+    vault::{self, RedactFilter},
+    verify, worktree,
+};
 use colored::Colorize;
-use gix::{
-    ObjectId, Repository,
-    actor::Signature,
-    bstr::BString,
-    date::time,
-    refs::transaction::{Change, LogChange, RefEdit},
+use gix::{ObjectId, Repository, actor::Signature, bstr::BString, date::time};
+use std::{
+    collections::HashSet,
+    path::PathBuf,
+    time::{Duration, Instant},
 };
-use std::collections::{HashMap, HashSet};
 
-struct CommitDescriptor {
-    original_id: ObjectId,
-    original_parent_ids: Vec<ObjectId>,
-    tree: ObjectId,
-    author: Signature,
-    committer: Signature,
-    encoding: Option<BString>,
-    message: BString,
-    extra_headers: Vec<(BString, BString)>,
+pub(crate) struct CommitDescriptor {
+    pub(crate) original_id: ObjectId,
+    pub(crate) original_parent_ids: Vec<ObjectId>,
+    pub(crate) tree: ObjectId,
+    pub(crate) author: Signature,
+    pub(crate) committer: Signature,
+    pub(crate) encoding: Option<BString>,
+    pub(crate) message: BString,
+    pub(crate) extra_headers: Vec<(BString, BString)>,
+    /// Left untouched by the rewrite; only re-written if one of its parents changed.
+    pub(crate) keep_original: bool,
+    /// Not written; children are attached to its (single) parent instead.
+    pub(crate) dropped: bool,
 }
 
-pub fn mirror(base: &str, target: &str, dry_run: bool) -> AnyResult<()> {
-    let repo = gix::open(".")?;
+#[derive(Clone, Debug, Default)]
+pub struct MirrorOptions {
+    pub dry_run: bool,
+    /// With `dry_run`, list what changes in each commit below it.
+    pub verbose: bool,
+    /// Update `refs/heads/<target>` to the rewritten tip instead of creating a mirror.
+    pub in_place: bool,
+    /// With `in_place`, rewrite the target even where it is checked out.
+    pub force: bool,
+    /// With `in_place`, update the files of worktrees that have the target checked out.
+    pub update_worktrees: bool,
+    pub naming: MirrorNaming,
+    /// Rules deriving extra commit headers from message content.
+    pub header_rules: Vec<HeaderRule>,
+    /// Only rewrite commits committed on or after this time; older ones keep their OIDs
+    /// unless an ancestor had to change.
+    pub after: Option<gix::date::Time>,
+    /// When the target is an annotated tag, create a copy of it pointing at the mirror.
+    pub retarget_tag: bool,
+    /// Check the mirror out in a linked worktree under the temporary directory.
+    pub checkout_mirror: bool,
+    /// Attach a summary of the run to the mirror tip as a note under
+    /// [`notes::SUMMARY_NOTES_REF`].
+    pub summary_note: bool,
+    /// Additional revisions whose histories are left out of the rewrite, like `base`.
+    pub excludes: Vec<String>,
+    /// Fail unless `base` is an ancestor of `target`.
+    pub no_ff_base: bool,
+    /// Rewrite `base` itself too, on top of its original parents.
+    pub include_base: bool,
+    /// What to do with commits an earlier run already rewrote.
+    pub on_rewritten: RewrittenAction,
+    /// Identity given to rewritten commits.
+    pub identity: RewriteIdentity,
+    /// Rules mapping original identities to new ones, which win over `identity`.
+    pub map_file: Option<PathBuf>,
+    /// Fail, writing a stub of rules, if `map_file` has no rule for some original identities,
+    /// instead of asking for them.
+    pub strict_identities: bool,
+    /// Canonicalize identities with the repository's mailmap, over `identity` but under
+    /// `map_file`.
+    pub use_mailmap: bool,
+    /// Whether the author, the committer or both are changed by the identity options.
+    pub identity_scope: IdentityScope,
+    /// Emails of people erased from the history: their commits are reassigned to
+    /// `erased_as`, and their names and emails removed from messages.
+    pub erase_identities: Vec<String>,
+    /// Identity erased people are replaced with, see [`NamedIdentity::default`] if unset.
+    pub erased_as: Option<NamedIdentity>,
+    /// CODEOWNERS file whose owners become the authors of commits touching their paths.
+    pub owners_file: Option<PathBuf>,
+    /// Domain of the emails of owners given as `@handles`, see
+    /// [`identity::DEFAULT_OWNERS_DOMAIN`] if unset.
+    pub owners_domain: Option<String>,
+    /// Merges whose two parents are swapped, as revisions.
+    pub swap_parents: Vec<String>,
+    /// Also swap the parents of merges that look like their mainline is the second parent.
+    pub swap_parents_auto: bool,
+    /// Merges replaced by their second-parent commits, replayed onto the first parent.
+    pub demerge: Vec<String>,
+    /// File of `<from-rev> <to-rev> <branch name>` runs to move onto merged branches.
+    pub remerge: Option<PathBuf>,
+    /// Group the commits up to each tag matching this glob onto a merged branch.
+    pub remerge_tags: Option<String>,
+    /// `<rev>..<rev>` ranges collapsed into single commits.
+    pub squash: Vec<String>,
+    /// Message template for squashed commits, see [`topology::DEFAULT_SQUASH_TEMPLATE`].
+    pub squash_template: Option<String>,
+    /// Which dates squashed commits carry.
+    pub squash_dates: topology::DatePolicy,
+    /// Strip mail artifacts from messages and take authors from embedded `From:` headers.
+    pub scrub_mbox: bool,
+    /// What to do with trailers such as `git-svn-id`, per key.
+    pub trailer_rules: Vec<TrailerRule>,
+    /// Shell command turning each message on stdin into its replacement, e.g. a translator.
+    pub message_command: Option<String>,
+    /// git-svn style authors file mapping usernames to identities.
+    pub authors_file: Option<PathBuf>,
+    /// Offset in seconds given to signatures recorded at `+0000`.
+    pub default_timezone: Option<i32>,
+    /// Seconds to move every author and committer date by.
+    pub shift_dates: Option<i64>,
+    /// First and last date, in seconds since the UNIX epoch, to spread the rewritten commits
+    /// evenly over.
+    pub spread_dates: Option<(i64, i64)>,
+    /// Working schedule every author and committer date is moved into.
+    pub business_hours: Option<BusinessHours>,
+    /// Most seconds each author and committer date is moved either way by random noise that
+    /// keeps their order.
+    pub jitter: Option<i64>,
+    /// Seed of the `jitter` noise; a random one is picked and printed without it.
+    pub seed: Option<u64>,
+    /// Give author and committer of each commit the same date, once the other date options
+    /// are applied.
+    pub date_coupling: Option<DateCoupling>,
+    /// Offset in seconds every author and committer date is recorded in, keeping the instant.
+    pub set_timezone: Option<i32>,
+    /// Drop commits that don't change their parent's tree.
+    pub drop_empty: bool,
+    /// Replace identities, and messages if asked to, with pseudonyms.
+    pub anonymize: Option<Anonymize>,
+    /// Sign every written commit and retargeted tag with this key.
+    pub resign_with: Option<SigningKey>,
+    /// Keep the signatures of rewritten commits, although they no longer verify.
+    pub keep_broken_signatures: bool,
+    /// Shell command run on the tree of every written commit; the run aborts if it fails.
+    pub exec: Option<String>,
+    /// Directories checked out for `exec`; the whole tree when empty.
+    pub exec_paths: Vec<String>,
+    /// Shell command run in a checkout of every commit's tree, whose files become its tree.
+    pub tree_filter: Option<String>,
+    /// Shell command given the entries of every commit's tree, printing the ones to change.
+    pub index_filter: Option<String>,
+    /// When set, the run fails if a commit changes a field the policy doesn't allow.
+    pub policy: Option<ChangePolicy>,
+    /// When set, the run fails if a rewritten commit breaks these rules.
+    pub history_policy: Option<HistoryPolicy>,
+    /// Only keep paths matching one of these patterns.
+    pub keep_paths: Vec<PathPattern>,
+    /// Remove paths matching one of these patterns.
+    pub remove_paths: Vec<PathPattern>,
+    /// Files and directories moved to a new path in every rewritten tree, in order.
+    pub rename_paths: Vec<PathRename>,
+    /// Directory that becomes the root of every rewritten tree; commits that don't change it
+    /// are pruned.
+    pub subdirectory: Option<String>,
+    /// Also keep or remove the earlier names of files matched by `keep_paths` or
+    /// `remove_paths`.
+    pub follow_renames: bool,
+    /// Similarity thresholds and limits of the rename and copy detection `follow_renames` uses.
+    pub rewrites: gix::diff::Rewrites,
+    /// CSV file mapping issue ids of an old tracker to new ids or URLs.
+    pub issue_map: Option<PathBuf>,
+    /// What happens to gitmoji and other emoji at the start of subjects.
+    pub gitmoji: Option<GitmojiPolicy>,
+    /// Conventional Commit types of emoji for `GitmojiPolicy::Conventional`, over the built-in
+    /// ones.
+    pub gitmoji_types: Vec<GitmojiType>,
+    /// Regex replacements applied to every message, in order.
+    pub message_replacements: Vec<MessageReplacement>,
+    /// Template the messages of merge commits are rebuilt from, see [`MergeMessages`].
+    pub merge_message_template: Option<String>,
+    /// Names merged branches go by in regenerated merge messages.
+    pub merged_branch_renames: Vec<BranchRename>,
+    /// Template every message is rebuilt from, see [`MessageTemplate`].
+    pub message_template: Option<String>,
+    /// Trailers added to every message that lacks them, in order.
+    pub added_trailers: Vec<AddedTrailer>,
+    /// Template replacing copyright lines; `{years}`, `{first}` and `{year}` are substituted.
+    pub copyright_template: Option<String>,
+    /// Files whose copyright line is updated; all files if empty.
+    pub copyright_paths: Vec<PathPattern>,
+    /// File of `<text>==><replacement>` rules replacing text in every file.
+    pub replace_text: Option<PathBuf>,
+    /// Line endings every text file is given.
+    pub normalize_eol: Option<LineEnding>,
+    /// Blobs at matching paths are moved into Git LFS, leaving pointer files.
+    pub lfs_convert: Vec<PathPattern>,
+    /// Remove every path the target's `.gitignore` files ignore from all rewritten trees.
+    pub apply_gitignore: bool,
+    /// Blobs larger than this many bytes are removed from all rewritten trees.
+    pub max_blob_size: Option<u64>,
+    /// Blobs over [`MirrorOptions::max_blob_size`] are replaced with a stub naming them
+    /// instead.
+    pub stub_stripped_blobs: bool,
+    /// Maximum bytes of blobs a single commit may add or modify.
+    pub size_budget: Option<u64>,
+    pub size_budget_action: BudgetAction,
+    /// Blobs at matching paths are replaced by a placeholder and kept encrypted in the vault.
+    pub redact_paths: Vec<PathPattern>,
+    /// Vault file for redacted blobs, `.git/magitulator/vault.json` if unset.
+    pub vault: Option<PathBuf>,
+    /// Warn instead of failing when path filters undo each other on the same file.
+    pub allow_filter_conflicts: bool,
+    /// Report the time each filter took and what it changed after the run.
+    pub profile_filters: bool,
+    /// Write timings, commit and object counts and cache hit rates of the run to this file.
+    pub metrics_out: Option<PathBuf>,
+    /// Stop writing commits after this long, saving a checkpoint the next run of the same
+    /// command resumes from.
+    pub time_budget: Option<Duration>,
+    /// Keep the descriptors dry runs filtered, for dry runs with the same filters to reuse.
+    pub cache_descriptors: bool,
+}
 
-    let base_commit_id = resolve_commit_id(&repo, &base)?;
-    let target_commit_id = resolve_commit_id(&repo, &target)?;
+impl MirrorOptions {
+    /// Whether signatures that no longer verify are removed from rewritten commits; signing
+    /// replaces them anyway.
+    fn strip_signatures(&self) -> bool {
+        !self.keep_broken_signatures && self.resign_with.is_none()
+    }
 
-    let commits_to_rewrite = get_commits_to_rewrite(&repo, base_commit_id, target_commit_id)?;
-    if commits_to_rewrite.is_empty() {
-        return Ok(());
+    fn filters(&self, repo: &Repository, target_id: ObjectId) -> AnyResult<Vec<Box<dyn Filter>>> {
+        let mut filters: Vec<Box<dyn Filter>> = Vec::new();
+        // Before the trailer rules, which only see trailers once signatures are gone.
+        if self.scrub_mbox {
+            filters.push(Box::new(MboxScrub::new()?));
+        }
+        if !self.trailer_rules.is_empty() {
+            filters.push(Box::new(Trailers::new(self.trailer_rules.clone())));
+        }
+        if let Some(command) = &self.message_command {
+            filters.push(Box::new(MessageCommand::new(repo, command)));
+        }
+        if let Some(path) = &self.authors_file {
+            filters.push(Box::new(AuthorsFile::load(path, self.identity_scope)?));
+        }
+        if let Some(offset) = self.default_timezone {
+            filters.push(Box::new(DefaultTimezone::new(offset)));
+        }
+        if let Some(seconds) = self.shift_dates {
+            filters.push(Box::new(ShiftDates::new(seconds)));
+        }
+        if let Some((start, end)) = self.spread_dates {
+            filters.push(Box::new(SpreadDates::new(start, end)));
+        }
+        if let Some(schedule) = &self.business_hours {
+            filters.push(Box::new(schedule.clone()));
+        }
+        if let Some(seconds) = self.jitter {
+            let seed = self.seed.unwrap_or_else(filter::random_seed);
+            filters.push(Box::new(Jitter::new(seconds, seed)));
+        }
+        if let Some(coupling) = self.date_coupling {
+            filters.push(Box::new(CoupleDates::new(coupling)));
+        }
+        if let Some(offset) = self.set_timezone {
+            filters.push(Box::new(SetTimezone::new(offset)));
+        }
+        if !self.swap_parents.is_empty() || self.swap_parents_auto {
+            let listed = self
+                .swap_parents
+                .iter()
+                .map(|rev| resolve_commit_id(repo, rev))
+                .collect::<AnyResult<_>>()?;
+            filters.push(Box::new(ParentOrder::new(listed, self.swap_parents_auto)));
+        }
+        if let Some(path) = &self.issue_map {
+            filters.push(Box::new(IssueMap::load(path)?));
+        }
+        if let Some(policy) = self.gitmoji {
+            filters.push(Box::new(Gitmoji::new(policy, &self.gitmoji_types)));
+        }
+        for replacement in &self.message_replacements {
+            filters.push(Box::new(replacement.clone()));
+        }
+        if let Some(template) = &self.merge_message_template {
+            filters.push(Box::new(MergeMessages::new(
+                template,
+                self.merged_branch_renames.clone(),
+            )?));
+        }
+        if let Some(template) = &self.message_template {
+            filters.push(Box::new(MessageTemplate::new(template)?));
+        }
+        for trailer in &self.added_trailers {
+            filters.push(Box::new(trailer.clone()));
+        }
+        // After the identity and message filters, so nothing they bring in is left in clear.
+        if !self.erase_identities.is_empty() {
+            filters.push(Box::new(EraseIdentity::new(
+                repo,
+                target_id,
+                &self.erase_identities,
+                self.erased_as.clone().unwrap_or_default(),
+            )?));
+        }
+        if let Some(settings) = &self.anonymize {
+            filters.push(Box::new(Anonymizer::new(repo, settings, self.dry_run)?));
+        }
+        // After the anonymizer, so commits of owned paths are attributed to teams rather than
+        // pseudonyms.
+        if let Some(path) = &self.owners_file {
+            filters.push(Box::new(CodeOwners::load(
+                path,
+                self.owners_domain
+                    .as_deref()
+                    .unwrap_or(identity::DEFAULT_OWNERS_DOMAIN),
+            )?));
+        }
+        // Headers are derived from the message as the message filters left it.
+        for rule in &self.header_rules {
+            filters.push(Box::new(rule.clone()));
+        }
+        // First, so the other path filters see the new paths.
+        if !self.rename_paths.is_empty() {
+            filters.push(Box::new(RenameFilter::new(self.rename_paths.clone())));
+        }
+        if let Some(directory) = &self.subdirectory {
+            filters.push(Box::new(SubdirectoryFilter::new(directory)));
+        }
+        for (mode, patterns) in [
+            (PathMode::Keep, &self.keep_paths),
+            (PathMode::Remove, &self.remove_paths),
+        ] {
+            if patterns.is_empty() {
+                continue;
+            }
+            let mut filter = PathFilter::new(mode, patterns.clone());
+            if self.follow_renames {
+                filter = filter.follow_renames(repo, target_id, self.rewrites)?;
+            }
+            filters.push(Box::new(filter));
+        }
+        if self.apply_gitignore {
+            filters.push(Box::new(IgnoreFilter::from_commit(repo, target_id)?));
+        }
+        if let Some(template) = &self.copyright_template {
+            filters.push(Box::new(CopyrightFilter::new(
+                template.clone(),
+                self.copyright_paths.clone(),
+            )));
+        }
+        if let Some(path) = &self.replace_text {
+            filters.push(Box::new(TextReplacements::load(path)?));
+        }
+        if let Some(eol) = self.normalize_eol {
+            filters.push(Box::new(EolNormalization::new(eol)));
+        }
+        // Before the LFS, redaction and size filters, so they see the files the commands add.
+        if let Some(command) = &self.index_filter {
+            filters.push(Box::new(IndexCommand::new(repo, command)));
+        }
+        if let Some(command) = &self.tree_filter {
+            filters.push(Box::new(TreeCommand::new(command)));
+        }
+        if !self.lfs_convert.is_empty() {
+            filters.push(Box::new(LfsConvert::new(
+                repo,
+                self.lfs_convert.clone(),
+                self.dry_run,
+            )));
+        }
+        if !self.redact_paths.is_empty() {
+            let vault = self
+                .vault
+                .clone()
+                .unwrap_or_else(|| vault::default_path(repo));
+            filters.push(Box::new(RedactFilter::new(
+                self.redact_paths.clone(),
+                vault,
+            )?));
+        }
+        if let Some(limit) = self.max_blob_size {
+            filters.push(Box::new(BlobSizeLimit::new(
+                limit,
+                self.stub_stripped_blobs,
+            )));
+        }
+        // After the other tree filters, so the budget applies to trees as they left them.
+        if let Some(budget) = self.size_budget {
+            filters.push(Box::new(SizeBudget::new(budget, self.size_budget_action)));
+        }
+        // After every filter that changes trees, so commits they emptied are dropped too.
+        if self.drop_empty {
+            filters.push(Box::new(DropEmpty::default()));
+        }
+        Ok(filters)
+    }
+
+    /// The path-matching rules of the path filters, in the order the filters run.
+    fn path_rules(&self, repo: &Repository, target_id: ObjectId) -> AnyResult<Vec<PathRule>> {
+        let mut rules: Vec<PathRule> = self.rename_paths.iter().map(PathRule::rename).collect();
+        for (flag, patterns, effect) in [
+            ("--keep-path", &self.keep_paths, Effect::Keep),
+            ("--remove-path", &self.remove_paths, Effect::Remove),
+        ] {
+            rules.extend(
+                patterns
+                    .iter()
+                    .map(|pattern| PathRule::pattern(flag, pattern, effect)),
+            );
+        }
+        if self.apply_gitignore {
+            rules.push(PathRule::gitignore(repo, target_id)?);
+        }
+        if self.copyright_template.is_some() {
+            if self.copyright_paths.is_empty() {
+                rules.push(PathRule::all(
+                    "--copyright-template",
+                    Effect::UpdateCopyright,
+                ));
+            }
+            rules.extend(self.copyright_paths.iter().map(|pattern| {
+                PathRule::pattern("--copyright-path", pattern, Effect::UpdateCopyright)
+            }));
+        }
+        rules.extend(
+            self.redact_paths
+                .iter()
+                .map(|pattern| PathRule::pattern("--redact", pattern, Effect::Redact)),
+        );
+        Ok(rules)
+    }
+}
+
+/// Bundles of options for common conversions.
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum Preset {
+    /// Clean up repositories converted from SVN or CVS: strip `git-svn-id` trailers (unless a
+    /// `--trailer` rule handles them), drop empty
+    /// branch creation commits and give `+0000` dates the local timezone.
+    SvnCleanup,
+}
+
+impl Preset {
+    /// Fill in the options this preset implies, keeping those already given.
+    pub fn apply(self, options: &mut MirrorOptions) {
+        match self {
+            Preset::SvnCleanup => {
+                if !options
+                    .trailer_rules
+                    .iter()
+                    .any(|rule| rule.key().eq_ignore_ascii_case("git-svn-id"))
+                {
+                    options
+                        .trailer_rules
+                        .push(TrailerRule::delete("git-svn-id"));
+                }
+                options.drop_empty = true;
+                options
+                    .default_timezone
+                    .get_or_insert_with(|| gix::date::Time::now_local_or_utc().offset);
+            }
+        }
+    }
+}
+
+pub fn mirror(
+    repo: &Repository,
+    base: &str,
+    target: &str,
+    options: &MirrorOptions,
+) -> AnyResult<()> {
+    mirror_in_turn(repo, base, target, options, None)
+}
+
+/// [`mirror`], waiting for `turn` before printing results or updating refs, when it runs side
+/// by side with others.
+pub(crate) fn mirror_in_turn(
+    repo: &Repository,
+    base: &str,
+    target: &str,
+    options: &MirrorOptions,
+    mut turn: Option<&mut Turn<'_>>,
+) -> AnyResult<()> {
+    let mut repo = repo.clone();
+    if options.dry_run {
+        // Filters write trees and blobs as they go; keep those out of the object database.
+        repo = repo.with_object_memory();
     }
+    let loose_before = match &options.metrics_out {
+        Some(_) if !options.dry_run => Some(metrics::count_loose_objects(&repo)?),
+        _ => None,
+    };
+    let mut phases = Phases::start();
+    let deadline = options.time_budget.map(|budget| Instant::now() + budget);
 
-    let descriptors = generate_descriptors(&repo, &commits_to_rewrite)?;
+    let Plan {
+        target_rev,
+        base_commit_id,
+        target_commit_id,
+        commits_to_rewrite,
+        descriptors,
+        resumed,
+        filtered,
+        mut filters,
+        mut profiles,
+        cache,
+    } = plan(&repo, base, target, options, deadline, &mut phases)?;
 
-    if dry_run {
+    if options.dry_run {
+        if let Some(cache) = &cache {
+            cache.save(&mut repo, &descriptors)?;
+        }
+        // Signing and `--exec` are left out: they have effects beyond the object database.
+        let mut written = Written::default();
+        execute_mirror(
+            &repo,
+            &descriptors,
+            &mut written,
+            None,
+            options.strip_signatures(),
+            None,
+            None,
+        )?;
+        let Written {
+            tip,
+            map: commit_map,
+            designed,
+            ..
+        } = written;
+        phases.lap("write");
+        if let Some(turn) = turn.as_mut() {
+            turn.wait();
+        }
         println!("--- Commits that would be rewritten (dry run) ---");
-        for descriptor in descriptors.iter().rev() {
-            print_commit_descriptor_oneline(descriptor)?;
+        for descriptor in descriptors.iter().rev().filter(|d| !d.dropped) {
+            let new_id = commit_map.entries.get(&descriptor.original_id).copied();
+            print_commit_descriptor_oneline(descriptor, new_id)?;
+            if options.verbose {
+                DescriptorDiff::compute(&repo, descriptor)?.print();
+            }
+        }
+        if let Some(tip) = tip {
+            println!("Predicted tip: {tip}");
+        }
+        if designed > 0 {
+            println!("Would remove the signatures of {designed} signed commit(s)");
+        }
+        if options.in_place {
+            match apply::earlier_backup(&repo, target)? {
+                Some(earlier) => println!(
+                    "Would update refs/heads/{target}, keeping its tip as a backup ref in place \
+                     of the earlier backup {}",
+                    earlier.to_hex_with_len(7)
+                ),
+                None => {
+                    println!("Would update refs/heads/{target}, keeping its tip as a backup ref")
+                }
+            }
+        }
+        if let Some(profiles) = profiles.as_ref().filter(|_| options.profile_filters) {
+            filter::print_profiles(&filters, profiles);
         }
     } else {
-        let last_new_oid = execute_mirror(&repo, &descriptors)?;
+        let signer = options
+            .resign_with
+            .as_ref()
+            .map(|key| Signer::from_key(&repo, key))
+            .transpose()?;
+        let mut exec = options
+            .exec
+            .as_deref()
+            .map(|command| Exec::new(command, &options.exec_paths));
+        let _marker = RunMarker::create(&repo, exec.iter().map(|e| e.dir().to_owned()).collect())?;
+        let (done, mut written) = match resumed {
+            Some(checkpoint) => (checkpoint.done, checkpoint.written),
+            None => (0, Written::default()),
+        };
+        let executed = execute_mirror(
+            &repo,
+            &descriptors[done..done + filtered],
+            &mut written,
+            signer.as_ref(),
+            options.strip_signatures(),
+            exec.as_mut(),
+            deadline,
+        );
+        // A failed command's checkout is left for inspection, an interrupted one isn't.
+        if let Some(exec) = &exec
+            && (executed.is_ok() || gix::interrupt::is_triggered())
+        {
+            exec.finish()?;
+        }
+        let done = done + executed?;
+        phases.lap("write");
+        if done < descriptors.len() {
+            let checkpoint = Checkpoint { done, written };
+            let path = checkpoint::save(
+                &repo,
+                target,
+                base_commit_id,
+                target_commit_id,
+                &checkpoint,
+                descriptors.len(),
+            )?;
+            return Err(Failure::Paused(format!(
+                "the time budget ran out after {done} of {} commit(s); run the same command \
+                 again to resume from {}",
+                descriptors.len(),
+                path.display()
+            ))
+            .into());
+        }
+        let Written {
+            tip: last_new_oid,
+            map: commit_map,
+            designed,
+            ..
+        } = written;
+        if let Some(turn) = turn.as_mut() {
+            turn.wait();
+        }
+        // The last chance to stop with nothing changed. After this the run finishes updating
+        // refs even if a signal arrives, unless a second one kills it.
+        interrupt::check()?;
+        if designed > 0 {
+            println!(
+                "Removed the signatures of {designed} signed commit(s), which no longer verify; \
+                 --gpg-sign signs the rewritten commits, --keep-broken-signatures keeps them"
+            );
+        }
 
         match last_new_oid {
             Some(final_oid) => {
-                create_branch(&repo, &target, final_oid)?;
+                let mirror_ref = options.naming.mirror_ref(target).try_into()?;
+                verify::ensure_consistent_with_mirrors(
+                    &repo,
+                    &options.naming,
+                    &mirror_ref,
+                    &commit_map,
+                )?;
+
+                // The map stays under the mirror's name, where later runs look for it.
+                let written_ref = if options.in_place {
+                    apply::replace_branch(
+                        &repo,
+                        target,
+                        target_commit_id,
+                        final_oid,
+                        &format!("magitulator rewrite: {base} {target}"),
+                        None,
+                        options.update_worktrees,
+                    )?;
+                    format!("refs/heads/{target}").try_into()?
+                } else {
+                    let name = refs::create_mirror_ref(
+                        &repo,
+                        &options.naming,
+                        target,
+                        target_commit_id,
+                        final_oid,
+                    )?;
+                    MirrorRecipe::current().save(&repo, name.as_ref())?;
+                    name
+                };
+                commit_map.save(&repo, mirror_ref.as_ref())?;
+                checkpoint::remove(&repo, target)?;
+                filter::finish_filters(&mut filters, &repo, &commit_map, profiles.as_mut())?;
+                if let Some(profiles) = profiles.as_ref().filter(|_| options.profile_filters) {
+                    filter::print_profiles(&filters, profiles);
+                }
+
+                let report = RunReport {
+                    created_at: gix::date::Time::now_utc().seconds,
+                    base: base.to_owned(),
+                    target: target.to_owned(),
+                    target_is_branch: Some(
+                        repo.try_find_reference(format!("refs/heads/{target}").as_str())?
+                            .is_some(),
+                    ),
+                    excludes: options.excludes.clone(),
+                    include_base: options.include_base,
+                    in_place: options.in_place,
+                    base_commit: base_commit_id.to_string(),
+                    target_commit: target_commit_id.to_string(),
+                    mirror_ref: written_ref.as_bstr().to_string(),
+                    mirror_tip: final_oid.to_string(),
+                    rewritten: commits_to_rewrite.iter().map(|id| id.to_string()).collect(),
+                };
+                report.save(&repo)?;
+                if options.summary_note {
+                    let dropped = descriptors.iter().filter(|d| d.dropped).count();
+                    notes::add_summary(&repo, &report, dropped)?;
+                    println!(
+                        "Added a summary note to {} under {}",
+                        final_oid.to_hex_with_len(7),
+                        notes::SUMMARY_NOTES_REF
+                    );
+                }
+
+                if options.checkout_mirror {
+                    let path = worktree::checkout_mirror(&repo, written_ref.as_ref(), final_oid)?;
+                    println!(
+                        "Checked out {} in {path}; remove it with `git worktree remove {path}`",
+                        written_ref.shorten(),
+                        path = path.display()
+                    );
+                }
+
+                if let Some(tag) = &target_rev.tag {
+                    if options.retarget_tag {
+                        let tag_ref =
+                            refs::create_retargeted_tag(&repo, tag.id, final_oid, signer.as_ref())?;
+                        println!("Created tag {}", tag_ref.shorten());
+                    } else {
+                        println!(
+                            "{} pass --retarget-tag to also create `{RETARGETED_TAG_PREFIX}{}` pointing at the mirror",
+                            "hint:".dimmed(),
+                            tag.name,
+                        );
+                    }
+                }
             }
             None => {
-                return Err("No commits were processed".into());
+                return Err(Failure::NothingToDo("No commits were processed".into()).into());
             }
         }
     }
 
+    if let Some(path) = &options.metrics_out {
+        phases.lap("finish");
+        let mut metrics = Metrics::new(phases, base, target, options.dry_run);
+        metrics.commits = CommitCounts {
+            walked: commits_to_rewrite.len(),
+            rewritten: descriptors
+                .iter()
+                .filter(|d| !d.keep_original && !d.dropped)
+                .count(),
+            dropped: descriptors.iter().filter(|d| d.dropped).count(),
+            kept: descriptors.iter().filter(|d| d.keep_original).count(),
+        };
+        if let Some(before) = loose_before {
+            metrics.objects_written =
+                Some(metrics::count_loose_objects(&repo)?.saturating_sub(before));
+        }
+        metrics.filters = filter::metrics(&filters, profiles.as_deref().unwrap_or_default());
+        metrics.save(path)?;
+        println!("Wrote metrics to {}", path.display());
+    }
     Ok(())
 }
 
+/// The commits of a run as the filters leave them, before anything is written.
+struct Plan {
+    target_rev: ResolvedRev,
+    base_commit_id: ObjectId,
+    target_commit_id: ObjectId,
+    commits_to_rewrite: Vec<ObjectId>,
+    descriptors: Vec<CommitDescriptor>,
+    /// What the runs before wrote, of the first descriptors, with `--time-budget`.
+    resumed: Option<Checkpoint>,
+    /// Descriptors filtered after those of `resumed` before the deadline.
+    filtered: usize,
+    filters: Vec<Box<dyn Filter>>,
+    profiles: Option<Vec<FilterProfile>>,
+    /// The descriptors filtered before, with `--cache-descriptors`.
+    cache: Option<DescriptorCache>,
+}
+
+fn plan(
+    repo: &Repository,
+    base: &str,
+    target: &str,
+    options: &MirrorOptions,
+    deadline: Option<Instant>,
+    phases: &mut Phases,
+) -> AnyResult<Plan> {
+    if options.time_budget.is_some() && options.dry_run {
+        return Err(
+            "--time-budget can't be combined with --dry-run, which writes nothing to \
+                    resume from"
+                .into(),
+        );
+    }
+    if options.cache_descriptors && !options.dry_run {
+        return Err("--cache-descriptors only works with --dry-run".into());
+    }
+    let base_rev = resolve_rev(repo, base)?;
+    let target_rev = resolve_rev(repo, target)?;
+    print_peeled_tag(base, &base_rev);
+    print_peeled_tag(target, &target_rev);
+
+    let base_commit_id = base_rev.commit_id;
+    let target_commit_id = target_rev.commit_id;
+
+    let exclude_ids = options
+        .excludes
+        .iter()
+        .map(|exclude| resolve_commit_id(repo, exclude))
+        .collect::<AnyResult<Vec<_>>>()?;
+
+    if options.in_place {
+        let branch = format!("refs/heads/{target}");
+        let tip = repo
+            .try_find_reference(branch.as_str())?
+            .map(|mut reference| reference.peel_to_id_in_place())
+            .transpose()?;
+        if tip.is_none_or(|tip| tip != target_commit_id) {
+            return Err(
+                format!("`{target}` is not a branch, so it can't be rewritten in place").into(),
+            );
+        }
+        apply::ensure_no_operation_in_progress(repo)?;
+        let worktrees = apply::ensure_not_checked_out(repo, target, options.force)?;
+        if options.dry_run && options.update_worktrees {
+            for path in worktrees {
+                println!("Would update the files in {}", path.display());
+            }
+        }
+    }
+    if options.no_ff_base {
+        ensure_base_reachable(repo, base, base_commit_id, target, target_commit_id)?;
+    }
+    let commits_to_rewrite = get_commits_to_rewrite(
+        repo,
+        base_commit_id,
+        target_commit_id,
+        &exclude_ids,
+        options.include_base,
+    )?;
+    phases.lap("walk");
+    if commits_to_rewrite.is_empty() {
+        return Err(Failure::NothingToDo(format!(
+            "`{target}` has no commits after `{base}`; nothing to rewrite"
+        ))
+        .into());
+    }
+
+    let previously_rewritten = provenance::check(repo, &commits_to_rewrite, options.on_rewritten)?;
+    let mut filters = options.filters(repo, target_commit_id)?;
+    let partial = match (options.time_budget, options.cache_descriptors) {
+        (Some(_), _) => Some("--time-budget"),
+        (None, true) => Some("--cache-descriptors"),
+        (None, false) => None,
+    };
+    if let Some(option) = partial
+        && let Some(filter) = filters.iter().find(|filter| !filter.resumable())
+    {
+        return Err(format!(
+            "{option} can't be combined with {}, whose result depends on the commits \
+             rewritten before",
+            filter.name()
+        )
+        .into());
+    }
+    let mut descriptors =
+        generate_descriptors(repo, &commits_to_rewrite, &previously_rewritten, options)?;
+    let demerge = options
+        .demerge
+        .iter()
+        .map(|rev| resolve_commit_id(repo, rev))
+        .collect::<AnyResult<Vec<_>>>()?;
+    topology::demerge(repo, &mut descriptors, &demerge)?;
+    let mut groups = match &options.remerge {
+        Some(path) => topology::load_groups(repo, path)?,
+        None => Vec::new(),
+    };
+    if let Some(pattern) = &options.remerge_tags {
+        groups.extend(topology::tag_groups(repo, &descriptors, pattern)?);
+    }
+    topology::remerge(repo, &mut descriptors, &groups)?;
+    let squash = options
+        .squash
+        .iter()
+        .map(|range| topology::SquashRange::parse(repo, range))
+        .collect::<AnyResult<Vec<_>>>()?;
+    topology::squash(
+        repo,
+        &mut descriptors,
+        &squash,
+        options
+            .squash_template
+            .as_deref()
+            .unwrap_or(topology::DEFAULT_SQUASH_TEMPLATE),
+        options.squash_dates,
+    )?;
+    paths::check_conflicts(
+        repo,
+        &options.path_rules(repo, target_commit_id)?,
+        &descriptors,
+        options.allow_filter_conflicts,
+    )?;
+    phases.lap("descriptors");
+    let resumed = match options.time_budget {
+        Some(_) => checkpoint::load(repo, target, base_commit_id, target_commit_id)?,
+        None => None,
+    };
+    let done = resumed.as_ref().map_or(0, |checkpoint| checkpoint.done);
+    let mut profiles = (options.profile_filters || options.metrics_out.is_some()).then(Vec::new);
+    let mut cache = options
+        .cache_descriptors
+        .then(|| DescriptorCache::load(repo, options, target, target_commit_id))
+        .transpose()?;
+    let filtered = match &mut cache {
+        Some(cache) => {
+            let reused =
+                cache.apply_filters(&mut filters, repo, &mut descriptors, profiles.as_mut())?;
+            println!(
+                "Reused {reused} of {} filtered commit(s) from {}",
+                descriptors.len(),
+                cache.dir().display()
+            );
+            descriptors.len()
+        }
+        None => filter::apply_filters(
+            &mut filters,
+            repo,
+            &mut descriptors[done..],
+            profiles.as_mut(),
+            deadline,
+        )?,
+    };
+    let pending = &descriptors[done..done + filtered];
+    if let Some(policy) = &options.policy {
+        policy.check(repo, pending)?;
+    }
+    if let Some(policy) = &options.history_policy {
+        policy.check(repo, pending)?;
+    }
+    phases.lap("filters");
+    Ok(Plan {
+        target_rev,
+        base_commit_id,
+        target_commit_id,
+        commits_to_rewrite,
+        descriptors,
+        resumed,
+        filtered,
+        filters,
+        profiles,
+        cache,
+    })
+}
+
+/// What mirroring `target` onto `base` with `options` would change in each commit, without
+/// writing anything: the field-level diffs `--dry-run --verbose` prints, for review UIs of
+/// their own.
+pub fn preview(
+    repo: &Repository,
+    base: &str,
+    target: &str,
+    options: &MirrorOptions,
+) -> AnyResult<Vec<DescriptorDiff>> {
+    let repo = repo.clone().with_object_memory();
+    let plan = plan(&repo, base, target, options, None, &mut Phases::start())?;
+    plan.descriptors
+        .iter()
+        .map(|descriptor| DescriptorDiff::compute(&repo, descriptor))
+        .collect()
+}
+
 fn generate_descriptors(
     repo: &Repository,
     commits_to_rewrite: &[ObjectId],
+    previously_rewritten: &HashSet<ObjectId>,
+    options: &MirrorOptions,
 ) -> AnyResult<Vec<CommitDescriptor>> {
+    let identity = options.identity.resolve(repo)?;
+    let mut identity_map = options
+        .map_file
+        .as_deref()
+        .map(IdentityMap::load)
+        .transpose()?;
+    let keeps_original = |id: &ObjectId, committed_at: gix::date::Time| {
+        previously_rewritten.contains(id)
+            || options
+                .after
+                .is_some_and(|after| committed_at.seconds < after.seconds)
+    };
+    if let (Some(map), Some(path)) = (&mut identity_map, &options.map_file) {
+        let mut originals = Vec::new();
+        for id in commits_to_rewrite {
+            let commit = repo.find_commit(*id)?;
+            if keeps_original(id, commit.committer()?.time()?) {
+                continue;
+            }
+            if options.identity_scope.author() {
+                originals.push(Signature::from(commit.author()?));
+            }
+            if options.identity_scope.committer() {
+                originals.push(Signature::from(commit.committer()?));
+            }
+        }
+        map.fill_gaps(path, &originals, options.strict_identities, options.dry_run)?;
+    }
+    let mailmap = options
+        .use_mailmap
+        .then(|| identity::load_mailmap(repo))
+        .transpose()?;
     let mut descriptors = Vec::new();
     for old_id in commits_to_rewrite {
+        interrupt::check()?;
         let old_commit = repo.find_object(*old_id)?.try_into_commit()?;
         let old_commit_ref = old_commit.decode()?;
 
-        let mut author = old_commit.author()?;
-        author.name = "Dr. Magitulator".into();
+        let committed_at = old_commit.committer()?.time()?;
+        let mut author: Signature = old_commit.author()?.into();
+        let mut committer: Signature = old_commit.committer()?.into();
 
-        let mut committer = old_commit.committer()?;
-        committer.name = "Dr. Magitulator".into();
+        let keep_original = keeps_original(old_id, committed_at);
+        if !keep_original {
+            let (original_author, original_committer) = (author.clone(), committer.clone());
+            identity.apply(&mut author, &mut committer);
+            if let Some(mailmap) = &mailmap {
+                identity::apply_mailmap(mailmap, old_commit.author()?, &mut author);
+                identity::apply_mailmap(mailmap, old_commit.committer()?, &mut committer);
+            }
+            if let Some(map) = &identity_map {
+                map.apply(&original_author, &mut author);
+                map.apply(&original_committer, &mut committer);
+            }
+            if !options.identity_scope.author() {
+                author = original_author;
+            }
+            if !options.identity_scope.committer() {
+                committer = original_committer;
+            }
+        }
 
         let descriptor = CommitDescriptor {
             original_id: *old_id,
             original_parent_ids: old_commit.parent_ids().map(|oid| oid.detach()).collect(),
             tree: old_commit.tree_id()?.detach(),
-            author: author.into(),
-            committer: committer.into(),
+            author,
+            committer,
             encoding: old_commit_ref.encoding.map(|s| s.into()),
             message: old_commit_ref.message.into(),
             extra_headers: old_commit_ref
@@ -82,29 +1070,102 @@ fn generate_descriptors(
                 .into_iter()
                 .map(|(k, v)| (k.into(), BString::from(v.as_ref())))
                 .collect(),
+            keep_original,
+            dropped: false,
         };
         descriptors.push(descriptor);
     }
     Ok(descriptors)
 }
 
+/// What [`execute_mirror`] wrote so far.
+#[derive(Default)]
+pub(crate) struct Written {
+    pub(crate) map: CommitMap,
+    pub(crate) dropped: HashSet<ObjectId>,
+    /// Dropped commits without a parent to stand in for them.
+    pub(crate) vanished: HashSet<ObjectId>,
+    pub(crate) tip: Option<ObjectId>,
+    /// Commits whose stale signatures were removed.
+    pub(crate) designed: usize,
+}
+
+/// Write the commits of `descriptors`, oldest first, after those already `written`. Stops at
+/// `deadline` once at least one was written, returning how many were.
 fn execute_mirror(
     repo: &Repository,
     descriptors: &[CommitDescriptor],
-) -> AnyResult<Option<ObjectId>> {
-    let mut parent_map = HashMap::new();
-    let mut last_new_oid = None;
+    written: &mut Written,
+    signer: Option<&Signer>,
+    strip_signatures: bool,
+    mut exec: Option<&mut Exec>,
+    deadline: Option<Instant>,
+) -> AnyResult<usize> {
+    let Written {
+        map: parent_map,
+        dropped,
+        vanished,
+        tip: last_new_oid,
+        designed,
+    } = written;
 
-    for descriptor in descriptors {
+    for (count, descriptor) in descriptors.iter().enumerate() {
+        interrupt::check()?;
+        if count > 0 && deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Ok(count);
+        }
         // Map original parent IDs to their newly created counterparts.
         // If a parent wasn't rewritten, it's in the base history, so we use its original ID.
-        let new_parent_ids: Vec<ObjectId> = descriptor
+        let mut new_parent_ids: Vec<ObjectId> = descriptor
             .original_parent_ids
             .iter()
-            .map(|parent_id| *parent_map.get(parent_id).unwrap_or(parent_id))
+            .filter(|parent_id| !vanished.contains(*parent_id))
+            .map(|parent_id| *parent_map.entries.get(parent_id).unwrap_or(parent_id))
             .collect();
+        // Parents that were dropped may have become the same commit.
+        let mut seen = HashSet::new();
+        new_parent_ids.retain(|id| seen.insert(*id));
 
-        let new_commit = gix::objs::Commit {
+        // With the commits of a side branch dropped, a merge may join a commit with one of
+        // its ancestors, which adds nothing.
+        if new_parent_ids.len() > 1
+            && descriptor
+                .original_parent_ids
+                .iter()
+                .any(|parent_id| dropped.contains(parent_id))
+        {
+            new_parent_ids = independent(repo, &new_parent_ids);
+        }
+        let redundant_merge = descriptor.original_parent_ids.len() > 1
+            && match new_parent_ids[..] {
+                [parent] => repo.find_commit(parent)?.tree_id()? == descriptor.tree,
+                _ => false,
+            };
+
+        if descriptor.dropped || redundant_merge {
+            // A dropped root leaves its children without that parent.
+            let Some(&new_parent) = new_parent_ids.first() else {
+                vanished.insert(descriptor.original_id);
+                continue;
+            };
+            parent_map
+                .entries
+                .insert(descriptor.original_id, new_parent);
+            dropped.insert(descriptor.original_id);
+            *last_new_oid = Some(new_parent);
+            continue;
+        }
+
+        if descriptor.keep_original && new_parent_ids == descriptor.original_parent_ids {
+            // Nothing below this commit changed, so its original object is still valid.
+            parent_map
+                .entries
+                .insert(descriptor.original_id, descriptor.original_id);
+            *last_new_oid = Some(descriptor.original_id);
+            continue;
+        }
+
+        let mut new_commit = gix::objs::Commit {
             tree: descriptor.tree,
             parents: new_parent_ids.into(),
             author: descriptor.author.clone(),
@@ -113,18 +1174,48 @@ fn execute_mirror(
             message: descriptor.message.clone(),
             extra_headers: descriptor.extra_headers.clone(),
         };
+        if let Some(signer) = signer {
+            signer.sign_commit(&mut new_commit)?;
+        } else if strip_signatures
+            && signatures::strip_stale(&mut new_commit, descriptor.original_id)?
+        {
+            *designed += 1;
+        }
 
         let new_oid = repo.write_object(&new_commit)?.into();
+        if let Some(exec) = exec.as_deref_mut() {
+            exec.run(repo, new_oid, descriptor.original_id, descriptor.tree)?;
+        }
 
-        parent_map.insert(descriptor.original_id, new_oid);
-        last_new_oid = Some(new_oid);
+        parent_map.entries.insert(descriptor.original_id, new_oid);
+        *last_new_oid = Some(new_oid);
     }
 
-    Ok(last_new_oid)
+    Ok(descriptors.len())
 }
 
-fn print_commit_descriptor_oneline(descriptor: &CommitDescriptor) -> AnyResult<()> {
-    let t = gix::date::Time::from(descriptor.author.time).format(time::format::DEFAULT);
+/// `parents` without those that are ancestors of another one.
+fn independent(repo: &Repository, parents: &[ObjectId]) -> Vec<ObjectId> {
+    parents
+        .iter()
+        .copied()
+        .filter(|&parent| {
+            !parents.iter().any(|&other| {
+                other != parent
+                    && repo
+                        .merge_base(parent, other)
+                        .is_ok_and(|base| base.detach() == parent)
+            })
+        })
+        .collect()
+}
+
+/// With a `new_id` other than the original, it is shown after the original hash.
+fn print_commit_descriptor_oneline(
+    descriptor: &CommitDescriptor,
+    new_id: Option<ObjectId>,
+) -> AnyResult<()> {
+    let t = descriptor.author.time.format(time::format::DEFAULT);
     let message: String = descriptor
         .message
         .to_string()
@@ -136,36 +1227,135 @@ fn print_commit_descriptor_oneline(descriptor: &CommitDescriptor) -> AnyResult<(
         .take(15)
         .collect();
 
+    let new_id = match new_id {
+        Some(id) if id != descriptor.original_id => format!(" -> {}", id.to_hex_with_len(7)),
+        _ => String::new(),
+    };
     println!(
-        "{} ({}) {} {}",
+        "{}{} ({}) {} {}{}",
         &descriptor.original_id.to_string()[0..7].dimmed(),
+        new_id.dimmed(),
         t.blue(),
         // descriptor.author.name.to_string().green(),
         descriptor.author.email.to_string().green(),
         message,
+        if descriptor.keep_original {
+            " [kept]".dimmed()
+        } else {
+            "".normal()
+        },
     );
 
     Ok(())
 }
 
-fn resolve_commit_id(repo: &Repository, object_ref: &str) -> AnyResult<ObjectId> {
-    Ok(repo
-        .rev_parse_single(object_ref)?
-        .object()?
-        .peel_to_commit()?
-        .id)
+/// A revision peeled to a commit, remembering the annotated tag it was peeled from.
+pub(crate) struct ResolvedRev {
+    pub(crate) commit_id: ObjectId,
+    pub(crate) tag: Option<PeeledTag>,
 }
 
-fn get_commits_to_rewrite(
+pub(crate) struct PeeledTag {
+    pub(crate) id: ObjectId,
+    pub(crate) name: BString,
+}
+
+pub(crate) fn resolve_rev(repo: &Repository, object_ref: &str) -> AnyResult<ResolvedRev> {
+    let object = repo.rev_parse_single(object_ref)?.object()?;
+
+    let tag = if object.kind == gix::object::Kind::Tag {
+        Some(PeeledTag {
+            id: object.id,
+            name: object.clone().into_tag().decode()?.name.into(),
+        })
+    } else {
+        None
+    };
+
+    Ok(ResolvedRev {
+        commit_id: object.peel_to_commit()?.id,
+        tag,
+    })
+}
+
+pub(crate) fn resolve_commit_id(repo: &Repository, object_ref: &str) -> AnyResult<ObjectId> {
+    Ok(resolve_rev(repo, object_ref)?.commit_id)
+}
+
+fn print_peeled_tag(object_ref: &str, resolved: &ResolvedRev) {
+    if let Some(tag) = &resolved.tag {
+        println!(
+            "{} `{object_ref}` is annotated tag `{}` ({}), using the commit it points to ({})",
+            "note:".yellow(),
+            tag.name,
+            tag.id.to_hex_with_len(7),
+            resolved.commit_id.to_hex_with_len(7),
+        );
+    }
+}
+
+/// Fail with an explanation when `base` isn't an ancestor of `target`, in which case the
+/// rewrite would start at their merge base rather than at `base`.
+fn ensure_base_reachable(
+    repo: &Repository,
+    base: &str,
+    base_id: ObjectId,
+    target: &str,
+    target_id: ObjectId,
+) -> AnyResult<()> {
+    let merge_base = repo
+        .merge_base(base_id, target_id)
+        .ok()
+        .map(|id| id.detach());
+    if merge_base == Some(base_id) {
+        return Ok(());
+    }
+
+    let short = |id: ObjectId| id.to_hex_with_len(7).to_string();
+    let reason = match merge_base {
+        Some(id) if id == target_id => format!(
+            "`{target}` is an ancestor of `{base}` instead; are the arguments swapped? Try \
+             `gitm mirror {target} {base}`"
+        ),
+        Some(id) => format!(
+            "they diverged at {0}, so the rewrite would start there instead of at `{base}`. If that is \
+             what you want, say so with `gitm mirror {0} {target}`",
+            short(id)
+        ),
+        None => format!("they share no history, so every commit of `{target}` would be rewritten"),
+    };
+    Err(Failure::Validation(format!(
+        "`{base}` ({}) is not an ancestor of `{target}` ({}): {reason}",
+        short(base_id),
+        short(target_id)
+    ))
+    .into())
+}
+
+pub(crate) fn get_commits_to_rewrite(
     repo: &Repository,
     base_id: ObjectId,
     target_id: ObjectId,
+    exclude_ids: &[ObjectId],
+    include_base: bool,
 ) -> AnyResult<Vec<ObjectId>> {
+    let mut excluded_tips = exclude_ids.to_vec();
+    if include_base {
+        // Keeping only the parents means the base is rewritten onto them.
+        excluded_tips.extend(
+            repo.find_commit(base_id)?
+                .parent_ids()
+                .map(|id| id.detach()),
+        );
+    } else if base_id != target_id {
+        excluded_tips.push(base_id);
+    }
+
     let mut base_commits = HashSet::new();
-    if base_id != target_id {
+    if !excluded_tips.is_empty() {
         for info in gix::traverse::commit::topo::Builder::from_iters(
             &repo.objects,
-            [base_id],
+            excluded_tips,
             None::<Vec<ObjectId>>,
         )
         .build()?
@@ -189,35 +1379,18 @@ fn get_commits_to_rewrite(
         }
     }
 
-    if !base_commits.contains(&base_id) && !commits_to_rewrite.contains(&base_id) {
-        if repo
+    if !base_commits.contains(&base_id)
+        && !commits_to_rewrite.contains(&base_id)
+        && repo
             .find_object(base_id)?
             .try_into_commit()?
             .parent_ids()
             .count()
             == 0
-        {
-            commits_to_rewrite.push(base_id);
-        }
+    {
+        commits_to_rewrite.push(base_id);
     }
 
     commits_to_rewrite.reverse();
     Ok(commits_to_rewrite)
 }
-
-fn create_branch(repo: &Repository, target_name: &str, final_oid: ObjectId) -> AnyResult<()> {
-    let new_branch_name = format!("{}{BRANCH_POSTFIX}", target_name);
-    let full_ref_name = format!("refs/heads/{}", new_branch_name);
-
-    repo.edit_reference(RefEdit {
-        change: Change::Update {
-            log: LogChange::default(),
-            expected: gix::refs::transaction::PreviousValue::Any,
-            new: gix::refs::Target::Object(final_oid),
-        },
-        name: full_ref_name.clone().try_into()?,
-        deref: false,
-    })?;
-
-    Ok(())
-}
@@ -0,0 +1,76 @@
+//! Git notes describing mirror runs.
+use crate::{AnyResult, report::RunReport};
+use gix::{ObjectId, Repository, date::time, objs::tree::EntryKind};
+
+/// Notes ref the run summaries are attached under.
+pub const SUMMARY_NOTES_REF: &str = "refs/notes/magitulator-summary";
+
+/// Attach a summary of the run in `report` to its mirror tip, replacing an earlier note on the
+/// same commit.
+pub(crate) fn add_summary(repo: &Repository, report: &RunReport, dropped: usize) -> AnyResult<()> {
+    let operator = match repo.committer() {
+        Some(committer) => {
+            let committer = committer?;
+            format!("{} <{}>", committer.name, committer.email)
+        }
+        None => "unknown".to_owned(),
+    };
+    let date = gix::date::Time::new(report.created_at, 0).format(time::format::ISO8601);
+    // The command line is the most faithful list of the filters that were applied. Notes are
+    // shared with the branch, so the emails of erased people are left out.
+    let mut erased_next = false;
+    let command: Vec<String> = std::iter::once("gitm".to_owned())
+        .chain(std::env::args().skip(1).map(|arg| {
+            let erased = std::mem::replace(&mut erased_next, arg == "--erase-identity");
+            match arg.split_once('=') {
+                _ if erased => "<erased>".to_owned(),
+                Some(("--erase-identity", _)) => "--erase-identity=<erased>".to_owned(),
+                _ => arg,
+            }
+        }))
+        .collect();
+
+    let summary = format!(
+        "Rewritten by magitulator {}\n\
+         \n\
+         Base: {} ({})\n\
+         Target: {} ({})\n\
+         Commits: {} rewritten, {dropped} of them dropped\n\
+         Command: {}\n\
+         Operator: {operator}\n\
+         Date: {date}\n",
+        env!("CARGO_PKG_VERSION"),
+        report.base,
+        report.base_commit,
+        report.target,
+        report.target_commit,
+        report.rewritten.len(),
+        command.join(" "),
+    );
+
+    let tip: ObjectId = report.mirror_tip.parse()?;
+    add_note(repo, SUMMARY_NOTES_REF, tip, &summary)
+}
+
+/// Like `git notes --ref <notes_ref> add -f -m <text> <object>`.
+fn add_note(repo: &Repository, notes_ref: &str, object: ObjectId, text: &str) -> AnyResult<()> {
+    let previous = repo
+        .try_find_reference(notes_ref)?
+        .map(|mut reference| reference.peel_to_commit())
+        .transpose()?;
+    let mut editor = match &previous {
+        Some(commit) => commit.tree()?.edit()?,
+        None => repo.empty_tree().edit()?,
+    };
+    let blob = repo.write_blob(text)?;
+    editor.upsert(object.to_string(), EntryKind::Blob, blob)?;
+    let tree = editor.write()?;
+
+    repo.commit(
+        notes_ref,
+        "Notes added by 'gitm mirror'",
+        tree,
+        previous.map(|commit| commit.id),
+    )?;
+    Ok(())
+}
@@ -0,0 +1,703 @@
+//! Filters deciding which paths exist in rewritten trees.
+use crate::{
+    AnyResult,
+    error::Failure,
+    filter::Filter,
+    map::CommitMap,
+    metrics::CacheStats,
+    mirror::CommitDescriptor,
+    tree::{self, EntryAction, PathPattern, TreeRewriter},
+};
+use colored::Colorize;
+use gix::{
+    ObjectId, Repository,
+    bstr::{BStr, BString, ByteSlice},
+    glob::pattern::Case,
+    ignore::{Search, search::Ignore},
+    object::tree::diff::ChangeDetached,
+    objs::tree::EntryMode,
+    revision::walk::Sorting,
+    traverse::tree::Recorder,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Removes every path the `.gitignore` files of a reference commit would ignore.
+pub(crate) struct IgnoreFilter {
+    search: Search,
+    rewriter: TreeRewriter,
+    removed: usize,
+}
+
+impl IgnoreFilter {
+    /// Use the `.gitignore` files tracked in `commit_id`, at any depth.
+    pub(crate) fn from_commit(repo: &Repository, commit_id: ObjectId) -> AnyResult<Self> {
+        Ok(IgnoreFilter {
+            search: ignore_search(repo, commit_id)?,
+            rewriter: TreeRewriter::default(),
+            removed: 0,
+        })
+    }
+}
+
+/// The patterns of the `.gitignore` files tracked in `commit_id`.
+fn ignore_search(repo: &Repository, commit_id: ObjectId) -> AnyResult<Search> {
+    let tree = repo.find_commit(commit_id)?.tree()?;
+    let mut recorder = Recorder::default();
+    tree.traverse().breadthfirst(&mut recorder)?;
+
+    let mut search = Search::default();
+    // Breadth-first order adds deeper files later, which gives them precedence.
+    for entry in recorder.records {
+        if !entry.mode.is_blob() || !entry.filepath.ends_with(b".gitignore") {
+            continue;
+        }
+        let source = PathBuf::from(entry.filepath.to_string());
+        if source.file_name().is_none_or(|name| name != ".gitignore") {
+            continue;
+        }
+        let blob = repo.find_blob(entry.oid)?;
+        search.add_patterns_buffer(&blob.data, source, Some(Path::new("")), Ignore::default());
+    }
+    Ok(search)
+}
+
+impl Filter for IgnoreFilter {
+    fn name(&self) -> String {
+        "--apply-gitignore".into()
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        Some(self.rewriter.stats())
+    }
+
+    fn apply(&mut self, repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        let search = &self.search;
+        let rewritten = self
+            .rewriter
+            .rewrite(repo, descriptor.tree, &mut |path, mode, _| {
+                let ignored = search
+                    .pattern_matching_relative_path(path, Some(mode.is_tree()), Case::Sensitive)
+                    .is_some_and(|m| !m.pattern.is_negative());
+                Ok(if ignored {
+                    EntryAction::Remove
+                } else {
+                    EntryAction::Keep
+                })
+            })?;
+
+        descriptor.tree = rewritten.id;
+        self.removed += rewritten.changes.len();
+        Ok(())
+    }
+
+    fn finish(&mut self, _repo: &Repository, _map: &CommitMap) -> AnyResult<()> {
+        if self.removed > 0 {
+            println!(
+                "Removed {} ignored path(s) across rewritten commits",
+                self.removed
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Whether a [`PathFilter`] keeps or removes the paths it matches.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PathMode {
+    /// Keep only matching paths.
+    Keep,
+    Remove,
+}
+
+/// Keeps or removes the paths matching `patterns`, and optionally the paths the matching files
+/// had before being renamed.
+pub(crate) struct PathFilter {
+    mode: PathMode,
+    patterns: Vec<PathPattern>,
+    /// Earlier names of matching files, found by following renames back from the target.
+    renamed_from: HashSet<BString>,
+    rewriter: TreeRewriter,
+    removed: usize,
+}
+
+impl PathFilter {
+    pub(crate) fn new(mode: PathMode, patterns: Vec<PathPattern>) -> Self {
+        PathFilter {
+            mode,
+            patterns,
+            renamed_from: HashSet::new(),
+            rewriter: TreeRewriter::default(),
+            removed: 0,
+        }
+    }
+
+    /// Also match the paths matching files had before the renames `rewrites` detects in the
+    /// history of `target_id`, so their history from before a move is kept (or removed) too.
+    /// Copies are followed to their source when `rewrites` looks for them.
+    pub(crate) fn follow_renames(
+        mut self,
+        repo: &Repository,
+        target_id: ObjectId,
+        rewrites: gix::diff::Rewrites,
+    ) -> AnyResult<Self> {
+        // Newest first, so a file renamed twice is traced back through both renames.
+        for info in repo
+            .rev_walk([target_id])
+            .sorting(Sorting::BreadthFirst)
+            .all()?
+        {
+            let commit = info?.object()?;
+            let parent_tree = match commit.parent_ids().next() {
+                Some(parent) => Some(repo.find_commit(parent)?.tree_id()?.detach()),
+                None => None,
+            };
+            let tree = commit.tree_id()?.detach();
+            for change in tree::diff_with_renames(repo, parent_tree, tree, rewrites)? {
+                if let ChangeDetached::Rewrite {
+                    source_location,
+                    source_entry_mode,
+                    location,
+                    ..
+                } = change
+                    // Files only: following a directory would pull in unrelated files it held.
+                    && !source_entry_mode.is_tree()
+                    && self.matches(location.as_ref())
+                    && !self.matches(source_location.as_ref())
+                {
+                    self.renamed_from.insert(source_location);
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    fn matches(&self, path: &BStr) -> bool {
+        self.renamed_from.contains(path) || self.patterns.iter().any(|p| p.matches(path))
+    }
+}
+
+impl Filter for PathFilter {
+    fn name(&self) -> String {
+        match self.mode {
+            PathMode::Keep => "--keep-path",
+            PathMode::Remove => "--remove-path",
+        }
+        .into()
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        Some(self.rewriter.stats())
+    }
+
+    fn apply(&mut self, repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        let mut rewriter = std::mem::take(&mut self.rewriter);
+        let (mode, filter) = (self.mode, &*self);
+        let matches = |path: &BStr| filter.matches(path);
+        let mut visit = |path: &BStr, entry_mode: EntryMode, _| {
+            Ok(match (mode, matches(path)) {
+                (PathMode::Keep, true) | (PathMode::Remove, false) => EntryAction::Keep,
+                // Directories may hold matching paths further down.
+                (PathMode::Keep, false) if entry_mode.is_tree() => EntryAction::Keep,
+                _ => EntryAction::Remove,
+            })
+        };
+        let rewritten = match mode {
+            // Only matching paths are acted on.
+            PathMode::Remove => rewriter.rewrite_commit(repo, descriptor, &matches, &mut visit),
+            PathMode::Keep => rewriter.rewrite(repo, descriptor.tree, &mut visit),
+        };
+        self.rewriter = rewriter;
+        let rewritten = rewritten?;
+
+        descriptor.tree = rewritten.id;
+        self.removed += rewritten.changes.len();
+        Ok(())
+    }
+
+    fn finish(&mut self, _repo: &Repository, _map: &CommitMap) -> AnyResult<()> {
+        if !self.renamed_from.is_empty() {
+            println!(
+                "Followed {} earlier name(s) of matching paths back through history",
+                self.renamed_from.len()
+            );
+        }
+        if self.removed > 0 {
+            println!("Removed {} path(s) across rewritten commits", self.removed);
+        }
+        Ok(())
+    }
+}
+
+/// Makes a directory the root of every rewritten tree, and prunes the commits that don't
+/// change it, so its history can become a repository of its own.
+pub(crate) struct SubdirectoryFilter {
+    directory: BString,
+    /// The directory's tree in every commit seen, missing where it doesn't exist, by original
+    /// commit.
+    subtrees: HashMap<ObjectId, Option<ObjectId>>,
+    pruned: usize,
+    extracted: usize,
+}
+
+impl SubdirectoryFilter {
+    pub(crate) fn new(directory: &str) -> Self {
+        SubdirectoryFilter {
+            directory: directory.into(),
+            subtrees: HashMap::new(),
+            pruned: 0,
+            extracted: 0,
+        }
+    }
+
+    /// The directory's tree in `parent`, or `None` for commits that don't exist, being
+    /// synthesized by the rewrite.
+    fn parent_subtree(
+        &self,
+        repo: &Repository,
+        parent: ObjectId,
+    ) -> AnyResult<Option<Option<ObjectId>>> {
+        if let Some(parent_subtree) = self.subtrees.get(&parent) {
+            return Ok(Some(*parent_subtree));
+        }
+        match repo.find_commit(parent) {
+            Ok(commit) => Ok(Some(self.subtree(repo, commit.tree_id()?.detach())?)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn subtree(&self, repo: &Repository, tree: ObjectId) -> AnyResult<Option<ObjectId>> {
+        let entry = repo
+            .find_tree(tree)?
+            .peel_to_entry_by_path(gix::path::from_bstr(self.directory.as_bstr()))?;
+        Ok(entry
+            .filter(|entry| entry.mode().is_tree())
+            .map(|entry| entry.object_id()))
+    }
+}
+
+impl Filter for SubdirectoryFilter {
+    fn name(&self) -> String {
+        "--subdirectory-filter".into()
+    }
+
+    fn apply(&mut self, repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        let subtree = self.subtree(repo, descriptor.tree)?;
+        self.subtrees.insert(descriptor.original_id, subtree);
+        let mut parent_subtrees = Vec::new();
+        for parent in &descriptor.original_parent_ids {
+            match self.parent_subtree(repo, *parent)? {
+                Some(parent_subtree) => parent_subtrees.push(parent_subtree),
+                // Synthesized by the rewrite.
+                None => return Ok(()),
+            }
+        }
+        let unchanged = match parent_subtrees[..] {
+            [] => subtree.is_none(),
+            [parent_subtree] => subtree == parent_subtree,
+            // Other merges are kept, since they join the histories of their parents.
+            _ => subtree.is_none() && parent_subtrees.iter().all(Option::is_none),
+        };
+        if unchanged {
+            descriptor.dropped = true;
+            self.pruned += 1;
+            return Ok(());
+        }
+        descriptor.tree = subtree.unwrap_or_else(|| ObjectId::empty_tree(repo.object_hash()));
+        self.extracted += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self, _repo: &Repository, _map: &CommitMap) -> AnyResult<()> {
+        println!(
+            "Made {} the root of the rewritten history, pruning {} of {} commit(s) that didn't \
+             change it",
+            self.directory,
+            self.pruned,
+            self.pruned + self.extracted
+        );
+        Ok(())
+    }
+}
+
+/// `<old>:<new>`: a file or directory moved to a new path in every rewritten commit, as if it
+/// had always been there.
+#[derive(Clone, Debug)]
+pub struct PathRename {
+    from: BString,
+    to: BString,
+}
+
+impl FromStr for PathRename {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected `<old>:<new>` like `lib/util:src/util`, got `{s}`");
+        let (from, to) = s.split_once(':').ok_or_else(invalid)?;
+        let normalize = |path: &str| {
+            let path = path.trim_matches('/');
+            let valid = !path.is_empty()
+                && path
+                    .split('/')
+                    .all(|c| !c.is_empty() && c != "." && c != "..");
+            valid.then(|| BString::from(path)).ok_or_else(invalid)
+        };
+        let (from, to) = (normalize(from)?, normalize(to)?);
+        if from == to {
+            return Err(format!("`{s}` renames a path to itself"));
+        }
+        Ok(PathRename { from, to })
+    }
+}
+
+impl PathRename {
+    /// Where `path` is once moved, if it is `from` or inside it.
+    fn moved(&self, path: &BStr) -> Option<BString> {
+        let rest = path.strip_prefix(self.from.as_slice())?;
+        if !rest.is_empty() && rest[0] != b'/' {
+            return None;
+        }
+        let mut moved = self.to.clone();
+        moved.extend_from_slice(rest);
+        Some(moved)
+    }
+}
+
+impl fmt::Display for PathRename {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.from, self.to)
+    }
+}
+
+/// Moves paths as their [`PathRename`]s say, in order, in every rewritten tree.
+pub(crate) struct RenameFilter {
+    renames: Vec<PathRename>,
+    /// Rewritten trees by original tree.
+    trees: HashMap<ObjectId, ObjectId>,
+    moved: usize,
+}
+
+impl RenameFilter {
+    pub(crate) fn new(renames: Vec<PathRename>) -> Self {
+        RenameFilter {
+            renames,
+            trees: HashMap::new(),
+            moved: 0,
+        }
+    }
+
+    /// `tree_id` of commit `id` with the paths moved.
+    fn rename(
+        &self,
+        repo: &Repository,
+        id: ObjectId,
+        mut tree_id: ObjectId,
+    ) -> AnyResult<ObjectId> {
+        for rename in &self.renames {
+            let tree = repo.find_tree(tree_id)?;
+            let entry_at = |path: &BString| {
+                tree.clone()
+                    .peel_to_entry_by_path(gix::path::from_bstr(path.as_bstr()))
+            };
+            let Some(entry) = entry_at(&rename.from)? else {
+                continue;
+            };
+            // A path inside the one moved is gone once it moves.
+            let inside =
+                rename.to.starts_with(&rename.from) && rename.to[rename.from.len()] == b'/';
+            if !inside
+                && let Some(existing) = entry_at(&rename.to)?
+                && (existing.object_id() != entry.object_id() || existing.mode() != entry.mode())
+            {
+                return Err(Failure::Validation(format!(
+                    "--rename-path {rename}: {} already exists in commit {}",
+                    rename.to,
+                    id.to_hex_with_len(7)
+                ))
+                .into());
+            }
+            let mut editor = repo.edit_tree(tree_id)?;
+            editor.remove(rename.from.as_bstr())?;
+            editor.upsert(rename.to.as_bstr(), entry.mode().kind(), entry.object_id())?;
+            tree_id = editor.write()?.detach();
+        }
+        Ok(tree_id)
+    }
+}
+
+impl Filter for RenameFilter {
+    fn name(&self) -> String {
+        "--rename-path".into()
+    }
+
+    fn apply(&mut self, repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        let renamed = match self.trees.get(&descriptor.tree) {
+            Some(renamed) => *renamed,
+            None => {
+                let renamed = self.rename(repo, descriptor.original_id, descriptor.tree)?;
+                self.trees.insert(descriptor.tree, renamed);
+                renamed
+            }
+        };
+        if renamed != descriptor.tree {
+            descriptor.tree = renamed;
+            self.moved += 1;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, _repo: &Repository, _map: &CommitMap) -> AnyResult<()> {
+        println!(
+            "Moved {} in {} commit(s)",
+            self.renames
+                .iter()
+                .map(|rename| format!("{} to {}", rename.from, rename.to))
+                .collect::<Vec<_>>()
+                .join(", "),
+            self.moved
+        );
+        Ok(())
+    }
+}
+
+/// What a filter does to the paths one of its rules matches.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Effect {
+    /// Moved by `--rename-path`, which runs before the other path filters.
+    Rename,
+    Keep,
+    Remove,
+    UpdateCopyright,
+    Redact,
+}
+
+impl Effect {
+    /// The effects that run after this one and undo it, or make it pointless, on a path both
+    /// match.
+    fn overridden_by(self) -> &'static [Effect] {
+        match self {
+            Effect::Keep => &[Effect::Remove],
+            Effect::UpdateCopyright => &[Effect::Remove, Effect::Redact],
+            Effect::Redact => &[Effect::Remove],
+            Effect::Rename | Effect::Remove => &[],
+        }
+    }
+
+    fn participle(self) -> &'static str {
+        match self {
+            Effect::Rename => "moved",
+            Effect::Keep => "kept",
+            Effect::Remove => "removed",
+            Effect::UpdateCopyright => "given a new copyright line",
+            Effect::Redact => "redacted",
+        }
+    }
+}
+
+enum Matcher {
+    Pattern(PathPattern),
+    Rename(PathRename),
+    Ignore(Search),
+    /// Every path, e.g. `--copyright-template` without `--copyright-path`.
+    All,
+}
+
+/// A path-matching rule of one of the path filters, as given on the command line.
+pub(crate) struct PathRule {
+    /// The option the rule comes from, e.g. `--remove-path vendor`.
+    option: String,
+    effect: Effect,
+    matcher: Matcher,
+}
+
+impl PathRule {
+    pub(crate) fn pattern(flag: &str, pattern: &PathPattern, effect: Effect) -> Self {
+        PathRule {
+            option: format!("{flag} {pattern}"),
+            effect,
+            matcher: Matcher::Pattern(pattern.clone()),
+        }
+    }
+
+    pub(crate) fn rename(rename: &PathRename) -> Self {
+        PathRule {
+            option: format!("--rename-path {rename}"),
+            effect: Effect::Rename,
+            matcher: Matcher::Rename(rename.clone()),
+        }
+    }
+
+    pub(crate) fn all(flag: &str, effect: Effect) -> Self {
+        PathRule {
+            option: flag.to_owned(),
+            effect,
+            matcher: Matcher::All,
+        }
+    }
+
+    /// The rule of `--apply-gitignore`, using the `.gitignore` files of `commit_id`.
+    pub(crate) fn gitignore(repo: &Repository, commit_id: ObjectId) -> AnyResult<Self> {
+        Ok(PathRule {
+            option: "--apply-gitignore".to_owned(),
+            effect: Effect::Remove,
+            matcher: Matcher::Ignore(ignore_search(repo, commit_id)?),
+        })
+    }
+
+    /// How specifically the rule matches `path`: the number of its components the rule
+    /// matched, or 0 for rules matching everything.
+    fn match_depth(&self, path: &BStr) -> Option<usize> {
+        match &self.matcher {
+            Matcher::Pattern(pattern) => pattern.match_depth(path),
+            Matcher::Rename(rename) => rename
+                .moved(path)
+                .map(|_| rename.from.split_str("/").count()),
+            Matcher::All => Some(0),
+            Matcher::Ignore(search) => {
+                let mut candidate = path;
+                let mut is_dir = false;
+                loop {
+                    if let Some(m) = search.pattern_matching_relative_path(
+                        candidate,
+                        Some(is_dir),
+                        Case::Sensitive,
+                    ) {
+                        return (!m.pattern.is_negative())
+                            .then(|| candidate.split_str("/").count());
+                    }
+                    let slash = candidate.rfind_byte(b'/')?;
+                    candidate = candidate[..slash].as_bstr();
+                    is_dir = true;
+                }
+            }
+        }
+    }
+}
+
+/// Most conflicts listed before the rest is summarized.
+const LISTED_CONFLICTS: usize = 10;
+
+/// Fail if two `rules` match a file added or changed by one of `descriptors` and the one
+/// applied later would undo or void the other, unless the later one matches the file more
+/// specifically, like `--keep-path src --remove-path src/generated`. With `allow`, only warn.
+///
+/// Renames come first, and the other rules are matched against the paths they leave, as the
+/// filters see them: a rule for the path a file had before it moved no longer applies to it.
+///
+/// Each file is reported once, at the oldest commit it conflicts in.
+pub(crate) fn check_conflicts(
+    repo: &Repository,
+    rules: &[PathRule],
+    descriptors: &[CommitDescriptor],
+    allow: bool,
+) -> AnyResult<()> {
+    if rules.len() < 2 {
+        return Ok(());
+    }
+    let mut seen = HashSet::new();
+    let mut conflicts = Vec::new();
+    for descriptor in descriptors
+        .iter()
+        .filter(|d| !d.keep_original && !d.dropped)
+    {
+        let parent_tree = match descriptor.original_parent_ids.first() {
+            Some(parent) => Some(repo.find_commit(*parent)?.tree_id()?.detach()),
+            None => None,
+        };
+        for change in tree::diff(repo, parent_tree, descriptor.tree)? {
+            let (location, mode) = match &change {
+                ChangeDetached::Addition {
+                    location,
+                    entry_mode,
+                    ..
+                }
+                | ChangeDetached::Modification {
+                    location,
+                    entry_mode,
+                    ..
+                } => (location, entry_mode),
+                _ => continue,
+            };
+            if mode.is_tree() || seen.contains(location) {
+                continue;
+            }
+            let mut path = location.clone();
+            let mut moved_by = None;
+            for rule in rules.iter().filter(|rule| rule.effect == Effect::Rename) {
+                if let Matcher::Rename(rename) = &rule.matcher
+                    && let Some(moved) = rename.moved(path.as_ref())
+                {
+                    path = moved;
+                    moved_by = Some(rule);
+                }
+            }
+            let later = || rules.iter().filter(|rule| rule.effect != Effect::Rename);
+            if let Some(rename) = moved_by
+                && let Some(voided) = later().find(|rule| {
+                    rule.match_depth(location.as_ref()).is_some()
+                        && rule.match_depth(path.as_ref()).is_none()
+                })
+            {
+                seen.insert(location.clone());
+                conflicts.push(format!(
+                    "{} {location}: {} by {} but moved to {path} by {} first",
+                    descriptor.original_id.to_hex_with_len(7),
+                    voided.effect.participle(),
+                    voided.option,
+                    rename.option,
+                ));
+                continue;
+            }
+            let matching: Vec<(&PathRule, usize)> = later()
+                .filter_map(|rule| Some((rule, rule.match_depth(path.as_ref())?)))
+                .collect();
+            for (undone, undone_depth) in &matching {
+                let Some((winner, _)) = matching.iter().find(|(rule, depth)| {
+                    undone.effect.overridden_by().contains(&rule.effect) && depth <= undone_depth
+                }) else {
+                    continue;
+                };
+                seen.insert(location.clone());
+                conflicts.push(format!(
+                    "{} {path}: {} by {} but {} by {}",
+                    descriptor.original_id.to_hex_with_len(7),
+                    undone.effect.participle(),
+                    undone.option,
+                    winner.effect.participle(),
+                    winner.option,
+                ));
+                break;
+            }
+        }
+    }
+    if conflicts.is_empty() {
+        return Ok(());
+    }
+
+    let count = conflicts.len();
+    let mut listing: String = conflicts
+        .iter()
+        .take(LISTED_CONFLICTS)
+        .map(|conflict| format!("\n  {conflict}"))
+        .collect();
+    if count > LISTED_CONFLICTS {
+        listing.push_str(&format!("\n  and {} more", count - LISTED_CONFLICTS));
+    }
+    if allow {
+        eprintln!(
+            "{} {count} path(s) matched by conflicting filters; the later filter wins:{listing}",
+            "warning:".yellow()
+        );
+        return Ok(());
+    }
+    eprintln!("Conflicting filters:{listing}");
+    Err(Failure::Validation(format!(
+        "{count} path(s) are matched by filters that undo each other; narrow the patterns, \
+         or pass --allow-filter-conflicts to let the later filter win"
+    ))
+    .into())
+}
@@ -0,0 +1,118 @@
+//! Allow-lists restricting which commit fields a rewrite may change.
+use crate::{AnyResult, error::Failure, mirror::CommitDescriptor, preview::DescriptorDiff};
+use gix::{Repository, bstr::ByteSlice};
+use std::collections::BTreeSet;
+
+/// Violations listed before the rest is summarized.
+const MAX_LISTED: usize = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum Field {
+    AuthorName,
+    AuthorEmail,
+    AuthorTime,
+    CommitterName,
+    CommitterEmail,
+    CommitterTime,
+    Encoding,
+    /// Extra headers, such as those set by `--derive-header`.
+    Headers,
+    /// The whole message.
+    Message,
+    /// Only the trailer block at the end of the message (`Key: value` lines).
+    Trailers,
+    Tree,
+    /// Parents, including commits dropped, squashed or synthesized by the rewrite.
+    Parents,
+}
+
+impl Field {
+    pub fn name(self) -> &'static str {
+        match self {
+            Field::AuthorName => "author name",
+            Field::AuthorEmail => "author email",
+            Field::AuthorTime => "author time",
+            Field::CommitterName => "committer name",
+            Field::CommitterEmail => "committer email",
+            Field::CommitterTime => "committer time",
+            Field::Encoding => "encoding",
+            Field::Headers => "extra headers",
+            Field::Message => "message",
+            Field::Trailers => "message trailers",
+            Field::Tree => "tree",
+            Field::Parents => "parents",
+        }
+    }
+}
+
+/// Fails a run when any rewritten commit changed a field outside `allowed`.
+#[derive(Clone, Debug)]
+pub struct ChangePolicy {
+    pub allowed: BTreeSet<Field>,
+}
+
+impl ChangePolicy {
+    pub(crate) fn check(
+        &self,
+        repo: &Repository,
+        descriptors: &[CommitDescriptor],
+    ) -> AnyResult<()> {
+        let mut violations = Vec::new();
+        for descriptor in descriptors {
+            for field in changed_fields(repo, descriptor)? {
+                if !self.allows(field) {
+                    violations.push((descriptor.original_id, field));
+                }
+            }
+        }
+        if violations.is_empty() {
+            return Ok(());
+        }
+
+        eprintln!("Changes outside the allowed fields:");
+        for (id, field) in violations.iter().take(MAX_LISTED) {
+            eprintln!("  {} {}", id.to_hex_with_len(7), field.name());
+        }
+        if violations.len() > MAX_LISTED {
+            eprintln!("  ... and {} more", violations.len() - MAX_LISTED);
+        }
+        Err(Failure::Validation(format!(
+            "{} change(s) violate the --allow-change policy; nothing was written",
+            violations.len()
+        ))
+        .into())
+    }
+
+    fn allows(&self, field: Field) -> bool {
+        self.allowed.contains(&field)
+            || (field == Field::Trailers && self.allowed.contains(&Field::Message))
+    }
+}
+
+fn changed_fields(repo: &Repository, descriptor: &CommitDescriptor) -> AnyResult<Vec<Field>> {
+    Ok(DescriptorDiff::compute(repo, descriptor)?
+        .fields()
+        .collect())
+}
+
+/// Split a message into the part before its trailer block and the trailer block, where the
+/// trailer block is a last paragraph made only of `Key: value` lines.
+pub(crate) fn split_trailers(message: &[u8]) -> (&[u8], &[u8]) {
+    let trimmed = message.trim_end();
+    let start = trimmed.rfind(b"\n\n").map_or(0, |i| i + 2);
+    let paragraph = &trimmed[start..];
+
+    let is_trailer = |line: &[u8]| {
+        line.find(b": ").is_some_and(|colon| {
+            colon > 0
+                && line[..colon]
+                    .iter()
+                    .all(|b| b.is_ascii_alphanumeric() || *b == b'-')
+        })
+    };
+    if start > 0 && !paragraph.is_empty() && paragraph.lines().all(is_trailer) {
+        (message[..start].trim_end(), paragraph)
+    } else {
+        (trimmed, &[])
+    }
+}
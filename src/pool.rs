@@ -0,0 +1,231 @@
+//! Mirroring many branches at once, on a pool of threads.
+use crate::{
+    AnyResult,
+    error::{self, Failure, NOTHING_TO_DO_EXIT_CODE},
+    filter,
+    mirror::{self, MirrorOptions},
+    refs::MirrorNaming,
+};
+use colored::Colorize;
+use gix::Repository;
+use std::{
+    sync::{
+        Condvar, Mutex, PoisonError,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+};
+
+/// Hands out turns one at a time, in a fixed order.
+#[derive(Default)]
+pub(crate) struct Turnstile {
+    next: Mutex<usize>,
+    turned: Condvar,
+}
+
+impl Turnstile {
+    /// The turn after those with lower `index`, announced with `label`.
+    pub(crate) fn turn(&self, index: usize, label: String) -> Turn<'_> {
+        Turn {
+            turnstile: self,
+            index,
+            label,
+            taken: false,
+        }
+    }
+}
+
+/// The place of one pipeline in a [`Turnstile`]. Dropping it passes the turn on, after waiting
+/// for it if need be, so a pipeline that fails early doesn't hold up the ones after it.
+pub(crate) struct Turn<'a> {
+    turnstile: &'a Turnstile,
+    index: usize,
+    label: String,
+    taken: bool,
+}
+
+impl Turn<'_> {
+    /// Block until every earlier turn has passed, then print the label.
+    pub(crate) fn wait(&mut self) {
+        if self.taken {
+            return;
+        }
+        let mut next = self
+            .turnstile
+            .next
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        while *next != self.index {
+            next = self
+                .turnstile
+                .turned
+                .wait(next)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        self.taken = true;
+        println!("{} {}", "==>".bold(), self.label);
+    }
+}
+
+impl Drop for Turn<'_> {
+    fn drop(&mut self) {
+        self.wait();
+        *self
+            .turnstile
+            .next
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) += 1;
+        self.turnstile.turned.notify_all();
+    }
+}
+
+/// The local branches besides `base` and mirrors, by name.
+fn branches(repo: &Repository, base: &str, naming: &MirrorNaming) -> AnyResult<Vec<String>> {
+    let mut names = Vec::new();
+    for reference in repo.references()?.local_branches()? {
+        let reference = reference?;
+        if naming
+            .target_of(&reference.name().as_bstr().to_string())
+            .is_some()
+        {
+            continue;
+        }
+        let name = reference.name().shorten().to_string();
+        if name != base {
+            names.push(name);
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Mirror every local branch but `base` and the mirrors onto `base`, running up to `jobs`
+/// pipelines at once.
+///
+/// While they filter and write commits, pipelines share nothing but the object database, where
+/// the same commit is always written with the same id. Then, one branch at a time in the order
+/// of their names, each prints its results and updates its refs, map and report, checked
+/// against the maps of the mirrors before it. So the outcome is that of mirroring the branches
+/// one after the other, however the threads are scheduled.
+pub fn mirror_all(
+    repo: &Repository,
+    base: &str,
+    mut options: MirrorOptions,
+    jobs: usize,
+) -> AnyResult<()> {
+    if options.metrics_out.is_some() {
+        return Err("--metrics-out measures a single run and can't be combined with --all".into());
+    }
+    if options.time_budget.is_some() {
+        return Err(
+            "--time-budget resumes a single run and can't be combined with --all; give each \
+             branch a run of its own"
+                .into(),
+        );
+    }
+    // One seed for all branches, so a single `--seed` repeats the whole run.
+    if options.jitter.is_some() && options.seed.is_none() {
+        options.seed = Some(filter::random_seed());
+    }
+    let targets = branches(repo, base, &options.naming)?;
+    if targets.is_empty() {
+        return Err(Failure::NothingToDo(format!("no branches besides `{base}` to mirror")).into());
+    }
+
+    // A branch at the base would otherwise have its whole history rewritten.
+    let base_id = mirror::resolve_commit_id(repo, base)?;
+    let at_base = targets
+        .iter()
+        .map(|target| Ok(mirror::resolve_commit_id(repo, target)? == base_id))
+        .collect::<AnyResult<Vec<bool>>>()?;
+
+    let shared = repo.clone().into_sync();
+    let turnstile = Turnstile::default();
+    let next = AtomicUsize::new(0);
+    let mut outcomes: Vec<(usize, AnyResult<()>)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs.clamp(1, targets.len()))
+            .map(|_| {
+                scope.spawn(|| {
+                    let repo = shared.to_thread_local();
+                    let mut outcomes = Vec::new();
+                    // Branches are taken in order, so every earlier turn belongs to a
+                    // pipeline that is already running.
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(target) = targets.get(index) else {
+                            break;
+                        };
+                        let mut turn = turnstile.turn(index, target.clone());
+                        let outcome = match at_base[index] {
+                            true => Err(Failure::NothingToDo(format!(
+                                "`{target}` points at `{base}`; nothing to rewrite"
+                            ))
+                            .into()),
+                            false => mirror::mirror_in_turn(
+                                &repo,
+                                base,
+                                target,
+                                &options,
+                                Some(&mut turn),
+                            ),
+                        };
+                        turn.wait();
+                        match &outcome {
+                            Err(e) if error::exit_code(&**e) == NOTHING_TO_DO_EXIT_CODE => {
+                                println!("{e}")
+                            }
+                            Err(e) => eprintln!("{} {target}: {e}", "error:".red()),
+                            Ok(()) => {}
+                        }
+                        drop(turn);
+                        outcomes.push((index, outcome));
+                    }
+                    outcomes
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect()
+    });
+    outcomes.sort_by_key(|(index, _)| *index);
+
+    let failed = outcomes
+        .iter()
+        .filter(|(_, outcome)| outcome.is_err())
+        .count();
+    let unchanged = outcomes
+        .iter()
+        .filter(|(_, outcome)| {
+            outcome
+                .as_ref()
+                .is_err_and(|e| error::exit_code(&**e) == NOTHING_TO_DO_EXIT_CODE)
+        })
+        .count();
+    println!();
+    println!(
+        "Mirrored {} of {} branch(es); {unchanged} had nothing to rewrite",
+        outcomes.len() - failed,
+        targets.len()
+    );
+    if failed > unchanged {
+        return Err(format!(
+            "{} of {} branch(es) failed",
+            failed - unchanged,
+            targets.len()
+        )
+        .into());
+    }
+    if unchanged == targets.len() {
+        return Err(Failure::NothingToDo(format!(
+            "no branch has commits after `{base}`; nothing to rewrite"
+        ))
+        .into());
+    }
+    Ok(())
+}
@@ -0,0 +1,200 @@
+//! What a rewrite changes in each commit, field by field, for review before anything is
+//! written.
+use crate::{AnyResult, mirror::CommitDescriptor, policy::Field};
+use colored::Colorize;
+use gix::{
+    ObjectId, Repository,
+    actor::Signature,
+    bstr::{BString, ByteSlice},
+    date::time,
+};
+
+/// One field a rewrite changes, with its value before and after.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldChange {
+    pub field: Field,
+    /// Missing for commits the rewrite synthesized, and for absent encodings.
+    pub old: Option<BString>,
+    /// Missing for dropped commits, and for absent encodings.
+    pub new: Option<BString>,
+}
+
+/// The fields a rewritten commit changes compared with its original, in the order of
+/// [`Field`].
+#[derive(Clone, Debug)]
+pub struct DescriptorDiff {
+    pub original_id: ObjectId,
+    pub changes: Vec<FieldChange>,
+}
+
+fn describe_ids(ids: &[ObjectId]) -> BString {
+    ids.iter()
+        .map(|id| id.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+        .into()
+}
+
+fn describe_headers<'a>(headers: impl IntoIterator<Item = (&'a [u8], &'a [u8])>) -> BString {
+    let mut described = BString::default();
+    for (key, value) in headers {
+        if !described.is_empty() {
+            described.push(b'\n');
+        }
+        described.extend_from_slice(key);
+        described.push(b' ');
+        described.extend_from_slice(value);
+    }
+    described
+}
+
+impl DescriptorDiff {
+    /// Compare `descriptor` with the commit it was created from.
+    pub(crate) fn compute(repo: &Repository, descriptor: &CommitDescriptor) -> AnyResult<Self> {
+        let mut diff = DescriptorDiff {
+            original_id: descriptor.original_id,
+            changes: Vec::new(),
+        };
+        let parents = Some(describe_ids(&descriptor.original_parent_ids));
+        // Synthesized commits have no original to compare with.
+        let Ok(original) = repo.find_commit(descriptor.original_id) else {
+            diff.push(Field::Parents, None, parents);
+            return Ok(diff);
+        };
+        let original_parents: Vec<ObjectId> = original.parent_ids().map(|id| id.detach()).collect();
+        if descriptor.dropped {
+            diff.push(Field::Parents, Some(describe_ids(&original_parents)), None);
+            return Ok(diff);
+        }
+        let decoded = original.decode()?;
+
+        let author: Signature = decoded.author().into();
+        let committer: Signature = decoded.committer().into();
+        for (old, new, fields) in [
+            (
+                &author,
+                &descriptor.author,
+                [Field::AuthorName, Field::AuthorEmail, Field::AuthorTime],
+            ),
+            (
+                &committer,
+                &descriptor.committer,
+                [
+                    Field::CommitterName,
+                    Field::CommitterEmail,
+                    Field::CommitterTime,
+                ],
+            ),
+        ] {
+            if old.name != new.name {
+                diff.push(fields[0], Some(old.name.clone()), Some(new.name.clone()));
+            }
+            if old.email != new.email {
+                diff.push(fields[1], Some(old.email.clone()), Some(new.email.clone()));
+            }
+            if old.time != new.time {
+                let format = |t: &gix::date::Time| t.format(time::format::DEFAULT).into();
+                diff.push(fields[2], Some(format(&old.time)), Some(format(&new.time)));
+            }
+        }
+
+        let encoding = decoded.encoding.map(|e| e.to_owned());
+        if encoding != descriptor.encoding {
+            diff.push(Field::Encoding, encoding, descriptor.encoding.clone());
+        }
+        let headers = describe_headers(
+            decoded
+                .extra_headers
+                .iter()
+                .map(|(k, v)| (k.as_bytes(), v.as_bytes())),
+        );
+        let new_headers = describe_headers(
+            descriptor
+                .extra_headers
+                .iter()
+                .map(|(k, v)| (k.as_bytes(), v.as_bytes())),
+        );
+        if headers != new_headers {
+            diff.push(Field::Headers, Some(headers), Some(new_headers));
+        }
+        if decoded.message != descriptor.message {
+            let (old_body, old_trailers) = crate::policy::split_trailers(decoded.message);
+            let (new_body, new_trailers) = crate::policy::split_trailers(&descriptor.message);
+            if old_body == new_body {
+                diff.push(
+                    Field::Trailers,
+                    Some(old_trailers.into()),
+                    Some(new_trailers.into()),
+                );
+            } else {
+                diff.push(
+                    Field::Message,
+                    Some(decoded.message.into()),
+                    Some(descriptor.message.clone()),
+                );
+            }
+        }
+        let tree = original.tree_id()?.detach();
+        if tree != descriptor.tree {
+            diff.push(
+                Field::Tree,
+                Some(tree.to_string().into()),
+                Some(descriptor.tree.to_string().into()),
+            );
+        }
+        if original_parents != descriptor.original_parent_ids {
+            diff.push(
+                Field::Parents,
+                Some(describe_ids(&original_parents)),
+                parents,
+            );
+        }
+        Ok(diff)
+    }
+
+    fn push(&mut self, field: Field, old: Option<BString>, new: Option<BString>) {
+        self.changes.push(FieldChange { field, old, new });
+    }
+
+    /// The changed fields, in order.
+    pub fn fields(&self) -> impl Iterator<Item = Field> + '_ {
+        self.changes.iter().map(|change| change.field)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Print the changes indented below a commit's one-line summary, values of several lines
+    /// as removed and added lines.
+    pub(crate) fn print(&self) {
+        for change in &self.changes {
+            let old = change.old.as_ref().map(|v| v.to_str_lossy().into_owned());
+            let new = change.new.as_ref().map(|v| v.to_str_lossy().into_owned());
+            let multiline = [&old, &new]
+                .into_iter()
+                .flatten()
+                .any(|v| v.trim_end().contains('\n'));
+            if !multiline {
+                let show = |v: Option<String>| match v {
+                    Some(v) if !v.is_empty() => v,
+                    _ => "(none)".into(),
+                };
+                println!(
+                    "    {}: {} -> {}",
+                    change.field.name(),
+                    show(old).red(),
+                    show(new).green()
+                );
+                continue;
+            }
+            println!("    {}:", change.field.name());
+            for line in old.iter().flat_map(|v| v.trim_end().lines()) {
+                println!("      {}", format!("-{line}").red());
+            }
+            for line in new.iter().flat_map(|v| v.trim_end().lines()) {
+                println!("      {}", format!("+{line}").green());
+            }
+        }
+    }
+}
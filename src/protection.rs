@@ -0,0 +1,239 @@
+//! Branch protection rules of the hosting service behind a remote.
+use crate::AnyResult;
+use colored::Colorize;
+use gix::{Repository, remote::Direction};
+use serde::Deserialize;
+
+pub const GITHUB_TOKEN_ENV: &str = "GITHUB_TOKEN";
+pub const GITLAB_TOKEN_ENV: &str = "GITLAB_TOKEN";
+
+enum Host {
+    GitHub { api: String },
+    GitLab { api: String },
+}
+
+/// What the hosting service says about force-pushing to a branch.
+enum Verdict {
+    Unprotected,
+    /// Protected, but force-pushes are allowed.
+    ForcePushAllowed,
+    Rejected,
+    /// Protected, but the rules can't be read without (more) credentials.
+    ProtectedUnknown,
+    /// Nothing can be read without credentials.
+    Unknown,
+}
+
+/// What a hosting service answered to a request.
+enum Response {
+    Body(ureq::Body),
+    NotFound,
+    /// 401 or 403: the token, or the lack of one, isn't enough.
+    Denied(u16),
+}
+
+#[derive(Deserialize)]
+struct GitHubBranch {
+    protected: bool,
+}
+
+#[derive(Deserialize)]
+struct GitHubProtection {
+    allow_force_pushes: Option<GitHubToggle>,
+}
+
+#[derive(Deserialize)]
+struct GitHubToggle {
+    enabled: bool,
+}
+
+#[derive(Deserialize)]
+struct GitLabProtectedBranch {
+    allow_force_push: bool,
+}
+
+/// Warn if force-pushing the mirror of `target` to `remote` would be rejected by the remote's
+/// branch protection.
+pub fn protection(repo: &Repository, target: &str, remote: &str) -> AnyResult<()> {
+    let branch = target.strip_prefix("refs/heads/").unwrap_or(target);
+
+    let remote = repo.find_remote(remote)?;
+    let Some(url) = remote.url(Direction::Push) else {
+        return Err("remote has no URL".into());
+    };
+    let Some(host_name) = url.host() else {
+        return Err(format!("remote URL {url} has no host").into());
+    };
+    let project = url
+        .path
+        .to_string()
+        .trim_start_matches('/')
+        .trim_end_matches('/')
+        .trim_end_matches(".git")
+        .to_owned();
+
+    // Tokens go to exactly these hosts, never to one that merely looks like them.
+    let config = repo.config_snapshot();
+    let configured = |key: &str| {
+        config
+            .string(key)
+            .is_some_and(|host| host.eq_ignore_ascii_case(host_name.as_bytes()))
+    };
+    let host = if host_name == "github.com" {
+        Host::GitHub {
+            api: "https://api.github.com".into(),
+        }
+    } else if configured("magitulator.githubHost") {
+        Host::GitHub {
+            api: format!("https://{host_name}/api/v3"),
+        }
+    } else if host_name == "gitlab.com" || configured("magitulator.gitlabHost") {
+        Host::GitLab {
+            api: format!("https://{host_name}/api/v4"),
+        }
+    } else {
+        return Err(format!(
+            "{host_name}: only github.com and gitlab.com remotes are supported; set \
+             magitulator.githubHost or magitulator.gitlabHost for a self-hosted instance"
+        )
+        .into());
+    };
+
+    let verdict = match host {
+        Host::GitHub { api } => github(&api, &project, branch)?,
+        Host::GitLab { api } => gitlab(&api, &project, branch)?,
+    };
+
+    let place = format!("`{branch}` on {host_name}/{project}");
+    match verdict {
+        Verdict::Unprotected => println!("{} {place} is not protected", "ok:".green()),
+        Verdict::ForcePushAllowed => {
+            println!(
+                "{} {place} is protected but allows force-pushes",
+                "ok:".green()
+            )
+        }
+        Verdict::Rejected => println!(
+            "{} {place} is protected against force-pushes; pushing the mirror will be rejected",
+            "warning:".yellow()
+        ),
+        Verdict::ProtectedUnknown => println!(
+            "{} {place} is protected; set {GITHUB_TOKEN_ENV} to a token that can read its rules",
+            "warning:".yellow(),
+        ),
+        Verdict::Unknown => println!(
+            "{} can't tell whether {place} is protected; set {GITLAB_TOKEN_ENV}",
+            "warning:".yellow(),
+        ),
+    }
+    Ok(())
+}
+
+fn get(url: &str, auth: Option<(&str, String)>) -> AnyResult<Response> {
+    let mut request = ureq::get(url).header("User-Agent", "magitulator");
+    if let Some((header, value)) = auth {
+        request = request.header(header, value);
+    }
+    match request.call() {
+        Ok(response) => Ok(Response::Body(response.into_body())),
+        Err(ureq::Error::StatusCode(404)) => Ok(Response::NotFound),
+        Err(ureq::Error::StatusCode(status @ (401 | 403))) => Ok(Response::Denied(status)),
+        Err(e) => Err(format!("{url}: {e}").into()),
+    }
+}
+
+/// Why a request with the token in `env` got `status`, rather than reading as "not protected".
+fn refused(env: &str, status: u16) -> String {
+    format!(
+        "the hosting service refused {env} (HTTP {status}); check that it is valid and can \
+         read the repository"
+    )
+}
+
+fn github(api: &str, project: &str, branch: &str) -> AnyResult<Verdict> {
+    let token = std::env::var(GITHUB_TOKEN_ENV).ok();
+    let auth = || {
+        token
+            .as_ref()
+            .map(|t| ("Authorization", format!("Bearer {t}")))
+    };
+
+    let url = format!("{api}/repos/{project}/branches/{}", encode(branch));
+    let mut body = match get(&url, auth())? {
+        Response::Body(body) => body,
+        Response::Denied(status) if token.is_some() => {
+            return Err(refused(GITHUB_TOKEN_ENV, status).into());
+        }
+        _ => {
+            return Err(format!("{project}: branch `{branch}` not found, or not visible").into());
+        }
+    };
+    if !body.read_json::<GitHubBranch>()?.protected {
+        return Ok(Verdict::Unprotected);
+    }
+
+    // Reading the rules themselves needs admin rights on the repository.
+    let Response::Body(mut body) = get(&format!("{url}/protection"), auth())? else {
+        return Ok(Verdict::ProtectedUnknown);
+    };
+    let rules: GitHubProtection = body.read_json()?;
+    Ok(match rules.allow_force_pushes {
+        Some(GitHubToggle { enabled: true }) => Verdict::ForcePushAllowed,
+        _ => Verdict::Rejected,
+    })
+}
+
+fn gitlab(api: &str, project: &str, branch: &str) -> AnyResult<Verdict> {
+    let auth = std::env::var(GITLAB_TOKEN_ENV)
+        .ok()
+        .map(|t| ("PRIVATE-TOKEN", t));
+    let url = format!(
+        "{api}/projects/{}/protected_branches/{}",
+        encode(project),
+        encode(branch)
+    );
+
+    let mut body = match get(&url, auth.clone())? {
+        Response::Body(body) => body,
+        Response::Denied(status) => {
+            return match auth {
+                Some(_) => Err(refused(GITLAB_TOKEN_ENV, status).into()),
+                None => Ok(Verdict::Unknown),
+            };
+        }
+        Response::NotFound if auth.is_none() => return Ok(Verdict::Unknown),
+        // GitLab answers 404 both for unprotected branches and for projects it won't show.
+        Response::NotFound => {
+            let project_url = format!("{api}/projects/{}", encode(project));
+            return match get(&project_url, auth)? {
+                Response::Body(_) => Ok(Verdict::Unprotected),
+                Response::NotFound => Err(format!(
+                    "{project}: not found, or not visible to {GITLAB_TOKEN_ENV}"
+                )
+                .into()),
+                Response::Denied(status) => Err(refused(GITLAB_TOKEN_ENV, status).into()),
+            };
+        }
+    };
+    Ok(
+        if body.read_json::<GitLabProtectedBranch>()?.allow_force_push {
+            Verdict::ForcePushAllowed
+        } else {
+            Verdict::Rejected
+        },
+    )
+}
+
+/// Percent-encode a path segment for the APIs; GitLab takes `group/project` as one segment, and
+/// branch names can hold `/` and `#`.
+fn encode(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
@@ -0,0 +1,127 @@
+//! Recognizing commits that an earlier run already rewrote.
+use crate::{AnyResult, map::CommitMap, notes};
+use colored::Colorize;
+use gix::{ObjectId, Repository};
+use std::collections::{HashMap, HashSet};
+
+/// Name given to authors and committers of rewritten commits.
+pub(crate) const REWRITER_NAME: &str = "Dr. Magitulator";
+
+/// How many previously rewritten commits are listed before the rest is summarized.
+const LISTED: usize = 5;
+
+/// What happens to commits that already carry magitulator provenance.
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
+pub enum RewrittenAction {
+    /// Report them and rewrite them again.
+    #[default]
+    Warn,
+    /// Keep them unchanged, unless one of their parents changes.
+    Skip,
+    /// Abort the rewrite.
+    Fail,
+}
+
+/// Everything a previous run leaves behind that identifies the commits it wrote.
+pub(crate) struct Provenance {
+    /// Rewritten commits, with the mirror ref whose map records them.
+    mapped: HashMap<ObjectId, String>,
+    /// Mirror tips carrying a summary note.
+    noted: HashSet<ObjectId>,
+}
+
+impl Provenance {
+    pub(crate) fn load(repo: &Repository) -> AnyResult<Self> {
+        let mut mapped = HashMap::new();
+        for (name, map) in CommitMap::load_all(repo)? {
+            // Commits kept by `--after` map to themselves and were never rewritten.
+            for (old, new) in map.entries {
+                if old != new {
+                    mapped.insert(new, name.as_bstr().to_string());
+                }
+            }
+        }
+
+        let mut noted = HashSet::new();
+        if let Some(mut reference) = repo.try_find_reference(notes::SUMMARY_NOTES_REF)? {
+            for entry in reference.peel_to_commit()?.tree()?.iter() {
+                if let Ok(id) = ObjectId::from_hex(entry?.filename()) {
+                    noted.insert(id);
+                }
+            }
+        }
+
+        Ok(Provenance { mapped, noted })
+    }
+
+    /// Why `id` looks like the output of an earlier run, if it does.
+    pub(crate) fn of(&self, repo: &Repository, id: ObjectId) -> AnyResult<Option<String>> {
+        if let Some(mirror_ref) = self.mapped.get(&id) {
+            return Ok(Some(format!("recorded in the map of {mirror_ref}")));
+        }
+        if self.noted.contains(&id) {
+            return Ok(Some(format!(
+                "has a note under {}",
+                notes::SUMMARY_NOTES_REF
+            )));
+        }
+        let commit = repo.find_commit(id)?;
+        if commit.committer()?.name == REWRITER_NAME {
+            return Ok(Some(format!("committed by {REWRITER_NAME}")));
+        }
+        Ok(None)
+    }
+}
+
+/// Find the commits among `commits` that an earlier run wrote, and act on them as `action`
+/// says. Returns the commits to keep unchanged.
+pub(crate) fn check(
+    repo: &Repository,
+    commits: &[ObjectId],
+    action: RewrittenAction,
+) -> AnyResult<HashSet<ObjectId>> {
+    let provenance = Provenance::load(repo)?;
+    let mut found = Vec::new();
+    for id in commits {
+        if let Some(reason) = provenance.of(repo, *id)? {
+            found.push((*id, reason));
+        }
+    }
+    if found.is_empty() {
+        return Ok(HashSet::new());
+    }
+
+    let count = found.len();
+    let mut listing = String::new();
+    // Newest first, like the dry-run listing.
+    for (id, reason) in found.iter().rev().take(LISTED) {
+        listing.push_str(&format!("\n  {} {reason}", id.to_hex_with_len(7)));
+    }
+    if count > LISTED {
+        listing.push_str(&format!("\n  and {} more", count - LISTED));
+    }
+
+    match action {
+        RewrittenAction::Fail => {
+            eprintln!("Already rewritten by magitulator:{listing}");
+            Err(format!(
+                "{count} commit(s) were already rewritten by magitulator, and rewriting them again \
+                 would change their hashes a second time; pass --on-rewritten skip to keep them"
+            )
+            .into())
+        }
+        RewrittenAction::Warn => {
+            eprintln!(
+                "{} rewriting {count} commit(s) that magitulator already rewrote:{listing}\n\
+                 {} pass --on-rewritten skip to keep them unchanged",
+                "warning:".yellow(),
+                "hint:".dimmed()
+            );
+            Ok(HashSet::new())
+        }
+        RewrittenAction::Skip => {
+            println!("Keeping {count} commit(s) that magitulator already rewrote:{listing}");
+            Ok(found.into_iter().map(|(id, _)| id).collect())
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! Finding the tips branches had before magitulator rewrote them, for `recover --list`.
+use crate::{
+    AnyResult, clean,
+    refs::{self, BACKUP_REF_PREFIX},
+    report::RunReport,
+};
+use colored::Colorize;
+use gix::{
+    ObjectId, Repository,
+    bstr::ByteSlice,
+    date::{Time, time},
+};
+use std::collections::BTreeMap;
+
+/// What the reflog messages of runs updating a branch start with: `magitulator apply:` and
+/// `magitulator rewrite:`.
+const RUN_REFLOG_PREFIX: &str = "magitulator ";
+
+/// A commit a branch can be restored to.
+struct RestorePoint {
+    time: Time,
+    /// Where it was found.
+    sources: Vec<String>,
+}
+
+/// The restore points of every branch, by branch and commit.
+type Points = BTreeMap<String, BTreeMap<ObjectId, RestorePoint>>;
+
+fn add(points: &mut Points, branch: &str, id: ObjectId, time: Time, source: String) {
+    if id.is_null() {
+        return;
+    }
+    let point = points
+        .entry(branch.to_owned())
+        .or_default()
+        .entry(id)
+        .or_insert(RestorePoint {
+            time,
+            sources: Vec::new(),
+        });
+    point.time = point.time.max(time);
+    if !point.sources.contains(&source) {
+        point.sources.push(source);
+    }
+}
+
+/// Whether `target` names something other than a branch, like a tag or a commit. Targets that
+/// resolve to nothing are taken to be deleted branches.
+fn names_no_branch(repo: &Repository, target: &str) -> AnyResult<bool> {
+    Ok(repo
+        .try_find_reference(format!("refs/heads/{target}").as_str())?
+        .is_none()
+        && repo.rev_parse_single(target).is_ok())
+}
+
+/// Collect the restore points recorded by `reference`: the tips a branch had before a run
+/// updated it in its reflog, those backed up under [`BACKUP_REF_PREFIX`], and the tips mirrors
+/// were created from.
+fn scan(
+    repo: &Repository,
+    points: &mut Points,
+    reference: &mut gix::Reference<'_>,
+) -> AnyResult<()> {
+    let name = reference.name().as_bstr().to_string();
+    let short = reference.name().shorten().to_string();
+    if let Some(branch) = name.strip_prefix(BACKUP_REF_PREFIX)
+        && let Ok(id) = reference.peel_to_id_in_place()
+    {
+        let time = clean::last_ref_update(repo, &reference.name().to_owned())
+            .unwrap_or_else(|| Time::new(0, 0));
+        add(
+            points,
+            branch,
+            id.detach(),
+            time,
+            format!("backup ref {name}"),
+        );
+    }
+    let mut log = reference.log_iter();
+    let Some(lines) = log.rev()? else {
+        return Ok(());
+    };
+    for line in lines {
+        let line = line?;
+        let message = line.message.to_str_lossy();
+        let time = line.signature.time;
+        if let Some(branch) = name.strip_prefix(BACKUP_REF_PREFIX) {
+            // Each update of a backup ref replaced an older backup.
+            add(
+                points,
+                branch,
+                line.previous_oid,
+                time,
+                format!("reflog of {name}"),
+            );
+        } else if let Some(rest) = message.strip_prefix(refs::REFLOG_PREFIX) {
+            // `<source tip> <target>`, where mirrors of tags or commits have no branch to restore.
+            if let Some((tip, target)) = rest.split_once(' ')
+                && let Ok(tip) = ObjectId::from_hex(tip.as_bytes())
+                && !names_no_branch(repo, target)?
+            {
+                add(points, target, tip, time, format!("mirror {short}"));
+            }
+        } else if message.starts_with(RUN_REFLOG_PREFIX)
+            && let Some(branch) = name.strip_prefix("refs/heads/")
+        {
+            add(
+                points,
+                branch,
+                line.previous_oid,
+                time,
+                format!("reflog of {branch}: {message}"),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// List the commits branches magitulator touched pointed at before it rewrote them, found in
+/// backup refs, reflogs and the last run's report, if its target was a branch, so a branch can
+/// be restored even after its backup ref was deleted.
+pub fn list(repo: &Repository) -> AnyResult<()> {
+    let mut points = Points::new();
+    for reference in repo.references()?.all()? {
+        scan(repo, &mut points, &mut reference?)?;
+    }
+    // Reports from before targets were told apart are taken to name branches.
+    if let Some(report) = RunReport::load(repo)?.filter(|r| r.target_is_branch != Some(false)) {
+        add(
+            &mut points,
+            &report.target,
+            report.target_commit_id()?,
+            Time::new(report.created_at, 0),
+            format!("report of the last run, {} {}", report.base, report.target),
+        );
+    }
+
+    let mut pruned = 0;
+    let mut listed = 0;
+    for (branch, commits) in &points {
+        let current = repo
+            .try_find_reference(format!("refs/heads/{branch}").as_str())?
+            .and_then(|mut reference| reference.peel_to_id_in_place().ok())
+            .map(|id| id.detach());
+        let mut commits: Vec<(&ObjectId, &RestorePoint)> = commits.iter().collect();
+        commits.sort_by_key(|(_, point)| std::cmp::Reverse(point.time));
+        let mut lines = Vec::new();
+        for (id, point) in commits {
+            let Ok(commit) = repo.find_commit(*id) else {
+                pruned += 1;
+                continue;
+            };
+            let subject = commit
+                .message_raw()?
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_str_lossy()
+                .into_owned();
+            let state = if current == Some(*id) {
+                " (current tip)".green().to_string()
+            } else {
+                String::new()
+            };
+            lines.push(format!(
+                "  {} {} {subject}{state}\n      {}",
+                id.to_hex_with_len(7).to_string().yellow(),
+                point.time.format(time::format::SHORT).dimmed(),
+                point.sources.join("; ").dimmed()
+            ));
+        }
+        if lines.is_empty() {
+            continue;
+        }
+        listed += lines.len();
+        let status = if current.is_none() { " (deleted)" } else { "" };
+        println!("{}{status}", branch.bold());
+        for line in lines {
+            println!("{line}");
+        }
+    }
+    if pruned > 0 {
+        println!(
+            "{} {pruned} earlier tip(s) were pruned by `git gc` and can't be restored",
+            "note:".yellow()
+        );
+    }
+    if listed == 0 {
+        println!("No tips from before a rewrite were found");
+        return Ok(());
+    }
+    println!(
+        "{} `git branch -f <branch> <commit>` restores a branch, `git reset --keep <commit>` the \
+         checked-out one",
+        "hint:".dimmed()
+    );
+    Ok(())
+}
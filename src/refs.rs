@@ -0,0 +1,233 @@
+use crate::{AnyResult, BRANCH_POSTFIX, mirror, signatures::Signer};
+use gix::{
+    ObjectId, Repository,
+    bstr::{BString, ByteSlice},
+    refs::{
+        FullName,
+        transaction::{Change, LogChange, PreviousValue, RefEdit, RefLog},
+    },
+};
+
+/// Reflog messages of mirror refs start with this, followed by `<source-oid> <target>`.
+pub(crate) const REFLOG_PREFIX: &str = "magitulator mirror: ";
+
+/// Original branch tips saved before a branch is replaced by its mirror.
+pub const BACKUP_REF_PREFIX: &str = "refs/magitulator/backup/";
+
+/// Names of the copies of annotated tags retargeted at mirrors, `<prefix><tag>`. Kept apart
+/// from the `-magitied` names of mirror branches, which a tag of the same name would make
+/// ambiguous.
+pub const RETARGETED_TAG_PREFIX: &str = "magitulator/";
+
+/// Where mirrored branches are created.
+#[derive(Clone, Debug, Default)]
+pub enum MirrorNaming {
+    /// `refs/heads/<target>-magitied`, visible to `git branch`.
+    #[default]
+    Postfix,
+    /// `<namespace><target>`, e.g. `refs/magitied/<target>`.
+    Namespace(String),
+}
+
+impl MirrorNaming {
+    pub fn namespace(prefix: &str) -> AnyResult<Self> {
+        if !prefix.starts_with("refs/") {
+            return Err(format!("mirror namespace `{prefix}` must start with `refs/`").into());
+        }
+        let prefix = prefix.trim_end_matches('/');
+        Ok(MirrorNaming::Namespace(format!("{prefix}/")))
+    }
+
+    pub fn mirror_ref(&self, target: &str) -> String {
+        match self {
+            MirrorNaming::Postfix => format!("refs/heads/{target}{BRANCH_POSTFIX}"),
+            MirrorNaming::Namespace(prefix) => format!("{prefix}{target}"),
+        }
+    }
+
+    /// The target a mirror ref was created for, if `full_name` is a mirror ref at all.
+    pub fn target_of<'a>(&self, full_name: &'a str) -> Option<&'a str> {
+        match self {
+            MirrorNaming::Postfix => full_name
+                .strip_prefix("refs/heads/")?
+                .strip_suffix(BRANCH_POSTFIX),
+            MirrorNaming::Namespace(prefix) => full_name.strip_prefix(prefix.as_str()),
+        }
+    }
+}
+
+pub struct MirrorRef {
+    pub name: FullName,
+    pub tip: ObjectId,
+    /// The target the mirror was created from, as given on the command line.
+    pub target: String,
+    /// The target's commit at the time the mirror was created, if recorded.
+    pub source_tip: Option<ObjectId>,
+}
+
+pub enum MirrorState {
+    /// The target still points where it did when the mirror was created.
+    Pending,
+    /// The target already points at the mirror's tip.
+    Applied,
+    /// The target gained commits the mirror doesn't contain.
+    Advanced { new_commits: usize },
+    /// The target no longer contains the commit the mirror was created from.
+    Diverged,
+    /// The target can't be resolved anymore.
+    TargetMissing,
+    /// The mirror carries no record of its source commit.
+    Unknown,
+}
+
+pub fn mirror_state(repo: &Repository, mirror: &MirrorRef) -> AnyResult<MirrorState> {
+    let Ok(current) = mirror::resolve_commit_id(repo, &mirror.target) else {
+        return Ok(MirrorState::TargetMissing);
+    };
+    if current == mirror.tip {
+        return Ok(MirrorState::Applied);
+    }
+    let Some(source_tip) = mirror.source_tip else {
+        return Ok(MirrorState::Unknown);
+    };
+    if current == source_tip {
+        return Ok(MirrorState::Pending);
+    }
+
+    let is_ancestor = repo
+        .merge_base(source_tip, current)
+        .is_ok_and(|base| base.detach() == source_tip);
+    if !is_ancestor {
+        return Ok(MirrorState::Diverged);
+    }
+
+    let new_commits = repo
+        .rev_walk([current])
+        .with_hidden([source_tip])
+        .all()?
+        .count();
+    Ok(MirrorState::Advanced { new_commits })
+}
+
+pub fn create_mirror_ref(
+    repo: &Repository,
+    naming: &MirrorNaming,
+    target: &str,
+    source_tip: ObjectId,
+    final_oid: ObjectId,
+) -> AnyResult<FullName> {
+    let name: FullName = naming.mirror_ref(target).try_into()?;
+
+    repo.edit_reference(RefEdit {
+        change: Change::Update {
+            log: LogChange {
+                mode: RefLog::AndReference,
+                force_create_reflog: true,
+                message: format!("{REFLOG_PREFIX}{source_tip} {target}").into(),
+            },
+            expected: PreviousValue::Any,
+            new: gix::refs::Target::Object(final_oid),
+        },
+        name: name.clone(),
+        deref: false,
+    })?;
+
+    Ok(name)
+}
+
+pub fn list_mirrors(repo: &Repository, naming: &MirrorNaming) -> AnyResult<Vec<MirrorRef>> {
+    let platform = repo.references()?;
+    let references = match naming {
+        MirrorNaming::Postfix => platform.local_branches()?,
+        MirrorNaming::Namespace(prefix) => platform.prefixed(prefix.as_bytes().as_bstr())?,
+    };
+
+    let mut mirrors = Vec::new();
+    for reference in references {
+        let reference = reference?;
+        let full_name = reference.name().as_bstr().to_string();
+        let Some(target) = naming.target_of(&full_name) else {
+            continue;
+        };
+        let target = target.to_owned();
+
+        mirrors.push(to_mirror_ref(reference, target)?);
+    }
+
+    Ok(mirrors)
+}
+
+/// The mirror `target` has under `naming`, if it exists.
+pub fn find_mirror(
+    repo: &Repository,
+    naming: &MirrorNaming,
+    target: &str,
+) -> AnyResult<Option<MirrorRef>> {
+    match repo.try_find_reference(naming.mirror_ref(target).as_str())? {
+        Some(reference) => Ok(Some(to_mirror_ref(reference, target.to_owned())?)),
+        None => Ok(None),
+    }
+}
+
+fn to_mirror_ref(mut reference: gix::Reference<'_>, target: String) -> AnyResult<MirrorRef> {
+    let source_tip = recorded_source_tip(&mut reference)?;
+    let tip = reference.peel_to_id_in_place()?.detach();
+
+    Ok(MirrorRef {
+        name: reference.name().to_owned(),
+        tip,
+        target,
+        source_tip,
+    })
+}
+
+fn recorded_source_tip(reference: &mut gix::Reference<'_>) -> AnyResult<Option<ObjectId>> {
+    let mut log = reference.log_iter();
+    let Some(lines) = log.rev()? else {
+        return Ok(None);
+    };
+
+    for line in lines {
+        let message: BString = line?.message;
+        if let Some(rest) = message.to_str_lossy().strip_prefix(REFLOG_PREFIX) {
+            let oid = rest.split(' ').next().unwrap_or_default();
+            return Ok(ObjectId::from_hex(oid.as_bytes()).ok());
+        }
+    }
+
+    Ok(None)
+}
+
+/// Copy the annotated tag `tag_id` under [`RETARGETED_TAG_PREFIX`], pointing at `new_target`
+/// and named after its ref. A signature on the original tag is dropped; it wouldn't verify
+/// against the copy. With a `signer`, the copy is signed anew.
+pub(crate) fn create_retargeted_tag(
+    repo: &Repository,
+    tag_id: ObjectId,
+    new_target: ObjectId,
+    signer: Option<&Signer>,
+) -> AnyResult<FullName> {
+    let mut tag: gix::objs::Tag = repo.find_tag(tag_id)?.decode()?.into();
+    tag.name = format!("{RETARGETED_TAG_PREFIX}{}", tag.name).into();
+    let name: FullName = format!("refs/tags/{}", tag.name).try_into()?;
+
+    tag.target = new_target;
+    tag.target_kind = gix::object::Kind::Commit;
+    tag.pgp_signature = None;
+    if let Some(signer) = signer {
+        signer.sign_tag(&mut tag)?;
+    }
+    let new_tag_id = repo.write_object(&tag)?.detach();
+
+    repo.edit_reference(RefEdit {
+        change: Change::Update {
+            log: LogChange::default(),
+            expected: PreviousValue::Any,
+            new: gix::refs::Target::Object(new_tag_id),
+        },
+        name: name.clone(),
+        deref: false,
+    })?;
+
+    Ok(name)
+}
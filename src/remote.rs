@@ -0,0 +1,158 @@
+//! Rewrites of remote repositories that never need a local checkout.
+use crate::{
+    AnyResult,
+    batch::{self, Manifest},
+    error::Failure,
+    files,
+    refs::MirrorNaming,
+    report::RunReport,
+};
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+pub struct RemoteRewriteOptions {
+    pub dry_run: bool,
+    pub naming: MirrorNaming,
+    /// Manifest holding the profile.
+    pub manifest: Option<PathBuf>,
+    /// Profile of `manifest` giving the revisions and `mirror` options.
+    pub profile: Option<String>,
+    /// Override the profile's `base`.
+    pub base: Option<String>,
+    /// Override the profile's `target`.
+    pub target: Option<String>,
+    /// Passed to `mirror` after the profile's arguments.
+    pub args: Vec<String>,
+    /// Remote the mirror is pushed to.
+    pub push_to: String,
+    /// Ref the mirror is pushed as; `refs/heads/<target>` when `target` is a branch.
+    pub push_ref: Option<String>,
+    /// Allow replacing a ref the push remote already has.
+    pub force: bool,
+    /// Clone `url`, a local path, with `git clone --shared`, and repack the clone before
+    /// pushing.
+    pub share_objects: bool,
+}
+
+/// Clone `url` bare into a temporary directory, mirror it there and push the mirror to
+/// `options.push_to`. The clone is removed afterwards, whether the rewrite succeeded or not.
+pub fn remote_rewrite(url: &str, options: &RemoteRewriteOptions) -> AnyResult<()> {
+    let manifest = options
+        .manifest
+        .as_deref()
+        .map(Manifest::load)
+        .transpose()?;
+    let profile = match (&manifest, &options.profile) {
+        (Some(manifest), Some(name)) => Some(manifest.profile(name)?),
+        (None, Some(name)) => {
+            return Err(format!("profile `{name}` needs a --manifest to be read from").into());
+        }
+        _ => None,
+    };
+    let base = options
+        .base
+        .as_ref()
+        .or(profile.and_then(|p| p.base.as_ref()))
+        .ok_or("no base given with --base or by the profile")?;
+    let target = options
+        .target
+        .as_ref()
+        .or(profile.and_then(|p| p.target.as_ref()))
+        .ok_or("no target given with --target or by the profile")?;
+
+    let dir = std::env::temp_dir().join(format!("magitulator-remote-{}", std::process::id()));
+    if dir.exists() {
+        files::remove_dir_all(&dir)?;
+    }
+    let result = rewrite_in(
+        &dir,
+        url,
+        base,
+        target,
+        profile.map(|p| &p.args[..]),
+        options,
+    );
+    if dir.exists() {
+        files::remove_dir_all(&dir)?;
+    }
+    result
+}
+
+fn rewrite_in(
+    dir: &Path,
+    url: &str,
+    base: &str,
+    target: &str,
+    profile_args: Option<&[String]>,
+    options: &RemoteRewriteOptions,
+) -> AnyResult<()> {
+    println!("Cloning {url}");
+    batch::fetch(url, dir, options.share_objects)?;
+
+    let started = gix::date::Time::now_utc().seconds;
+    batch::run_mirror(
+        &std::env::current_exe()?,
+        dir,
+        [base, target],
+        profile_args.unwrap_or_default().iter().chain(&options.args),
+        options.dry_run,
+        &options.naming,
+    )?;
+    if options.dry_run {
+        println!("Dry run; nothing was pushed to {}", options.push_to);
+        return Ok(());
+    }
+
+    let repo = gix::open(dir)?;
+    let report = RunReport::load(&repo)?
+        .filter(|report| report.created_at >= started)
+        .ok_or_else(|| {
+            Failure::NothingToDo("the mirror run rewrote nothing; not pushing".into())
+        })?;
+    let push_ref = match &options.push_ref {
+        Some(push_ref) => push_ref.clone(),
+        None => {
+            let branch = format!("refs/heads/{target}");
+            if repo.try_find_reference(branch.as_str())?.is_none() {
+                return Err(format!(
+                    "`{target}` is not a branch of {url}; pass --push-ref to name the ref to push"
+                )
+                .into());
+            }
+            branch
+        }
+    };
+
+    // A `git gc` in the original mustn't remove objects the push still reads.
+    batch::dissociate(dir)?;
+    let mut command = Command::new("git");
+    command.arg("--git-dir").arg(dir).args(["push", "--quiet"]);
+    if options.force {
+        command.arg("--force");
+    }
+    let output = command
+        .arg(&options.push_to)
+        .arg(format!("{}:{push_ref}", report.mirror_tip))
+        .output()
+        .map_err(|e| format!("running git push: {e}"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        // ` ! [rejected] ... (non-fast-forward)` says more than the final `error:` line.
+        let reason = stderr
+            .lines()
+            .find(|line| line.starts_with(" ! "))
+            .or_else(|| stderr.lines().rfind(|line| !line.starts_with("hint:")))
+            .unwrap_or_default()
+            .trim();
+        return Err(format!("pushing to {}: {reason}", options.push_to).into());
+    }
+    println!(
+        "Pushed {} ({} commit(s) rewritten) to {} as {push_ref}",
+        &report.mirror_tip[..7],
+        report.rewritten.len(),
+        options.push_to
+    );
+    Ok(())
+}
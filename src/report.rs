@@ -0,0 +1,99 @@
+use crate::{AnyResult, files, state};
+use gix::{ObjectId, Repository, refs::FullNameRef};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::PathBuf};
+
+/// What a mirror run did, saved so later runs can tell what changed since.
+#[derive(Serialize, Deserialize)]
+pub struct RunReport {
+    /// Seconds since the UNIX epoch.
+    pub created_at: i64,
+    pub base: String,
+    pub target: String,
+    /// Whether `target` named a branch, rather than a tag or commit. Missing from reports
+    /// written before it was recorded.
+    #[serde(default)]
+    pub target_is_branch: Option<bool>,
+    pub excludes: Vec<String>,
+    /// Missing from reports written before `--include-base` existed.
+    #[serde(default)]
+    pub include_base: bool,
+    /// The target branch itself was updated, rather than a mirror created.
+    #[serde(default)]
+    pub in_place: bool,
+    pub base_commit: String,
+    pub target_commit: String,
+    pub mirror_ref: String,
+    pub mirror_tip: String,
+    /// Original commits that were rewritten, oldest first.
+    pub rewritten: Vec<String>,
+}
+
+impl RunReport {
+    pub fn path(repo: &Repository) -> PathBuf {
+        state::dir(repo).join("last-report.json")
+    }
+
+    pub fn load(repo: &Repository) -> AnyResult<Option<Self>> {
+        let path = Self::path(repo);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(&fs::read(path)?)?))
+    }
+
+    pub fn save(&self, repo: &Repository) -> AnyResult<()> {
+        let path = Self::path(repo);
+        fs::create_dir_all(state::dir(repo))?;
+        fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn target_commit_id(&self) -> AnyResult<ObjectId> {
+        Ok(self.target_commit.parse()?)
+    }
+
+    pub fn mirror_tip_id(&self) -> AnyResult<ObjectId> {
+        Ok(self.mirror_tip.parse()?)
+    }
+}
+
+/// The command line a mirror was created with, so that `apply --remirror` can create it again
+/// once the target gained commits.
+#[derive(Serialize, Deserialize)]
+pub struct MirrorRecipe {
+    pub command: Vec<String>,
+}
+
+impl MirrorRecipe {
+    /// The command line of this process.
+    pub(crate) fn current() -> Self {
+        MirrorRecipe {
+            command: std::iter::once("gitm".to_owned())
+                .chain(std::env::args().skip(1))
+                .collect(),
+        }
+    }
+
+    pub fn path(repo: &Repository, mirror_ref: &FullNameRef) -> PathBuf {
+        state::recipes_dir(repo).join(format!("{}.json", mirror_ref.as_bstr()))
+    }
+
+    /// The recipe recorded for `mirror_ref`, if it was created since recipes are kept.
+    pub fn load(repo: &Repository, mirror_ref: &FullNameRef) -> AnyResult<Option<Self>> {
+        let path = Self::path(repo, mirror_ref);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(&fs::read(path)?)?))
+    }
+
+    pub(crate) fn save(&self, repo: &Repository, mirror_ref: &FullNameRef) -> AnyResult<()> {
+        let path = Self::path(repo, mirror_ref);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        files::write_atomically(&path, &serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+}
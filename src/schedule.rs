@@ -0,0 +1,164 @@
+//! Moving commit dates into a weekly working schedule.
+use crate::{AnyResult, filter::Filter, mirror::CommitDescriptor};
+use gix::Repository;
+use jiff::{
+    Timestamp, ToSpan,
+    civil::{Date, Time},
+    tz::{Offset, TimeZone},
+};
+use std::{fmt, str::FromStr};
+
+const WEEKDAYS: [&str; 7] = [
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+
+/// `<days> <from>-<until> [<zone>]`, e.g. `Mon-Fri 09:00-17:00 Europe/Helsinki`: the hours
+/// commits may be dated in. Without a zone, each date is judged in its own offset.
+///
+/// Dates outside the schedule move to the nearest time inside it. Moving never takes one date
+/// past another, so dates keep their order.
+#[derive(Clone, Debug)]
+pub struct BusinessHours {
+    spec: String,
+    /// By days from Monday.
+    days: [bool; 7],
+    from: Time,
+    until: Time,
+    zone: Option<TimeZone>,
+}
+
+/// A weekday by its name or an abbreviation of at least three letters, counted from Monday.
+fn parse_weekday(name: &str) -> Result<usize, String> {
+    let lowercase = name.to_ascii_lowercase();
+    WEEKDAYS
+        .iter()
+        .position(|day| lowercase.len() >= 3 && day.starts_with(&lowercase))
+        .ok_or_else(|| format!("unknown weekday `{name}`"))
+}
+
+fn parse_time(time: &str) -> Result<Time, String> {
+    let invalid = || format!("expected a time like `09:00`, got `{time}`");
+    let (hour, minute) = time.split_once(':').ok_or_else(invalid)?;
+    let hour: i8 = hour.parse().map_err(|_| invalid())?;
+    let minute: i8 = minute.parse().map_err(|_| invalid())?;
+    Time::new(hour, minute, 0, 0).map_err(|_| invalid())
+}
+
+impl FromStr for BusinessHours {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split_whitespace().collect();
+        let (days_spec, hours, zone) = match parts[..] {
+            [days, hours] => (days, hours, None),
+            [days, hours, zone] => (days, hours, Some(zone)),
+            _ => {
+                return Err(format!(
+                    "expected `<days> <from>-<until> [<zone>]` like `Mon-Fri 09:00-17:00 \
+                     Europe/Helsinki`, got `{s}`"
+                ));
+            }
+        };
+
+        let mut days = [false; 7];
+        for item in days_spec.split(',') {
+            let (first, last) = match item.split_once('-') {
+                Some((first, last)) => (parse_weekday(first)?, parse_weekday(last)?),
+                None => (parse_weekday(item)?, parse_weekday(item)?),
+            };
+            // `Sat-Mon` wraps around the end of the week.
+            let mut day = first;
+            loop {
+                days[day] = true;
+                if day == last {
+                    break;
+                }
+                day = (day + 1) % 7;
+            }
+        }
+
+        let (from, until) = hours
+            .split_once('-')
+            .ok_or_else(|| format!("expected hours like `09:00-17:00`, got `{hours}`"))?;
+        let (from, until) = (parse_time(from)?, parse_time(until)?);
+        if until <= from {
+            return Err(format!(
+                "`{hours}` must end after it starts on the same day"
+            ));
+        }
+
+        let zone = zone
+            .map(|name| TimeZone::get(name).map_err(|e| format!("unknown timezone `{name}`: {e}")))
+            .transpose()?;
+        Ok(BusinessHours {
+            spec: s.to_owned(),
+            days,
+            from,
+            until,
+            zone,
+        })
+    }
+}
+
+impl fmt::Display for BusinessHours {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.spec)
+    }
+}
+
+impl BusinessHours {
+    /// The time inside the schedule nearest to `seconds`, judged in `zone`.
+    fn nearest(&self, seconds: i64, zone: &TimeZone) -> AnyResult<i64> {
+        let zoned = Timestamp::from_second(seconds)?.to_zoned(zone.clone());
+        let mut best: Option<i64> = None;
+        // A week either way always holds a working day.
+        for days in -7..=7 {
+            let date: Date = zoned.date().checked_add(days.days())?;
+            if !self.days[date.weekday().to_monday_zero_offset() as usize] {
+                continue;
+            }
+            let start = date.to_datetime(self.from).to_zoned(zone.clone())?;
+            let end = date.to_datetime(self.until).to_zoned(zone.clone())?;
+            let (start, end) = (start.timestamp().as_second(), end.timestamp().as_second());
+            let candidate = seconds.clamp(start, end);
+            if best.is_none_or(|best| candidate.abs_diff(seconds) < best.abs_diff(seconds)) {
+                best = Some(candidate);
+            }
+        }
+        Ok(best.expect("a schedule has at least one working day"))
+    }
+
+    /// Move `time` into the schedule, but not before `earliest`, and record it in the zone's
+    /// offset.
+    fn fit(&self, time: &mut gix::date::Time, earliest: Option<i64>) -> AnyResult<i64> {
+        let zone = match &self.zone {
+            Some(zone) => zone.clone(),
+            None => TimeZone::fixed(Offset::from_seconds(time.offset)?),
+        };
+        let seconds = self.nearest(time.seconds, &zone)?;
+        let seconds = earliest.map_or(seconds, |earliest| seconds.max(earliest));
+        time.seconds = seconds;
+        time.offset = zone.to_offset(Timestamp::from_second(seconds)?).seconds();
+        Ok(seconds)
+    }
+}
+
+impl Filter for BusinessHours {
+    fn name(&self) -> String {
+        format!("--business-hours {self}")
+    }
+
+    fn apply(&mut self, _repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        let author = self.fit(&mut descriptor.author.time, None)?;
+        // Dates in different offsets may have crossed; a commit can't be committed before it
+        // was written.
+        self.fit(&mut descriptor.committer.time, Some(author))?;
+        Ok(())
+    }
+}
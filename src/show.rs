@@ -0,0 +1,200 @@
+use crate::{AnyResult, map::CommitMap, mirror, tree};
+use colored::Colorize;
+use gix::{
+    ObjectId, Repository,
+    bstr::{BString, ByteSlice},
+    date::time,
+    object::tree::diff::ChangeDetached,
+};
+use std::collections::BTreeMap;
+
+/// The parts of a commit compared between a rewritten commit and its original.
+struct Fields {
+    parents: Vec<ObjectId>,
+    author: String,
+    committer: String,
+    encoding: Option<BString>,
+    extra_headers: Vec<(BString, BString)>,
+    message: BString,
+}
+
+impl Fields {
+    fn of(repo: &Repository, id: ObjectId) -> AnyResult<(Self, ObjectId)> {
+        let commit = repo.find_commit(id)?;
+        let decoded = commit.decode()?;
+        let signature = |s: gix::actor::SignatureRef<'_>| -> AnyResult<String> {
+            Ok(format!(
+                "{} <{}> {}",
+                s.name,
+                s.email,
+                s.time()?.format(time::format::DEFAULT)
+            ))
+        };
+        let fields = Fields {
+            parents: commit.parent_ids().map(|id| id.detach()).collect(),
+            author: signature(decoded.author())?,
+            committer: signature(decoded.committer())?,
+            encoding: decoded.encoding.map(Into::into),
+            extra_headers: decoded
+                .extra_headers
+                .iter()
+                .map(|(k, v)| ((*k).into(), v.as_ref().into()))
+                .collect(),
+            message: decoded.message.into(),
+        };
+        Ok((fields, commit.tree_id()?.detach()))
+    }
+}
+
+/// Show rewritten commit `rev` next to the original it was created from, marking what changed.
+pub fn show(repo: &Repository, rev: &str) -> AnyResult<()> {
+    let new_id = mirror::resolve_commit_id(repo, rev)?;
+
+    // Original → rewritten over all recorded maps, including those of applied mirrors and
+    // in-place rewrites, and the reverse for this commit and its parents.
+    let mut forward = BTreeMap::new();
+    let mut mirrors = Vec::new();
+    for (name, map) in CommitMap::load_all(repo)? {
+        if map.entries.values().any(|new| *new == new_id) {
+            mirrors.push(name.shorten().to_string());
+        }
+        forward.extend(map.entries);
+    }
+    let originals: Vec<ObjectId> = forward
+        .iter()
+        .filter(|(old, new)| **new == new_id && repo.find_commit(**old).is_ok())
+        .map(|(old, _)| *old)
+        .collect();
+
+    // Dropped or squashed commits map onto the commit they were folded into; the commit the
+    // rewrite is really derived from is the one without a parent in the same set.
+    let is_folded = |old: &ObjectId| {
+        repo.find_commit(*old).is_ok_and(|commit| {
+            commit
+                .parent_ids()
+                .any(|parent| originals.contains(&parent.detach()))
+        })
+    };
+    let Some(&original_id) = originals.iter().find(|old| !is_folded(old)) else {
+        return Err(format!(
+            "{new_id} has no original: it isn't recorded in any commit map, or the rewrite synthesized it"
+        )
+        .into());
+    };
+
+    println!("{} {new_id}", "commit".yellow());
+    println!("{} {original_id}", "original".yellow());
+    if !mirrors.is_empty() {
+        println!("{} {}", "mirror".yellow(), mirrors.join(", "));
+    }
+    let folded: Vec<String> = originals
+        .iter()
+        .filter(|old| **old != original_id)
+        .map(|old| old.to_hex_with_len(7).to_string())
+        .collect();
+    if !folded.is_empty() {
+        println!("{} {}", "folded in".yellow(), folded.join(", "));
+    }
+    if original_id == new_id {
+        println!();
+        println!("Kept as is by the rewrite.");
+        return Ok(());
+    }
+    println!();
+
+    let (new, new_tree) = Fields::of(repo, new_id)?;
+    let (old, old_tree) = Fields::of(repo, original_id)?;
+
+    // Parents match when they are the rewritten counterparts of the original parents.
+    let translated: Vec<ObjectId> = old
+        .parents
+        .iter()
+        .map(|parent| *forward.get(parent).unwrap_or(parent))
+        .collect();
+    let describe_parents = |parents: &[ObjectId]| {
+        parents
+            .iter()
+            .map(|id| id.to_hex_with_len(7).to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    field(
+        "parents",
+        &describe_parents(&old.parents),
+        &describe_parents(&new.parents),
+        translated == new.parents,
+    );
+    field("author", &old.author, &new.author, old.author == new.author);
+    field(
+        "committer",
+        &old.committer,
+        &new.committer,
+        old.committer == new.committer,
+    );
+    if old.encoding.is_some() || new.encoding.is_some() {
+        let show = |e: &Option<BString>| e.as_ref().map(|e| e.to_string()).unwrap_or_default();
+        field(
+            "encoding",
+            &show(&old.encoding),
+            &show(&new.encoding),
+            old.encoding == new.encoding,
+        );
+    }
+    let headers = |h: &[(BString, BString)]| {
+        h.iter()
+            .map(|(k, v)| format!("{k} {v}"))
+            .collect::<Vec<_>>()
+            .join("; ")
+    };
+    if !old.extra_headers.is_empty() || !new.extra_headers.is_empty() {
+        field(
+            "headers",
+            &headers(&old.extra_headers),
+            &headers(&new.extra_headers),
+            old.extra_headers == new.extra_headers,
+        );
+    }
+
+    if old.message == new.message {
+        println!("{}", "message (unchanged)".dimmed());
+        for line in new.message.lines() {
+            println!("    {}", line.to_str_lossy().dimmed());
+        }
+    } else {
+        println!("{}", "message".bold());
+        for line in old.message.lines() {
+            println!("  - {}", line.to_str_lossy().red());
+        }
+        for line in new.message.lines() {
+            println!("  + {}", line.to_str_lossy().green());
+        }
+    }
+
+    if old_tree == new_tree {
+        println!("{}", "tree (unchanged)".dimmed());
+    } else {
+        println!("{}", "tree".bold());
+        for change in tree::diff(repo, Some(old_tree), new_tree)? {
+            if change.entry_mode().is_tree() {
+                continue;
+            }
+            let (status, color): (&str, fn(String) -> colored::ColoredString) = match change {
+                ChangeDetached::Addition { .. } => ("A", |s| s.green()),
+                ChangeDetached::Deletion { .. } => ("D", |s| s.red()),
+                _ => ("M", |s| s.yellow()),
+            };
+            println!("  {}", color(format!("{status} {}", change.location())));
+        }
+    }
+
+    Ok(())
+}
+
+fn field(name: &str, old: &str, new: &str, same: bool) {
+    if same {
+        println!("{} {}", format!("{name:<9}").dimmed(), new.dimmed());
+    } else {
+        println!("{} {}", format!("{name:<9}").bold(), old.red());
+        println!("{:<9} {}", "", new.green());
+    }
+}
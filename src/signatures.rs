@@ -0,0 +1,403 @@
+//! Commit signature inspection, verified with the same tools git uses.
+use crate::{AnyResult, mirror};
+use colored::Colorize;
+use gix::{
+    ObjectId, Repository,
+    bstr::{BStr, BString, ByteSlice},
+    objs::WriteTo,
+};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum State {
+    Good,
+    ExpiredSignature,
+    ExpiredKey,
+    RevokedKey,
+    Bad,
+    UnknownKey,
+    /// Signed, but there is nothing to verify the signature with.
+    Unverified,
+    Unsigned,
+}
+
+impl State {
+    fn label(self) -> colored::ColoredString {
+        match self {
+            State::Good => "good".green(),
+            State::ExpiredSignature => "expired signature".yellow(),
+            State::ExpiredKey => "expired key".yellow(),
+            State::RevokedKey => "revoked key".red(),
+            State::Bad => "bad signature".red(),
+            State::UnknownKey => "unknown key".yellow(),
+            State::Unverified => "unverified".dimmed(),
+            State::Unsigned => "unsigned".dimmed(),
+        }
+    }
+}
+
+struct Verification {
+    kind: &'static str,
+    state: State,
+    key: Option<String>,
+}
+
+/// Report, for every commit `mirror <base> <target>` would rewrite, whether it is signed, by
+/// which key, and whether that key can still be trusted.
+pub fn audit(repo: &Repository, base: &str, target: &str) -> AnyResult<()> {
+    let base_id = mirror::resolve_commit_id(repo, base)?;
+    let target_id = mirror::resolve_commit_id(repo, target)?;
+    let commits = mirror::get_commits_to_rewrite(repo, base_id, target_id, &[], false)?;
+
+    let mut states: BTreeMap<State, usize> = BTreeMap::new();
+    let mut keys: BTreeMap<String, usize> = BTreeMap::new();
+    for (index, id) in commits.iter().enumerate().rev() {
+        let commit = repo.find_commit(*id)?;
+        let subject = commit
+            .message_raw()?
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_str_lossy()
+            .into_owned();
+        let verification = match commit.signature()? {
+            Some((signature, signed)) => verify(
+                repo,
+                *id,
+                index,
+                signature.as_ref(),
+                &signed.to_bstring(),
+                commit.committer()?.email,
+            )?,
+            None => Verification {
+                kind: "",
+                state: State::Unsigned,
+                key: None,
+            },
+        };
+
+        *states.entry(verification.state).or_default() += 1;
+        if let Some(key) = &verification.key {
+            *keys.entry(key.clone()).or_default() += 1;
+        }
+
+        let mut details = vec![verification.state.label().to_string()];
+        details.extend(
+            [Some(verification.kind.to_owned()), verification.key]
+                .into_iter()
+                .flatten()
+                .filter(|detail| !detail.is_empty()),
+        );
+        println!(
+            "{} {} {subject}",
+            id.to_hex_with_len(7).to_string().dimmed(),
+            details.join(" ")
+        );
+    }
+
+    println!();
+    let summary: Vec<String> = states
+        .iter()
+        .map(|(state, n)| format!("{n} {}", state.label()))
+        .collect();
+    println!("{} commit(s): {}", commits.len(), summary.join(", "));
+    for (key, n) in &keys {
+        println!("  key {key}: {n} commit(s)");
+    }
+    let untrusted: usize = states
+        .iter()
+        .filter(|(state, _)| **state != State::Good)
+        .map(|(_, n)| n)
+        .sum();
+    if untrusted > 0 {
+        println!(
+            "{untrusted} commit(s) lack a good signature; re-signing the rewrite would cover them"
+        );
+    }
+    Ok(())
+}
+
+fn verify(
+    repo: &Repository,
+    id: ObjectId,
+    index: usize,
+    signature: &BStr,
+    payload: &[u8],
+    committer_email: &BStr,
+) -> AnyResult<Verification> {
+    let sig_path = std::env::temp_dir().join(format!(
+        "magitulator-{}-{index}-{}.sig",
+        std::process::id(),
+        id.to_hex_with_len(7)
+    ));
+    fs::write(&sig_path, signature)?;
+    let result = if signature.starts_with(b"-----BEGIN SSH SIGNATURE-----") {
+        verify_ssh(repo, &sig_path, payload, committer_email)
+    } else if signature.starts_with(b"-----BEGIN PGP SIGNATURE-----") {
+        verify_gpg(repo, &sig_path, payload)
+    } else {
+        Ok(Verification {
+            kind: "x509",
+            state: State::Unverified,
+            key: None,
+        })
+    };
+    fs::remove_file(&sig_path)?;
+    result
+}
+
+/// Run `program args...` with `payload` on stdin and return its stdout and success.
+fn run(program: &str, args: &[&std::ffi::OsStr], payload: &[u8]) -> AnyResult<(String, bool)> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("running {program}: {e}"))?;
+    child
+        .stdin
+        .take()
+        .ok_or("no stdin for signature check")?
+        .write_all(payload)?;
+    let output = child.wait_with_output()?;
+    Ok((
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        output.status.success(),
+    ))
+}
+
+fn verify_gpg(repo: &Repository, sig_path: &Path, payload: &[u8]) -> AnyResult<Verification> {
+    let program = repo
+        .config_snapshot()
+        .string("gpg.program")
+        .map_or_else(|| "gpg".to_owned(), |p| p.to_string());
+    let (status, _) = run(
+        &program,
+        &[
+            "--status-fd=1".as_ref(),
+            "--verify".as_ref(),
+            sig_path.as_os_str(),
+            "-".as_ref(),
+        ],
+        payload,
+    )?;
+
+    let mut state = State::UnknownKey;
+    let mut key = None;
+    for line in status.lines() {
+        let mut fields = line
+            .strip_prefix("[GNUPG:] ")
+            .unwrap_or_default()
+            .split(' ');
+        let keyword = fields.next().unwrap_or_default();
+        let argument = fields.next().map(str::to_owned);
+        let found = match keyword {
+            "GOODSIG" => State::Good,
+            "EXPSIG" => State::ExpiredSignature,
+            "EXPKEYSIG" => State::ExpiredKey,
+            "REVKEYSIG" => State::RevokedKey,
+            "BADSIG" => State::Bad,
+            "ERRSIG" | "NO_PUBKEY" => State::UnknownKey,
+            // The full fingerprint is more useful than the long key id of the lines above.
+            "VALIDSIG" => {
+                key = argument;
+                continue;
+            }
+            _ => continue,
+        };
+        state = found;
+        key = key.or(argument);
+    }
+    Ok(Verification {
+        kind: "gpg",
+        state,
+        key,
+    })
+}
+
+fn verify_ssh(
+    repo: &Repository,
+    sig_path: &Path,
+    payload: &[u8],
+    committer_email: &BStr,
+) -> AnyResult<Verification> {
+    let Some(allowed_signers) = repo
+        .config_snapshot()
+        .trusted_path("gpg.ssh.allowedSignersFile")
+        .transpose()?
+    else {
+        return Ok(Verification {
+            kind: "ssh",
+            state: State::Unverified,
+            key: None,
+        });
+    };
+    let allowed_signers: PathBuf = allowed_signers.into_owned();
+    let principal = committer_email.to_string();
+    let (output, success) = run(
+        "ssh-keygen",
+        &[
+            "-Y".as_ref(),
+            "verify".as_ref(),
+            "-f".as_ref(),
+            allowed_signers.as_os_str(),
+            "-I".as_ref(),
+            principal.as_ref(),
+            "-n".as_ref(),
+            "git".as_ref(),
+            "-s".as_ref(),
+            sig_path.as_os_str(),
+        ],
+        payload,
+    )?;
+
+    // `Good "git" signature for <principal> with <type> key <fingerprint>`
+    let key = output.split(" key ").nth(1).map(|k| k.trim().to_owned());
+    Ok(Verification {
+        kind: "ssh",
+        state: if success {
+            State::Good
+        } else {
+            State::UnknownKey
+        },
+        key,
+    })
+}
+
+/// Headers holding a commit's signature, over SHA-1 and SHA-256 object names.
+const SIGNATURE_HEADERS: [&str; 2] = ["gpgsig", "gpgsig-sha256"];
+
+/// Remove the signature from `commit`, rewritten from `original_id`, unless the rewrite left it
+/// byte for byte the same, so the signature still verifies. Returns whether one was removed.
+pub(crate) fn strip_stale(
+    commit: &mut gix::objs::Commit,
+    original_id: ObjectId,
+) -> AnyResult<bool> {
+    let signed = |commit: &gix::objs::Commit| {
+        commit
+            .extra_headers
+            .iter()
+            .any(|(key, _)| SIGNATURE_HEADERS.contains(&key.to_str_lossy().as_ref()))
+    };
+    if !signed(commit) {
+        return Ok(false);
+    }
+    let mut payload = Vec::new();
+    commit.write_to(&mut payload)?;
+    if gix::objs::compute_hash(original_id.kind(), gix::object::Kind::Commit, &payload)?
+        == original_id
+    {
+        return Ok(false);
+    }
+    commit
+        .extra_headers
+        .retain(|(key, _)| !SIGNATURE_HEADERS.contains(&key.to_str_lossy().as_ref()));
+    Ok(true)
+}
+
+/// Key rewritten commits are signed with.
+#[derive(Clone, Debug)]
+pub enum SigningKey {
+    /// `user.signingkey` from the git config.
+    Configured,
+    Given(String),
+}
+
+/// Signs rewritten objects with a single key, using `gpg.format` and `gpg.program` like git.
+pub(crate) struct Signer {
+    key: String,
+    program: String,
+    ssh: bool,
+}
+
+impl Signer {
+    pub(crate) fn new(repo: &Repository, key: &str) -> Self {
+        let config = repo.config_snapshot();
+        let ssh = config
+            .string("gpg.format")
+            .is_some_and(|format| format.as_ref() == "ssh");
+        let program = config
+            .string(if ssh {
+                "gpg.ssh.program"
+            } else {
+                "gpg.program"
+            })
+            .map_or_else(
+                || if ssh { "ssh-keygen" } else { "gpg" }.to_owned(),
+                |p| p.to_string(),
+            );
+        Signer {
+            key: key.to_owned(),
+            program,
+            ssh,
+        }
+    }
+
+    /// Sign with `key`, looking `user.signingkey` up for [`SigningKey::Configured`].
+    pub(crate) fn from_key(repo: &Repository, key: &SigningKey) -> AnyResult<Self> {
+        match key {
+            SigningKey::Given(key) => Ok(Signer::new(repo, key)),
+            SigningKey::Configured => {
+                let key = repo
+                    .config_snapshot()
+                    .string("user.signingkey")
+                    .ok_or("user.signingkey is not set; pass a key with --gpg-sign=<keyid>")?;
+                Ok(Signer::new(repo, &key.to_str_lossy()))
+            }
+        }
+    }
+
+    /// A detached, armored signature over `payload`.
+    pub(crate) fn sign(&self, payload: &[u8]) -> AnyResult<BString> {
+        let key: &std::ffi::OsStr = self.key.as_ref();
+        let args: Vec<&std::ffi::OsStr> = if self.ssh {
+            vec![
+                "-Y".as_ref(),
+                "sign".as_ref(),
+                "-n".as_ref(),
+                "git".as_ref(),
+                "-f".as_ref(),
+                key,
+            ]
+        } else {
+            vec!["--status-fd=2".as_ref(), "-bsau".as_ref(), key]
+        };
+        let (signature, success) = run(&self.program, &args, payload)?;
+        if !success || signature.is_empty() {
+            return Err(format!("{} failed to sign with key `{}`", self.program, self.key).into());
+        }
+        Ok(signature.into())
+    }
+
+    /// Replace any signature on `commit` with one made by this signer.
+    pub(crate) fn sign_commit(&self, commit: &mut gix::objs::Commit) -> AnyResult<()> {
+        commit
+            .extra_headers
+            .retain(|(key, _)| !SIGNATURE_HEADERS.contains(&key.to_str_lossy().as_ref()));
+        let mut payload = Vec::new();
+        commit.write_to(&mut payload)?;
+        let signature = self.sign(&payload)?;
+        commit
+            .extra_headers
+            .push(("gpgsig".into(), signature.trim_end().into()));
+        Ok(())
+    }
+
+    pub(crate) fn sign_tag(&self, tag: &mut gix::objs::Tag) -> AnyResult<()> {
+        tag.pgp_signature = None;
+        let mut payload = Vec::new();
+        tag.write_to(&mut payload)?;
+        // The signature follows the message on a line of its own, and git verifies everything
+        // before it.
+        payload.push(b'\n');
+        tag.pgp_signature = Some(self.sign(&payload)?);
+        Ok(())
+    }
+}
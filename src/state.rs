@@ -0,0 +1,49 @@
+//! Locations of magitulator's own bookkeeping inside the git directory.
+use gix::Repository;
+use std::path::PathBuf;
+
+/// `.git/magitulator`, shared by all worktrees of the repository. Under a ref namespace it is
+/// `.git/magitulator/refs/namespaces/<name>`, so that tenants of a shared repository never see
+/// each other's maps, reports or vaults.
+pub fn dir(repo: &Repository) -> PathBuf {
+    let dir = repo.common_dir().join("magitulator");
+    match repo.namespace() {
+        Some(namespace) => dir.join(gix::path::from_bstr(namespace.as_bstr())),
+        None => dir,
+    }
+}
+
+/// Checkpoints of runs that ran out of their `--time-budget`, to resume from.
+pub fn checkpoints_dir(repo: &Repository) -> PathBuf {
+    dir(repo).join("checkpoints")
+}
+
+/// Objects and files set aside by a run until they are confirmed or discarded.
+pub fn quarantine_dir(repo: &Repository) -> PathBuf {
+    dir(repo).join("quarantine")
+}
+
+/// Records of runs in progress, one file per process.
+pub fn runs_dir(repo: &Repository) -> PathBuf {
+    dir(repo).join("runs")
+}
+
+/// Original → rewritten commit maps, one file per mirror ref.
+pub fn maps_dir(repo: &Repository) -> PathBuf {
+    dir(repo).join("maps")
+}
+
+/// Command lines mirrors were created with, one file per mirror ref.
+pub fn recipes_dir(repo: &Repository) -> PathBuf {
+    dir(repo).join("recipes")
+}
+
+/// Outputs of `--message-command`, by the hash of command and message.
+pub fn message_cache_dir(repo: &Repository) -> PathBuf {
+    dir(repo).join("messages")
+}
+
+/// Descriptors filtered by dry runs with `--cache-descriptors`, by the digest of the filters.
+pub fn descriptor_cache_dir(repo: &Repository) -> PathBuf {
+    dir(repo).join("descriptors")
+}
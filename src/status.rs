@@ -0,0 +1,111 @@
+use crate::{
+    AnyResult, mirror,
+    refs::{self, MirrorNaming, MirrorState},
+    report::RunReport,
+};
+use colored::Colorize;
+use gix::{ObjectId, Repository, date::time};
+use std::collections::HashSet;
+
+pub fn status(repo: &Repository, naming: &MirrorNaming) -> AnyResult<()> {
+    let Some(report) = RunReport::load(repo)? else {
+        println!("No previous run recorded");
+        return Ok(());
+    };
+
+    println!(
+        "Last run: {} {} {} -> {} ({} commit(s), {})",
+        if report.in_place { "rewrite" } else { "mirror" },
+        report.base,
+        report.target,
+        report.mirror_ref,
+        report.rewritten.len(),
+        gix::date::Time::new(report.created_at, 0).format(time::format::DEFAULT),
+    );
+
+    let Ok(target_now) = mirror::resolve_commit_id(repo, &report.target) else {
+        println!(
+            "{} `{}` can't be resolved anymore",
+            "drift:".red(),
+            report.target
+        );
+        return Ok(());
+    };
+    // A target rewritten in place started over at the rewritten tip.
+    let target_then = if report.in_place {
+        report.mirror_tip_id()?
+    } else {
+        report.target_commit_id()?
+    };
+    if target_now == target_then {
+        println!("{} `{}` unchanged", "target:".green(), report.target);
+    } else {
+        println!(
+            "{} `{}` moved {} -> {}",
+            "drift:".yellow(),
+            report.target,
+            target_then.to_hex_with_len(7),
+            target_now.to_hex_with_len(7),
+        );
+    }
+
+    let base_now = mirror::resolve_commit_id(repo, &report.base)?;
+    let mut exclude_ids = report
+        .excludes
+        .iter()
+        .map(|exclude| mirror::resolve_commit_id(repo, exclude))
+        .collect::<AnyResult<Vec<_>>>()?;
+    if report.in_place {
+        // The rewritten commits replaced the originals on the target itself.
+        exclude_ids.push(report.mirror_tip_id()?);
+    }
+    let previously: HashSet<ObjectId> = report
+        .rewritten
+        .iter()
+        .filter_map(|id| id.parse().ok())
+        .collect();
+
+    let new_commits: Vec<ObjectId> = mirror::get_commits_to_rewrite(
+        repo,
+        base_now,
+        target_now,
+        &exclude_ids,
+        report.include_base,
+    )?
+    .into_iter()
+    .filter(|id| !previously.contains(id))
+    .collect();
+    if new_commits.is_empty() {
+        println!("No new commits to rewrite");
+    } else {
+        println!("{} new commit(s) to rewrite:", new_commits.len());
+        for id in new_commits.iter().rev() {
+            let commit = repo.find_commit(*id)?;
+            println!(
+                "  {} {}",
+                id.to_hex_with_len(7).to_string().dimmed(),
+                commit.message()?.summary()
+            );
+        }
+    }
+
+    if report.in_place {
+        return Ok(());
+    }
+    match refs::find_mirror(repo, naming, &report.target)? {
+        Some(mirror) if mirror.tip != report.mirror_tip_id()? => {
+            println!("{} was re-created since", mirror.name.as_bstr());
+        }
+        Some(mirror) => match refs::mirror_state(repo, &mirror)? {
+            MirrorState::Applied => println!("{}", "Mirror applied".green()),
+            MirrorState::Pending => println!("{}", "Apply pending".yellow()),
+            _ => println!(
+                "{} apply would drop the target's new commits",
+                "stale:".red()
+            ),
+        },
+        None => println!("{} no longer exists", report.mirror_ref),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,31 @@
+//! Scratch repositories for tests, built with the git CLI.
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Run git in `dir`, ignoring the system and global configuration, and fail the test if it
+/// fails.
+pub(crate) fn git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+        .args(args)
+        .current_dir(dir)
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .status()
+        .expect("running git");
+    assert!(status.success(), "git {args:?} failed");
+}
+
+/// A new repository in a scratch directory, on an unborn `main`, with an identity configured
+/// for gitm to read.
+pub(crate) fn scratch_repo() -> PathBuf {
+    let dir =
+        std::env::temp_dir().join(format!("magitulator-test-{}", crate::files::unique_name()));
+    std::fs::create_dir_all(&dir).unwrap();
+    git(&dir, &["init", "-q", "-b", "main"]);
+    git(&dir, &["config", "user.name", "Test"]);
+    git(&dir, &["config", "user.email", "test@example.com"]);
+    dir
+}
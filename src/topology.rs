@@ -0,0 +1,508 @@
+//! Restructuring of history: squashing, and turning merges into linear sequences and back.
+use crate::{
+    AnyResult,
+    mirror::{self, CommitDescriptor},
+    tree,
+};
+use gix::{
+    ObjectId, Repository, bstr::ByteSlice, glob::wildmatch, merge::tree::TreatAsUnresolved,
+    object::tree::diff::ChangeDetached,
+};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::Path,
+};
+
+/// Replace each merge in `merges` by its second-parent commits, replayed onto the first parent.
+///
+/// The second-parent side must be linear and part of the rewrite. Replayed commits keep their
+/// metadata. The merge itself disappears when the replayed side ends up with its tree, and
+/// otherwise stays as an ordinary commit on top, carrying whatever the merge resolved.
+pub(crate) fn demerge(
+    repo: &Repository,
+    descriptors: &mut Vec<CommitDescriptor>,
+    merges: &[ObjectId],
+) -> AnyResult<()> {
+    if merges.is_empty() {
+        return Ok(());
+    }
+    let index: HashMap<ObjectId, usize> = descriptors
+        .iter()
+        .enumerate()
+        .map(|(i, d)| (d.original_id, i))
+        .collect();
+
+    for &merge_id in merges {
+        let Some(&merge) = index.get(&merge_id) else {
+            return Err(format!("--demerge: {merge_id} is not among the rewritten commits").into());
+        };
+        let [first, second] = descriptors[merge].original_parent_ids[..] else {
+            return Err(format!("--demerge: {merge_id} is not a merge of two parents").into());
+        };
+
+        let side = side_chain(repo, first, second)?;
+        let mut tree = repo.find_commit(first)?.tree_id()?.detach();
+        let mut parent = first;
+        for &commit_id in &side {
+            let Some(&i) = index.get(&commit_id) else {
+                return Err(format!(
+                    "--demerge {merge_id}: side commit {commit_id} is not among the rewritten commits"
+                )
+                .into());
+            };
+            tree =
+                replay(repo, commit_id, tree).map_err(|e| format!("--demerge {merge_id}: {e}"))?;
+
+            let descriptor = &mut descriptors[i];
+            descriptor.original_parent_ids = vec![parent];
+            descriptor.tree = tree;
+            descriptor.keep_original = false;
+            parent = commit_id;
+        }
+
+        let descriptor = &mut descriptors[merge];
+        descriptor.original_parent_ids = vec![parent];
+        descriptor.keep_original = false;
+        descriptor.dropped = descriptor.tree == tree;
+    }
+
+    sort_parents_first(descriptors);
+    Ok(())
+}
+
+/// The commits only reachable through `second`, oldest first. Fails unless they form a chain.
+fn side_chain(repo: &Repository, first: ObjectId, second: ObjectId) -> AnyResult<Vec<ObjectId>> {
+    let side: HashSet<ObjectId> = repo
+        .rev_walk([second])
+        .with_hidden([first])
+        .all()?
+        .map(|info| info.map(|info| info.id))
+        .collect::<Result<_, _>>()?;
+
+    let mut chain = Vec::new();
+    let mut current = second;
+    while side.contains(&current) {
+        let commit = repo.find_commit(current)?;
+        let mut parents = commit.parent_ids();
+        let (Some(parent), None) = (parents.next(), parents.next()) else {
+            return Err(format!("{current} on the merged side is itself a merge").into());
+        };
+        chain.push(current);
+        current = parent.detach();
+    }
+    if chain.len() != side.len() {
+        return Err(format!("the side merged at {second} isn't a linear chain").into());
+    }
+
+    chain.reverse();
+    Ok(chain)
+}
+
+/// Apply the changes `commit_id` made to its parent onto `onto`, returning the resulting tree.
+fn replay(repo: &Repository, commit_id: ObjectId, onto: ObjectId) -> AnyResult<ObjectId> {
+    let commit = repo.find_commit(commit_id)?;
+    let theirs = commit.tree_id()?.detach();
+    let ancestor = match commit.parent_ids().next() {
+        Some(parent) => repo.find_commit(parent)?.tree_id()?.detach(),
+        None => ObjectId::empty_tree(repo.object_hash()),
+    };
+    if ancestor == onto {
+        return Ok(theirs);
+    }
+
+    let mut outcome = repo.merge_trees(
+        ancestor,
+        onto,
+        theirs,
+        Default::default(),
+        repo.tree_merge_options()?,
+    )?;
+    if outcome.has_unresolved_conflicts(TreatAsUnresolved::default()) {
+        return Err(format!("{commit_id} doesn't apply cleanly").into());
+    }
+    Ok(outcome.tree.write()?.detach())
+}
+
+/// A run of linear commits, `from` to `to` inclusive, to be shown as a merged branch.
+pub(crate) struct Group {
+    from: ObjectId,
+    to: ObjectId,
+    name: String,
+}
+
+/// Read groups from `path`, one `<from-rev> <to-rev> <branch name>` line each.
+pub(crate) fn load_groups(repo: &Repository, path: &Path) -> AnyResult<Vec<Group>> {
+    let mut groups = Vec::new();
+    for (index, line) in fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.splitn(3, char::is_whitespace);
+        let (Some(from), Some(to), Some(name)) = (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(format!(
+                "{}:{}: expected `<from-rev> <to-rev> <branch name>`",
+                path.display(),
+                index + 1
+            )
+            .into());
+        };
+        groups.push(Group {
+            from: mirror::resolve_commit_id(repo, from)?,
+            to: mirror::resolve_commit_id(repo, to)?,
+            name: name.trim().to_owned(),
+        });
+    }
+    Ok(groups)
+}
+
+/// One group per rewritten commit tagged with a tag matching `pattern`, reaching back along
+/// first parents to the previous such commit.
+pub(crate) fn tag_groups(
+    repo: &Repository,
+    descriptors: &[CommitDescriptor],
+    pattern: &str,
+) -> AnyResult<Vec<Group>> {
+    let by_id: HashMap<ObjectId, &CommitDescriptor> =
+        descriptors.iter().map(|d| (d.original_id, d)).collect();
+
+    let mut boundaries = HashMap::new();
+    for tag in repo.references()?.tags()? {
+        let mut tag = tag?;
+        let name = tag.name().shorten().to_owned();
+        if !wildmatch(pattern.into(), name.as_ref(), wildmatch::Mode::empty()) {
+            continue;
+        }
+        let id = tag.peel_to_commit()?.id;
+        if by_id.contains_key(&id) {
+            boundaries.insert(id, name.to_string());
+        }
+    }
+
+    let mut groups = Vec::new();
+    for descriptor in descriptors {
+        let Some(name) = boundaries.get(&descriptor.original_id) else {
+            continue;
+        };
+        let mut from = descriptor;
+        while let Some(parent) = from
+            .original_parent_ids
+            .first()
+            .filter(|p| !boundaries.contains_key(*p))
+            .and_then(|p| by_id.get(p))
+        {
+            from = parent;
+        }
+        groups.push(Group {
+            from: from.original_id,
+            to: descriptor.original_id,
+            name: name.clone(),
+        });
+    }
+    Ok(groups)
+}
+
+/// Move each group onto a branch of its own, merged back by a synthetic merge commit.
+///
+/// The merge's first parent is the commit before the group and its second parent the group's
+/// last commit; its tree is the last commit's, so descendants see no change in content.
+pub(crate) fn remerge(
+    repo: &Repository,
+    descriptors: &mut Vec<CommitDescriptor>,
+    groups: &[Group],
+) -> AnyResult<()> {
+    if groups.is_empty() {
+        return Ok(());
+    }
+
+    let mut grouped = HashSet::new();
+    for group in groups {
+        let index: HashMap<ObjectId, usize> = descriptors
+            .iter()
+            .enumerate()
+            .map(|(i, d)| (d.original_id, i))
+            .collect();
+        let fail = |reason: &str| format!("--remerge `{}`: {reason}", group.name);
+
+        let (members, before) =
+            linear_run(descriptors, &index, group.from, group.to).map_err(|e| fail(&e))?;
+        for member in &members {
+            if !grouped.insert(*member) {
+                return Err(fail(&format!("{member} is already part of another group")).into());
+            }
+        }
+        let Some(before) = before else {
+            return Err(fail("a group can't start at a root commit").into());
+        };
+
+        let last = &descriptors[index[&group.to]];
+        let merge_id = gix::objs::compute_hash(
+            repo.object_hash(),
+            gix::object::Kind::Blob,
+            format!("magitulator remerge {}", group.to).as_bytes(),
+        )?;
+        let merge = CommitDescriptor {
+            original_id: merge_id,
+            original_parent_ids: vec![before, group.to],
+            tree: last.tree,
+            author: last.committer.clone(),
+            committer: last.committer.clone(),
+            encoding: None,
+            message: format!("Merge branch '{}'\n", group.name).into(),
+            extra_headers: Vec::new(),
+            keep_original: false,
+            dropped: false,
+        };
+
+        for descriptor in descriptors.iter_mut() {
+            if members.contains(&descriptor.original_id) {
+                continue;
+            }
+            for parent in &mut descriptor.original_parent_ids {
+                if *parent == group.to {
+                    *parent = merge_id;
+                    descriptor.keep_original = false;
+                }
+            }
+        }
+        descriptors.insert(index[&group.to] + 1, merge);
+    }
+
+    sort_parents_first(descriptors);
+    Ok(())
+}
+
+/// Message of a squashed commit unless `--squash-template` says otherwise.
+pub const DEFAULT_SQUASH_TEMPLATE: &str =
+    "{first}\n\nSquashed {count} commits:\n{subjects}\n\nTouched paths:\n{paths}\n";
+
+/// Listing more changed paths than this summarizes them per top-level directory instead.
+const MAX_LISTED_PATHS: usize = 20;
+
+/// Which dates a squashed commit carries.
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
+pub enum DatePolicy {
+    /// The author date of the first squashed commit.
+    #[default]
+    First,
+    /// The author date of the last squashed commit.
+    Last,
+    /// The newest author date among the squashed commits.
+    NewestAuthor,
+    /// The time of the rewrite, as both author and committer date.
+    Now,
+}
+
+/// Commits after `after` up to and including `to`, collapsed into one.
+pub(crate) struct SquashRange {
+    after: ObjectId,
+    to: ObjectId,
+}
+
+impl SquashRange {
+    /// Parse `<rev>..<rev>`, which like in git excludes the first revision.
+    pub(crate) fn parse(repo: &Repository, range: &str) -> AnyResult<Self> {
+        let Some((after, to)) = range.split_once("..") else {
+            return Err(format!("--squash: expected `<rev>..<rev>`, got `{range}`").into());
+        };
+        Ok(SquashRange {
+            after: mirror::resolve_commit_id(repo, after)?,
+            to: mirror::resolve_commit_id(repo, to)?,
+        })
+    }
+}
+
+/// Collapse each range into a single commit with the tree of its last commit, the author of
+/// its first, and a message rendered from `template`. The author date is chosen by `dates`;
+/// the committer date stays that of the last commit, except with [`DatePolicy::Now`].
+///
+/// The template may use `{first}` and `{last}` (subjects), `{count}`, `{subjects}` (one
+/// `- <subject>` line per squashed commit, oldest first) and `{paths}` (the changed paths).
+pub(crate) fn squash(
+    repo: &Repository,
+    descriptors: &mut [CommitDescriptor],
+    ranges: &[SquashRange],
+    template: &str,
+    dates: DatePolicy,
+) -> AnyResult<()> {
+    let now = gix::date::Time::now_local_or_utc();
+    for range in ranges {
+        let index: HashMap<ObjectId, usize> = descriptors
+            .iter()
+            .enumerate()
+            .map(|(i, d)| (d.original_id, i))
+            .collect();
+        let fail = |reason: String| format!("--squash {}..{}: {reason}", range.after, range.to);
+
+        let mut members = Vec::new();
+        let mut current = range.to;
+        while current != range.after {
+            let Some(&i) = index.get(&current) else {
+                return Err(fail(format!("{current} is not among the rewritten commits")).into());
+            };
+            let [parent] = descriptors[i].original_parent_ids[..] else {
+                return Err(fail(format!("{current} doesn't have exactly one parent")).into());
+            };
+            members.push(i);
+            current = parent;
+        }
+        members.reverse();
+        let (Some(&first), Some(&last)) = (members.first(), members.last()) else {
+            continue;
+        };
+
+        let subject = |i: usize| {
+            descriptors[i]
+                .message
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_str_lossy()
+                .into_owned()
+        };
+        let subjects: Vec<String> = members
+            .iter()
+            .map(|&i| format!("- {}", subject(i)))
+            .collect();
+        let before_tree = repo.find_commit(range.after)?.tree_id()?.detach();
+        let paths = summarize_paths(&tree::diff(
+            repo,
+            Some(before_tree),
+            descriptors[last].tree,
+        )?);
+
+        let message = template
+            .replace("{first}", &subject(first))
+            .replace("{last}", &subject(last))
+            .replace("{count}", &members.len().to_string())
+            .replace("{subjects}", &subjects.join("\n"))
+            .replace("{paths}", &paths);
+
+        let mut author = descriptors[first].author.clone();
+        author.time = match dates {
+            DatePolicy::First => author.time,
+            DatePolicy::Last => descriptors[last].author.time,
+            DatePolicy::NewestAuthor => members
+                .iter()
+                .map(|&i| descriptors[i].author.time)
+                .max_by_key(|time| time.seconds)
+                .unwrap_or(author.time),
+            DatePolicy::Now => now,
+        };
+        for &i in &members {
+            descriptors[i].keep_original = false;
+            descriptors[i].dropped = i != last;
+        }
+        let squashed = &mut descriptors[last];
+        squashed.original_parent_ids = vec![range.after];
+        squashed.author = author;
+        if let DatePolicy::Now = dates {
+            squashed.committer.time = now;
+        }
+        squashed.message = message.into();
+    }
+    Ok(())
+}
+
+fn summarize_paths(changes: &[ChangeDetached]) -> String {
+    let changes: Vec<(char, String)> = changes
+        .iter()
+        .filter(|change| !change.entry_mode().is_tree())
+        .map(|change| {
+            let status = match change {
+                ChangeDetached::Addition { .. } => 'A',
+                ChangeDetached::Deletion { .. } => 'D',
+                _ => 'M',
+            };
+            (status, change.location().to_string())
+        })
+        .collect();
+
+    if changes.len() <= MAX_LISTED_PATHS {
+        return changes
+            .iter()
+            .map(|(status, path)| format!("{status} {path}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+    }
+
+    let mut per_dir: BTreeMap<String, usize> = BTreeMap::new();
+    for (_, path) in &changes {
+        let top = match path.split_once('/') {
+            Some((dir, _)) => format!("{dir}/"),
+            None => path.clone(),
+        };
+        *per_dir.entry(top).or_default() += 1;
+    }
+    per_dir
+        .iter()
+        .map(|(dir, n)| format!("{dir} ({n} file(s))"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The commits from `to` back to `from` along single parents, newest first, and the parent of
+/// `from` unless it is a root commit.
+fn linear_run(
+    descriptors: &[CommitDescriptor],
+    index: &HashMap<ObjectId, usize>,
+    from: ObjectId,
+    to: ObjectId,
+) -> Result<(Vec<ObjectId>, Option<ObjectId>), String> {
+    let mut members = Vec::new();
+    let mut current = to;
+    loop {
+        let Some(&i) = index.get(&current) else {
+            return Err(format!("{current} is not among the rewritten commits"));
+        };
+        members.push(current);
+        let parent = match descriptors[i].original_parent_ids[..] {
+            [parent] => Some(parent),
+            [] if current == from => None,
+            _ => return Err(format!("{current} doesn't have exactly one parent")),
+        };
+        if current == from {
+            return Ok((members, parent));
+        }
+        current = parent.ok_or_else(|| format!("{from} is not an ancestor of {to}"))?;
+    }
+}
+
+/// Order descriptors so every commit comes after its parents, keeping the given order otherwise.
+pub(crate) fn sort_parents_first(descriptors: &mut Vec<CommitDescriptor>) {
+    let index: HashMap<ObjectId, usize> = descriptors
+        .iter()
+        .enumerate()
+        .map(|(i, d)| (d.original_id, i))
+        .collect();
+
+    fn visit(
+        i: usize,
+        descriptors: &[CommitDescriptor],
+        index: &HashMap<ObjectId, usize>,
+        visited: &mut [bool],
+        order: &mut Vec<usize>,
+    ) {
+        if visited[i] {
+            return;
+        }
+        visited[i] = true;
+        for parent in &descriptors[i].original_parent_ids {
+            if let Some(&p) = index.get(parent) {
+                visit(p, descriptors, index, visited, order);
+            }
+        }
+        order.push(i);
+    }
+
+    let mut visited = vec![false; descriptors.len()];
+    let mut order = Vec::with_capacity(descriptors.len());
+    for i in 0..descriptors.len() {
+        visit(i, descriptors, &index, &mut visited, &mut order);
+    }
+
+    let mut slots: Vec<Option<CommitDescriptor>> = descriptors.drain(..).map(Some).collect();
+    descriptors.extend(order.into_iter().filter_map(|i| slots[i].take()));
+}
@@ -0,0 +1,115 @@
+//! Message rewriting by an external command, such as a translator.
+use crate::{
+    AnyResult, files, filter::Filter, map::CommitMap, metrics::CacheStats,
+    mirror::CommitDescriptor, state,
+};
+use gix::{Repository, bstr::BString};
+use std::{
+    fs,
+    io::Write,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+/// Replaces every message with the output of `command`, run with `sh -c` and the message on
+/// stdin.
+///
+/// Results are cached on disk by the hash of command and message, so re-running a mirror
+/// doesn't ask a (slow, paid) translation service again.
+pub(crate) struct MessageCommand {
+    command: String,
+    cache_dir: PathBuf,
+    translated: usize,
+    cached: usize,
+}
+
+impl MessageCommand {
+    pub(crate) fn new(repo: &Repository, command: &str) -> Self {
+        MessageCommand {
+            command: command.to_owned(),
+            cache_dir: state::message_cache_dir(repo),
+            translated: 0,
+            cached: 0,
+        }
+    }
+
+    fn run(&self, descriptor: &CommitDescriptor) -> AnyResult<BString> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .env("MAGITULATOR_ORIGINAL", descriptor.original_id.to_string())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("running `{}`: {e}", self.command))?;
+        child
+            .stdin
+            .take()
+            .ok_or("no stdin for the message command")?
+            .write_all(&descriptor.message)?;
+        let output = child.wait_with_output()?;
+
+        if !output.status.success() {
+            return Err(format!(
+                "`{}` failed for the message of {}",
+                self.command, descriptor.original_id
+            )
+            .into());
+        }
+        if output.stdout.iter().all(u8::is_ascii_whitespace) {
+            return Err(format!(
+                "`{}` printed an empty message for {}",
+                self.command, descriptor.original_id
+            )
+            .into());
+        }
+        Ok(output.stdout.into())
+    }
+}
+
+impl Filter for MessageCommand {
+    fn name(&self) -> String {
+        "--message-command".into()
+    }
+
+    fn apply(&mut self, repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        let mut key = self.command.as_bytes().to_vec();
+        key.push(0);
+        key.extend_from_slice(&descriptor.message);
+        let hash = gix::objs::compute_hash(repo.object_hash(), gix::object::Kind::Blob, &key)?;
+        let cache_file = self.cache_dir.join(hash.to_string());
+
+        descriptor.message = match fs::read(&cache_file) {
+            Ok(message) => {
+                self.cached += 1;
+                message.into()
+            }
+            Err(_) => {
+                let message = self.run(descriptor)?;
+                fs::create_dir_all(&self.cache_dir)?;
+                // Another pipeline may be reading the same entry.
+                files::write_atomically(&cache_file, &message)?;
+                self.translated += 1;
+                message
+            }
+        };
+        Ok(())
+    }
+
+    fn finish(&mut self, _repo: &Repository, _map: &CommitMap) -> AnyResult<()> {
+        println!(
+            "Rewrote {} message(s) with `{}`, {} from cache",
+            self.translated + self.cached,
+            self.command,
+            self.cached
+        );
+        Ok(())
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        Some(CacheStats {
+            hits: self.cached,
+            misses: self.translated,
+        })
+    }
+}
@@ -0,0 +1,287 @@
+use crate::{AnyResult, metrics::CacheStats, mirror::CommitDescriptor};
+use gix::{
+    ObjectId, Repository,
+    bstr::{BStr, BString, ByteSlice},
+    glob::wildmatch,
+    object::tree::diff::ChangeDetached,
+    objs::tree::{Entry, EntryMode},
+};
+use std::{collections::HashMap, fmt, str::FromStr};
+
+/// What to do with a single tree entry.
+pub(crate) enum EntryAction {
+    Keep,
+    Remove,
+    Replace { id: ObjectId, mode: EntryMode },
+}
+
+/// An entry the visitor removed or replaced, with its full path.
+#[derive(Clone)]
+pub(crate) struct EntryChange {
+    pub(crate) path: BString,
+    pub(crate) old_id: ObjectId,
+}
+
+#[derive(Clone)]
+pub(crate) struct RewrittenTree {
+    pub(crate) id: ObjectId,
+    pub(crate) changes: Vec<EntryChange>,
+}
+
+type Visitor<'a> = dyn FnMut(&BStr, EntryMode, ObjectId) -> AnyResult<EntryAction> + 'a;
+
+/// Rewrites trees entry by entry.
+///
+/// The visitor must decide on path, mode and id alone, so that subtrees seen at the same
+/// path in earlier commits can be reused instead of being walked again.
+#[derive(Default)]
+pub(crate) struct TreeRewriter {
+    cache: HashMap<(BString, ObjectId), (ObjectId, Vec<EntryChange>)>,
+    /// Input and rewritten tree of every commit seen by [`TreeRewriter::rewrite_commit`].
+    commits: HashMap<ObjectId, (ObjectId, RewrittenTree)>,
+    stats: CacheStats,
+}
+
+impl TreeRewriter {
+    /// Forget every rewritten tree, for a visitor whose decisions changed.
+    pub(crate) fn clear(&mut self) {
+        self.cache.clear();
+        self.commits.clear();
+    }
+
+    /// Subtrees (and commits applied to their parent's tree) reused, and trees walked.
+    pub(crate) fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    pub(crate) fn rewrite(
+        &mut self,
+        repo: &Repository,
+        tree_id: ObjectId,
+        visit: &mut Visitor<'_>,
+    ) -> AnyResult<RewrittenTree> {
+        let (id, changes) = self.rewrite_at(repo, BString::default(), tree_id, visit)?;
+        Ok(RewrittenTree { id, changes })
+    }
+
+    /// Rewrite the tree of `descriptor`, for a visitor that only acts on `filtered` paths.
+    ///
+    /// When the commit changes no filtered path relative to its first parent, the visitor would
+    /// do everything it did to the parent again, so the changes are applied to the parent's
+    /// rewritten tree instead of walking every changed directory.
+    pub(crate) fn rewrite_commit(
+        &mut self,
+        repo: &Repository,
+        descriptor: &CommitDescriptor,
+        filtered: &dyn Fn(&BStr) -> bool,
+        visit: &mut Visitor<'_>,
+    ) -> AnyResult<RewrittenTree> {
+        let parent = descriptor
+            .original_parent_ids
+            .first()
+            .and_then(|parent| self.commits.get(parent));
+        let rewritten = match parent {
+            Some((parent_tree, parent_rewritten)) => {
+                let changes = diff(repo, Some(*parent_tree), descriptor.tree)?;
+                if changes
+                    .iter()
+                    .any(|change| filtered(change.location()) || filtered(change.source_location()))
+                {
+                    self.rewrite(repo, descriptor.tree, visit)?
+                } else {
+                    let mut editor = repo.edit_tree(parent_rewritten.id)?;
+                    for change in &changes {
+                        let (mode, id) = change.entry_mode_and_id();
+                        match change {
+                            ChangeDetached::Deletion { .. } => {
+                                editor.remove(change.location())?;
+                            }
+                            // Children are listed too, and the parent's subtree may hold
+                            // rewritten entries that must be kept.
+                            _ if mode.is_tree() => {}
+                            _ => {
+                                editor.upsert(change.location(), mode.kind(), id)?;
+                            }
+                        }
+                    }
+                    self.stats.hits += 1;
+                    RewrittenTree {
+                        id: editor.write()?.detach(),
+                        changes: parent_rewritten.changes.clone(),
+                    }
+                }
+            }
+            None => self.rewrite(repo, descriptor.tree, visit)?,
+        };
+
+        self.commits
+            .insert(descriptor.original_id, (descriptor.tree, rewritten.clone()));
+        Ok(rewritten)
+    }
+
+    fn rewrite_at(
+        &mut self,
+        repo: &Repository,
+        prefix: BString,
+        tree_id: ObjectId,
+        visit: &mut Visitor<'_>,
+    ) -> AnyResult<(ObjectId, Vec<EntryChange>)> {
+        let key = (prefix, tree_id);
+        if let Some(cached) = self.cache.get(&key) {
+            self.stats.hits += 1;
+            return Ok(cached.clone());
+        }
+        self.stats.misses += 1;
+        let prefix = &key.0;
+
+        let tree = repo.find_tree(tree_id)?;
+        let mut entries = Vec::new();
+        let mut changes = Vec::new();
+        let mut modified = false;
+
+        for entry in tree.decode()?.entries {
+            let mut path = prefix.clone();
+            if !path.is_empty() {
+                path.push(b'/');
+            }
+            path.extend_from_slice(entry.filename);
+
+            let old_id = entry.oid.to_owned();
+            let mut new_entry = Entry {
+                mode: entry.mode,
+                filename: entry.filename.into(),
+                oid: old_id,
+            };
+
+            match visit(path.as_ref(), entry.mode, old_id)? {
+                EntryAction::Keep if entry.mode.is_tree() => {
+                    let (sub_id, sub_changes) = self.rewrite_at(repo, path, old_id, visit)?;
+                    modified |= sub_id != old_id;
+                    changes.extend(sub_changes);
+                    // Git can't check out empty directories, so they go along with their entries.
+                    if sub_id != old_id && sub_id == ObjectId::empty_tree(repo.object_hash()) {
+                        continue;
+                    }
+                    new_entry.oid = sub_id;
+                }
+                EntryAction::Keep => {}
+                EntryAction::Remove => {
+                    modified = true;
+                    changes.push(EntryChange { path, old_id });
+                    continue;
+                }
+                EntryAction::Replace { id, mode } => {
+                    modified |= id != old_id || mode != entry.mode;
+                    changes.push(EntryChange { path, old_id });
+                    new_entry.oid = id;
+                    new_entry.mode = mode;
+                }
+            }
+            entries.push(new_entry);
+        }
+
+        let new_id = if modified {
+            // Replacements may turn a tree into a blob or vice versa, which changes sort order.
+            entries.sort();
+            repo.write_object(&gix::objs::Tree { entries })?.detach()
+        } else {
+            tree_id
+        };
+
+        self.cache.insert(key.clone(), (new_id, changes.clone()));
+        Ok((new_id, changes))
+    }
+}
+
+/// Changes turning `old` (or the empty tree) into `new`, with full paths and without rename tracking.
+pub(crate) fn diff(
+    repo: &Repository,
+    old: Option<ObjectId>,
+    new: ObjectId,
+) -> AnyResult<Vec<ChangeDetached>> {
+    let old = old.map(|id| repo.find_tree(id)).transpose()?;
+    let new = repo.find_tree(new)?;
+    Ok(repo.diff_tree_to_tree(old.as_ref(), &new, gix::diff::Options::default())?)
+}
+
+/// Like [`diff`], with the renames and copies `rewrites` detects reported as
+/// [`ChangeDetached::Rewrite`].
+pub(crate) fn diff_with_renames(
+    repo: &Repository,
+    old: Option<ObjectId>,
+    new: ObjectId,
+    rewrites: gix::diff::Rewrites,
+) -> AnyResult<Vec<ChangeDetached>> {
+    let old = old.map(|id| repo.find_tree(id)).transpose()?;
+    let new = repo.find_tree(new)?;
+    let options = gix::diff::Options::default().with_rewrites(Some(rewrites));
+    Ok(repo.diff_tree_to_tree(old.as_ref(), &new, options)?)
+}
+
+/// A glob matched against repository paths.
+///
+/// Patterns without a `/` match a file or directory name anywhere, others match from the root.
+/// A pattern matching a directory matches everything below it.
+#[derive(Clone, Debug)]
+pub struct PathPattern {
+    pattern: BString,
+    anchored: bool,
+}
+
+impl PathPattern {
+    pub fn matches(&self, path: &BStr) -> bool {
+        self.match_depth(path).is_some()
+    }
+
+    /// How many components of `path` the pattern matches: all of them when it matches the path
+    /// itself, fewer when it only matches a directory above it.
+    pub fn match_depth(&self, path: &BStr) -> Option<usize> {
+        let mut candidate = path;
+        loop {
+            if self.matches_exactly(candidate) {
+                return Some(candidate.split_str("/").count());
+            }
+            match candidate.rfind_byte(b'/') {
+                Some(slash) => candidate = candidate[..slash].as_bstr(),
+                None => return None,
+            }
+        }
+    }
+
+    fn matches_exactly(&self, path: &BStr) -> bool {
+        let subject = if self.anchored {
+            path
+        } else {
+            path.rsplit_str("/").next().unwrap_or_default().as_bstr()
+        };
+        wildmatch(
+            self.pattern.as_ref(),
+            subject,
+            wildmatch::Mode::NO_MATCH_SLASH_LITERAL,
+        )
+    }
+}
+
+impl FromStr for PathPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pattern = s.trim_start_matches('/').trim_end_matches('/');
+        if pattern.is_empty() {
+            return Err(format!("empty path pattern `{s}`"));
+        }
+        Ok(PathPattern {
+            anchored: s.starts_with('/') || pattern.contains('/'),
+            pattern: pattern.into(),
+        })
+    }
+}
+
+impl fmt::Display for PathPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.anchored && !self.pattern.contains(&b'/') {
+            write!(f, "/")?;
+        }
+        write!(f, "{}", self.pattern)
+    }
+}
@@ -0,0 +1,301 @@
+//! Encrypted storage for blob contents redacted from rewritten history.
+use crate::{
+    AnyResult,
+    filter::Filter,
+    map::CommitMap,
+    metrics::CacheStats,
+    mirror::{self, CommitDescriptor},
+    state,
+    tree::{EntryAction, PathPattern, TreeRewriter},
+};
+use argon2::Argon2;
+use chacha20poly1305::{
+    AeadCore, ChaCha20Poly1305, Key, KeyInit, Nonce,
+    aead::{Aead, OsRng, Payload, rand_core::RngCore},
+};
+use gix::{
+    ObjectId, Repository,
+    bstr::{BStr, BString},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Environment variable holding the vault passphrase.
+pub const PASSPHRASE_ENV: &str = "MAGITULATOR_VAULT_PASSPHRASE";
+
+/// Content of redacted blobs in rewritten trees.
+const PLACEHOLDER: &[u8] = b"This file was redacted by magitulator. \
+Its original content is kept in the vault; restore it with `gitm recover <commit> <path>`.\n";
+
+/// Encrypted with the vault key to detect a wrong passphrase before anything is added.
+const CHECK_PLAINTEXT: &[u8] = b"magitulator vault";
+
+pub fn default_path(repo: &Repository) -> PathBuf {
+    state::dir(repo).join("vault.json")
+}
+
+#[derive(Serialize, Deserialize)]
+struct VaultFile {
+    salt: String,
+    check: Sealed,
+    entries: Vec<VaultEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct VaultEntry {
+    /// The rewritten commit the redacted blob belongs to.
+    commit: String,
+    path: String,
+    content: Sealed,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Sealed {
+    nonce: String,
+    data: String,
+}
+
+pub struct Vault {
+    path: PathBuf,
+    file: VaultFile,
+    cipher: ChaCha20Poly1305,
+}
+
+impl Vault {
+    /// Open the vault at `path`, creating it if it doesn't exist yet.
+    pub fn open_or_create(path: &Path, passphrase: &str) -> AnyResult<Self> {
+        if path.exists() {
+            return Self::open(path, passphrase);
+        }
+
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        let cipher = cipher(passphrase, &salt)?;
+        let check = seal(&cipher, CHECK_PLAINTEXT, b"check")?;
+
+        Ok(Vault {
+            path: path.to_owned(),
+            file: VaultFile {
+                salt: hex::encode(salt),
+                check,
+                entries: Vec::new(),
+            },
+            cipher,
+        })
+    }
+
+    pub fn open(path: &Path, passphrase: &str) -> AnyResult<Self> {
+        let file: VaultFile = serde_json::from_slice(&fs::read(path)?)?;
+        let cipher = cipher(passphrase, &hex::decode(&file.salt)?)?;
+        if open(&cipher, &file.check, b"check").is_err() {
+            return Err(format!("wrong passphrase for vault {}", path.display()).into());
+        }
+
+        Ok(Vault {
+            path: path.to_owned(),
+            file,
+            cipher,
+        })
+    }
+
+    pub fn add(&mut self, commit: ObjectId, path: &str, content: &[u8]) -> AnyResult<()> {
+        let commit = commit.to_string();
+        let content = seal(&self.cipher, content, entry_aad(&commit, path).as_bytes())?;
+
+        self.file
+            .entries
+            .retain(|entry| entry.commit != commit || entry.path != path);
+        self.file.entries.push(VaultEntry {
+            commit,
+            path: path.to_owned(),
+            content,
+        });
+        Ok(())
+    }
+
+    pub fn get(&self, commit: ObjectId, path: &str) -> AnyResult<Option<Vec<u8>>> {
+        let commit = commit.to_string();
+        let Some(entry) = self
+            .file
+            .entries
+            .iter()
+            .find(|entry| entry.commit == commit && entry.path == path)
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(open(
+            &self.cipher,
+            &entry.content,
+            entry_aad(&commit, path).as_bytes(),
+        )?))
+    }
+
+    pub fn save(&self) -> AnyResult<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_vec_pretty(&self.file)?)?;
+        Ok(())
+    }
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> AnyResult<ChaCha20Poly1305> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| format!("deriving vault key: {e}"))?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+/// Binds an entry's ciphertext to its commit and path, so entries can't be swapped.
+fn entry_aad(commit: &str, path: &str) -> String {
+    format!("{commit} {path}")
+}
+
+fn seal(cipher: &ChaCha20Poly1305, plaintext: &[u8], aad: &[u8]) -> AnyResult<Sealed> {
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let data = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| "encrypting vault entry failed")?;
+
+    Ok(Sealed {
+        nonce: hex::encode(nonce),
+        data: hex::encode(data),
+    })
+}
+
+fn open(cipher: &ChaCha20Poly1305, sealed: &Sealed, aad: &[u8]) -> AnyResult<Vec<u8>> {
+    let nonce = hex::decode(&sealed.nonce)?;
+    let data = hex::decode(&sealed.data)?;
+    Ok(cipher
+        .decrypt(Nonce::from_slice(&nonce), Payload { msg: &data, aad })
+        .map_err(|_| "vault entry failed to decrypt")?)
+}
+
+pub(crate) fn passphrase() -> AnyResult<String> {
+    std::env::var(PASSPHRASE_ENV)
+        .map_err(|_| format!("set {PASSPHRASE_ENV} to the vault passphrase").into())
+}
+
+/// Replaces blobs at matching paths with a placeholder, keeping the originals in the vault.
+///
+/// The vault is opened before anything is rewritten, so a wrong passphrase stops the run
+/// before redacted content could end up only in the rewritten commits' placeholders.
+pub(crate) struct RedactFilter {
+    patterns: Vec<PathPattern>,
+    vault: Vault,
+    rewriter: TreeRewriter,
+    placeholder: Option<ObjectId>,
+    /// `(original commit, path, original blob)` for every redacted blob.
+    redacted: Vec<(ObjectId, BString, ObjectId)>,
+}
+
+impl RedactFilter {
+    pub(crate) fn new(patterns: Vec<PathPattern>, vault_path: PathBuf) -> AnyResult<Self> {
+        Ok(RedactFilter {
+            patterns,
+            vault: Vault::open_or_create(&vault_path, &passphrase()?)?,
+            rewriter: TreeRewriter::default(),
+            placeholder: None,
+            redacted: Vec::new(),
+        })
+    }
+}
+
+impl Filter for RedactFilter {
+    fn name(&self) -> String {
+        "--redact".into()
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        Some(self.rewriter.stats())
+    }
+
+    fn apply(&mut self, repo: &Repository, descriptor: &mut CommitDescriptor) -> AnyResult<()> {
+        let placeholder = match self.placeholder {
+            Some(id) => id,
+            None => *self
+                .placeholder
+                .insert(repo.write_blob(PLACEHOLDER)?.detach()),
+        };
+
+        let patterns = &self.patterns;
+        let filtered = |path: &BStr| patterns.iter().any(|p| p.matches(path));
+        let rewritten =
+            self.rewriter
+                .rewrite_commit(repo, descriptor, &filtered, &mut |path, mode, _| {
+                    Ok(
+                        if mode.is_blob() && patterns.iter().any(|p| p.matches(path)) {
+                            EntryAction::Replace {
+                                id: placeholder,
+                                mode,
+                            }
+                        } else {
+                            EntryAction::Keep
+                        },
+                    )
+                })?;
+
+        descriptor.tree = rewritten.id;
+        for change in rewritten.changes {
+            if change.old_id != placeholder {
+                self.redacted
+                    .push((descriptor.original_id, change.path, change.old_id));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self, repo: &Repository, map: &CommitMap) -> AnyResult<()> {
+        if self.redacted.is_empty() {
+            return Ok(());
+        }
+
+        for (original, path, blob) in &self.redacted {
+            let Some(commit) = map.entries.get(original) else {
+                continue;
+            };
+            let content = repo.find_blob(*blob)?;
+            self.vault.add(*commit, &path.to_string(), &content.data)?;
+        }
+        self.vault.save()?;
+
+        println!(
+            "Redacted {} blob(s) into {}",
+            self.redacted.len(),
+            self.vault.path.display()
+        );
+        Ok(())
+    }
+}
+
+/// Write the original content of `path` in `commit` to stdout.
+pub fn recover(
+    repo: &Repository,
+    commit: &str,
+    path: &str,
+    vault_path: Option<&Path>,
+) -> AnyResult<()> {
+    let vault_path = vault_path.map_or_else(|| default_path(repo), Path::to_owned);
+
+    let vault = Vault::open(&vault_path, &passphrase()?)?;
+    let commit_id = mirror::resolve_commit_id(repo, commit)?;
+    let Some(content) = vault.get(commit_id, path)? else {
+        return Err(format!("`{path}` in {commit_id} is not in the vault").into());
+    };
+
+    std::io::stdout().write_all(&content)?;
+    Ok(())
+}
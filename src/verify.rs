@@ -0,0 +1,97 @@
+use crate::{
+    AnyResult,
+    error::Failure,
+    map::CommitMap,
+    refs::{self, MirrorNaming},
+};
+use colored::Colorize;
+use gix::{ObjectId, Repository, refs::FullName};
+use std::collections::BTreeMap;
+
+/// An original commit that different mirrors rewrote into different commits.
+pub struct Divergence {
+    pub original: ObjectId,
+    pub rewritten: Vec<(FullName, ObjectId)>,
+}
+
+/// Check that every original commit shared by the given maps was rewritten into the same commit.
+pub fn check_consistency(maps: &[(FullName, CommitMap)]) -> Vec<Divergence> {
+    let mut by_original: BTreeMap<ObjectId, Vec<(FullName, ObjectId)>> = BTreeMap::new();
+    for (name, map) in maps {
+        for (old, new) in &map.entries {
+            by_original
+                .entry(*old)
+                .or_default()
+                .push((name.clone(), *new));
+        }
+    }
+
+    by_original
+        .into_iter()
+        .filter(|(_, rewritten)| rewritten.iter().any(|(_, new)| *new != rewritten[0].1))
+        .map(|(original, rewritten)| Divergence {
+            original,
+            rewritten,
+        })
+        .collect()
+}
+
+/// Maps of all existing mirrors, skipping mirrors created without one.
+pub fn mirror_maps(
+    repo: &Repository,
+    naming: &MirrorNaming,
+) -> AnyResult<Vec<(FullName, CommitMap)>> {
+    let mut maps = Vec::new();
+    for mirror in refs::list_mirrors(repo, naming)? {
+        if let Some(map) = CommitMap::load(repo, mirror.name.as_ref())? {
+            maps.push((mirror.name, map));
+        }
+    }
+    Ok(maps)
+}
+
+/// Fail if `map`, about to be recorded for `mirror_ref`, disagrees with any other mirror.
+pub fn ensure_consistent_with_mirrors(
+    repo: &Repository,
+    naming: &MirrorNaming,
+    mirror_ref: &FullName,
+    map: &CommitMap,
+) -> AnyResult<()> {
+    let mut maps = mirror_maps(repo, naming)?;
+    maps.retain(|(name, _)| name != mirror_ref);
+    maps.push((mirror_ref.clone(), map.clone()));
+
+    report(&check_consistency(&maps))
+}
+
+pub fn verify(repo: &Repository, naming: &MirrorNaming) -> AnyResult<()> {
+    let maps = mirror_maps(repo, naming)?;
+
+    report(&check_consistency(&maps))?;
+    println!("{} mirror(s) consistent", maps.len());
+
+    Ok(())
+}
+
+fn report(divergences: &[Divergence]) -> AnyResult<()> {
+    if divergences.is_empty() {
+        return Ok(());
+    }
+
+    for divergence in divergences {
+        eprintln!(
+            "{} was rewritten inconsistently:",
+            divergence.original.to_hex_with_len(7).to_string().yellow()
+        );
+        for (name, new) in &divergence.rewritten {
+            eprintln!("  {} in {}", new.to_hex_with_len(7), name.as_bstr());
+        }
+    }
+
+    Err(Failure::Validation(format!(
+        "{} shared commit(s) map to more than one rewritten commit; \
+         re-mirror the affected branches with the same options",
+        divergences.len()
+    ))
+    .into())
+}
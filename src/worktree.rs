@@ -0,0 +1,60 @@
+//! Scratch checkouts of mirrors in linked worktrees.
+use crate::AnyResult;
+use gix::{ObjectId, Repository, refs::FullNameRef};
+use std::{path::PathBuf, process::Command};
+
+/// Check out `mirror_ref` in a new linked worktree under the temporary directory and return
+/// its path.
+///
+/// gix can't create linked worktrees yet, so this runs `git worktree add`. Branches are checked
+/// out as such; mirrors kept under a ref namespace, or in a repository's git ref namespace, get
+/// a detached `HEAD`.
+pub(crate) fn checkout_mirror(
+    repo: &Repository,
+    mirror_ref: &FullNameRef,
+    tip: ObjectId,
+) -> AnyResult<PathBuf> {
+    let name: String = mirror_ref
+        .shorten()
+        .to_string()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    let path = std::env::temp_dir().join(format!("magitulator-{name}-{}", tip.to_hex_with_len(7)));
+
+    let mut command = Command::new("git");
+    // Deep trees exceed the 260 characters Windows allows by default.
+    command
+        .args(["-c", "core.longpaths=true"])
+        .arg("--git-dir")
+        .arg(repo.common_dir())
+        .args(["worktree", "add"]);
+    // git resolves branches outside the ref namespace, where `HEAD` of a worktree lives too.
+    let branch = match repo.namespace() {
+        Some(_) => None,
+        None => mirror_ref
+            .as_bstr()
+            .to_string()
+            .strip_prefix("refs/heads/")
+            .map(str::to_owned),
+    };
+    match branch {
+        Some(branch) => command.arg(&path).arg(branch),
+        None => command.arg("--detach").arg(&path).arg(tip.to_string()),
+    };
+    let output = command
+        .output()
+        .map_err(|e| format!("running git worktree add: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "git worktree add {} failed: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr)
+                .lines()
+                .last()
+                .unwrap_or_default()
+        )
+        .into());
+    }
+    Ok(path)
+}